@@ -16,7 +16,7 @@ fn main() -> sombra::Result<()> {
     let msg = "Hello sombra";
 
     // Creating sombra obj
-    let tcp_server = sombra::build("tcp_server", executable_path, vec![])?;
+    let tcp_server = sombra::build("tcp_server", executable_path, Vec::<String>::new())?;
     // Creating and starting a service, in this case, a backgrounding TCP Server
     tcp_server.create()?;
 
@@ -24,13 +24,13 @@ fn main() -> sombra::Result<()> {
     let mut stream = TcpStream::connect("127.0.0.1:30222")?;
     println!("Sending \"{}\" to Background TCP Server...", msg);
     // Sending a message to the backgrounding TCP Server
-    stream.write(msg.as_bytes())?;
+    stream.write_all(msg.as_bytes())?;
     println!("Message sent with success.");
 
     // Wait the response of backgrounding TCP Server
-    stream.read(&mut buffer)?;
+    let len = stream.read(&mut buffer)?;
     // Cast 'buffer' to Vector
-    let mut buffer = buffer.to_vec();
+    let mut buffer = buffer[..len].to_vec();
     // Retain only non empty bytes
     buffer.retain(|&x| x != 0);
     println!("Receive from Background TCP Server: {}", std::str::from_utf8(buffer.as_slice()).unwrap());
@@ -0,0 +1,40 @@
+use std::path::{Path, PathBuf};
+
+/// Something a backend would install for a service, from `Sombra::render`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Artifact {
+    /// Where the file is written, or `None` for a registration the service
+    /// manager keeps itself, which `content` describes
+    pub path: Option<PathBuf>,
+    pub content: String,
+    /// Whether the file is a script the service manager runs
+    pub executable: bool,
+}
+
+impl Artifact {
+    pub(crate) fn file<P: AsRef<Path>>(path: P, content: String) -> Self {
+        Artifact { path: Some(path.as_ref().to_path_buf()), content, executable: false }
+    }
+
+    pub(crate) fn script<P: AsRef<Path>>(path: P, content: String) -> Self {
+        Artifact { path: Some(path.as_ref().to_path_buf()), content, executable: true }
+    }
+
+    pub(crate) fn registration(content: String) -> Self {
+        Artifact { path: None, content, executable: false }
+    }
+}
+
+impl std::fmt::Display for Artifact {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.path {
+            Some(path) => writeln!(f, "==> {} <==", path.display())?,
+            None => writeln!(f, "==> registration <==")?,
+        }
+        write!(f, "{}", self.content)?;
+        if !self.content.ends_with('\n') {
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
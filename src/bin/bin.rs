@@ -6,44 +6,210 @@ use colored::*;
 #[derive(StructOpt, Debug)]
 #[structopt(name = "sombra")]
 enum CLIArgs {
-    /// Create a service and start it
+    /// Install a service and start it
+    #[structopt(alias = "create", setting = AppSettings::AllowLeadingHyphen)]
+    Install {
+        /// Name of service
+        name: String,
+        /// Path of service executable
+        #[structopt(parse(from_os_str))]
+        path: std::path::PathBuf,
+        /// Arguments of target process
+        #[structopt(parse(from_os_str))]
+        args: Vec<std::ffi::OsString>,
+    },
+    /// Print the files an install would write, without installing
     #[structopt(setting = AppSettings::AllowLeadingHyphen)]
-    Create {
+    Render {
         /// Name of service
         name: String,
         /// Path of service executable
-        path: String,
+        #[structopt(parse(from_os_str))]
+        path: std::path::PathBuf,
         /// Arguments of target process
-        args: Vec<String>,
+        #[structopt(parse(from_os_str))]
+        args: Vec<std::ffi::OsString>,
+    },
+    /// Print a script installing the service, for another machine
+    #[structopt(setting = AppSettings::AllowLeadingHyphen)]
+    Export {
+        /// Print the script uninstalling it instead
+        #[structopt(long)]
+        uninstall: bool,
+        /// Name of service
+        name: String,
+        /// Path of service executable
+        #[structopt(parse(from_os_str))]
+        path: std::path::PathBuf,
+        /// Arguments of target process
+        #[structopt(parse(from_os_str))]
+        args: Vec<std::ffi::OsString>,
     },
     /// Delete a service from system
-    Delete {
+    #[structopt(alias = "delete")]
+    Uninstall {
         /// Name of service
         name: String
     },
+    /// Start an installed service
+    Start {
+        /// Name of service
+        name: String
+    },
+    /// Stop a running service
+    Stop {
+        /// Name of service
+        name: String
+    },
+    /// Stop a service and start it again
+    Restart {
+        /// Name of service
+        name: String
+    },
+    /// Show the status of a service
+    Status {
+        /// Name of service
+        name: String
+    },
+    /// Check the executable of a service wasn't replaced since install
+    Verify {
+        /// Name of service
+        name: String
+    },
+    /// Create, update and delete services to match a manifest
+    Apply {
+        /// Path of the manifest
+        #[structopt(default_value = "sombra.toml")]
+        manifest: String,
+        /// File listing the services of previous applies, by default the
+        /// manifest path with `.applied` appended
+        #[structopt(long)]
+        state: Option<String>,
+        /// Print the changes instead of applying them
+        #[structopt(long)]
+        dry_run: bool,
+    },
+    /// Store a secret of a service, read from the standard input
+    Secret {
+        /// Remove the secret instead
+        #[structopt(long)]
+        remove: bool,
+        /// Name of service
+        name: String,
+        /// Key of the secret
+        key: String,
+    },
+    /// Print the output captured from a service
+    Logs {
+        /// Name of service
+        name: String,
+        /// Wait for new lines
+        #[structopt(short, long)]
+        follow: bool,
+    },
+}
+
+/// An installed service, for the commands that don't install it
+#[cfg(target_os = "windows")]
+fn existing(name: &str) -> sombra::Result<sombra::SombraWindows> {
+    sombra::SombraWindows::from_existing(name)
+}
+
+#[cfg(not(target_os = "windows"))]
+fn existing(name: &str) -> sombra::Result<impl Sombra> {
+    sombra::build(name, ".", Vec::<String>::new())
 }
 
-fn cli_handler(args: CLIArgs) -> sombra::Result<String> {
+fn cli_handler(args: CLIArgs) -> sombra::Result<Option<String>> {
     let success_msg = match args {
-        CLIArgs::Create {name, path, mut args } => {
-            args.retain(|x| x != "");
-            sombra::build(&name, &path, args)?.create()?;
+        CLIArgs::Install {name, path, mut args } => {
+            args.retain(|x| !x.is_empty());
+            sombra::build(&name, path, args)?.create()?;
             format!("Service {} created with success", name)
         },
-        CLIArgs::Delete {name} => {
-            sombra::build(&name, ".", vec![])?.delete()?;
+        CLIArgs::Render {name, path, mut args } => {
+            args.retain(|x| !x.is_empty());
+            for artifact in sombra::build(&name, path, args)?.render()? {
+                print!("{}", artifact);
+            }
+            return Ok(None);
+        },
+        CLIArgs::Export {uninstall, name, path, mut args } => {
+            args.retain(|x| !x.is_empty());
+            let scripts = sombra::build(&name, path, args)?.export()?;
+            print!("{}", if uninstall { scripts.uninstall } else { scripts.install });
+            return Ok(None);
+        },
+        CLIArgs::Secret {remove, name, key} => {
+            let store = sombra::SecretStore::new(&name);
+            if remove {
+                store.remove(&key)?;
+                format!("Secret {} of {} removed with success", key, name)
+            } else {
+                let mut secret = String::new();
+                std::io::Read::read_to_string(&mut std::io::stdin(), &mut secret)?;
+                let secret = secret.strip_suffix('\n').unwrap_or(&secret);
+                let secret = secret.strip_suffix('\r').unwrap_or(secret);
+                store.set(&key, &sombra::SecretString::from(secret))?;
+                format!("Secret {} of {} stored with success", key, name)
+            }
+        },
+        CLIArgs::Uninstall {name} => {
+            sombra::build(&name, ".", Vec::<String>::new())?.delete()?;
             format!("Service {} deleted with success", name)
-        }
+        },
+        CLIArgs::Start {name} => {
+            existing(&name)?.start()?;
+            format!("Service {} started with success", name)
+        },
+        CLIArgs::Stop {name} => {
+            existing(&name)?.stop()?;
+            format!("Service {} stopped with success", name)
+        },
+        CLIArgs::Restart {name} => {
+            existing(&name)?.restart()?;
+            format!("Service {} restarted with success", name)
+        },
+        CLIArgs::Status {name} => {
+            format!("Service {} is {:?}", name, existing(&name)?.status()?)
+        },
+        CLIArgs::Verify {name} => {
+            existing(&name)?.verify()?;
+            format!("Service {} runs the executable it was installed with", name)
+        },
+        CLIArgs::Apply {manifest, state, dry_run} => {
+            let state = state.unwrap_or_else(|| format!("{}.applied", manifest));
+            if dry_run {
+                print!("{}", sombra::Manifest::load(&manifest)?.plan(state.as_ref())?);
+                return Ok(None);
+            }
+            let applied = sombra::Manifest::load(&manifest)?.apply(state.as_ref())?;
+            format!("Manifest {} applied with success: {} created, {} updated, {} removed",
+                    manifest, applied.created.len(), applied.updated.len(),
+                    applied.removed.len())
+        },
+        CLIArgs::Logs {name, follow} => {
+            for line in existing(&name)?.logs(follow)? {
+                println!("{}", line?);
+            }
+            return Ok(None);
+        },
     };
 
-    Ok(success_msg)
+    Ok(Some(success_msg))
 }
 
 fn main() {
     let args = CLIArgs::from_args();
 
     match cli_handler(args) {
-        Ok(success_msg) => println!("[{}] {}", "OK".green(), success_msg),
-        Err(e) => println!("[{}] {}", "ERR".red(), e.to_string()),
+        Ok(Some(success_msg)) => println!("[{}] {}", "OK".green(), success_msg),
+        Ok(None) => {},
+        Err(e) => {
+            println!("[{}] {}", "ERR".red(), e);
+            if e.kind() == sombra::ErrorKind::AccessDenied && !sombra::is_elevated() {
+                println!("Run it again as root, or from an administrator terminal on Windows");
+            }
+        },
     }
 }
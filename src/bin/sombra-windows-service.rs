@@ -0,0 +1,13 @@
+//! sombra-windows-service.exe, the wrapper the SCM starts for a service
+//! created by `SombraWindows`. It runs the target as its child process.
+
+#[cfg(target_os = "windows")]
+fn main() {
+    std::process::exit(sombra::run_wrapper());
+}
+
+#[cfg(not(target_os = "windows"))]
+fn main() {
+    eprintln!("sombra-windows-service only runs as a Windows service");
+    std::process::exit(1);
+}
@@ -0,0 +1,704 @@
+use crate::{Account, FailureActions, Hardening, HealthCheck, LogRotation, Operation, Plan,
+            Priority, Probe, Readiness, ResourceLimits, RestartPolicy, Scope, Sombra,
+            SombraConfig, StartType, StopSignal, Trigger};
+
+/// Collects the settings of a service before building the platform
+/// implementation. Options not set keep the platform defaults.
+///
+/// ```no_run
+/// use sombra::{Sombra, SombraBuilder};
+///
+/// let service = SombraBuilder::new("tcp_echo", "executables/tcp_echo")
+///     .arg("-p")
+///     .arg("30200")
+///     .description("TCP echo server")
+///     .build()?;
+/// service.create()?;
+/// # Ok::<(), sombra::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct SombraBuilder {
+    config: SombraConfig,
+}
+
+impl From<SombraConfig> for SombraBuilder {
+    fn from(config: SombraConfig) -> Self {
+        SombraBuilder { config }
+    }
+}
+
+impl SombraBuilder {
+    pub fn new<P: AsRef<std::path::Path>>(name: &str, path: P) -> Self {
+        SombraBuilder {
+            config: SombraConfig::new(name, path),
+        }
+    }
+
+    /// Arguments of the target, which may not be valid UTF-8. Windows
+    /// passes them as is; unit files and plists are UTF-8, so systemd and
+    /// launchd reject them.
+    pub fn args<I, S>(mut self, args: I) -> Self
+        where I: IntoIterator<Item = S>, S: Into<std::ffi::OsString> {
+        self.config.args = args.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn arg<S: AsRef<std::ffi::OsStr>>(mut self, arg: S) -> Self {
+        self.config.args.push(arg.as_ref().to_os_string());
+        self
+    }
+
+    /// Human-readable name, e.g. shown in services.msc. Defaults to the name.
+    pub fn display_name(mut self, display_name: &str) -> Self {
+        self.config.display_name = Some(display_name.to_string());
+        self
+    }
+
+    pub fn description(mut self, description: &str) -> Self {
+        self.config.description = Some(description.to_string());
+        self
+    }
+
+    pub fn start_type(mut self, start_type: StartType) -> Self {
+        self.config.start_type = start_type;
+        self
+    }
+
+    /// Installs the service for the current user instead of the machine,
+    /// with `Scope::User`. On Linux the unit goes to
+    /// `~/.config/systemd/user` and runs without root; on macOS the plist
+    /// goes to `~/Library/LaunchAgents` and loads into the GUI session. On
+    /// Windows the service is a per-user template, which Windows copies as
+    /// `<name>_<LUID>` at each logon to run with the token of the user;
+    /// creating the template still needs administrator rights.
+    pub fn scope(mut self, scope: Scope) -> Self {
+        self.config.scope = scope;
+        self
+    }
+
+    pub fn account(mut self, account: Account) -> Self {
+        self.config.account = account;
+        self
+    }
+
+    /// Reads the password of the `Account::User` from the `SecretStore` of
+    /// the service, under `key`, when the service is created or updated,
+    /// so it is in neither the code nor a manifest
+    pub fn password_secret(mut self, key: &str) -> Self {
+        self.config.password_secret = Some(key.to_string());
+        self
+    }
+
+    /// Runs the target with `group` as its primary group. systemd and
+    /// launchd only.
+    pub fn group(mut self, group: &str) -> Self {
+        self.config.group = Some(group.to_string());
+        self
+    }
+
+    /// Sets the file mode creation mask of the target, e.g. `0o027` for
+    /// files the other users can't read. systemd and launchd only.
+    pub fn umask(mut self, umask: u32) -> Self {
+        self.config.umask = Some(umask);
+        self
+    }
+
+    /// Declares a service that must be running before this one. On Linux a
+    /// name without unit suffix refers to `<name>.service`. launchd has no
+    /// dependencies, so the macOS backend ignores them.
+    pub fn dependency(mut self, service: &str) -> Self {
+        self.config.dependencies.push(service.to_string());
+        self
+    }
+
+    /// Starts the service when `trigger` holds, e.g. once the network is
+    /// available. systemd and Windows only.
+    pub fn trigger(mut self, trigger: Trigger) -> Self {
+        self.config.triggers.push(trigger);
+        self
+    }
+
+    pub fn failure_actions(mut self, failure_actions: FailureActions) -> Self {
+        self.config.failure_actions = Some(failure_actions);
+        self
+    }
+
+    /// Sets an environment variable of the target process
+    pub fn env(mut self, key: &str, value: &str) -> Self {
+        self.config.env.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Current directory of the target process. Defaults to the directory
+    /// of the executable.
+    pub fn working_directory<P: AsRef<std::path::Path>>(mut self, path: P) -> Self {
+        self.config.working_directory = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Appends the standard output of the target process to a file
+    pub fn stdout_log<P: AsRef<std::path::Path>>(mut self, path: P) -> Self {
+        self.config.stdout_log = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Appends the standard error of the target process to a file
+    pub fn stderr_log<P: AsRef<std::path::Path>>(mut self, path: P) -> Self {
+        self.config.stderr_log = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn log_rotation(mut self, log_rotation: LogRotation) -> Self {
+        self.config.log_rotation = Some(log_rotation);
+        self
+    }
+
+    /// Sends the output of the target to journald instead of log files,
+    /// tagged with the service name. Only supported by systemd.
+    pub fn journal(mut self, enabled: bool) -> Self {
+        self.config.journal = enabled;
+        self
+    }
+
+    /// Restarts a crashed target with an exponential backoff, until it
+    /// crashes too often. Takes precedence over the restarts of
+    /// `failure_actions`. Not supported by launchd.
+    pub fn restart_policy(mut self, restart_policy: RestartPolicy) -> Self {
+        self.config.restart_policy = Some(restart_policy);
+        self
+    }
+
+    /// Signal sent to the target on stop, `StopSignal::Terminate` by default
+    pub fn stop_signal(mut self, stop_signal: StopSignal) -> Self {
+        self.config.stop_signal = stop_signal;
+        self
+    }
+
+    /// Time the target has to exit after the stop signal, before it is
+    /// killed
+    pub fn stop_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.config.stop_timeout = Some(timeout);
+        self
+    }
+
+    /// Whether stopping also kills the children of the target, which is
+    /// the default. Without it, only the target itself is signalled.
+    pub fn kill_process_tree(mut self, enabled: bool) -> Self {
+        self.config.kill_process_tree = enabled;
+        self
+    }
+
+    /// Limits the memory and CPU the target may use. Not supported by
+    /// launchd.
+    pub fn resource_limits(mut self, resource_limits: ResourceLimits) -> Self {
+        self.config.resource_limits = Some(resource_limits);
+        self
+    }
+
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.config.priority = Some(priority);
+        self
+    }
+
+    /// Restricts the target to the CPUs whose bits are set in `mask`. Not
+    /// supported by launchd.
+    pub fn cpu_affinity(mut self, mask: u64) -> Self {
+        self.config.cpu_affinity = Some(mask);
+        self
+    }
+
+    /// Sandboxes the target, e.g. with `Hardening::strict()`. systemd only.
+    pub fn hardening(mut self, hardening: Hardening) -> Self {
+        self.config.hardening = Some(hardening);
+        self
+    }
+
+    /// Grants a Linux capability to the target, e.g. `CAP_NET_BIND_SERVICE`
+    /// so an unprivileged account can bind ports 80 and 443. systemd only.
+    pub fn capability(mut self, capability: &str) -> Self {
+        self.config.capabilities.push(capability.to_string());
+        self
+    }
+
+    /// Confines the target to `path`, which it sees as `/`. The target and
+    /// the working directory must be under it. systemd and the launcher of
+    /// the other init systems, as root, only.
+    pub fn root_directory<P: AsRef<std::path::Path>>(mut self, path: P) -> Self {
+        self.config.root_directory = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Makes a file or directory of this machine available at the same
+    /// path within `root_directory`: mounted read-only by systemd, copied
+    /// at install elsewhere
+    pub fn bind_path<P: AsRef<std::path::Path>>(mut self, path: P) -> Self {
+        self.config.bind_paths.push(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Probes the target periodically, restarting it when it stops
+    /// answering. Only the Windows wrapper runs the check; the other
+    /// backends refuse it, and a `HealthMonitor` runs it instead.
+    pub fn health_check(mut self, health_check: HealthCheck) -> Self {
+        self.config.health_check = Some(health_check);
+        self
+    }
+
+    /// Treats the target as started only once it sends `READY=1` with the
+    /// sd_notify protocol. The Windows wrapper reads it from the named pipe
+    /// in `SOMBRA_NOTIFY_PIPE`, as systemd does from `NOTIFY_SOCKET`.
+    pub fn notify(mut self, enabled: bool) -> Self {
+        self.config.notify = enabled;
+        self
+    }
+
+    /// Time the target has to start, after which the service manager
+    /// considers the start failed. Ignored by launchd.
+    pub fn start_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.config.start_timeout = Some(timeout);
+        self
+    }
+
+    /// Restarts the target when it doesn't send `WATCHDOG=1` (see
+    /// `sombra::notify`) within `interval`, which it reads from
+    /// `WATCHDOG_USEC`. Not supported by launchd.
+    pub fn watchdog(mut self, interval: std::time::Duration) -> Self {
+        self.config.watchdog = Some(interval);
+        self
+    }
+
+    /// Makes `create()` return only once `probe` succeeds, or fail after
+    /// `timeout`
+    pub fn wait_ready(mut self, probe: Probe, timeout: std::time::Duration) -> Self {
+        self.config.readiness = Some(Readiness { probe, timeout });
+        self
+    }
+
+    /// Makes the Windows wrapper serve Prometheus metrics on
+    /// `127.0.0.1:<port>/metrics`. Elsewhere, see `MetricsServer` (feature
+    /// `metrics`).
+    pub fn metrics_port(mut self, port: u16) -> Self {
+        self.config.metrics_port = Some(port);
+        self
+    }
+
+    /// Path of `sombra-windows-service.exe`, the wrapper running the target
+    /// as a Windows service. By default it is looked up next to the current
+    /// executable, then in the `executables` directory. With the
+    /// `embed-wrapper` feature, the copy built into the crate is extracted
+    /// to `%ProgramData%\sombra\<name>` instead.
+    pub fn wrapper_path<P: AsRef<std::path::Path>>(mut self, path: P) -> Self {
+        self.config.wrapper_path = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Keeps Windows paths in their extended-length `\\?\` form instead of
+    /// simplifying them, for paths over 260 characters and UNC shares.
+    /// Targets on a share are only accepted for accounts with network
+    /// credentials, i.e. not `LocalService`.
+    pub fn extended_paths(mut self, enabled: bool) -> Self {
+        self.config.extended_paths = enabled;
+        self
+    }
+
+    /// Registers the target path without checking it exists, for pipelines
+    /// creating the service before copying the binary. The path is only
+    /// made absolute; `create()` registers the service without starting it
+    /// while the target is missing, and `start()` fails until it is there.
+    pub fn defer_path(mut self, deferred: bool) -> Self {
+        self.config.deferred_path = deferred;
+        self
+    }
+
+    /// Checks the target exists, is executable and is a binary of the host
+    /// architecture before registering anything. `start()` checks it
+    /// again, e.g. for a `defer_path` target copied after `create()`.
+    pub fn validate_target(mut self, enabled: bool) -> Self {
+        self.config.validate_target = enabled;
+        self
+    }
+
+    /// Verifies the Authenticode signatures of the target and of the
+    /// wrapper with WinVerifyTrust before creating or updating the service,
+    /// refusing unsigned or untrusted binaries. Windows only.
+    pub fn verify_signature(mut self, enabled: bool) -> Self {
+        self.config.verify_signature = enabled;
+        self
+    }
+
+    /// Makes the Windows wrapper report start, stop, crash and restart of
+    /// the target to the Application event log, under a source named after
+    /// the service. Other platforms log these events natively.
+    pub fn event_log(mut self, enabled: bool) -> Self {
+        self.config.event_log = enabled;
+        self
+    }
+
+    /// Runs the service with a write-restricted SID, so the wrapper and the
+    /// target can only write to files granting `NT SERVICE\<name>` access,
+    /// such as the log files. Windows only.
+    pub fn restricted_sid(mut self, enabled: bool) -> Self {
+        self.config.restricted_sid = enabled;
+        self
+    }
+
+    /// Keeps `privilege`, e.g. `SeChangeNotifyPrivilege`, in the token of
+    /// the service. Once one is given, the SCM strips all the others the
+    /// account holds. Windows only.
+    pub fn service_privilege(mut self, privilege: &str) -> Self {
+        self.config.service_privileges.push(privilege.to_string());
+        self
+    }
+
+    /// Lets `account`, a user or a group, start, stop and query the service
+    /// without administrator rights, through the security descriptor of
+    /// the service. On Linux `account` is a group, whose members polkit
+    /// allows to start, stop and restart the unit. Windows and systemd only.
+    pub fn operator(mut self, account: &str) -> Self {
+        self.config.operators.push(account.to_string());
+        self
+    }
+
+    /// Without administrator rights, runs the target under the launcher of
+    /// `SombraAutostart` instead of failing with `AccessDenied`: the same
+    /// API then spawns and restarts the target in the session of the
+    /// user, and `AutoStart` starts it at logon rather than at boot. The
+    /// service stays there until it is deleted. Windows only.
+    pub fn user_mode_fallback(mut self, enabled: bool) -> Self {
+        self.config.user_mode_fallback = enabled;
+        self
+    }
+
+    /// Manages the service on another computer, `\\HOST`, through its
+    /// SCM and remote registry, for deployments run from one box. The
+    /// target, the working directory, the log files and `wrapper_path`,
+    /// which is required, are absolute paths on that computer; they are
+    /// checked through its administrative shares. Windows only.
+    pub fn target_machine(mut self, machine: &str) -> Self {
+        self.config.machine = Some(format!("\\\\{}", machine.trim_start_matches('\\')));
+        self
+    }
+
+    /// Installs the systemd service on another host over SSH, `user@host`
+    /// as ssh(1) takes it, for agentless deployments. The unit is rendered
+    /// here and written there; the remote user is root or may sudo without
+    /// a password, and keys come from the agent or the ssh config. The
+    /// paths are the ones of that host. Linux only.
+    #[cfg(feature = "ssh")]
+    pub fn ssh_host(mut self, destination: &str) -> Self {
+        self.config.ssh_host = Some(destination.to_string());
+        self
+    }
+
+    fn absolute(path: std::path::PathBuf) -> crate::Result<std::path::PathBuf> {
+        if path.is_relative() {
+            Ok(std::env::current_dir()?.join(path))
+        } else {
+            Ok(path)
+        }
+    }
+
+    /// Whether `value` is a GUID, `xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx`,
+    /// with or without braces
+    fn is_guid(value: &str) -> bool {
+        let guid = value.strip_prefix('{').and_then(|guid| guid.strip_suffix('}'))
+            .unwrap_or(value);
+        let groups: Vec<&str> = guid.split('-').collect();
+        groups.iter().map(|group| group.len()).eq([8, 4, 4, 4, 12])
+            && groups.iter().all(|group| group.chars().all(|c| c.is_ascii_hexdigit()))
+    }
+
+    /// Validates the settings, resolving the executable path. A `.ps1`,
+    /// `.bat`, `.cmd`, `.py` or `.sh` target becomes an argument of its
+    /// interpreter, which is then the path.
+    pub fn config(self) -> crate::Result<SombraConfig> {
+        let mut config = self.config;
+        if let Account::Managed(name) = &config.account {
+            if !name.ends_with('$') {
+                return Err(crate::Error::new(crate::ErrorKind::Other,
+                                             "Managed service account names must end with $"
+                                                 .to_string())
+                    .content(name.clone()));
+            }
+        }
+        // sc.exe reads the GUIDs back bare and lowercase
+        for trigger in config.triggers.iter_mut() {
+            if let Trigger::Device(class) = trigger {
+                if cfg!(windows) && SombraBuilder::is_guid(class) {
+                    *class = class.trim_start_matches('{').trim_end_matches('}')
+                        .to_ascii_lowercase();
+                }
+            }
+        }
+        for trigger in &config.triggers {
+            // Which backends start on triggers is up to them
+            let unsupported = match trigger {
+                Trigger::Device(class) if cfg!(windows) && !SombraBuilder::is_guid(class) =>
+                    Some("Device triggers take a device interface class GUID"),
+                Trigger::Device(path) if cfg!(not(windows)) && !path.starts_with("/dev/") =>
+                    Some("Device triggers take a path under /dev"),
+                Trigger::NetworkAvailable if cfg!(not(windows)) && config.scope == Scope::User =>
+                    Some("Network triggers only apply to the system service manager"),
+                _ => None,
+            };
+            if let Some(description) = unsupported {
+                return Err(crate::Error::new(crate::ErrorKind::Other, description.to_string())
+                    .content(format!("{:?}", trigger)));
+            }
+        }
+        if config.password_secret.is_some() {
+            let unsupported = match &config.account {
+                Account::User { password: None, .. } => None,
+                Account::User { .. } => Some("The password is given and stored at once"),
+                _ => Some("Stored passwords are only for user accounts"),
+            };
+            if let Some(description) = unsupported {
+                return Err(crate::Error::new(crate::ErrorKind::Other, description.to_string()));
+            }
+        }
+        if cfg!(not(windows)) && config.verify_signature {
+            return Err(crate::Error::new(crate::ErrorKind::Other,
+                                         "Signature verification is only supported on Windows"
+                                             .to_string()));
+        }
+        if cfg!(not(windows)) && config.user_mode_fallback {
+            return Err(crate::Error::new(crate::ErrorKind::Other,
+                                         "User-mode fallback is only supported on Windows"
+                                             .to_string()));
+        }
+        if cfg!(not(windows)) && (config.restricted_sid || !config.service_privileges.is_empty()) {
+            return Err(crate::Error::new(crate::ErrorKind::Other,
+                                         "Service SIDs and privileges are only supported on \
+                                          Windows".to_string()));
+        }
+        if let Some(privilege) = config.service_privileges.iter()
+            .find(|privilege| !privilege.starts_with("Se") || !privilege.ends_with("Privilege")) {
+            return Err(crate::Error::new(crate::ErrorKind::Other,
+                                         "Privileges are named like SeChangeNotifyPrivilege"
+                                             .to_string())
+                .content(privilege.clone()));
+        }
+        if let Some(machine) = &config.machine {
+            let unsupported = if cfg!(not(windows)) {
+                Some("Remote machines are only supported on Windows")
+            } else if config.wrapper_path.is_none() {
+                Some("Services of another machine need the path of the wrapper there")
+            } else if !config.path.is_absolute() {
+                Some("Services of another machine need the absolute path of the target")
+            } else if config.user_mode_fallback || config.scope == Scope::User {
+                Some("Per-user services are only managed on the local machine")
+            } else {
+                None
+            };
+            if let Some(description) = unsupported {
+                return Err(crate::Error::new(crate::ErrorKind::Other, description.to_string())
+                    .content(machine.clone()));
+            }
+        }
+        if let Some(destination) = config.ssh_host() {
+            let unsupported = if cfg!(not(target_os = "linux")) {
+                Some("SSH hosts are only supported by the Linux backend")
+            } else if !config.path.is_absolute() {
+                Some("Services of another machine need the absolute path of the target")
+            } else if config.scope == Scope::User {
+                Some("Per-user services are only managed on the local machine")
+            } else {
+                None
+            };
+            if let Some(description) = unsupported {
+                return Err(crate::Error::new(crate::ErrorKind::Other, description.to_string())
+                    .content(destination.to_string()));
+            }
+        }
+        if config.scope == Scope::User
+            && !cfg!(any(target_os = "linux", target_os = "macos", windows)) {
+            return Err(crate::Error::new(crate::ErrorKind::Other,
+                                         "Per-user services are only supported by systemd, \
+                                          launchd and Windows"
+                                             .to_string()));
+        }
+        if let Some(capability) = config.capabilities.iter().find(|capability| {
+            !capability.starts_with("CAP_")
+                || !capability.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit()
+                                           || c == '_')
+        }) {
+            return Err(crate::Error::new(crate::ErrorKind::Other,
+                                         "Capabilities are named like CAP_NET_BIND_SERVICE"
+                                             .to_string())
+                .content(capability.clone()));
+        }
+        if config.journal && (config.stdout_log.is_some() || config.stderr_log.is_some()) {
+            return Err(crate::Error::new(crate::ErrorKind::Other,
+                                         "Journal and log files are mutually exclusive"
+                                             .to_string()));
+        }
+        if config.is_unc() && config.account == Account::LocalService {
+            return Err(crate::Error::new(crate::ErrorKind::Other,
+                                         "LocalService has no network credentials to run a \
+                                          target on a share".to_string())
+                .content(config.path.display().to_string()));
+        }
+        if config.is_remote() {
+            // Paths of the other machine, which this one can't resolve
+        } else if config.deferred_path {
+            config.path = SombraBuilder::absolute(config.path)?;
+            config.working_directory = config.working_directory.map(SombraBuilder::absolute)
+                .transpose()?;
+        } else {
+            config.path = config.canonicalize(&config.path)?;
+            config.working_directory = match &config.working_directory {
+                Some(dir) => Some(config.canonicalize(dir)?),
+                None => None,
+            };
+        }
+        if config.working_directory.is_none() {
+            config.working_directory = config.path.parent().map(|dir| dir.to_path_buf());
+        }
+        if let Some((interpreter, mut args)) = crate::script::interpreter(&config.path)? {
+            args.push(config.path.into_os_string());
+            args.append(&mut config.args);
+            config.path = interpreter;
+            config.args = args;
+            // The work of cmd.exe and shells runs in child processes
+            config.kill_process_tree = true;
+        }
+        // The targets of SSH hosts aren't reachable from here
+        if config.validate_target && !config.deferred_path && config.ssh_host().is_none() {
+            crate::validate::validate_target(&config.local_path(&config.path))?;
+        }
+        if let Some(root) = config.root_directory.take() {
+            let root = if config.is_remote() { root } else { config.canonicalize(&root)? };
+            let unsupported = if config.scope == Scope::User {
+                Some("Only the system service manager can change the root directory")
+            } else if !config.path.starts_with(&root) {
+                Some("The target must be under the root directory")
+            } else if config.working_directory.as_ref().is_some_and(|dir| !dir.starts_with(&root)) {
+                Some("The working directory must be under the root directory")
+            } else if config.bind_paths.iter().any(|path| !path.is_absolute()) {
+                Some("Bind paths must be absolute")
+            } else {
+                None
+            };
+            if let Some(description) = unsupported {
+                return Err(crate::Error::new(crate::ErrorKind::Other, description.to_string())
+                    .content(root.display().to_string()));
+            }
+            config.root_directory = Some(root);
+        } else if !config.bind_paths.is_empty() {
+            return Err(crate::Error::new(crate::ErrorKind::Other,
+                                         "Bind paths need a root directory".to_string()));
+        }
+        // Log files may not exist yet, so they can't be canonicalized
+        if !config.is_remote() {
+            config.stdout_log = config.stdout_log.map(SombraBuilder::absolute).transpose()?;
+            config.stderr_log = config.stderr_log.map(SombraBuilder::absolute).transpose()?;
+        }
+        Ok(config)
+    }
+
+    /// Operations `create_or_update()` of a specific backend would perform,
+    /// without changing anything
+    pub fn plan_create_as<S: Sombra>(self) -> crate::Result<Plan> {
+        let config = self.config()?;
+        let exists = S::from_config(config.clone())?.exists()?;
+        let operation = if exists {
+            Operation::Update(Box::new(config))
+        } else {
+            Operation::Create(Box::new(config))
+        };
+        Ok(Plan { operations: vec![operation] })
+    }
+
+    /// Operations `delete()` of a specific backend would perform, without
+    /// changing anything
+    pub fn plan_delete_as<S: Sombra>(self) -> crate::Result<Plan> {
+        let name = self.config.name.clone();
+        let service: S = self.build_as()?;
+        let operations = if service.exists()? {
+            vec![Operation::Delete(name)]
+        } else {
+            vec![]
+        };
+        Ok(Plan { operations })
+    }
+
+    /// Builds a specific backend, instead of the default of the platform.
+    pub fn build_as<S: Sombra>(self) -> crate::Result<S> {
+        S::from_config(self.config()?)
+    }
+
+    #[cfg(target_os = "windows")]
+    pub fn build(self) -> crate::Result<crate::SombraWindows> {
+        self.build_as()
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn build(self) -> crate::Result<crate::SombraLinux> {
+        self.build_as()
+    }
+
+    #[cfg(target_os = "macos")]
+    pub fn build(self) -> crate::Result<crate::SombraMacos> {
+        self.build_as()
+    }
+
+    #[cfg(target_os = "freebsd")]
+    pub fn build(self) -> crate::Result<crate::SombraFreebsd> {
+        self.build_as()
+    }
+
+    #[cfg(any(target_os = "illumos", target_os = "solaris"))]
+    pub fn build(self) -> crate::Result<crate::SombraSmf> {
+        self.build_as()
+    }
+
+    #[cfg(target_os = "windows")]
+    pub fn plan_create(self) -> crate::Result<Plan> {
+        self.plan_create_as::<crate::SombraWindows>()
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn plan_create(self) -> crate::Result<Plan> {
+        self.plan_create_as::<crate::SombraLinux>()
+    }
+
+    #[cfg(target_os = "macos")]
+    pub fn plan_create(self) -> crate::Result<Plan> {
+        self.plan_create_as::<crate::SombraMacos>()
+    }
+
+    #[cfg(target_os = "freebsd")]
+    pub fn plan_create(self) -> crate::Result<Plan> {
+        self.plan_create_as::<crate::SombraFreebsd>()
+    }
+
+    #[cfg(any(target_os = "illumos", target_os = "solaris"))]
+    pub fn plan_create(self) -> crate::Result<Plan> {
+        self.plan_create_as::<crate::SombraSmf>()
+    }
+
+    #[cfg(target_os = "windows")]
+    pub fn plan_delete(self) -> crate::Result<Plan> {
+        self.plan_delete_as::<crate::SombraWindows>()
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn plan_delete(self) -> crate::Result<Plan> {
+        self.plan_delete_as::<crate::SombraLinux>()
+    }
+
+    #[cfg(target_os = "macos")]
+    pub fn plan_delete(self) -> crate::Result<Plan> {
+        self.plan_delete_as::<crate::SombraMacos>()
+    }
+
+    #[cfg(target_os = "freebsd")]
+    pub fn plan_delete(self) -> crate::Result<Plan> {
+        self.plan_delete_as::<crate::SombraFreebsd>()
+    }
+
+    #[cfg(any(target_os = "illumos", target_os = "solaris"))]
+    pub fn plan_delete(self) -> crate::Result<Plan> {
+        self.plan_delete_as::<crate::SombraSmf>()
+    }
+}
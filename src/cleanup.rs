@@ -0,0 +1,68 @@
+use crate::{ManagedService, Sombra, SombraConfig};
+
+/// Deletes the services created by this crate whose target executable no
+/// longer exists, or whose name starts with `prefix`, e.g. the leftovers
+/// of crashed test runs on a CI agent. Returns the names of the deleted
+/// services.
+pub fn cleanup_as<S: Sombra>(prefix: Option<&str>) -> crate::Result<Vec<String>> {
+    let orphans = S::list_managed()?.into_iter().filter(|service| is_orphan(service, prefix));
+    let mut deleted = vec![];
+    for ManagedService { name, path, .. } in orphans {
+        let mut config = SombraConfig::new(&name, "");
+        config.path = path;
+        S::from_config(config)?.delete()
+            .map_err(|e| e.context(&format!("cleanup of {}", name)))?;
+        deleted.push(name);
+    }
+    Ok(deleted)
+}
+
+fn is_orphan(service: &ManagedService, prefix: Option<&str>) -> bool {
+    !service.path.exists() || prefix.is_some_and(|prefix| service.name.starts_with(prefix))
+}
+
+#[cfg(target_os = "windows")]
+pub fn cleanup(prefix: Option<&str>) -> crate::Result<Vec<String>> {
+    cleanup_as::<crate::SombraWindows>(prefix)
+}
+
+#[cfg(target_os = "linux")]
+pub fn cleanup(prefix: Option<&str>) -> crate::Result<Vec<String>> {
+    cleanup_as::<crate::SombraLinux>(prefix)
+}
+
+#[cfg(target_os = "macos")]
+pub fn cleanup(prefix: Option<&str>) -> crate::Result<Vec<String>> {
+    cleanup_as::<crate::SombraMacos>(prefix)
+}
+
+#[cfg(target_os = "freebsd")]
+pub fn cleanup(prefix: Option<&str>) -> crate::Result<Vec<String>> {
+    cleanup_as::<crate::SombraFreebsd>(prefix)
+}
+
+#[cfg(any(target_os = "illumos", target_os = "solaris"))]
+pub fn cleanup(prefix: Option<&str>) -> crate::Result<Vec<String>> {
+    cleanup_as::<crate::SombraSmf>(prefix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::SombraMock;
+
+    #[test]
+    fn cleanup_deletes_orphans() {
+        let target = std::env::current_exe().unwrap();
+        let target = target.to_str().unwrap();
+        for (name, path) in &[("api", target), ("test_echo", target),
+                              ("worker", "/opt/missing/worker")] {
+            SombraMock::from_config(SombraConfig::new(name, path)).unwrap().create().unwrap();
+        }
+
+        assert_eq!(cleanup_as::<SombraMock>(None), Ok(vec!["worker".to_string()]));
+        assert_eq!(cleanup_as::<SombraMock>(Some("test_")), Ok(vec!["test_echo".to_string()]));
+        let remaining = SombraMock::list_managed().unwrap();
+        assert_eq!(remaining.iter().map(|service| &service.name).collect::<Vec<_>>(), vec!["api"]);
+    }
+}
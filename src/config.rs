@@ -0,0 +1,657 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use crate::{HealthCheck, Readiness, SecretString};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StartType {
+    /// Started by the service manager at boot
+    AutoStart,
+    /// Started shortly after boot (Windows); same as `AutoStart` elsewhere
+    DelayedAutoStart,
+    /// Started only by `create()`, `start()` or an operator
+    OnDemand,
+    /// Installed but never started
+    Disabled,
+}
+
+/// Event starting the service once it holds, for on-demand services that
+/// need a precondition. systemd and Windows only.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Trigger {
+    /// The network is up: the first IP address on Windows,
+    /// network-online.target with systemd
+    NetworkAvailable,
+    /// A device arrives: a device interface class GUID on Windows, the
+    /// path of the device, e.g. `/dev/ttyUSB0`, with systemd, which stops
+    /// the service when the device goes away
+    Device(String),
+    /// The machine joins a domain. systemd only runs the service where
+    /// the join left a keytab in /etc/krb5.keytab.
+    DomainJoin,
+}
+
+/// Instance of the service manager the service is installed into
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    /// The manager of the machine, which needs root or administrator rights
+    System,
+    /// The manager of the current user, `systemctl --user` on Linux and
+    /// a LaunchAgent in the GUI session on macOS. The service runs as that
+    /// user, without privileges. On Windows, a per-user service template
+    /// instantiated at the logon of each user.
+    User,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Account {
+    /// LocalSystem on Windows, the user running sombra elsewhere
+    LocalSystem,
+    /// NT AUTHORITY\LocalService on Windows, `nobody` elsewhere
+    LocalService,
+    /// NT AUTHORITY\NetworkService on Windows, `nobody` elsewhere
+    NetworkService,
+    /// A local or domain account. Unix backends ignore the password.
+    User { name: String, password: Option<SecretString> },
+    /// The per-service virtual account (NT SERVICE\<name>) on Windows, whose
+    /// profile Windows creates on first start; a systemd DynamicUser on Linux
+    Virtual,
+    /// A group managed service account (DOMAIN\name$), Windows only. The
+    /// password is managed by the domain.
+    Managed(String),
+}
+
+impl Account {
+    #[cfg(unix)]
+    pub(crate) fn unix_name(&self, service: &str) -> crate::Result<String> {
+        match self {
+            Account::LocalSystem => Ok(whoami::username()),
+            Account::LocalService | Account::NetworkService => Ok("nobody".to_string()),
+            Account::User { name, .. } => Ok(name.clone()),
+            Account::Virtual => Ok(service.to_string()),
+            Account::Managed(_) => Err(crate::Error::new(crate::ErrorKind::Other,
+                "Group managed service accounts are only supported on Windows".to_string())),
+        }
+    }
+
+    /// Inverse of `unix_name`. `nobody` reads back as `LocalService`.
+    #[cfg(unix)]
+    pub(crate) fn from_unix_name(name: &str) -> Self {
+        if name == whoami::username() {
+            Account::LocalSystem
+        } else if name == "nobody" {
+            Account::LocalService
+        } else {
+            Account::User { name: name.to_string(), password: None }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureAction {
+    None,
+    /// Restart the service after the delay
+    Restart(Duration),
+}
+
+/// What the service manager does when the service fails. Windows applies
+/// each action in turn and counts failures again after `reset_period`.
+/// systemd and launchd only restart, after the delay of the first restart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FailureActions {
+    pub first: FailureAction,
+    pub second: FailureAction,
+    pub subsequent: FailureAction,
+    pub reset_period: Duration,
+}
+
+impl FailureActions {
+    /// Always restart after `delay`
+    pub fn restart(delay: Duration) -> Self {
+        FailureActions {
+            first: FailureAction::Restart(delay),
+            second: FailureAction::Restart(delay),
+            subsequent: FailureAction::Restart(delay),
+            reset_period: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+
+    pub(crate) fn restart_delay(&self) -> Option<Duration> {
+        [self.first, self.second, self.subsequent].iter()
+            .find_map(|action| match action {
+                FailureAction::Restart(delay) => Some(*delay),
+                FailureAction::None => None,
+            })
+    }
+}
+
+/// Signal asking the target to stop. If it is still running after the
+/// stop timeout, it is killed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopSignal {
+    /// SIGTERM, or CTRL_BREAK_EVENT to a console target on Windows
+    Terminate,
+    /// SIGINT, or CTRL_C_EVENT to a console target on Windows
+    Interrupt,
+}
+
+impl StopSignal {
+    #[cfg(unix)]
+    pub(crate) fn unix_name(&self) -> &'static str {
+        match self {
+            StopSignal::Terminate => "SIGTERM",
+            StopSignal::Interrupt => "SIGINT",
+        }
+    }
+}
+
+/// Restarts of a crashed target, waiting `initial_delay` and doubling the
+/// delay up to `max_delay`. After `max_restarts` restarts within `window`
+/// the service is left `Failed`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RestartPolicy {
+    pub max_restarts: u32,
+    pub window: Duration,
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RestartPolicy {
+    /// Number of times the delay doubles before reaching `max_delay`
+    #[cfg(target_os = "linux")]
+    pub(crate) fn backoff_steps(&self) -> u32 {
+        let mut delay = self.initial_delay.max(Duration::from_millis(1));
+        let mut steps = 0;
+        while delay < self.max_delay {
+            delay *= 2;
+            steps += 1;
+        }
+        steps
+    }
+}
+
+/// Rotation of the stdout/stderr log files. A file is rotated when it grows
+/// past `max_size` bytes or gets older than `max_age`, and `keep` rotated
+/// files are retained.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogRotation {
+    pub max_size: Option<u64>,
+    pub max_age: Option<Duration>,
+    pub keep: u32,
+}
+
+/// Scheduling priority of the target: a Windows priority class, or a nice
+/// value on Unix
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    Idle,
+    BelowNormal,
+    Normal,
+    AboveNormal,
+    High,
+}
+
+impl Priority {
+    #[cfg(unix)]
+    pub(crate) fn nice(&self) -> i32 {
+        match self {
+            Priority::Idle => 19,
+            Priority::BelowNormal => 10,
+            Priority::Normal => 0,
+            Priority::AboveNormal => -5,
+            Priority::High => -10,
+        }
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    pub(crate) fn from_nice(nice: i32) -> Option<Self> {
+        [Priority::Idle, Priority::BelowNormal, Priority::Normal, Priority::AboveNormal,
+         Priority::High].iter()
+            .find(|priority| priority.nice() == nice)
+            .copied()
+    }
+}
+
+/// Caps applied to the target and its children. `cpu_quota` is a
+/// percentage of one CPU, so 200 allows two full CPUs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ResourceLimits {
+    pub memory_max: Option<u64>,
+    pub cpu_quota: Option<u32>,
+}
+
+/// Parts of the file system the target sees read-only
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtectSystem {
+    /// /usr and the boot loader directories
+    Yes,
+    /// /etc too
+    Full,
+    /// The whole file system but /dev, /proc, /sys and `read_write_paths`
+    Strict,
+}
+
+/// What the target sees of /home, /root and /run/user
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtectHome {
+    /// Empty and inaccessible
+    Yes,
+    ReadOnly,
+    /// An empty writable file system, discarded when the service stops
+    Tmpfs,
+}
+
+/// Sandboxing of the target, written into the systemd unit. The other
+/// backends have no sandbox and refuse it.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Hardening {
+    pub protect_system: Option<ProtectSystem>,
+    pub protect_home: Option<ProtectHome>,
+    /// A /tmp of its own, emptied when the service stops
+    pub private_tmp: bool,
+    /// Forbids the target and its children to gain privileges, through
+    /// setuid executables among others
+    pub no_new_privileges: bool,
+    /// Paths the target may still write to under `protect_system`
+    pub read_write_paths: Vec<PathBuf>,
+    /// System calls the target may make, as names and systemd groups such
+    /// as `@system-service`, or may not with a leading `~`, as in
+    /// `~@privileged`. The target is killed on any other.
+    pub system_call_filter: Vec<String>,
+    /// Architectures whose system calls the target may make, e.g.
+    /// `native` to refuse the 32-bit ones on a 64-bit host
+    pub system_call_architectures: Vec<String>,
+}
+
+impl Hardening {
+    /// Least privilege: a read-only file system, home directories hidden,
+    /// a private /tmp and no privilege gain. Whatever the target writes to
+    /// goes in `read_write_paths`; the log files are opened by systemd
+    /// before the sandbox applies.
+    pub fn strict() -> Self {
+        Hardening {
+            protect_system: Some(ProtectSystem::Strict),
+            protect_home: Some(ProtectHome::Yes),
+            private_tmp: true,
+            no_new_privileges: true,
+            read_write_paths: vec![],
+            system_call_filter: vec![],
+            system_call_architectures: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SombraConfig {
+    pub name: String,
+    pub path: PathBuf,
+    pub args: Vec<OsString>,
+    pub display_name: Option<String>,
+    pub description: Option<String>,
+    pub start_type: StartType,
+    pub scope: Scope,
+    pub account: Account,
+    /// Key of the `SecretStore` of the service holding the password of the
+    /// `Account::User`, read when the service is created or updated
+    pub password_secret: Option<String>,
+    /// Group the target runs as, instead of the primary group of the
+    /// account. systemd and launchd only.
+    pub group: Option<String>,
+    /// File mode creation mask of the target, e.g. `0o027`, instead of the
+    /// one of the service manager. systemd and launchd only.
+    pub umask: Option<u32>,
+    pub dependencies: Vec<String>,
+    /// Events starting the service. An on-demand service with triggers is
+    /// left for them to start, instead of being started at install.
+    pub triggers: Vec<Trigger>,
+    pub failure_actions: Option<FailureActions>,
+    pub env: Vec<(String, String)>,
+    pub working_directory: Option<PathBuf>,
+    pub stdout_log: Option<PathBuf>,
+    pub stderr_log: Option<PathBuf>,
+    pub log_rotation: Option<LogRotation>,
+    pub event_log: bool,
+    pub journal: bool,
+    pub stop_signal: StopSignal,
+    /// How long the target has to exit after the stop signal, or the
+    /// default of the service manager
+    pub stop_timeout: Option<Duration>,
+    /// Whether stopping also takes down the processes spawned by the target
+    pub kill_process_tree: bool,
+    pub resource_limits: Option<ResourceLimits>,
+    pub priority: Option<Priority>,
+    /// Bit mask of the CPUs the target may run on
+    pub cpu_affinity: Option<u64>,
+    pub hardening: Option<Hardening>,
+    /// Linux capabilities granted to the target, e.g. `CAP_NET_BIND_SERVICE`
+    /// to bind ports below 1024 without root. systemd only.
+    pub capabilities: Vec<String>,
+    /// Directory the target is confined to, as its `/`. The path and the
+    /// working directory are the ones of this machine, under the root.
+    pub root_directory: Option<PathBuf>,
+    /// Paths of this machine the target needs within its root, such as
+    /// shared libraries or /etc/resolv.conf. systemd mounts them read-only
+    /// at the same place, the other backends copy them in at install.
+    pub bind_paths: Vec<PathBuf>,
+    pub health_check: Option<HealthCheck>,
+    pub readiness: Option<Readiness>,
+    /// Whether the target reports readiness with the sd_notify protocol
+    pub notify: bool,
+    /// How long the target may take to start, or the default of the
+    /// service manager
+    pub start_timeout: Option<Duration>,
+    /// Interval within which the target must send `WATCHDOG=1`, or it is
+    /// considered hung and restarted
+    pub watchdog: Option<Duration>,
+    pub restart_policy: Option<RestartPolicy>,
+    /// Local port of the Prometheus endpoint of the Windows wrapper
+    pub metrics_port: Option<u16>,
+    /// Wrapper executable of the Windows service, or the one found next to
+    /// the current executable
+    pub wrapper_path: Option<PathBuf>,
+    /// Keeps the `\\?\` prefix of canonical Windows paths, for paths over
+    /// 260 characters and UNC shares the SCM can't open otherwise
+    pub extended_paths: bool,
+    /// Registers the target path without requiring it to exist; the
+    /// service is only started once the target is in place
+    pub deferred_path: bool,
+    /// Checks the target is an executable of the host architecture before
+    /// registering or starting the service
+    pub validate_target: bool,
+    /// Refuses a target or wrapper without a trusted Authenticode
+    /// signature, Windows only
+    pub verify_signature: bool,
+    /// Runs the service with a write-restricted service SID: the token can
+    /// only write where `NT SERVICE\<name>` or Everyone may. Windows only.
+    pub restricted_sid: bool,
+    /// Privileges the token of the service keeps, e.g.
+    /// `SeChangeNotifyPrivilege`, the others being removed; all the ones
+    /// of the account when empty. Windows only.
+    pub service_privileges: Vec<String>,
+    /// Accounts or groups, e.g. `DOMAIN\operators`, allowed to start, stop
+    /// and query the service without being administrators. On Linux, groups
+    /// whose members may start, stop and restart the unit, through a polkit
+    /// rule. Windows and systemd only.
+    pub operators: Vec<String>,
+    /// Runs the target under a launcher in the session of the user when
+    /// the SCM refuses to create services, Windows only
+    pub user_mode_fallback: bool,
+    /// Computer whose SCM manages the service, `\\HOST`, instead of the
+    /// local one. Paths are the ones of that computer. Windows only.
+    pub machine: Option<String>,
+    /// Host the systemd service is installed on over SSH, `user@host`,
+    /// instead of this one. Paths are the ones of that host. Linux only,
+    /// with the `ssh` feature.
+    pub ssh_host: Option<String>,
+}
+
+impl SombraConfig {
+    pub fn new<P: AsRef<Path>>(name: &str, path: P) -> Self {
+        SombraConfig {
+            name: name.to_string(),
+            path: path.as_ref().to_path_buf(),
+            args: vec![],
+            display_name: None,
+            description: None,
+            start_type: StartType::OnDemand,
+            scope: Scope::System,
+            account: Account::LocalSystem,
+            password_secret: None,
+            group: None,
+            umask: None,
+            dependencies: vec![],
+            triggers: vec![],
+            failure_actions: None,
+            env: vec![],
+            working_directory: None,
+            stdout_log: None,
+            stderr_log: None,
+            log_rotation: None,
+            event_log: false,
+            journal: false,
+            stop_signal: StopSignal::Terminate,
+            stop_timeout: None,
+            kill_process_tree: true,
+            resource_limits: None,
+            priority: None,
+            cpu_affinity: None,
+            hardening: None,
+            capabilities: vec![],
+            root_directory: None,
+            bind_paths: vec![],
+            health_check: None,
+            readiness: None,
+            notify: false,
+            start_timeout: None,
+            watchdog: None,
+            restart_policy: None,
+            metrics_port: None,
+            wrapper_path: None,
+            extended_paths: false,
+            deferred_path: false,
+            validate_target: false,
+            verify_signature: false,
+            restricted_sid: false,
+            service_privileges: vec![],
+            operators: vec![],
+            user_mode_fallback: false,
+            machine: None,
+            ssh_host: None,
+        }
+    }
+
+    /// Log files of the target, standard output first
+    pub(crate) fn log_files(&self) -> Vec<PathBuf> {
+        self.stdout_log.iter()
+            .chain(self.stderr_log.iter())
+            .cloned()
+            .collect()
+    }
+
+    /// Canonical form of `path`, extended-length on Windows with
+    /// `extended_paths`
+    pub(crate) fn canonicalize(&self, path: &Path) -> crate::Result<PathBuf> {
+        let canonical = if self.extended_paths {
+            std::fs::canonicalize(path)
+        } else {
+            dunce::canonicalize(path)
+        };
+        canonical.map_err(|e| crate::Error::from(e).content(path.display().to_string()))
+    }
+
+    #[cfg(feature = "ssh")]
+    pub(crate) fn ssh_host(&self) -> Option<&str> {
+        self.ssh_host.as_deref()
+    }
+
+    #[cfg(not(feature = "ssh"))]
+    pub(crate) fn ssh_host(&self) -> Option<&str> {
+        None
+    }
+
+    /// Whether the service goes to another machine, whose paths this one
+    /// can't resolve
+    pub(crate) fn is_remote(&self) -> bool {
+        self.machine.is_some() || self.ssh_host().is_some()
+    }
+
+    /// Where this machine reaches a file of a service managed on another
+    /// machine, through the administrative share of its drive
+    pub(crate) fn local_path(&self, path: &Path) -> PathBuf {
+        let (machine, path) = match (&self.machine, path.to_str()) {
+            (Some(machine), Some(path)) => (machine, path),
+            _ => return path.to_path_buf(),
+        };
+        let mut chars = path.chars();
+        match (chars.next(), chars.next()) {
+            (Some(drive), Some(':')) if drive.is_ascii_alphabetic() => {
+                PathBuf::from(format!("{}\\{}${}", machine, drive, &path[2..]))
+            },
+            _ => PathBuf::from(path),
+        }
+    }
+
+    /// Whether the target of a `deferred_path` service isn't in place yet
+    pub(crate) fn target_pending(&self) -> bool {
+        self.deferred_path && !self.local_path(&self.path).is_file()
+    }
+
+    /// Fails while the target of a `deferred_path` service isn't in place,
+    /// or with `validate_target` while the host can't run it
+    pub(crate) fn check_target(&self) -> crate::Result<()> {
+        if self.target_pending() {
+            return Err(crate::Error::new(crate::ErrorKind::Io,
+                                         "Target executable is not in place yet".to_string())
+                .content(self.path.display().to_string()));
+        }
+        if self.validate_target {
+            crate::validate::validate_target(&self.local_path(&self.path))?;
+        }
+        Ok(())
+    }
+
+    /// `path` as the target sees it within `root_directory`
+    #[cfg(unix)]
+    pub(crate) fn path_in_root(&self, path: &Path) -> PathBuf {
+        match self.root_directory.as_ref().and_then(|root| path.strip_prefix(root).ok()) {
+            Some(relative) => Path::new("/").join(relative),
+            None => path.to_path_buf(),
+        }
+    }
+
+    /// Inverse of `path_in_root`
+    #[cfg(unix)]
+    pub(crate) fn path_from_root(&self, path: &Path) -> PathBuf {
+        match (&self.root_directory, path.strip_prefix("/")) {
+            (Some(root), Ok(relative)) => root.join(relative),
+            _ => path.to_path_buf(),
+        }
+    }
+
+    /// The account, with the password `password_secret` names read from
+    /// the `SecretStore` of the service
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub(crate) fn account_with_password(&self) -> crate::Result<Account> {
+        match (&self.account, &self.password_secret) {
+            (Account::User { name, .. }, Some(key)) => Ok(Account::User {
+                name: name.clone(),
+                password: Some(crate::SecretStore::new(&self.name).get(key)?),
+            }),
+            (account, _) => Ok(account.clone()),
+        }
+    }
+
+    /// Whether the service is on demand and left for its triggers to start
+    #[cfg_attr(not(any(windows, target_os = "linux")), allow(dead_code))]
+    pub(crate) fn is_triggered(&self) -> bool {
+        self.start_type == StartType::OnDemand && !self.triggers.is_empty()
+    }
+
+    /// Whether the target is on a network share, `\\server\share\...`
+    pub(crate) fn is_unc(&self) -> bool {
+        use std::path::{Component, Prefix};
+        match self.path.components().next() {
+            Some(Component::Prefix(prefix)) =>
+                matches!(prefix.kind(), Prefix::UNC(..) | Prefix::VerbatimUNC(..)),
+            _ => false,
+        }
+    }
+
+    /// Settings of `self` that differ in `installed`. Passwords can't be
+    /// read back, so they aren't compared.
+    pub(crate) fn diff(&self, installed: &SombraConfig) -> Vec<Difference> {
+        let without_password = |account: &Account| match account {
+            Account::User { name, .. } => Account::User { name: name.clone(), password: None },
+            account => account.clone(),
+        };
+        let mut differences = vec![];
+        macro_rules! compare {
+            ($($field:ident),*) => {
+                $(if self.$field != installed.$field {
+                    differences.push(Difference {
+                        field: stringify!($field),
+                        desired: format!("{:?}", self.$field),
+                        installed: format!("{:?}", installed.$field),
+                    });
+                })*
+            };
+        }
+        compare!(path, args, display_name, description, start_type);
+        if without_password(&self.account) != without_password(&installed.account) {
+            differences.push(Difference {
+                field: "account",
+                desired: format!("{:?}", self.account),
+                installed: format!("{:?}", installed.account),
+            });
+        }
+        compare!(group, umask, dependencies, triggers, failure_actions, env, working_directory,
+                 stdout_log, stderr_log, log_rotation, event_log, journal, stop_signal,
+                 stop_timeout, kill_process_tree, resource_limits, priority, cpu_affinity,
+                 hardening, capabilities, root_directory, bind_paths, health_check, readiness,
+                 notify, start_timeout, watchdog, restart_policy, metrics_port, restricted_sid,
+                 service_privileges, operators);
+        differences
+    }
+}
+
+/// A setting of the installed service that drifted from the desired one.
+/// The values are shown as in `Debug`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Difference {
+    pub field: &'static str,
+    pub desired: String,
+    pub installed: String,
+}
+
+impl std::fmt::Display for Difference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: desired {}, installed {}", self.field, self.desired, self.installed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::SombraMock;
+    use crate::Sombra;
+
+    #[test]
+    fn diff_reports_drift() {
+        let mut installed = SombraConfig::new("drift", "/opt/drift");
+        installed.account = Account::User {
+            name: "sombra".to_string(),
+            password: Some(SecretString::from("secret")),
+        };
+        SombraMock::from_config(installed.clone()).unwrap().create().unwrap();
+
+        let mut desired = installed.clone();
+        desired.account = Account::User { name: "sombra".to_string(), password: None };
+        assert_eq!(SombraMock::from_config(desired.clone()).unwrap().diff(), Ok(vec![]));
+
+        desired.path = PathBuf::from("/opt/drift2");
+        desired.start_type = StartType::AutoStart;
+        let differences = SombraMock::from_config(desired).unwrap().diff().unwrap();
+        assert_eq!(differences.iter().map(|d| d.to_string()).collect::<Vec<_>>(),
+                   vec!["path: desired \"/opt/drift2\", installed \"/opt/drift\"",
+                        "start_type: desired AutoStart, installed OnDemand"]);
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn unc_targets_detected() {
+        assert!(SombraConfig::new("unc", r"\\server\share\app.exe").is_unc());
+        assert!(SombraConfig::new("unc", r"\\?\UNC\server\share\app.exe").is_unc());
+        assert!(!SombraConfig::new("unc", r"\\?\C:\sombra\app.exe").is_unc());
+        assert!(!SombraConfig::new("unc", r"C:\sombra\app.exe").is_unc());
+    }
+
+    #[test]
+    fn remote_paths_go_through_the_admin_share() {
+        let mut config = SombraConfig::new("remote", r"C:\sombra\app.exe");
+        assert_eq!(config.local_path(&config.path), PathBuf::from(r"C:\sombra\app.exe"));
+        config.machine = Some(r"\\host".to_string());
+        assert_eq!(config.local_path(&config.path), PathBuf::from(r"\\host\C$\sombra\app.exe"));
+        assert_eq!(config.local_path(Path::new(r"\\server\share\app.exe")),
+                   PathBuf::from(r"\\server\share\app.exe"));
+    }
+}
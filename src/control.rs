@@ -0,0 +1,142 @@
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use crate::{Error, ErrorKind};
+
+/// A request of the control protocol. Each request is one line sent over
+/// its own connection; the answer is `OK` or `ERR <message>` followed by
+/// the payload lines, and ends when the listener closes the connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Request {
+    /// PID of the target, nothing while it isn't running
+    Pid,
+    /// Asks the target to reload its configuration
+    Reload,
+    /// Last lines of the output of the target
+    Logs(usize),
+    /// Exit code of the last run of the target, nothing while it runs
+    ExitCode,
+}
+
+impl Request {
+    fn encode(&self) -> String {
+        match self {
+            Request::Pid => "PID".to_string(),
+            Request::Reload => "RELOAD".to_string(),
+            Request::Logs(lines) => format!("LOGS {}", lines),
+            Request::ExitCode => "EXIT".to_string(),
+        }
+    }
+
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut words = line.split_whitespace();
+        let request = match words.next()? {
+            "PID" => Request::Pid,
+            "RELOAD" => Request::Reload,
+            "LOGS" => Request::Logs(words.next()?.parse().ok()?),
+            "EXIT" => Request::ExitCode,
+            _ => return None,
+        };
+        match words.next() {
+            Some(_) => None,
+            None => Some(request),
+        }
+    }
+}
+
+/// Client of the control channel the wrapper of a `SombraWindows` service
+/// listens on, for what the SCM can't tell or do
+pub struct ControlClient {
+    endpoint: PathBuf,
+}
+
+impl ControlClient {
+    pub fn new(name: &str) -> Self {
+        ControlClient::with_endpoint(ControlClient::endpoint(name))
+    }
+
+    /// Endpoint the wrapper listens on for a service, the named pipe
+    /// `\\.\pipe\sombra-<name>`
+    pub fn endpoint(name: &str) -> PathBuf {
+        PathBuf::from(format!(r"\\.\pipe\sombra-{}", name))
+    }
+
+    pub fn with_endpoint<P: Into<PathBuf>>(endpoint: P) -> Self {
+        ControlClient { endpoint: endpoint.into() }
+    }
+
+    pub fn pid(&self) -> crate::Result<Option<u32>> {
+        self.single(Request::Pid)
+    }
+
+    pub fn reload(&self) -> crate::Result<()> {
+        self.request(Request::Reload)?;
+        Ok(())
+    }
+
+    pub fn logs(&self, lines: usize) -> crate::Result<Vec<String>> {
+        self.request(Request::Logs(lines))
+    }
+
+    pub fn exit_code(&self) -> crate::Result<Option<i64>> {
+        self.single(Request::ExitCode)
+    }
+
+    fn single<T: std::str::FromStr>(&self, request: Request) -> crate::Result<Option<T>> {
+        match self.request(request)?.first() {
+            Some(value) => value.parse().map(Some).map_err(|_| {
+                Error::new(ErrorKind::Other, "Invalid control answer".to_string())
+                    .content(value.clone())
+            }),
+            None => Ok(None),
+        }
+    }
+
+    pub fn request(&self, request: Request) -> crate::Result<Vec<String>> {
+        let mut answer = String::new();
+        let mut pipe = std::fs::OpenOptions::new().read(true).write(true)
+            .open(&self.endpoint)?;
+        pipe.write_all(format!("{}\n", request.encode()).as_bytes())?;
+        pipe.read_to_string(&mut answer)?;
+
+        let mut lines = answer.lines();
+        match lines.next() {
+            Some("OK") => Ok(lines.map(|line| line.to_string()).collect()),
+            Some(status) => Err(Error::new(ErrorKind::Other,
+                                           status.trim_start_matches("ERR ").to_string())
+                .content(request.encode())),
+            None => Err(Error::new(ErrorKind::Io, "Empty control answer".to_string())
+                .content(request.encode())),
+        }
+    }
+}
+
+/// The answer of a listener to a request line, from the payload lines or
+/// the error message of `handler`
+pub(crate) fn answer<F>(line: &str, handler: F) -> String
+    where F: Fn(Request) -> Result<Vec<String>, String> {
+    match Request::parse(line) {
+        Some(request) => match handler(request) {
+            Ok(payload) => std::iter::once("OK".to_string())
+                .chain(payload)
+                .map(|line| format!("{}\n", line))
+                .collect(),
+            Err(message) => format!("ERR {}\n", message),
+        },
+        None => format!("ERR Unknown request {}\n", line),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_requests() {
+        for request in &[Request::Pid, Request::Reload, Request::Logs(20), Request::ExitCode] {
+            assert_eq!(Request::parse(&request.encode()), Some(*request));
+        }
+        assert_eq!(Request::parse("LOGS"), None);
+        assert_eq!(Request::parse("PID 1"), None);
+        assert_eq!(Request::parse("KILL"), None);
+    }
+}
@@ -0,0 +1,153 @@
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+/// Body of a program run as a service, given the stop requests
+pub type ServiceMain = fn(Shutdown) -> std::result::Result<(), Box<dyn std::error::Error>>;
+
+/// Tells a program running as a service that it was asked to stop: by the
+/// SCM on Windows, by SIGTERM or SIGINT elsewhere
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    requested: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl Shutdown {
+    pub fn is_requested(&self) -> bool {
+        *self.requested.0.lock().unwrap()
+    }
+
+    /// Blocks until a stop is requested
+    pub fn wait(&self) {
+        let (requested, changed) = &*self.requested;
+        let _requested = changed.wait_while(requested.lock().unwrap(), |r| !*r).unwrap();
+    }
+
+    /// Blocks until a stop is requested or `timeout` expires, and returns
+    /// whether it was requested
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let (requested, changed) = &*self.requested;
+        let (requested, _) = changed.wait_timeout_while(requested.lock().unwrap(), timeout,
+                                                        |r| !*r).unwrap();
+        *requested
+    }
+
+    pub(crate) fn request(&self) {
+        let (requested, changed) = &*self.requested;
+        *requested.lock().unwrap() = true;
+        changed.notify_all();
+    }
+}
+
+/// Exit code of the program once `main` returned, printing its error
+pub(crate) fn exit_code(result: std::result::Result<(), Box<dyn std::error::Error>>) -> i32 {
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        },
+    }
+}
+
+/// Runs `main` as the service `name` and returns the exit code of the
+/// program: under the service dispatcher on Windows, falling back to a
+/// plain run when started from a console; with the stop signals handled
+/// and readiness reported to systemd elsewhere. See `sombra_main!`.
+pub fn run_service(name: &str, main: ServiceMain) -> i32 {
+    #[cfg(windows)]
+    {
+        crate::windows::dispatcher::run(name, main)
+    }
+    #[cfg(unix)]
+    {
+        // The unit names the service
+        let _ = name;
+        let shutdown = Shutdown::default();
+        if let Err(e) = signals::forward(shutdown.clone()) {
+            eprintln!("{}", e);
+            return 1;
+        }
+        let _ = crate::notify::notify("READY=1");
+        let code = exit_code(main(shutdown));
+        let _ = crate::notify::notify("STOPPING=1");
+        code
+    }
+}
+
+/// Makes `main` of a function taking the `Shutdown` of the service, so the
+/// program itself runs as a service without the Windows wrapper
+///
+/// ```no_run
+/// fn serve(shutdown: sombra::Shutdown) -> Result<(), Box<dyn std::error::Error>> {
+///     while !shutdown.wait_timeout(std::time::Duration::from_secs(1)) {
+///         // ...
+///     }
+///     Ok(())
+/// }
+///
+/// sombra::sombra_main!("tcp_echo", serve);
+/// ```
+#[macro_export]
+macro_rules! sombra_main {
+    ($name:expr, $main:path) => {
+        fn main() {
+            std::process::exit($crate::run_service($name, $main));
+        }
+    };
+}
+
+#[cfg(unix)]
+mod signals {
+    use std::io::Read;
+    use std::os::unix::io::IntoRawFd;
+    use std::sync::atomic::{AtomicI32, Ordering};
+    use super::Shutdown;
+
+    /// Write end of the pipe from the signal handler to the thread
+    /// requesting the shutdown
+    static PIPE: AtomicI32 = AtomicI32::new(-1);
+
+    extern "C" fn handle(_signal: libc::c_int) {
+        // Only async-signal-safe calls here
+        let byte = 1u8;
+        unsafe {
+            libc::write(PIPE.load(Ordering::SeqCst), &byte as *const u8 as *const libc::c_void,
+                        1);
+        }
+    }
+
+    /// Requests `shutdown` on SIGTERM or SIGINT
+    pub fn forward(shutdown: Shutdown) -> crate::Result<()> {
+        let (mut reader, writer) = std::os::unix::net::UnixStream::pair()?;
+        PIPE.store(writer.into_raw_fd(), Ordering::SeqCst);
+        std::thread::spawn(move || {
+            let mut byte = [0u8; 1];
+            if reader.read_exact(&mut byte).is_ok() {
+                shutdown.request();
+            }
+        });
+        for signal in &[libc::SIGTERM, libc::SIGINT] {
+            let handler = handle as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            if unsafe { libc::signal(*signal, handler) } == libc::SIG_ERR {
+                return Err(std::io::Error::last_os_error().into());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shutdown_wakes_waiters() {
+        let shutdown = Shutdown::default();
+        assert!(!shutdown.wait_timeout(Duration::from_millis(10)));
+        let waiter = shutdown.clone();
+        let thread = std::thread::spawn(move || waiter.wait());
+        shutdown.request();
+        thread.join().unwrap();
+        assert!(shutdown.is_requested());
+    }
+}
@@ -1,8 +1,12 @@
-#[derive(Debug, PartialEq)]
+type Source = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+#[derive(Debug)]
 pub struct Error {
     kind: ErrorKind,
     desc: String,
     content: Option<String>,
+    source: Option<Source>,
+    os_code: Option<i32>,
 }
 
 impl Error {
@@ -11,48 +15,140 @@ impl Error {
             kind,
             desc,
             content: None,
+            source: None,
+            os_code: None,
         }
     }
 
+    /// Keeps the error this one was made from, returned by `source()`
+    pub(crate) fn with_source<E: Into<Source>>(mut self, source: E) -> Self {
+        self.source = Some(source.into());
+        self
+    }
+
     pub fn content(mut self, content: String) -> Self {
         self.content = Some(content);
         self
     }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// Code of the OS error this one comes from: the Win32 error code on
+    /// Windows, e.g. 1060 for ERROR_SERVICE_DOES_NOT_EXIST, errno elsewhere
+    pub fn os_code(&self) -> Option<i32> {
+        self.os_code
+    }
+
+    /// Keeps the OS error code, and on Windows translates the codes of the
+    /// common SCM failures to their kind
+    pub(crate) fn with_os_code(mut self, code: i32) -> Self {
+        self.os_code = Some(code);
+        #[cfg(windows)]
+        {
+            if let Some(kind) = ErrorKind::from_win32(code) {
+                self.kind = kind;
+            }
+        }
+        self
+    }
+
+    /// Prefixes the content with what was being done, e.g. a step of a
+    /// larger operation
+    pub(crate) fn context(mut self, context: &str) -> Self {
+        self.content = Some(match self.content {
+            Some(content) => format!("{}: {}", context, content),
+            None => context.to_string(),
+        });
+        self
+    }
 }
 
-#[derive(Debug, PartialEq)]
+/// Cause of an error, for callers to tell the failures apart
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ErrorKind {
     Other,
     Io,
     Utf8,
     WindowsService,
+    Timeout,
+    /// Creating a service whose name is taken
+    ServiceAlreadyExists,
+    /// Managing a service that isn't installed
+    ServiceNotFound,
+    /// Missing root or administrator privileges
+    AccessDenied,
+    /// The service is deleted once its last handle closes, and can't be
+    /// created again until then
+    MarkedForDeletion,
+    /// The target differs from the one the service was installed with
+    ChecksumMismatch,
 }
 
-impl ToString for Error {
-    fn to_string(&self) -> String {
+#[cfg(windows)]
+impl ErrorKind {
+    fn from_win32(code: i32) -> Option<Self> {
+        match code {
+            5 => Some(ErrorKind::AccessDenied),            // ERROR_ACCESS_DENIED
+            1053 => Some(ErrorKind::Timeout),              // ERROR_SERVICE_REQUEST_TIMEOUT
+            1060 => Some(ErrorKind::ServiceNotFound),      // ERROR_SERVICE_DOES_NOT_EXIST
+            1072 => Some(ErrorKind::MarkedForDeletion),    // ERROR_SERVICE_MARKED_FOR_DELETE
+            1073 => Some(ErrorKind::ServiceAlreadyExists), // ERROR_SERVICE_EXISTS
+            _ => None,
+        }
+    }
+}
+
+/// Errors are equal when their kind, description and content are, the
+/// sources aside
+impl PartialEq for Error {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind && self.desc == other.desc && self.content == other.content
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source.as_ref().map(|source| source.as_ref() as &(dyn std::error::Error + 'static))
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         if let Some(content) = &self.content {
-            format!("<{:?}> {}: {}", self.kind, content, self.desc)
+            write!(f, "<{:?}> {}: {}", self.kind, content, self.desc)
         } else {
-            format!("<{:?}> {}", self.kind, self.desc)
+            write!(f, "<{:?}> {}", self.kind, self.desc)
         }
     }
 }
 
 impl From<std::env::VarError> for Error {
     fn from(e: std::env::VarError) -> Self {
-        Error::new(ErrorKind::Io, e.to_string())
+        Error::new(ErrorKind::Io, e.to_string()).with_source(e)
     }
 }
 
 impl From<std::io::Error> for Error {
     fn from(e: std::io::Error) -> Self {
-        Error::new(ErrorKind::Io, e.to_string())
+        let kind = match e.kind() {
+            std::io::ErrorKind::PermissionDenied => ErrorKind::AccessDenied,
+            std::io::ErrorKind::TimedOut => ErrorKind::Timeout,
+            _ => ErrorKind::Io,
+        };
+        let error = Error::new(kind, e.to_string());
+        let error = match e.raw_os_error() {
+            Some(code) => error.with_os_code(code),
+            None => error,
+        };
+        error.with_source(e)
     }
 }
 
 impl From<std::str::Utf8Error> for Error {
     fn from(e: std::str::Utf8Error) -> Self {
-        Error::new(ErrorKind::Utf8, e.to_string())
+        Error::new(ErrorKind::Utf8, e.to_string()).with_source(e)
     }
 }
 
@@ -60,9 +156,44 @@ impl From<std::str::Utf8Error> for Error {
 impl std::convert::From<windows_service::Error> for Error {
     fn from(e: windows_service::Error) -> Self {
         match e {
-            windows_service::Error::Winapi(err) => Error::new(ErrorKind::WindowsService,
-                                                              err.to_string()),
-            _ => Error::new(ErrorKind::WindowsService, e.to_string())
+            // The SCM failures carry their Win32 error code
+            windows_service::Error::Winapi(err) => {
+                let error = Error::new(ErrorKind::WindowsService, err.to_string());
+                let error = match err.raw_os_error() {
+                    Some(code) => error.with_os_code(code),
+                    None => error,
+                };
+                error.with_source(err)
+            },
+            _ => Error::new(ErrorKind::WindowsService, e.to_string()).with_source(e)
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error as _;
+
+    #[test]
+    fn io_errors_keep_source() {
+        let io = std::io::Error::new(std::io::ErrorKind::PermissionDenied, "unit file");
+        let error = Error::from(io).context("create");
+        assert_eq!(error.kind(), ErrorKind::AccessDenied);
+        assert_eq!(error.to_string(), "<AccessDenied> create: unit file");
+        assert_eq!(error.source().map(|source| source.to_string()),
+                   Some("unit file".to_string()));
+        assert_eq!(error.os_code(), None);
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn win32_codes_translate() {
+        let error = Error::from(std::io::Error::from_raw_os_error(1072));
+        assert_eq!(error.kind(), ErrorKind::MarkedForDeletion);
+        assert_eq!(error.os_code(), Some(1072));
+        let error = Error::new(ErrorKind::WindowsService, "Failed".to_string())
+            .with_os_code(1060);
+        assert_eq!(error.kind(), ErrorKind::ServiceNotFound);
+    }
+}
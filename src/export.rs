@@ -0,0 +1,194 @@
+use crate::Artifact;
+
+/// Scripts installing and uninstalling a service the way `create()` and
+/// `delete()` do, for machines the crate can't run on, from
+/// `Sombra::export`: shell scripts on Unix, PowerShell on Windows
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scripts {
+    pub install: String,
+    pub uninstall: String,
+}
+
+/// Lines of the install and uninstall shell scripts. The install stops at
+/// the first failure, the uninstall goes on past them, as parts of the
+/// service may already be gone.
+#[cfg(unix)]
+pub(crate) struct Shell {
+    name: String,
+    install: Vec<String>,
+    uninstall: Vec<String>,
+}
+
+#[cfg(unix)]
+impl Shell {
+    pub(crate) fn new(name: &str) -> Self {
+        Shell { name: name.to_string(), install: vec![], uninstall: vec![] }
+    }
+
+    /// A command line, its words quoted when they need it
+    pub(crate) fn line(words: &[&dyn AsRef<std::ffi::OsStr>]) -> String {
+        let words: Vec<String> = words.iter()
+            .map(|word| crate::unix::shell::word(word.as_ref()))
+            .collect();
+        words.join(" ")
+    }
+
+    /// Writes the files among `artifacts`, leaving out the registrations
+    pub(crate) fn write(&mut self, artifacts: &[Artifact]) {
+        use crate::unix::shell::{quote, word};
+        for artifact in artifacts {
+            let path = match &artifact.path {
+                Some(path) => path,
+                None => continue,
+            };
+            if let Some(dir) = path.parent() {
+                self.install.push(Shell::line(&[&"mkdir", &"-p", &dir]));
+            }
+            // A here-document always ends with a newline
+            let content = &artifact.content;
+            if content.ends_with('\n') {
+                let mut delimiter = "SOMBRA_EOF".to_string();
+                while content.lines().any(|line| line == delimiter) {
+                    delimiter.push('_');
+                }
+                self.install.push(format!("cat > {} <<'{}'\n{}{}", word(path.as_os_str()),
+                                          delimiter, content, delimiter));
+            } else {
+                self.install.push(format!("printf '%s' {} > {}", quote(content.as_ref()),
+                                          word(path.as_os_str())));
+            }
+            let mode = if artifact.executable { "755" } else { "644" };
+            self.install.push(Shell::line(&[&"chmod", &mode, path]));
+        }
+    }
+
+    /// Gives the state directory, and the log files, to the account the
+    /// target runs as. The system account is root wherever the script
+    /// runs.
+    #[cfg(not(target_os = "macos"))]
+    pub(crate) fn chown(&mut self, config: &crate::SombraConfig, dir: &std::path::Path)
+                        -> crate::Result<()> {
+        let user = match config.account {
+            crate::Account::LocalSystem => return Ok(()),
+            ref account => account.unix_name(&config.name)?,
+        };
+        let logs = config.log_files();
+        for log in &logs {
+            self.install(Shell::line(&[&"touch", log]));
+        }
+        let mut words: Vec<&dyn AsRef<std::ffi::OsStr>> = vec![&"chown", &"-R", &user, &dir];
+        words.extend(logs.iter().map(|log| log as &dyn AsRef<std::ffi::OsStr>));
+        self.install(Shell::line(&words));
+        Ok(())
+    }
+
+    pub(crate) fn install(&mut self, line: String) {
+        self.install.push(line);
+    }
+
+    pub(crate) fn uninstall(&mut self, line: String) {
+        self.uninstall.push(line);
+    }
+
+    pub(crate) fn remove<P: AsRef<std::path::Path>>(&mut self, path: P) {
+        self.uninstall(Shell::line(&[&"rm", &"-f", &path.as_ref()]));
+    }
+
+    pub(crate) fn remove_dir<P: AsRef<std::path::Path>>(&mut self, path: P) {
+        self.uninstall(Shell::line(&[&"rm", &"-rf", &path.as_ref()]));
+    }
+
+    pub(crate) fn finish(self) -> Scripts {
+        let script = |action: &str, options: &str, lines: &[String]| {
+            format!("#!/bin/sh\n# {} the {} service\n{}{}\n", action, self.name, options,
+                    lines.join("\n"))
+        };
+        Scripts {
+            install: script("Installs", "set -e\n", &self.install),
+            uninstall: script("Uninstalls", "", &self.uninstall),
+        }
+    }
+}
+
+/// Lines of the install and uninstall PowerShell scripts, stopping at the
+/// first failure of the install and going on past those of the uninstall
+#[cfg(windows)]
+pub(crate) struct PowerShell {
+    name: String,
+    install: Vec<String>,
+    uninstall: Vec<String>,
+}
+
+#[cfg(windows)]
+impl PowerShell {
+    pub(crate) fn new(name: &str) -> Self {
+        PowerShell { name: name.to_string(), install: vec![], uninstall: vec![] }
+    }
+
+    /// Runs a program with the arguments quoted the way CommandLineToArgvW
+    /// splits them, which PowerShell doesn't do for native commands, into
+    /// `$p`
+    pub(crate) fn native(program: &str, args: &[String], checked: bool) -> String {
+        use crate::windows::powershell::quote;
+        use crate::windows::sombra_imp::SombraWindows;
+        let args: Vec<std::ffi::OsString> = args.iter().map(std::ffi::OsString::from).collect();
+        let mut line = format!("$p = Start-Process -FilePath {} -ArgumentList {} -Wait \
+                                -NoNewWindow -PassThru",
+                               quote(program), quote(&SombraWindows::command_line(&args)));
+        if checked {
+            line.push_str("; if ($p.ExitCode -ne 0) { exit $p.ExitCode }");
+        }
+        line
+    }
+
+    /// Writes the files among `artifacts`, leaving out the registrations,
+    /// without the byte order mark Windows PowerShell adds
+    pub(crate) fn write(&mut self, artifacts: &[Artifact]) {
+        use crate::windows::powershell::quote;
+        for artifact in artifacts {
+            let path = match &artifact.path {
+                Some(path) => path.to_string_lossy(),
+                None => continue,
+            };
+            if let Some(dir) = artifact.path.as_ref().and_then(|path| path.parent()) {
+                self.install.push(format!("New-Item -ItemType Directory -Force -Path {} \
+                                           | Out-Null", quote(&dir.to_string_lossy())));
+            }
+            self.install.push(format!("[IO.File]::WriteAllText({}, {})",
+                                      quote(&path), quote(&artifact.content)));
+        }
+    }
+
+    pub(crate) fn install(&mut self, line: String) {
+        self.install.push(line);
+    }
+
+    pub(crate) fn install_native(&mut self, program: &str, args: &[String]) {
+        self.install.push(PowerShell::native(program, args, true));
+    }
+
+    pub(crate) fn uninstall(&mut self, line: String) {
+        self.uninstall.push(line);
+    }
+
+    pub(crate) fn uninstall_native(&mut self, program: &str, args: &[String]) {
+        self.uninstall.push(PowerShell::native(program, args, false));
+    }
+
+    pub(crate) fn remove_dir(&mut self, path: &std::path::Path) {
+        use crate::windows::powershell::quote;
+        self.uninstall(format!("Remove-Item -LiteralPath {} -Recurse -Force",
+                               quote(&path.to_string_lossy())));
+    }
+
+    pub(crate) fn finish(self) -> Scripts {
+        let script = |action: &str, preference: &str, lines: &[String]| {
+            format!("# {} the {} service\n$ErrorActionPreference = '{}'\n{}\n", action,
+                    self.name, preference, lines.join("\n"))
+        };
+        Scripts {
+            install: script("Installs", "Stop", &self.install),
+            uninstall: script("Uninstalls", "Continue", &self.uninstall),
+        }
+    }
+}
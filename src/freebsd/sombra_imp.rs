@@ -0,0 +1,425 @@
+use crate::{Artifact, Difference, LogReader, ManagedService, Scripts, Sombra, ServiceStats,
+            ServiceStatus, SombraConfig, StartType, Usage};
+use crate::export::Shell;
+use crate::support::{self, Feature};
+use crate::unix::shell::quote;
+use crate::unix::supervisor::Supervisor;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Scripts of the services that don't come with the base system
+const RC_DIR: &str = "/usr/local/etc/rc.d";
+/// Read by `load_rc_config`, one file per service
+const RC_CONF_DIR: &str = "/etc/rc.conf.d";
+/// State directories of the launchers started by the scripts
+const STATE_DIR: &str = "/var/db/sombra/rc.d";
+
+/// Runs the target from an rc.d script in /usr/local/etc/rc.d, driven with
+/// service(8). The script starts the launcher of `SombraCron` detached by
+/// daemon(8), as the account of the service, which restarts the target as
+/// the restart policy asks. Services started at boot have their `_enable`
+/// knob set in /etc/rc.conf.d.
+pub struct SombraFreebsd {
+    config: SombraConfig,
+    supervisor: Supervisor,
+}
+
+impl SombraFreebsd {
+    fn script_path(name: &str) -> PathBuf {
+        Path::new(RC_DIR).join(name)
+    }
+
+    fn rc_conf_path(name: &str) -> PathBuf {
+        Path::new(RC_CONF_DIR).join(name)
+    }
+
+    fn check(config: &SombraConfig) -> crate::Result<()> {
+        let unsupported = if !config.name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            // rc.subr names the variables of the service after it
+            Some("rc.d service names can only have letters, digits and underscores")
+        } else {
+            support::unsupported(config, &[Feature::RootDirectory])
+        };
+        match unsupported {
+            Some(description) => Err(crate::Error::new(crate::ErrorKind::Other,
+                                                       description.to_string())
+                .content(config.name.clone())),
+            None => Ok(()),
+        }
+    }
+
+    fn is_root() -> crate::Result<()> {
+        if unsafe { libc::geteuid() } != 0 {
+            return Err(crate::Error::new(crate::ErrorKind::AccessDenied,
+                                         "Without root privileges.".to_string()));
+        }
+        Ok(())
+    }
+
+    fn script_content(config: &SombraConfig, supervisor: &Supervisor)
+        -> crate::Result<String> {
+        let user = config.account.unix_name(&config.name)?;
+        // daemon(8) makes the launcher lead a session of its own, for stop
+        // to signal
+        let account = if user == "root" {
+            String::new()
+        } else {
+            format!(" -u {}", quote(user.as_ref()))
+        };
+        let signal = config.stop_signal.unix_name().trim_start_matches("SIG");
+        let target = if config.kill_process_tree {
+            format!("kill -s {} -- -\"$supervisor\"", signal)
+        } else {
+            // Between restarts, the launcher ends once the delay is over
+            format!("if [ -r \"$state/target.pid\" ]; then \
+                     kill -s {} \"$(cat \"$state/target.pid\")\"; \
+                     else kill -s TERM \"$supervisor\"; fi", signal)
+        };
+        let timeout = config.stop_timeout.map(|timeout| timeout.as_secs()).unwrap_or(10);
+        let mut required = vec!["LOGIN".to_string()];
+        required.extend(config.dependencies.iter().cloned());
+        let description = config.description.as_deref()
+            .or(config.display_name.as_deref())
+            .unwrap_or(&config.name);
+
+        Ok(format!("#!/bin/sh\n\
+                    #\n\
+                    # PROVIDE: {name}\n\
+                    # REQUIRE: {required}\n\
+                    # KEYWORD: shutdown\n\
+                    #\n\
+                    {header}\n\
+                    \n\
+                    . /etc/rc.subr\n\
+                    \n\
+                    name={name}\n\
+                    rcvar={name}_enable\n\
+                    desc={description}\n\
+                    start_cmd=sombra_start\n\
+                    stop_cmd=sombra_stop\n\
+                    status_cmd=sombra_status\n\
+                    extra_commands=status\n\
+                    \n\
+                    state={state}\n\
+                    \n\
+                    running() {{\n\
+                    \t[ -r \"$state/supervisor.pid\" ] && \
+                    kill -0 \"$(cat \"$state/supervisor.pid\")\" 2>/dev/null\n\
+                    }}\n\
+                    \n\
+                    sombra_start() {{\n\
+                    \trunning && return 0\n\
+                    \techo \"Starting ${{name}}.\"\n\
+                    \trm -f \"$state/stopping\"\n\
+                    \t/usr/sbin/daemon -f{account} /bin/sh {launcher}\n\
+                    }}\n\
+                    \n\
+                    sombra_stop() {{\n\
+                    \trunning || return 0\n\
+                    \techo \"Stopping ${{name}}.\"\n\
+                    \ttouch \"$state/stopping\"\n\
+                    \tsupervisor=$(cat \"$state/supervisor.pid\")\n\
+                    \t{target}\n\
+                    \twaited=0\n\
+                    \twhile running; do\n\
+                    \t\tif [ \"$waited\" -ge {timeout} ]; then\n\
+                    \t\t\tkill -s KILL -- -\"$supervisor\"\n\
+                    \t\t\trm -f \"$state/supervisor.pid\" \"$state/target.pid\"\n\
+                    \t\t\tbreak\n\
+                    \t\tfi\n\
+                    \t\tsleep 1\n\
+                    \t\twaited=$((waited + 1))\n\
+                    \tdone\n\
+                    }}\n\
+                    \n\
+                    sombra_status() {{\n\
+                    \tif running; then\n\
+                    \t\techo \"${{name}} is running as pid $(cat \"$state/supervisor.pid\").\"\n\
+                    \telse\n\
+                    \t\techo \"${{name}} is not running.\"\n\
+                    \t\treturn 1\n\
+                    \tfi\n\
+                    }}\n\
+                    \n\
+                    load_rc_config $name\n\
+                    : ${{{name}_enable:=NO}}\n\
+                    \n\
+                    run_rc_command \"$1\"\n",
+                   name = config.name,
+                   required = required.join(" "),
+                   header = crate::settings::HEADER,
+                   description = quote(description.replace('\n', " ").as_ref()),
+                   state = quote(supervisor.dir().as_os_str()),
+                   account = account,
+                   launcher = quote(supervisor.launcher().as_os_str()),
+                   target = target,
+                   timeout = timeout))
+    }
+
+    fn run(command: &mut Command) -> crate::Result<()> {
+        let output = command.output()?;
+        if !output.status.success() {
+            return Err(crate::Error::new(crate::ErrorKind::Other,
+                                         String::from_utf8_lossy(&output.stderr).trim()
+                                             .to_string())
+                .content(format!("{:?}", command)));
+        }
+        Ok(())
+    }
+
+    /// Sets the `_enable` knob of the service with sysrc(8), keeping the
+    /// other variables an administrator put in its file
+    fn register(&self) -> crate::Result<()> {
+        let enable = matches!(self.config.start_type,
+                              StartType::AutoStart | StartType::DelayedAutoStart);
+        std::fs::create_dir_all(RC_CONF_DIR)?;
+        SombraFreebsd::run(Command::new("sysrc")
+            .arg("-f")
+            .arg(SombraFreebsd::rc_conf_path(&self.config.name))
+            .arg(format!("{}_enable={}", self.config.name, if enable { "YES" } else { "NO" })))
+    }
+
+    /// Takes the knob out, and the file with it once nothing else is left
+    fn unregister(&self) -> crate::Result<()> {
+        let path = SombraFreebsd::rc_conf_path(&self.config.name);
+        if !path.exists() {
+            return Ok(());
+        }
+        SombraFreebsd::run(Command::new("sysrc")
+            .arg("-f")
+            .arg(&path)
+            .arg("-x")
+            .arg(format!("{}_enable", self.config.name)))?;
+        if std::fs::read_to_string(&path)?.trim().is_empty() {
+            std::fs::remove_file(&path)?;
+        }
+        Ok(())
+    }
+
+    /// Installs the launcher and the script. The state directory belongs
+    /// to the account of the service, which the launcher runs as.
+    fn install(&self) -> crate::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        self.supervisor.install(&self.config)?;
+        let user = self.config.account.unix_name(&self.config.name)?;
+        if user != "root" {
+            let mut chown = Command::new("chown");
+            chown.arg("-R").arg(&user).arg(self.supervisor.dir());
+            for log in self.config.log_files() {
+                if !log.exists() {
+                    std::fs::write(&log, "")?;
+                }
+                chown.arg(log);
+            }
+            SombraFreebsd::run(&mut chown)?;
+        }
+        std::fs::create_dir_all(RC_DIR)?;
+        let path = SombraFreebsd::script_path(&self.config.name);
+        std::fs::write(&path, SombraFreebsd::script_content(&self.config, &self.supervisor)?)?;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))?;
+        Ok(())
+    }
+
+    /// The one- commands ignore the `_enable` knob, which only decides
+    /// what starts at boot
+    fn service(&self, action: &str) -> crate::Result<()> {
+        SombraFreebsd::run(Command::new("service").arg(&self.config.name)
+            .arg(format!("one{}", action)))
+    }
+
+    fn is_installed(&self) -> crate::Result<()> {
+        if self.supervisor.is_installed()
+            && SombraFreebsd::script_path(&self.config.name).exists() {
+            Ok(())
+        } else {
+            Err(crate::Error::new(crate::ErrorKind::ServiceNotFound,
+                                  format!("Service {} does not exist", self.config.name)))
+        }
+    }
+}
+
+impl Sombra for SombraFreebsd {
+    fn from_config(config: SombraConfig) -> crate::Result<Self> {
+        let supervisor = Supervisor::in_dir(Path::new(STATE_DIR).join(&config.name));
+        Ok(SombraFreebsd { config, supervisor })
+    }
+
+    fn create(&self) -> crate::Result<()> {
+        SombraFreebsd::check(&self.config)?;
+        SombraFreebsd::is_root()?;
+        if SombraFreebsd::script_path(&self.config.name).exists() {
+            return Err(crate::Error::new(crate::ErrorKind::ServiceAlreadyExists,
+                                         format!("Service {} already exist",
+                                                 self.config.name)));
+        }
+        self.install()?;
+        self.register()?;
+
+        if self.config.start_type == StartType::Disabled || self.config.target_pending() {
+            return Ok(());
+        }
+        self.start()?;
+        match &self.config.readiness {
+            Some(readiness) => readiness.wait(),
+            None => Ok(()),
+        }
+    }
+
+    fn update(&self) -> crate::Result<()> {
+        SombraFreebsd::check(&self.config)?;
+        SombraFreebsd::is_root()?;
+        self.is_installed()?;
+        // The launcher restarts the target with the new settings
+        self.install()?;
+        self.register()
+    }
+
+    fn delete(&self) -> crate::Result<()> {
+        self.is_installed()?;
+        self.service("stop")?;
+        self.unregister()?;
+        std::fs::remove_file(SombraFreebsd::script_path(&self.config.name))?;
+        self.supervisor.remove()
+    }
+
+    fn start(&self) -> crate::Result<()> {
+        self.config.check_target()?;
+        self.is_installed()?;
+        self.service("start")?;
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        Ok(())
+    }
+
+    fn stop(&self) -> crate::Result<()> {
+        self.is_installed()?;
+        self.service("stop")
+    }
+
+    fn status(&self) -> crate::Result<ServiceStatus> {
+        if self.is_installed().is_err() {
+            return Ok(ServiceStatus::NotInstalled);
+        }
+        Ok(self.supervisor.status())
+    }
+
+    fn pid(&self) -> crate::Result<Option<u32>> {
+        self.is_installed()?;
+        Ok(self.supervisor.pid())
+    }
+
+    fn usage(&self) -> crate::Result<Option<Usage>> {
+        self.is_installed()?;
+        self.supervisor.usage()
+    }
+
+    fn stats(&self) -> crate::Result<ServiceStats> {
+        self.is_installed()?;
+        Ok(self.supervisor.stats())
+    }
+
+    fn config(&self) -> crate::Result<SombraConfig> {
+        self.is_installed()?;
+        Ok(self.supervisor.config(&self.config.name)?.0)
+    }
+
+    fn diff(&self) -> crate::Result<Vec<Difference>> {
+        Ok(Supervisor::comparable(&self.config)?.diff(&self.config()?))
+    }
+
+    fn checksum(&self) -> crate::Result<Option<String>> {
+        self.is_installed()?;
+        Ok(self.supervisor.config(&self.config.name)?.1)
+    }
+
+    fn list_managed() -> crate::Result<Vec<ManagedService>> {
+        let mut services = vec![];
+        if !Path::new(RC_DIR).exists() {
+            return Ok(services);
+        }
+        for entry in std::fs::read_dir(RC_DIR)? {
+            let path = entry?.path();
+            let name = match path.file_name().and_then(|n| n.to_str()) {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            let script = std::fs::read_to_string(&path).unwrap_or_default();
+            let supervisor = Supervisor::in_dir(Path::new(STATE_DIR).join(&name));
+            if !script.lines().any(|line| line == crate::settings::HEADER)
+                || !supervisor.is_installed() {
+                continue;
+            }
+            let (config, _) = supervisor.config(&name)?;
+            let path = config.path.clone();
+            let status = SombraFreebsd::from_config(config)?.status()?;
+            services.push(ManagedService { name, status, path });
+        }
+        services.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(services)
+    }
+
+    fn logs(&self, follow: bool) -> crate::Result<LogReader> {
+        LogReader::files(self.config.log_files(), follow)
+    }
+
+    fn render(&self) -> crate::Result<Vec<Artifact>> {
+        SombraFreebsd::check(&self.config)?;
+        let mut artifacts = self.supervisor.render(&self.config)?;
+        let script = SombraFreebsd::script_content(&self.config, &self.supervisor)?;
+        artifacts.push(Artifact::script(SombraFreebsd::script_path(&self.config.name), script));
+        let enable = matches!(self.config.start_type,
+                              StartType::AutoStart | StartType::DelayedAutoStart);
+        artifacts.push(Artifact::file(SombraFreebsd::rc_conf_path(&self.config.name),
+                                      format!("{}_enable=\"{}\"\n", self.config.name,
+                                              if enable { "YES" } else { "NO" })));
+        Ok(artifacts)
+    }
+
+    fn export(&self) -> crate::Result<Scripts> {
+        let name = &self.config.name;
+        let mut shell = Shell::new(name);
+        shell.write(&self.render()?);
+        for line in Supervisor::provision_lines(&self.config) {
+            shell.install(line);
+        }
+        shell.chown(&self.config, self.supervisor.dir())?;
+        if self.config.start_type != StartType::Disabled {
+            shell.install(Shell::line(&[&"service", name, &"onestart"]));
+        }
+        shell.uninstall(Shell::line(&[&"service", name, &"onestop"]));
+        shell.remove(SombraFreebsd::rc_conf_path(name));
+        shell.remove(SombraFreebsd::script_path(name));
+        shell.remove_dir(self.supervisor.dir());
+        Ok(shell.finish())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Account;
+
+    #[test]
+    fn script_uses_rc_subr() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp_echo");
+        config.dependencies = vec!["NETWORKING".to_string()];
+        config.description = Some("Echo server".to_string());
+        config.account = Account::User { name: "sombra".to_string(), password: None };
+        let service = SombraFreebsd::from_config(config).unwrap();
+        let content = SombraFreebsd::script_content(&service.config, &service.supervisor)
+            .unwrap();
+        assert!(content.starts_with("#!/bin/sh\n#\n# PROVIDE: tcp_echo\n\
+                                     # REQUIRE: LOGIN NETWORKING\n"));
+        assert!(content.contains("\nname=tcp_echo\nrcvar=tcp_echo_enable\n\
+                                  desc='Echo server'\n"));
+        assert!(content.contains("\n\t/usr/sbin/daemon -f -u 'sombra' /bin/sh \
+                                  '/var/db/sombra/rc.d/tcp_echo/launcher.sh'\n"));
+        assert!(content.ends_with("\nload_rc_config $name\n: ${tcp_echo_enable:=NO}\n\n\
+                                   run_rc_command \"$1\"\n"));
+    }
+
+    #[test]
+    fn names_are_shell_variables() {
+        let config = SombraConfig::new("tcp-echo", "/opt/tcp_echo");
+        assert!(SombraFreebsd::check(&config).is_err());
+    }
+}
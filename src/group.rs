@@ -0,0 +1,214 @@
+use crate::{Readiness, ServiceStatus, Sombra};
+
+struct Member {
+    name: String,
+    service: Box<dyn Sombra>,
+    readiness: Option<Readiness>,
+}
+
+/// Services managed together in a declared order, e.g. a database before
+/// the API using it before the workers. Services start in order, each one
+/// after the readiness of the previous ones, and stop in reverse order.
+///
+/// ```no_run
+/// use sombra::{Probe, Readiness, SombraBuilder, SombraGroup};
+/// use std::time::Duration;
+///
+/// let db_ready = Readiness {
+///     probe: Probe::Tcp("127.0.0.1:5432".to_string()),
+///     timeout: Duration::from_secs(30),
+/// };
+/// let group = SombraGroup::new()
+///     .service("db", SombraBuilder::new("db", "/opt/db/db").build()?, Some(db_ready))
+///     .service("api", SombraBuilder::new("api", "/opt/api/api").build()?, None);
+/// group.start()?;
+/// # Ok::<(), sombra::Error>(())
+/// ```
+#[derive(Default)]
+pub struct SombraGroup {
+    members: Vec<Member>,
+}
+
+impl SombraGroup {
+    pub fn new() -> Self {
+        SombraGroup::default()
+    }
+
+    /// Appends a service, started after the ones added before it. The next
+    /// services wait for `readiness` once it is started.
+    pub fn service<S: Sombra + 'static>(mut self, name: &str, service: S,
+                                        readiness: Option<Readiness>) -> Self {
+        self.members.push(Member {
+            name: name.to_string(),
+            service: Box::new(service),
+            readiness,
+        });
+        self
+    }
+
+    /// Creates the services in order, waiting for the readiness of each.
+    /// If a service fails to create or get ready, the services created so
+    /// far are deleted again, and the error names the failed step and the
+    /// rollback.
+    pub fn create(&self) -> crate::Result<()> {
+        for (index, member) in self.members.iter().enumerate() {
+            let step = member.service.create().map_err(|e| (e, "create"))
+                .and_then(|()| member.wait().map_err(|e| (e, "readiness of")));
+            if let Err((e, step)) = step {
+                let rollback = self.rollback(index);
+                return Err(e.context(&format!("{} {} ({})", step, member.name, rollback)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Deletes the services up to `failed` in reverse order, the failed one
+    /// included as it may be half installed, and describes the outcome
+    fn rollback(&self, failed: usize) -> String {
+        let mut deleted = vec![];
+        let mut failures = vec![];
+        for member in self.members[..=failed].iter().rev() {
+            let result = match member.service.exists() {
+                Ok(true) => member.service.delete(),
+                Ok(false) => continue,
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(()) => deleted.push(member.name.clone()),
+                Err(e) => failures.push(format!("rollback of {} failed: {}", member.name, e)),
+            }
+        }
+        let mut outcome = vec![];
+        if !deleted.is_empty() {
+            outcome.push(format!("rolled back {}", deleted.join(", ")));
+        }
+        outcome.extend(failures);
+        if outcome.is_empty() {
+            "nothing to roll back".to_string()
+        } else {
+            outcome.join("; ")
+        }
+    }
+
+    /// Deletes the services in reverse order
+    pub fn delete(&self) -> crate::Result<()> {
+        for member in self.members.iter().rev() {
+            member.service.delete()?;
+        }
+        Ok(())
+    }
+
+    /// Starts the services in order, waiting for the readiness of each.
+    /// Services already running are only waited for.
+    pub fn start(&self) -> crate::Result<()> {
+        for member in &self.members {
+            if member.service.status()? != ServiceStatus::Running {
+                member.service.start()?;
+            }
+            member.wait()?;
+        }
+        Ok(())
+    }
+
+    /// Stops the running services in reverse order
+    pub fn stop(&self) -> crate::Result<()> {
+        for member in self.members.iter().rev() {
+            if member.service.status()? == ServiceStatus::Running {
+                member.service.stop()?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn restart(&self) -> crate::Result<()> {
+        self.stop()?;
+        self.start()
+    }
+
+    /// Status of each service, in order
+    pub fn status(&self) -> crate::Result<Vec<(String, ServiceStatus)>> {
+        self.members.iter()
+            .map(|member| Ok((member.name.clone(), member.service.status()?)))
+            .collect()
+    }
+}
+
+impl Member {
+    fn wait(&self) -> crate::Result<()> {
+        match &self.readiness {
+            Some(readiness) => readiness.wait(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::SombraMock;
+    use crate::{ErrorKind, Probe, SombraConfig};
+    use std::time::Duration;
+
+    fn mock(name: &str) -> SombraMock {
+        SombraMock::from_config(SombraConfig::new(name, "/opt/mock")).unwrap()
+    }
+
+    #[test]
+    fn group_starts_in_order() {
+        let group = SombraGroup::new()
+            .service("db", mock("db"), None)
+            .service("api", mock("api"), None);
+        assert_eq!(group.create(), Ok(()));
+        assert_eq!(group.status(), Ok(vec![("db".to_string(), ServiceStatus::Running),
+                                           ("api".to_string(), ServiceStatus::Running)]));
+        assert_eq!(group.stop(), Ok(()));
+        assert_eq!(group.status(), Ok(vec![("db".to_string(), ServiceStatus::Stopped),
+                                           ("api".to_string(), ServiceStatus::Stopped)]));
+        assert_eq!(group.delete(), Ok(()));
+    }
+
+    #[test]
+    fn group_create_rolls_back() {
+        let path = std::env::temp_dir().join("sombra_group_rollback_test");
+        let _ = std::fs::remove_file(&path);
+        let readiness = Readiness {
+            probe: Probe::File(path),
+            timeout: Duration::from_millis(100),
+        };
+        let group = SombraGroup::new()
+            .service("db", mock("db"), None)
+            .service("api", mock("api"), Some(readiness))
+            .service("worker", mock("worker"), None);
+
+        let error = group.create().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Timeout);
+        assert!(error.to_string()
+            .starts_with("<Timeout> readiness of api (rolled back api, db): "));
+        assert_eq!(group.status(), Ok(vec![("db".to_string(), ServiceStatus::NotInstalled),
+                                           ("api".to_string(), ServiceStatus::NotInstalled),
+                                           ("worker".to_string(), ServiceStatus::NotInstalled)]));
+    }
+
+    #[test]
+    fn group_waits_for_readiness() {
+        let path = std::env::temp_dir().join("sombra_group_test");
+        let _ = std::fs::remove_file(&path);
+        let readiness = Readiness {
+            probe: Probe::File(path.clone()),
+            timeout: Duration::from_millis(200),
+        };
+        mock("db").set(ServiceStatus::Stopped);
+        mock("api").set(ServiceStatus::Stopped);
+        let group = SombraGroup::new()
+            .service("db", mock("db"), Some(readiness))
+            .service("api", mock("api"), None);
+
+        assert_eq!(group.start().map_err(|e| e.kind()), Err(ErrorKind::Timeout));
+        assert_eq!(mock("api").status(), Ok(ServiceStatus::Stopped));
+
+        std::fs::write(&path, "ready").unwrap();
+        assert_eq!(group.start(), Ok(()));
+        assert_eq!(mock("api").status(), Ok(ServiceStatus::Running));
+        let _ = std::fs::remove_file(&path);
+    }
+}
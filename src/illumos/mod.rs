@@ -0,0 +1 @@
+pub mod sombra_imp;
@@ -0,0 +1,645 @@
+use crate::{Account, Artifact, Difference, ExitReason, LogReader, ManagedService, Scripts, Sombra,
+            ServiceStats, ServiceStatus, SombraConfig, StartType, StopSignal, Usage};
+use crate::export::Shell;
+use crate::support;
+use crate::unix::shell::quote;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+/// Manifests of the services of the site, imported again at boot
+const MANIFEST_DIR: &str = "/var/svc/manifest/site";
+/// Start methods and settings of the services, with the files the start
+/// methods keep
+const STATE_DIR: &str = "/var/lib/sombra/smf";
+/// Output of the methods without log files
+const SMF_LOG_DIR: &str = "/var/svc/log";
+
+/// Runs the target as an SMF service on illumos and Solaris. The manifest
+/// goes to /var/svc/manifest/site, is imported with `svccfg` and the
+/// service is driven with `svcadm`. svc.startd runs the start method as
+/// the child of the service, restarting it on every exit, and puts the
+/// service in maintenance past `critical_failure_count` failures within
+/// `critical_failure_period` seconds, which the restart policy maps to;
+/// the start method waits the restart delay. Stopping signals every
+/// process of the contract of the service, so the process tree always
+/// goes with it.
+pub struct SombraSmf {
+    config: SombraConfig,
+}
+
+impl SombraSmf {
+    fn fmri(name: &str) -> String {
+        format!("svc:/site/{}:default", name)
+    }
+
+    fn manifest_path(name: &str) -> PathBuf {
+        Path::new(MANIFEST_DIR).join(format!("{}.xml", name))
+    }
+
+    fn state(&self, file: &str) -> PathBuf {
+        Path::new(STATE_DIR).join(&self.config.name).join(file)
+    }
+
+    fn settings_path(name: &str) -> PathBuf {
+        Path::new(STATE_DIR).join(name).join("service.conf")
+    }
+
+    fn method_path(name: &str) -> PathBuf {
+        Path::new(STATE_DIR).join(name).join("method.sh")
+    }
+
+    /// Log of the methods kept by svc.startd
+    fn smf_log(name: &str) -> PathBuf {
+        Path::new(SMF_LOG_DIR).join(format!("site-{}:default.log", name))
+    }
+
+    /// Fails on the settings SMF can't apply
+    fn check(config: &SombraConfig) -> crate::Result<()> {
+        let unsupported = if !config.name.chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-".contains(c)) {
+            Some("SMF service names can only have letters, digits, hyphens and underscores")
+        } else {
+            support::unsupported(config, &[])
+        };
+        match unsupported {
+            Some(description) => Err(crate::Error::new(crate::ErrorKind::Other,
+                                                       description.to_string())
+                .content(config.name.clone())),
+            None => Ok(()),
+        }
+    }
+
+    fn is_root() -> crate::Result<()> {
+        if unsafe { libc::geteuid() } != 0 {
+            return Err(crate::Error::new(crate::ErrorKind::AccessDenied,
+                                         "Without root privileges.".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Escapes text for an attribute or an element of the manifest
+    fn escape(text: &str) -> String {
+        text.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+            .replace('\'', "&apos;")
+    }
+
+    /// Services of the site go by their name, other ones by their FMRI
+    fn dependency_fmri(dependency: &str) -> String {
+        if dependency.starts_with("svc:/") {
+            dependency.to_string()
+        } else if dependency.contains('/') {
+            format!("svc:/{}", dependency)
+        } else {
+            format!("svc:/site/{}", dependency)
+        }
+    }
+
+    fn manifest_content(config: &SombraConfig) -> crate::Result<String> {
+        let name = SombraSmf::escape(&config.name);
+        let mut lines = vec![
+            "<?xml version=\"1.0\"?>".to_string(),
+            "<!DOCTYPE service_bundle SYSTEM \"/usr/share/lib/xml/dtd/service_bundle.dtd.1\">"
+                .to_string(),
+            format!("<!-- {} -->", crate::settings::HEADER.trim_start_matches("# ")),
+            format!("<service_bundle type=\"manifest\" name=\"{}\">", name),
+            format!("  <service name=\"site/{}\" type=\"service\" version=\"1\">", name),
+            // Enabled by start(), persistently for services started at boot
+            "    <create_default_instance enabled=\"false\"/>".to_string(),
+            "    <single_instance/>".to_string(),
+        ];
+        let mut dependencies = vec![("filesystem", "require_all",
+                                     "svc:/system/filesystem/local".to_string()),
+                                    ("network", "optional_all",
+                                     "svc:/milestone/network".to_string())];
+        for dependency in &config.dependencies {
+            dependencies.push((dependency.as_str(), "require_all",
+                               SombraSmf::dependency_fmri(dependency)));
+        }
+        for (dependency, grouping, fmri) in dependencies {
+            let group: String = dependency.chars()
+                .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+                .collect();
+            lines.push(format!("    <dependency name=\"{}\" grouping=\"{}\" restart_on=\"none\" \
+                                type=\"service\">", group, grouping));
+            lines.push(format!("      <service_fmri value=\"{}\"/>", SombraSmf::escape(&fmri)));
+            lines.push("    </dependency>".to_string());
+        }
+
+        let user = config.account.unix_name(&config.name)?;
+        lines.push(match &config.working_directory {
+            Some(dir) => format!("    <method_context working_directory=\"{}\">",
+                                 SombraSmf::escape(&dir.to_string_lossy())),
+            None => "    <method_context>".to_string(),
+        });
+        if user != "root" {
+            lines.push(format!("      <method_credential user=\"{}\" group=\":default\"/>",
+                               SombraSmf::escape(&user)));
+        }
+        if !config.env.is_empty() {
+            lines.push("      <method_environment>".to_string());
+            for (key, value) in &config.env {
+                lines.push(format!("        <envvar name=\"{}\" value=\"{}\"/>",
+                                   SombraSmf::escape(key), SombraSmf::escape(value)));
+            }
+            lines.push("      </method_environment>".to_string());
+        }
+        lines.push("    </method_context>".to_string());
+
+        // % starts the tokens of the method
+        let method = quote(SombraSmf::method_path(&config.name).as_os_str()).replace('%', "%%");
+        lines.push(format!("    <exec_method type=\"method\" name=\"start\" exec=\"/bin/sh {}\" \
+                            timeout_seconds=\"0\"/>", SombraSmf::escape(&method)));
+        let signal = match config.stop_signal {
+            StopSignal::Terminate => ":kill".to_string(),
+            StopSignal::Interrupt => format!(":kill -{}",
+                                             config.stop_signal.unix_name()
+                                                 .trim_start_matches("SIG")),
+        };
+        let timeout = config.stop_timeout.map(|timeout| timeout.as_secs()).unwrap_or(10);
+        lines.push(format!("    <exec_method type=\"method\" name=\"stop\" exec=\"{}\" \
+                            timeout_seconds=\"{}\"/>", signal, timeout));
+
+        lines.push("    <property_group name=\"startd\" type=\"framework\">".to_string());
+        lines.push("      <propval name=\"duration\" type=\"astring\" value=\"child\"/>"
+            .to_string());
+        let restarts = config.failure_actions.as_ref().and_then(|f| f.restart_delay()).is_some();
+        let failures = match &config.restart_policy {
+            Some(policy) => Some((policy.max_restarts + 1,
+                                  Some(policy.window.as_millis().div_ceil(1000)))),
+            // Into maintenance at the first failure
+            None if !restarts => Some((1, None)),
+            None => None,
+        };
+        if let Some((count, period)) = failures {
+            lines.push(format!("      <propval name=\"critical_failure_count\" type=\"integer\" \
+                                value=\"{}\"/>", count));
+            if let Some(period) = period {
+                lines.push(format!("      <propval name=\"critical_failure_period\" \
+                                    type=\"integer\" value=\"{}\"/>", period));
+            }
+        }
+        lines.push("    </property_group>".to_string());
+
+        lines.push("    <stability value=\"Unstable\"/>".to_string());
+        lines.push("    <template>".to_string());
+        let common_name = config.display_name.as_deref().unwrap_or(&config.name);
+        lines.push(format!("      <common_name><loctext xml:lang=\"C\">{}</loctext>\
+                            </common_name>", SombraSmf::escape(common_name)));
+        if let Some(description) = &config.description {
+            lines.push(format!("      <description><loctext xml:lang=\"C\">{}</loctext>\
+                                </description>", SombraSmf::escape(description)));
+        }
+        lines.push("    </template>".to_string());
+        lines.push("  </service>".to_string());
+        lines.push("</service_bundle>".to_string());
+        lines.push(String::new());
+        Ok(lines.join("\n"))
+    }
+
+    /// Start method, run again by svc.startd after each exit of the
+    /// target. It waits the restart delay before running it again, doubled
+    /// on each failure, and records its pid, exit code and restarts.
+    fn method_content(config: &SombraConfig) -> String {
+        let seconds = |delay: Duration| delay.as_millis().div_ceil(1000);
+        let restart_delay = config.failure_actions.as_ref().and_then(|f| f.restart_delay());
+        let (initial_delay, max_delay, window) = match (&config.restart_policy, restart_delay) {
+            (Some(policy), _) => (Some(seconds(policy.initial_delay)),
+                                  seconds(policy.max_delay),
+                                  Some(seconds(policy.window))),
+            (None, Some(delay)) => (Some(seconds(delay)), seconds(delay), None),
+            (None, None) => (None, 0, None),
+        };
+
+        let state = quote(Path::new(STATE_DIR).join(&config.name).as_os_str());
+        let mut script = vec![
+            "#!/bin/sh".to_string(),
+            format!("# Start method of the {} service, generated by sombra", config.name),
+            format!("state={}", state),
+            "if [ -e \"$state/started\" ]; then".to_string(),
+            "    restarts=$(cat \"$state/restarts\" 2>/dev/null || echo 0)".to_string(),
+            "    echo $((restarts + 1)) > \"$state/restarts\"".to_string(),
+        ];
+        if let Some(initial_delay) = initial_delay {
+            script.push(format!("    delay=$(cat \"$state/delay\" 2>/dev/null || echo {})",
+                                initial_delay));
+            script.push("    sleep \"$delay\"".to_string());
+            script.push(format!("    delay=$((delay * 2)); [ \"$delay\" -gt {0} ] && \
+                                 delay={0}", max_delay));
+            script.push("    echo \"$delay\" > \"$state/delay\"".to_string());
+        }
+        script.push("fi".to_string());
+        script.push("date +%s > \"$state/started\"".to_string());
+
+        // The inner shell records its pid, then runs the target in its place
+        let mut command = vec!["/bin/sh -c 'echo $$ > \"$0\"; exec \"$@\"' \"$state/target.pid\""
+            .to_string()];
+        if let Some(priority) = config.priority {
+            command.push(format!("nice -n {}", priority.nice()));
+        }
+        command.push(quote(config.path.as_os_str()));
+        command.extend(config.args.iter().map(|arg| quote(arg)));
+        command.push("</dev/null".to_string());
+        if let Some(log) = &config.stdout_log {
+            command.push(format!(">>{}", quote(log.as_os_str())));
+        }
+        if let Some(log) = &config.stderr_log {
+            command.push(format!("2>>{}", quote(log.as_os_str())));
+        }
+        script.push(command.join(" "));
+        script.push("code=$?".to_string());
+        script.push("rm -f \"$state/target.pid\"".to_string());
+        script.push("echo \"$code\" > \"$state/exit_code\"".to_string());
+        if let (Some(_), Some(window)) = (initial_delay, window) {
+            // A target that ran for the whole window starts over
+            script.push(format!("[ $(($(date +%s) - $(cat \"$state/started\"))) -ge {} ] && \
+                                 rm -f \"$state/delay\"", window));
+        }
+        script.push("exit \"$code\"".to_string());
+        script.push(String::new());
+        script.join("\n")
+    }
+
+    fn settings_content(config: &SombraConfig) -> crate::Result<String> {
+        let user = config.account.unix_name(&config.name)?;
+        Ok(crate::settings::content(config, &[("user", user)]))
+    }
+
+    fn config_from_settings(name: &str, content: &str) -> (SombraConfig, Option<String>) {
+        let settings = crate::settings::parse(name, content);
+        let mut config = settings.config;
+        if let Some((_, user)) = settings.extra.iter().find(|(key, _)| key == "user") {
+            config.account = Account::from_unix_name(user);
+        }
+        (config, settings.checksum)
+    }
+
+    fn run(command: &mut Command) -> crate::Result<String> {
+        let output = command.output()?;
+        if !output.status.success() {
+            return Err(crate::Error::new(crate::ErrorKind::Other,
+                                         String::from_utf8_lossy(&output.stderr).trim()
+                                             .to_string())
+                .content(format!("{:?}", command)));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    /// Writes the manifest, the start method and the settings, and imports
+    /// the manifest. The state directory belongs to the account of the
+    /// service, which the start method runs as.
+    fn install(&self) -> crate::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        let name = &self.config.name;
+        let dir = Path::new(STATE_DIR).join(name);
+        std::fs::create_dir_all(&dir)
+            .map_err(|e| crate::Error::from(e).content(dir.display().to_string()))?;
+        std::fs::write(SombraSmf::settings_path(name),
+                       SombraSmf::settings_content(&self.config)?)?;
+        let method = SombraSmf::method_path(name);
+        std::fs::write(&method, SombraSmf::method_content(&self.config))?;
+        std::fs::set_permissions(&method, std::fs::Permissions::from_mode(0o755))?;
+        let user = self.config.account.unix_name(name)?;
+        if user != "root" {
+            let mut chown = Command::new("chown");
+            chown.arg("-R").arg(&user).arg(&dir);
+            for log in self.config.log_files() {
+                if !log.exists() {
+                    std::fs::write(&log, "")?;
+                }
+                chown.arg(log);
+            }
+            SombraSmf::run(&mut chown)?;
+        }
+        std::fs::create_dir_all(MANIFEST_DIR)?;
+        let manifest = SombraSmf::manifest_path(name);
+        std::fs::write(&manifest, SombraSmf::manifest_content(&self.config)?)?;
+        SombraSmf::run(Command::new("svccfg").arg("import").arg(&manifest))?;
+        Ok(())
+    }
+
+    fn svcadm(&self, args: &[&str]) -> crate::Result<()> {
+        SombraSmf::run(Command::new("svcadm").args(args).arg(SombraSmf::fmri(&self.config.name)))
+            .map(|_| ())
+    }
+
+    /// State of the instance as `svcs` prints it, with a `*` while it moves
+    /// to another state
+    fn state_name(&self) -> Option<String> {
+        SombraSmf::run(Command::new("svcs").arg("-H").arg("-o").arg("state")
+            .arg(SombraSmf::fmri(&self.config.name))).ok()
+    }
+
+    fn status_from_state(state: &str) -> ServiceStatus {
+        match state {
+            "online*" | "degraded*" => ServiceStatus::StopPending,
+            state if state.ends_with('*') => ServiceStatus::StartPending,
+            "online" | "degraded" | "legacy_run" => ServiceStatus::Running,
+            // Waiting for its dependencies
+            "offline" => ServiceStatus::StartPending,
+            "maintenance" => ServiceStatus::Failed,
+            _ => ServiceStatus::Stopped,
+        }
+    }
+
+    fn is_installed(&self) -> crate::Result<()> {
+        if SombraSmf::manifest_path(&self.config.name).exists()
+            && SombraSmf::settings_path(&self.config.name).exists() {
+            Ok(())
+        } else {
+            Err(crate::Error::new(crate::ErrorKind::ServiceNotFound,
+                                  format!("Service {} does not exist", self.config.name)))
+        }
+    }
+
+    fn installed(&self) -> crate::Result<(SombraConfig, Option<String>)> {
+        self.is_installed()?;
+        let path = SombraSmf::settings_path(&self.config.name);
+        let content = std::fs::read_to_string(&path)
+            .map_err(|e| crate::Error::from(e).content(path.display().to_string()))?;
+        Ok(SombraSmf::config_from_settings(&self.config.name, &content))
+    }
+
+    fn read_number(&self, file: &str) -> Option<u32> {
+        std::fs::read_to_string(self.state(file)).ok()?.trim().parse().ok()
+    }
+}
+
+impl Sombra for SombraSmf {
+    fn from_config(config: SombraConfig) -> crate::Result<Self> {
+        Ok(SombraSmf { config })
+    }
+
+    fn create(&self) -> crate::Result<()> {
+        SombraSmf::check(&self.config)?;
+        SombraSmf::is_root()?;
+        if self.is_installed().is_ok() {
+            return Err(crate::Error::new(crate::ErrorKind::ServiceAlreadyExists,
+                                         format!("Service {} already exist",
+                                                 self.config.name)));
+        }
+        self.install()?;
+
+        if self.config.start_type == StartType::Disabled || self.config.target_pending() {
+            return Ok(());
+        }
+        self.start()?;
+        match &self.config.readiness {
+            Some(readiness) => readiness.wait(),
+            None => Ok(()),
+        }
+    }
+
+    fn update(&self) -> crate::Result<()> {
+        SombraSmf::check(&self.config)?;
+        SombraSmf::is_root()?;
+        self.is_installed()?;
+        // svccfg refreshes the instance, a running target picks the start
+        // method up when it's restarted
+        self.install()
+    }
+
+    fn delete(&self) -> crate::Result<()> {
+        self.is_installed()?;
+        self.svcadm(&["disable", "-s"])?;
+        SombraSmf::run(Command::new("svccfg").arg("delete").arg("-f")
+            .arg(format!("svc:/site/{}", self.config.name)))?;
+        std::fs::remove_file(SombraSmf::manifest_path(&self.config.name))?;
+        let dir = Path::new(STATE_DIR).join(&self.config.name);
+        std::fs::remove_dir_all(&dir)
+            .map_err(|e| crate::Error::from(e).content(dir.display().to_string()))
+    }
+
+    fn start(&self) -> crate::Result<()> {
+        self.config.check_target()?;
+        self.is_installed()?;
+        for file in &["started", "restarts", "delay", "exit_code"] {
+            let _ = std::fs::remove_file(self.state(file));
+        }
+        if self.state_name().as_deref() == Some("maintenance") {
+            self.svcadm(&["clear"])?;
+        }
+        // Temporary unless the service starts at boot
+        match self.config.start_type {
+            StartType::AutoStart | StartType::DelayedAutoStart => self.svcadm(&["enable"]),
+            StartType::OnDemand | StartType::Disabled => self.svcadm(&["enable", "-t"]),
+        }?;
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        Ok(())
+    }
+
+    fn stop(&self) -> crate::Result<()> {
+        self.is_installed()?;
+        // Waits for the stop method, which kills the contract after its
+        // timeout
+        self.svcadm(&["disable", "-s", "-t"])
+    }
+
+    fn status(&self) -> crate::Result<ServiceStatus> {
+        if self.is_installed().is_err() {
+            return Ok(ServiceStatus::NotInstalled);
+        }
+        match self.state_name() {
+            Some(state) => Ok(SombraSmf::status_from_state(&state)),
+            None => Ok(ServiceStatus::NotInstalled),
+        }
+    }
+
+    fn pid(&self) -> crate::Result<Option<u32>> {
+        self.is_installed()?;
+        let alive = |pid: &u32| {
+            let signaled = unsafe { libc::kill(*pid as libc::pid_t, 0) } == 0;
+            signaled || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+        };
+        Ok(self.read_number("target.pid").filter(alive))
+    }
+
+    fn usage(&self) -> crate::Result<Option<Usage>> {
+        match self.pid()? {
+            Some(pid) => Ok(Some(crate::unix::ps::usage(pid)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn stats(&self) -> crate::Result<ServiceStats> {
+        let started_at = match self.pid()? {
+            Some(_) => std::fs::metadata(self.state("started"))
+                .and_then(|metadata| metadata.modified())
+                .ok(),
+            None => None,
+        };
+        // The shell reports a target killed by a signal as 128 + signal
+        let last_exit = self.read_number("exit_code").map(|code| match code {
+            129..=192 => ExitReason::Signal(code as i32 - 128),
+            code => ExitReason::Code(code as i64),
+        });
+        Ok(ServiceStats {
+            started_at,
+            restarts: self.read_number("restarts").unwrap_or(0),
+            last_exit,
+        })
+    }
+
+    fn config(&self) -> crate::Result<SombraConfig> {
+        Ok(self.installed()?.0)
+    }
+
+    fn diff(&self) -> crate::Result<Vec<Difference>> {
+        // The settings keep only what they render, so compare that part
+        let content = SombraSmf::settings_content(&self.config)?;
+        let desired = SombraSmf::config_from_settings(&self.config.name, &content).0;
+        Ok(desired.diff(&self.config()?))
+    }
+
+    fn checksum(&self) -> crate::Result<Option<String>> {
+        Ok(self.installed()?.1)
+    }
+
+    fn list_managed() -> crate::Result<Vec<ManagedService>> {
+        let mut services = vec![];
+        if !Path::new(STATE_DIR).exists() {
+            return Ok(services);
+        }
+        for entry in std::fs::read_dir(STATE_DIR)? {
+            let path = entry?.path();
+            let name = match path.file_name().and_then(|n| n.to_str()) {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            let content = std::fs::read_to_string(SombraSmf::settings_path(&name))
+                .unwrap_or_default();
+            if !content.starts_with(crate::settings::HEADER)
+                || !SombraSmf::manifest_path(&name).exists() {
+                continue;
+            }
+            let (config, _) = SombraSmf::config_from_settings(&name, &content);
+            let path = config.path.clone();
+            let status = SombraSmf::from_config(config)?.status()?;
+            services.push(ManagedService { name, status, path });
+        }
+        services.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(services)
+    }
+
+    fn logs(&self, follow: bool) -> crate::Result<LogReader> {
+        let mut files = self.config.log_files();
+        if self.config.stdout_log.is_none() || self.config.stderr_log.is_none() {
+            files.push(SombraSmf::smf_log(&self.config.name));
+        }
+        LogReader::files(files, follow)
+    }
+
+    fn render(&self) -> crate::Result<Vec<Artifact>> {
+        SombraSmf::check(&self.config)?;
+        let config = &self.config;
+        Ok(vec![
+            Artifact::file(SombraSmf::settings_path(&config.name),
+                           SombraSmf::settings_content(config)?),
+            Artifact::script(SombraSmf::method_path(&config.name),
+                             SombraSmf::method_content(config)),
+            Artifact::file(SombraSmf::manifest_path(&config.name),
+                           SombraSmf::manifest_content(config)?),
+        ])
+    }
+
+    fn export(&self) -> crate::Result<Scripts> {
+        let name = &self.config.name;
+        let dir = Path::new(STATE_DIR).join(name);
+        let fmri = SombraSmf::fmri(name);
+        let mut shell = Shell::new(name);
+        shell.write(&self.render()?);
+        shell.chown(&self.config, &dir)?;
+        shell.install(Shell::line(&[&"svccfg", &"import", &SombraSmf::manifest_path(name)]));
+        // Temporary unless the service starts at boot
+        match self.config.start_type {
+            StartType::AutoStart | StartType::DelayedAutoStart =>
+                shell.install(Shell::line(&[&"svcadm", &"enable", &fmri])),
+            StartType::OnDemand =>
+                shell.install(Shell::line(&[&"svcadm", &"enable", &"-t", &fmri])),
+            StartType::Disabled => {},
+        }
+        shell.uninstall(Shell::line(&[&"svcadm", &"disable", &"-s", &fmri]));
+        shell.uninstall(Shell::line(&[&"svccfg", &"delete", &"-f",
+                                      &format!("svc:/site/{}", name)]));
+        shell.remove(SombraSmf::manifest_path(name));
+        shell.remove_dir(dir);
+        Ok(shell.finish())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RestartPolicy;
+
+    #[test]
+    fn manifest_maps_restart_policy() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp_echo");
+        config.dependencies = vec!["network/ssh".to_string(), "db".to_string()];
+        config.account = Account::User { name: "sombra".to_string(), password: None };
+        config.env = vec![("GREETING".to_string(), "a \"b\" & c".to_string())];
+        config.stop_signal = StopSignal::Interrupt;
+        config.restart_policy = Some(RestartPolicy {
+            max_restarts: 3,
+            window: Duration::from_secs(60),
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(8),
+        });
+        let content = SombraSmf::manifest_content(&config).unwrap();
+        assert!(content.contains("\n  <service name=\"site/tcp_echo\" type=\"service\" \
+                                  version=\"1\">\n"));
+        assert!(content.contains("\n      <service_fmri value=\"svc:/network/ssh\"/>\n"));
+        assert!(content.contains("\n    <dependency name=\"db\" grouping=\"require_all\" \
+                                  restart_on=\"none\" type=\"service\">\n      \
+                                  <service_fmri value=\"svc:/site/db\"/>\n"));
+        assert!(content.contains("\n      <method_credential user=\"sombra\" \
+                                  group=\":default\"/>\n"));
+        assert!(content.contains("<envvar name=\"GREETING\" \
+                                  value=\"a &quot;b&quot; &amp; c\"/>"));
+        assert!(content.contains("exec=\"/bin/sh &apos;/var/lib/sombra/smf/tcp_echo/method.sh\
+                                  &apos;\""));
+        assert!(content.contains("name=\"stop\" exec=\":kill -INT\" timeout_seconds=\"10\""));
+        assert!(content.contains("\n      <propval name=\"critical_failure_count\" \
+                                  type=\"integer\" value=\"4\"/>\n      \
+                                  <propval name=\"critical_failure_period\" type=\"integer\" \
+                                  value=\"60\"/>\n"));
+
+        config.restart_policy = None;
+        let content = SombraSmf::manifest_content(&config).unwrap();
+        assert!(content.contains("name=\"critical_failure_count\" type=\"integer\" \
+                                  value=\"1\"/>"));
+        assert!(!content.contains("critical_failure_period"));
+    }
+
+    #[test]
+    fn method_waits_restart_delay() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp_echo");
+        config.stdout_log = Some(PathBuf::from("/var/log/tcp_echo.log"));
+        let content = SombraSmf::method_content(&config);
+        assert!(!content.contains("sleep"));
+        assert!(content.contains(" '/opt/tcp_echo' </dev/null >>'/var/log/tcp_echo.log'\n"));
+
+        config.restart_policy = Some(RestartPolicy {
+            max_restarts: 3,
+            window: Duration::from_secs(60),
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(8),
+        });
+        let content = SombraSmf::method_content(&config);
+        assert!(content.contains("\n    delay=$(cat \"$state/delay\" 2>/dev/null || echo 1)\n\
+                                  \x20   sleep \"$delay\"\n"));
+        assert!(content.ends_with(" -ge 60 ] && rm -f \"$state/delay\"\nexit \"$code\"\n"));
+    }
+
+    #[test]
+    fn svcs_state_mapping() {
+        assert_eq!(SombraSmf::status_from_state("online"), ServiceStatus::Running);
+        assert_eq!(SombraSmf::status_from_state("online*"), ServiceStatus::StopPending);
+        assert_eq!(SombraSmf::status_from_state("offline*"), ServiceStatus::StartPending);
+        assert_eq!(SombraSmf::status_from_state("maintenance"), ServiceStatus::Failed);
+        assert_eq!(SombraSmf::status_from_state("disabled"), ServiceStatus::Stopped);
+    }
+}
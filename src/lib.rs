@@ -1,27 +1,298 @@
 mod result;
 mod error;
+mod status;
+mod config;
+mod builder;
+mod secret;
+mod secrets;
+mod logs;
+mod probe;
+pub mod notify;
+#[cfg(feature = "async")]
+mod nonblocking;
+mod watch;
+#[cfg(target_os = "windows")]
+mod control;
+mod manifest;
+mod group;
+mod plan;
+mod cleanup;
+mod rename;
+mod scoped;
+mod entry;
+mod validate;
+mod digest;
+mod script;
+mod settings;
+mod privilege;
+mod artifact;
+mod export;
+mod support;
+#[cfg(feature = "metrics")]
+mod metrics;
+#[cfg(any(test, feature = "test-util"))]
+mod mock;
 
 pub use result::Result;
 pub use error::{Error, ErrorKind};
+pub use status::{ExitReason, ManagedService, ServiceStats, ServiceStatus, Usage};
+pub use config::{Account, Difference, FailureAction, FailureActions, Hardening, LogRotation,
+                 Priority, ProtectHome, ProtectSystem, ResourceLimits, RestartPolicy, Scope,
+                 SombraConfig, StartType, StopSignal, Trigger};
+pub use builder::SombraBuilder;
+pub use secret::SecretString;
+pub use secrets::SecretStore;
+pub use logs::LogReader;
+pub use probe::{HealthCheck, HealthMonitor, Probe, Readiness};
+#[cfg(feature = "async")]
+pub use nonblocking::{AsyncSombra, Blocking};
+pub use watch::StatusWatcher;
+#[cfg(target_os = "windows")]
+pub use control::{ControlClient, Request};
+pub use manifest::{Applied, Manifest};
+pub use group::SombraGroup;
+pub use plan::{Operation, Plan};
+pub use cleanup::{cleanup, cleanup_as};
+pub use scoped::ScopedService;
+pub use entry::{run_service, ServiceMain, Shutdown};
+pub use privilege::{elevate, is_elevated, Privileges};
+pub use artifact::Artifact;
+pub use export::Scripts;
+#[cfg(feature = "metrics")]
+pub use metrics::{MetricsHandle, MetricsServer};
+#[cfg(feature = "test-util")]
+pub use mock::{Call, SombraMock};
 
 #[cfg(target_os = "windows")]
 mod windows;
 #[cfg(target_os = "linux")]
 mod linux;
+#[cfg(target_os = "macos")]
+mod macos;
+#[cfg(target_os = "freebsd")]
+mod freebsd;
+#[cfg(any(target_os = "illumos", target_os = "solaris"))]
+mod illumos;
+#[cfg(unix)]
+mod unix;
+
+#[cfg(target_os = "windows")]
+pub use windows::sombra_imp::SombraWindows;
+#[cfg(target_os = "windows")]
+pub use windows::task::SombraTask;
+#[cfg(target_os = "windows")]
+pub use windows::autostart::SombraAutostart;
+/// Entry point of sombra-windows-service.exe
+#[cfg(target_os = "windows")]
+#[doc(hidden)]
+pub use windows::wrapper::run as run_wrapper;
+#[cfg(target_os = "linux")]
+pub use linux::sombra_imp::SombraLinux;
+#[cfg(target_os = "linux")]
+pub use linux::openrc::SombraOpenrc;
+#[cfg(target_os = "linux")]
+pub use linux::runit::SombraRunit;
+#[cfg(target_os = "linux")]
+pub use linux::s6::SombraS6;
+#[cfg(target_os = "linux")]
+pub use linux::sysv::SombraSysv;
+#[cfg(target_os = "macos")]
+pub use macos::sombra_imp::SombraMacos;
+#[cfg(target_os = "freebsd")]
+pub use freebsd::sombra_imp::SombraFreebsd;
+#[cfg(any(target_os = "illumos", target_os = "solaris"))]
+pub use illumos::sombra_imp::SombraSmf;
+#[cfg(unix)]
+pub use unix::cron::SombraCron;
 
 pub trait Sombra {
-    fn build(name: &str, path: &str, args: Vec<String>) -> Result<Self>
+    fn from_config(config: SombraConfig) -> Result<Self>
         where Self: std::marker::Sized;
+
+    fn build<P, I, S>(name: &str, path: P, args: I) -> Result<Self>
+        where Self: std::marker::Sized, P: AsRef<std::path::Path>, I: IntoIterator<Item = S>,
+              S: Into<std::ffi::OsString> {
+        SombraBuilder::new(name, path).args(args).build_as()
+    }
+
+    /// Creates a service running the current executable with `args`, for
+    /// daemons offering an `--install` flag
+    fn install_self<I, S>(name: &str, args: I) -> Result<Self>
+        where Self: std::marker::Sized, I: IntoIterator<Item = S>, S: Into<std::ffi::OsString> {
+        let path = std::env::current_exe()?;
+        let service: Self = SombraBuilder::new(name, path).args(args).build_as()?;
+        service.create()?;
+        Ok(service)
+    }
+
     fn create(&self) -> Result<()>;
     fn delete(&self) -> Result<()>;
+    /// Applies the configuration to the installed service, without
+    /// deleting it. A running target picks it up at its next start.
+    fn update(&self) -> Result<()>;
+    fn start(&self) -> Result<()>;
+    fn stop(&self) -> Result<()>;
+    fn status(&self) -> Result<ServiceStatus>;
+    /// Process ID of the target, not of a wrapper, while it runs
+    fn pid(&self) -> Result<Option<u32>>;
+    /// Memory, CPU time and open handles of the target, while it runs
+    fn usage(&self) -> Result<Option<Usage>>;
+    /// Start time, restart count and last exit of the target
+    fn stats(&self) -> Result<ServiceStats>;
+    /// Reads back the configuration of the installed service, as far as
+    /// the service manager keeps it. Secrets such as passwords aren't
+    /// read.
+    fn config(&self) -> Result<SombraConfig>;
+    /// Settings of the installed service that drifted from the ones this
+    /// service was built with. Settings the service manager doesn't keep
+    /// are left out.
+    fn diff(&self) -> Result<Vec<Difference>>;
+    /// SHA-256 of the target recorded when the service was created or
+    /// last updated, in hex, or nothing if the target wasn't there then
+    fn checksum(&self) -> Result<Option<String>>;
+    /// Services of the machine created by this crate, sorted by name
+    fn list_managed() -> Result<Vec<ManagedService>>
+        where Self: std::marker::Sized;
+    /// Reads the output captured from the target, waiting for new lines
+    /// when `follow` is set.
+    fn logs(&self, follow: bool) -> Result<LogReader>;
+    /// The files `create()` would write, or a description of what it
+    /// would register with the service manager, without installing
+    /// anything
+    fn render(&self) -> Result<Vec<Artifact>>;
+    /// Install and uninstall scripts doing what `create()` and `delete()`
+    /// do, for machines the crate can't run on. They expect the target, and
+    /// the wrapper on Windows, at the paths of the configuration.
+    fn export(&self) -> Result<Scripts> {
+        Err(Error::new(ErrorKind::Other,
+                       "Scripts can't be exported for this backend".to_string()))
+    }
+
+    /// Rights `create()` and the other operations need, so a CLI can
+    /// check `is_elevated()` and `elevate()` before it starts
+    fn required_privileges(&self) -> Privileges {
+        Privileges::Administrator
+    }
+
+    fn exists(&self) -> Result<bool> {
+        Ok(self.status()? != ServiceStatus::NotInstalled)
+    }
+
+    /// Installs the service, or updates it when it already exists, so it
+    /// can be called on every deployment.
+    fn create_or_update(&self) -> Result<()> {
+        if self.exists()? {
+            self.update()
+        } else {
+            self.create()
+        }
+    }
+
+    /// Moves the installed service to `new_name`, as service managers
+    /// can't rename one in place: the service is recreated from its
+    /// installed configuration, running again if it was, and the old one
+    /// is deleted. Log files keep their paths.
+    fn rename(&self, new_name: &str) -> Result<Self>
+        where Self: std::marker::Sized {
+        let mut config = self.config()?;
+        if config.display_name.as_ref() == Some(&config.name) {
+            config.display_name = None;
+        }
+        config.name = new_name.to_string();
+        let renamed = Self::from_config(config)?;
+        rename::move_service(self, &renamed, None)?;
+        Ok(renamed)
+    }
+
+    /// Fails with `ChecksumMismatch` if the target on disk was replaced or
+    /// changed since the service was created or last updated
+    fn verify(&self) -> Result<()> {
+        let path = self.config()?.path;
+        let recorded = self.checksum()?.ok_or_else(|| {
+            Error::new(ErrorKind::Other, "No checksum was recorded for the target".to_string())
+                .content(path.display().to_string())
+        })?;
+        if digest::sha256_file(&path)? != recorded {
+            return Err(Error::new(ErrorKind::ChecksumMismatch,
+                                  "Target changed since the service was installed".to_string())
+                .content(path.display().to_string()));
+        }
+        Ok(())
+    }
+
+    fn restart(&self) -> Result<()> {
+        self.stop()?;
+
+        let timeout = std::time::Duration::from_secs(30);
+        let begin = std::time::Instant::now();
+        while !matches!(self.status()?, ServiceStatus::Stopped | ServiceStatus::Failed) {
+            if begin.elapsed() > timeout {
+                return Err(Error::new(ErrorKind::Timeout,
+                                      "Service did not stop in time".to_string()));
+            }
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+
+        self.start()
+    }
 }
 
 #[cfg(target_os = "windows")]
-pub fn build(name: &str, path: &str, args: Vec<String>) -> Result<windows::sombra_imp::SombraWindows> {
-    windows::sombra_imp::SombraWindows::build(name, path, args)
+pub fn build<P, I, S>(name: &str, path: P, args: I) -> Result<SombraWindows>
+    where P: AsRef<std::path::Path>, I: IntoIterator<Item = S>, S: Into<std::ffi::OsString> {
+    SombraBuilder::new(name, path).args(args).build()
 }
 
 #[cfg(target_os = "linux")]
-pub fn build(name: &str, path: &str, args: Vec<String>) -> Result<linux::sombra_imp::SombraLinux> {
-    linux::sombra_imp::SombraLinux::build(name, path, args)
-}
\ No newline at end of file
+pub fn build<P, I, S>(name: &str, path: P, args: I) -> Result<SombraLinux>
+    where P: AsRef<std::path::Path>, I: IntoIterator<Item = S>, S: Into<std::ffi::OsString> {
+    SombraBuilder::new(name, path).args(args).build()
+}
+
+#[cfg(target_os = "macos")]
+pub fn build<P, I, S>(name: &str, path: P, args: I) -> Result<SombraMacos>
+    where P: AsRef<std::path::Path>, I: IntoIterator<Item = S>, S: Into<std::ffi::OsString> {
+    SombraBuilder::new(name, path).args(args).build()
+}
+
+#[cfg(target_os = "freebsd")]
+pub fn build<P, I, S>(name: &str, path: P, args: I) -> Result<SombraFreebsd>
+    where P: AsRef<std::path::Path>, I: IntoIterator<Item = S>, S: Into<std::ffi::OsString> {
+    SombraBuilder::new(name, path).args(args).build()
+}
+
+#[cfg(any(target_os = "illumos", target_os = "solaris"))]
+pub fn build<P, I, S>(name: &str, path: P, args: I) -> Result<SombraSmf>
+    where P: AsRef<std::path::Path>, I: IntoIterator<Item = S>, S: Into<std::ffi::OsString> {
+    SombraBuilder::new(name, path).args(args).build()
+}
+
+#[cfg(target_os = "windows")]
+pub fn install_self<I, S>(name: &str, args: I) -> Result<SombraWindows>
+    where I: IntoIterator<Item = S>, S: Into<std::ffi::OsString> {
+    SombraWindows::install_self(name, args)
+}
+
+#[cfg(target_os = "linux")]
+pub fn install_self<I, S>(name: &str, args: I) -> Result<SombraLinux>
+    where I: IntoIterator<Item = S>, S: Into<std::ffi::OsString> {
+    SombraLinux::install_self(name, args)
+}
+
+#[cfg(target_os = "macos")]
+pub fn install_self<I, S>(name: &str, args: I) -> Result<SombraMacos>
+    where I: IntoIterator<Item = S>, S: Into<std::ffi::OsString> {
+    SombraMacos::install_self(name, args)
+}
+
+#[cfg(target_os = "freebsd")]
+pub fn install_self<I, S>(name: &str, args: I) -> Result<SombraFreebsd>
+    where I: IntoIterator<Item = S>, S: Into<std::ffi::OsString> {
+    SombraFreebsd::install_self(name, args)
+}
+
+#[cfg(any(target_os = "illumos", target_os = "solaris"))]
+pub fn install_self<I, S>(name: &str, args: I) -> Result<SombraSmf>
+    where I: IntoIterator<Item = S>, S: Into<std::ffi::OsString> {
+    SombraSmf::install_self(name, args)
+}
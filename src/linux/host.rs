@@ -0,0 +1,201 @@
+use crate::SombraConfig;
+use crate::unix::shell::quote;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+use std::sync::OnceLock;
+
+/// Machine the files and commands of a systemd service go to: this one,
+/// or one reached with ssh(1) for `SombraBuilder::ssh_host`. Units are
+/// rendered here either way.
+pub(crate) struct Host {
+    /// Destination of ssh, `user@host`
+    ssh: Option<String>,
+    /// Whether the remote user isn't root, so commands run under `sudo -n`
+    sudo: OnceLock<bool>,
+}
+
+impl Host {
+    pub(crate) fn new(config: &SombraConfig) -> Self {
+        Host {
+            ssh: config.ssh_host().map(str::to_string),
+            sudo: OnceLock::new(),
+        }
+    }
+
+    pub(crate) fn is_remote(&self) -> bool {
+        self.ssh.is_some()
+    }
+
+    /// ssh running `line` on the host. Keys come from the agent or the ssh
+    /// config, as nothing can answer a password prompt.
+    fn ssh(destination: &str, line: &str) -> Command {
+        let mut ssh = Command::new("ssh");
+        ssh.args(["-o", "BatchMode=yes", "--"])
+            .arg(destination)
+            .arg(line)
+            .stdin(Stdio::null());
+        ssh
+    }
+
+    fn needs_sudo(&self, destination: &str) -> bool {
+        *self.sudo.get_or_init(|| {
+            Host::ssh(destination, "id -u").output()
+                .map(|output| String::from_utf8_lossy(&output.stdout).trim() != "0")
+                .unwrap_or(true)
+        })
+    }
+
+    /// `command` as the host runs it: as is here, or quoted for the remote
+    /// shell behind ssh
+    pub(crate) fn command(&self, command: Command) -> Command {
+        let destination = match &self.ssh {
+            Some(destination) => destination,
+            None => return command,
+        };
+        let mut line: Vec<String> = std::iter::once(command.get_program())
+            .chain(command.get_args())
+            .map(quote)
+            .collect();
+        if self.needs_sudo(destination) {
+            line.insert(0, "sudo -n".to_string());
+        }
+        Host::ssh(destination, &line.join(" "))
+    }
+
+    pub(crate) fn output(&self, command: &mut Command) -> std::io::Result<Output> {
+        if !self.is_remote() {
+            return command.output();
+        }
+        let program = std::mem::replace(command, Command::new(command.get_program()));
+        self.command(program).output()
+    }
+
+    /// Runs a command on the host, feeding it `input`, and fails with its
+    /// standard error unless it succeeds
+    fn run(&self, command: Command, input: Option<&[u8]>) -> crate::Result<String> {
+        let mut command = self.command(command);
+        if input.is_some() {
+            command.stdin(Stdio::piped());
+        }
+        let mut child = command.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+        if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+            stdin.write_all(input)?;
+        }
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            return Err(crate::Error::new(crate::ErrorKind::Other,
+                                         String::from_utf8_lossy(&output.stderr).trim()
+                                             .to_string())
+                .content(format!("{:?}", command)));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+
+    fn test(&self, flag: &str, path: &Path) -> bool {
+        let mut test = Command::new("test");
+        test.arg(flag).arg(path);
+        self.run(test, None).is_ok()
+    }
+
+    pub(crate) fn exists(&self, path: &Path) -> bool {
+        match self.ssh {
+            Some(_) => self.test("-e", path),
+            None => path.exists(),
+        }
+    }
+
+    pub(crate) fn is_file(&self, path: &Path) -> bool {
+        match self.ssh {
+            Some(_) => self.test("-f", path),
+            None => path.is_file(),
+        }
+    }
+
+    pub(crate) fn read_to_string(&self, path: &Path) -> crate::Result<String> {
+        if !self.is_remote() {
+            return Ok(std::fs::read_to_string(path)?);
+        }
+        let mut cat = Command::new("cat");
+        cat.arg(path);
+        self.run(cat, None)
+    }
+
+    /// Writes the file, creating its directory
+    pub(crate) fn write(&self, path: &Path, content: &str) -> crate::Result<()> {
+        if !self.is_remote() {
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            return Ok(std::fs::write(path, content)?);
+        }
+        let mut sh = Command::new("sh");
+        sh.args(["-c", "mkdir -p \"$(dirname \"$1\")\" && cat > \"$1\"", "sh"]).arg(path);
+        self.run(sh, Some(content.as_bytes()))?;
+        Ok(())
+    }
+
+    pub(crate) fn remove_file(&self, path: &Path) -> crate::Result<()> {
+        if !self.is_remote() {
+            return Ok(std::fs::remove_file(path)?);
+        }
+        let mut rm = Command::new("rm");
+        rm.arg(path);
+        self.run(rm, None)?;
+        Ok(())
+    }
+
+    /// Whether the commands run as root on the host, the remote user
+    /// being root or allowed to sudo without a password
+    pub(crate) fn is_root(&self) -> bool {
+        let destination = match &self.ssh {
+            Some(destination) => destination,
+            None => return unsafe { libc::geteuid() } == 0,
+        };
+        !self.needs_sudo(destination) || self.run(Command::new("true"), None).is_ok()
+    }
+
+    /// `SombraConfig::check_target`, which only checks whether the target
+    /// is in place on another host
+    pub(crate) fn check_target(&self, config: &SombraConfig) -> crate::Result<()> {
+        if !self.is_remote() {
+            return config.check_target();
+        }
+        if self.target_pending(config) {
+            return Err(crate::Error::new(crate::ErrorKind::Io,
+                                         "Target executable is not in place yet".to_string())
+                .content(config.path.display().to_string()));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn target_pending(&self, config: &SombraConfig) -> bool {
+        match self.ssh {
+            Some(_) => config.deferred_path && !self.is_file(&config.path),
+            None => config.target_pending(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn remote(sudo: bool) -> Host {
+        Host { ssh: Some("deploy@web1".to_string()), sudo: OnceLock::from(sudo) }
+    }
+
+    #[test]
+    fn remote_commands_are_quoted() {
+        let mut systemctl = Command::new("systemctl");
+        systemctl.arg("start").arg("it's.service");
+        let ssh = remote(true).command(systemctl);
+        assert_eq!(ssh.get_program(), "ssh");
+        assert_eq!(ssh.get_args().collect::<Vec<_>>(),
+                   vec!["-o", "BatchMode=yes", "--", "deploy@web1",
+                        "sudo -n 'systemctl' 'start' 'it'\\''s.service'"]);
+
+        let ssh = remote(false).command(Command::new("true"));
+        assert_eq!(ssh.get_args().last().unwrap(), "'true'");
+    }
+}
@@ -1,2 +1,8 @@
 pub mod sombra_imp;
+pub mod openrc;
+pub mod runit;
+pub mod s6;
+pub mod sysv;
 mod systemctl;
+mod host;
+pub(crate) mod procfs;
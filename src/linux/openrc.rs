@@ -0,0 +1,544 @@
+use crate::{Account, Artifact, Difference, FailureActions, LogReader, ManagedService, Priority,
+            RestartPolicy, Scripts, Sombra, ServiceStats, ServiceStatus, SombraConfig, StartType,
+            StopSignal, Usage};
+use crate::export::Shell;
+use crate::support;
+use crate::linux::procfs;
+use crate::unix::shell::{quote, word, words};
+use std::ffi::{OsStr, OsString};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Comment marking the init scripts created by sombra
+const MANAGED_MARKER: &str = "# Managed by sombra";
+/// Comment holding the SHA-256 of the target at install
+const CHECKSUM_COMMENT: &str = "# Target sha256 ";
+
+/// Stop timeout of the `retry` schedule when the config sets none
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Runs the target from an OpenRC init script, for Alpine, Gentoo and the
+/// other hosts without systemd. The target is supervised by
+/// `supervise-daemon` when the restart policy or failure actions ask for
+/// restarts, and backgrounded by `start-stop-daemon` otherwise.
+/// supervise-daemon waits the same delay before each restart, so a
+/// restart policy keeps its initial delay.
+pub struct SombraOpenrc {
+    config: SombraConfig,
+}
+
+impl SombraOpenrc {
+    fn script_path(name: &str) -> PathBuf {
+        PathBuf::from(format!("/etc/init.d/{}", name))
+    }
+
+    /// Fails on the settings OpenRC can't apply
+    fn check(config: &SombraConfig) -> crate::Result<()> {
+        let unsupported = support::unsupported(config, &[]);
+        match unsupported {
+            Some(description) => Err(crate::Error::new(crate::ErrorKind::Other,
+                                                       description.to_string())
+                .content(config.name.clone())),
+            None => Ok(()),
+        }
+    }
+
+    fn is_root() -> crate::Result<()> {
+        if unsafe { libc::geteuid() } != 0 {
+            return Err(crate::Error::new(crate::ErrorKind::AccessDenied,
+                                         "Without root privileges.".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Whether the restarts of the target need supervise-daemon
+    fn supervised(config: &SombraConfig) -> bool {
+        config.restart_policy.is_some() || config.failure_actions.as_ref()
+            .and_then(|f| f.restart_delay())
+            .is_some()
+    }
+
+    /// Value of a variable the service functions pass through `eval`,
+    /// quoted once for the assignment and once for `eval`
+    fn eval_value<S: AsRef<OsStr>>(words: &[S]) -> String {
+        let line: Vec<String> = words.iter().map(|w| word(w.as_ref())).collect();
+        quote(line.join(" ").as_ref())
+    }
+
+    fn script_content(config: &SombraConfig) -> crate::Result<String> {
+        let mut lines = vec!["#!/sbin/openrc-run".to_string(), MANAGED_MARKER.to_string()];
+        if let Ok(checksum) = crate::digest::sha256_file(&config.path) {
+            lines.push(format!("{}{}", CHECKSUM_COMMENT, checksum));
+        }
+        let mut assign = |key: &str, value: String| lines.push(format!("{}={}", key, value));
+        if let Some(display_name) = &config.display_name {
+            assign("name", quote(display_name.as_ref()));
+        }
+        if let Some(description) = &config.description {
+            assign("description", quote(description.as_ref()));
+        }
+        assign("command", SombraOpenrc::eval_value(&[&config.path]));
+        if !config.args.is_empty() {
+            assign("command_args", SombraOpenrc::eval_value(&config.args));
+        }
+        assign("command_user", SombraOpenrc::eval_value(
+            &[config.account.unix_name(&config.name)?]));
+        if let Some(dir) = &config.working_directory {
+            assign("directory", SombraOpenrc::eval_value(&[dir]));
+        }
+        if let Some(log) = &config.stdout_log {
+            assign("output_log", SombraOpenrc::eval_value(&[log]));
+        }
+        if let Some(log) = &config.stderr_log {
+            assign("error_log", SombraOpenrc::eval_value(&[log]));
+        }
+        // The pid of supervise-daemon itself, or of the backgrounded target
+        assign("pidfile", SombraOpenrc::eval_value(&[format!("/run/{}.pid", config.name)]));
+
+        if let Some(policy) = &config.restart_policy {
+            assign("supervisor", "supervise-daemon".to_string());
+            assign("respawn_delay", policy.initial_delay.as_secs().to_string());
+            assign("respawn_max", policy.max_restarts.to_string());
+            assign("respawn_period", policy.window.as_secs().to_string());
+        } else if let Some(delay) = config.failure_actions.as_ref()
+            .and_then(|f| f.restart_delay()) {
+            assign("supervisor", "supervise-daemon".to_string());
+            assign("respawn_delay", delay.as_secs().to_string());
+            // Unlimited
+            assign("respawn_max", "0".to_string());
+        } else {
+            assign("command_background", "true".to_string());
+        }
+
+        if config.stop_signal != StopSignal::Terminate || config.stop_timeout.is_some() {
+            assign("retry", format!("{}/{}/SIGKILL/5", config.stop_signal.unix_name(),
+                                    config.stop_timeout.unwrap_or(STOP_TIMEOUT).as_secs()));
+        }
+        if config.kill_process_tree {
+            // Kills what is left in the cgroup of the service on stop
+            assign("rc_cgroup_cleanup", "yes".to_string());
+        }
+
+        for (key, value) in &config.env {
+            lines.push(format!("export {}", quote(format!("{}={}", key, value).as_ref())));
+        }
+        // start-stop-daemon and supervise-daemon read it from the environment
+        if let Some(priority) = config.priority {
+            lines.push(format!("export SSD_NICELEVEL={}", priority.nice()));
+        }
+
+        if !config.dependencies.is_empty() {
+            lines.push(String::new());
+            lines.push("depend() {".to_string());
+            lines.push(format!("\tneed {}", config.dependencies.join(" ")));
+            lines.push("}".to_string());
+        }
+        lines.push(String::new());
+        Ok(lines.join("\n"))
+    }
+
+    /// Inverse of `script_content`. `enabled` is whether the script is in
+    /// the default runlevel.
+    fn config_from_script(name: &str, script: &str, enabled: bool) -> SombraConfig {
+        let mut config = SombraConfig::new(name, "");
+        config.start_type = if enabled { StartType::AutoStart } else { StartType::OnDemand };
+        config.kill_process_tree = false;
+        let mut respawn_delay = None;
+        let mut respawn_max = 0;
+        let mut respawn_period = None;
+        for line in script.lines() {
+            if let Some(need) = line.trim().strip_prefix("need ") {
+                config.dependencies = need.split_whitespace().map(str::to_string).collect();
+                continue;
+            }
+            if let Some(export) = line.strip_prefix("export ") {
+                let export = words(export).concat();
+                match export.split_once('=') {
+                    Some(("SSD_NICELEVEL", nice)) => config.priority = nice.parse().ok()
+                        .and_then(Priority::from_nice),
+                    Some((key, value)) => config.env.push((key.to_string(), value.to_string())),
+                    None => {},
+                }
+                continue;
+            }
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) if !key.contains(' ') => (key, words(value).concat()),
+                _ => continue,
+            };
+            let evaluated = words(&value);
+            let first = || evaluated.first().cloned().unwrap_or_default();
+            let secs = value.parse().ok().map(Duration::from_secs);
+            match key {
+                "name" => config.display_name = Some(value),
+                "description" => config.description = Some(value),
+                "command" => config.path = PathBuf::from(first()),
+                "command_args" => config.args = evaluated.iter().map(OsString::from).collect(),
+                "command_user" => config.account = Account::from_unix_name(&first()),
+                "directory" => config.working_directory = Some(PathBuf::from(first())),
+                "output_log" => config.stdout_log = Some(PathBuf::from(first())),
+                "error_log" => config.stderr_log = Some(PathBuf::from(first())),
+                "respawn_delay" => respawn_delay = secs,
+                "respawn_max" => respawn_max = value.parse().unwrap_or(0),
+                "respawn_period" => respawn_period = secs,
+                "retry" => {
+                    let mut schedule = value.split('/');
+                    if schedule.next() == Some(StopSignal::Interrupt.unix_name()) {
+                        config.stop_signal = StopSignal::Interrupt;
+                    }
+                    config.stop_timeout = schedule.next()
+                        .and_then(|timeout| timeout.parse().ok())
+                        .map(Duration::from_secs);
+                },
+                "rc_cgroup_cleanup" => config.kill_process_tree = value == "yes",
+                _ => {},
+            }
+        }
+        match (respawn_delay, respawn_period) {
+            (Some(delay), Some(window)) => config.restart_policy = Some(RestartPolicy {
+                max_restarts: respawn_max,
+                window,
+                initial_delay: delay,
+                max_delay: delay,
+            }),
+            (Some(delay), None) => config.failure_actions = Some(FailureActions::restart(delay)),
+            _ => {},
+        }
+        config
+    }
+
+    /// Maps the exit code of `rc-service <name> status`
+    fn status_from_code(code: Option<i32>) -> ServiceStatus {
+        match code {
+            Some(0) => ServiceStatus::Running,
+            Some(4) => ServiceStatus::StopPending,
+            Some(8) => ServiceStatus::StartPending,
+            Some(32) => ServiceStatus::Failed,
+            _ => ServiceStatus::Stopped,
+        }
+    }
+
+    fn rc_service(&self, action: &str) -> crate::Result<std::process::Output> {
+        Ok(std::process::Command::new("rc-service")
+            .arg(&self.config.name)
+            .arg(action)
+            .output()?)
+    }
+
+    fn run(command: &mut std::process::Command) -> crate::Result<()> {
+        let output = command.output()?;
+        if !output.status.success() {
+            return Err(crate::Error::new(crate::ErrorKind::Other,
+                                         String::from_utf8_lossy(&output.stderr).trim()
+                                             .to_string())
+                .content(format!("{:?}", command)));
+        }
+        Ok(())
+    }
+
+    fn is_enabled(name: &str) -> bool {
+        PathBuf::from(format!("/etc/runlevels/default/{}", name)).exists()
+    }
+
+    /// Adds the script to the default runlevel, or takes it out of it
+    fn update_runlevel(&self) -> crate::Result<()> {
+        let enable = matches!(self.config.start_type,
+                              StartType::AutoStart | StartType::DelayedAutoStart);
+        if enable == SombraOpenrc::is_enabled(&self.config.name) {
+            return Ok(());
+        }
+        SombraOpenrc::run(std::process::Command::new("rc-update")
+            .arg(if enable { "add" } else { "del" })
+            .arg(&self.config.name)
+            .arg("default"))
+    }
+
+    fn write_script(&self) -> crate::Result<()> {
+        let path = SombraOpenrc::script_path(&self.config.name);
+        let mut file = std::fs::File::create(&path)?;
+        file.write_all(SombraOpenrc::script_content(&self.config)?.as_bytes())?;
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))?;
+        Ok(())
+    }
+
+    fn is_installed(&self) -> crate::Result<()> {
+        if SombraOpenrc::script_path(&self.config.name).exists() {
+            Ok(())
+        } else {
+            Err(crate::Error::new(crate::ErrorKind::ServiceNotFound,
+                                  format!("Service {} does not exist", self.config.name)))
+        }
+    }
+
+    fn read_script(&self) -> crate::Result<String> {
+        Ok(std::fs::read_to_string(SombraOpenrc::script_path(&self.config.name))?)
+    }
+
+    /// supervise-daemon records the pid of the target among the options
+    /// of the service
+    fn pid_file(&self) -> crate::Result<PathBuf> {
+        if SombraOpenrc::supervised(&self.config()?) {
+            Ok(PathBuf::from(format!("/run/openrc/options/{}/child_pid", self.config.name)))
+        } else {
+            Ok(PathBuf::from(format!("/run/{}.pid", self.config.name)))
+        }
+    }
+}
+
+impl Sombra for SombraOpenrc {
+    fn from_config(config: SombraConfig) -> crate::Result<Self> {
+        Ok(SombraOpenrc { config })
+    }
+
+    fn create(&self) -> crate::Result<()> {
+        SombraOpenrc::check(&self.config)?;
+        SombraOpenrc::is_root()?;
+        if SombraOpenrc::script_path(&self.config.name).exists() {
+            return Err(crate::Error::new(crate::ErrorKind::ServiceAlreadyExists,
+                                         format!("Service {} already exist",
+                                                 self.config.name)));
+        }
+        self.write_script()?;
+        self.update_runlevel()?;
+
+        if self.config.start_type == StartType::Disabled || self.config.target_pending() {
+            return Ok(());
+        }
+        self.start()?;
+        match &self.config.readiness {
+            Some(readiness) => readiness.wait(),
+            None => Ok(()),
+        }
+    }
+
+    fn update(&self) -> crate::Result<()> {
+        SombraOpenrc::check(&self.config)?;
+        SombraOpenrc::is_root()?;
+        self.is_installed()?;
+        // openrc-run reads the script again at the next start
+        self.write_script()?;
+        self.update_runlevel()
+    }
+
+    fn delete(&self) -> crate::Result<()> {
+        self.is_installed()?;
+        let _ = self.rc_service("stop");
+        if SombraOpenrc::is_enabled(&self.config.name) {
+            SombraOpenrc::run(std::process::Command::new("rc-update")
+                .arg("del")
+                .arg(&self.config.name)
+                .arg("default"))?;
+        }
+        std::fs::remove_file(SombraOpenrc::script_path(&self.config.name))?;
+        Ok(())
+    }
+
+    fn start(&self) -> crate::Result<()> {
+        self.config.check_target()?;
+        self.is_installed()?;
+        if self.status()? == ServiceStatus::Running {
+            return Ok(());
+        }
+        let output = self.rc_service("start")?;
+        if !output.status.success() {
+            return Err(crate::Error::new(crate::ErrorKind::Other,
+                                         String::from_utf8_lossy(&output.stderr).trim()
+                                             .to_string())
+                .content(self.config.name.clone()));
+        }
+        Ok(())
+    }
+
+    fn stop(&self) -> crate::Result<()> {
+        self.is_installed()?;
+        // Also resets a crashed service, which is still marked started
+        let _ = self.rc_service("stop")?;
+        Ok(())
+    }
+
+    fn status(&self) -> crate::Result<ServiceStatus> {
+        if self.is_installed().is_err() {
+            return Ok(ServiceStatus::NotInstalled);
+        }
+        Ok(SombraOpenrc::status_from_code(self.rc_service("status")?.status.code()))
+    }
+
+    fn pid(&self) -> crate::Result<Option<u32>> {
+        self.is_installed()?;
+        if self.status()? != ServiceStatus::Running {
+            return Ok(None);
+        }
+        let pid = std::fs::read_to_string(self.pid_file()?).ok()
+            .and_then(|pid| pid.trim().parse().ok());
+        Ok(pid.filter(|pid| PathBuf::from(format!("/proc/{}", pid)).exists()))
+    }
+
+    fn usage(&self) -> crate::Result<Option<Usage>> {
+        match self.pid()? {
+            Some(pid) => Ok(Some(procfs::usage(pid)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn stats(&self) -> crate::Result<ServiceStats> {
+        let started_at = match self.pid()? {
+            Some(_) => std::fs::metadata(self.pid_file()?)
+                .and_then(|metadata| metadata.modified())
+                .ok(),
+            None => None,
+        };
+        // Counted by supervise-daemon, from the first start
+        let starts: u32 = std::fs::read_to_string(
+            format!("/run/openrc/options/{}/start_count", self.config.name)).ok()
+            .and_then(|count| count.trim().parse().ok())
+            .unwrap_or(0);
+        Ok(ServiceStats {
+            started_at,
+            restarts: starts.saturating_sub(1),
+            last_exit: None,
+        })
+    }
+
+    fn config(&self) -> crate::Result<SombraConfig> {
+        self.is_installed()?;
+        Ok(SombraOpenrc::config_from_script(&self.config.name, &self.read_script()?,
+                                            SombraOpenrc::is_enabled(&self.config.name)))
+    }
+
+    fn diff(&self) -> crate::Result<Vec<Difference>> {
+        // The script keeps only what it renders, so compare that part
+        let script = SombraOpenrc::script_content(&self.config)?;
+        let enabled = matches!(self.config.start_type,
+                               StartType::AutoStart | StartType::DelayedAutoStart);
+        let desired = SombraOpenrc::config_from_script(&self.config.name, &script, enabled);
+        Ok(desired.diff(&self.config()?))
+    }
+
+    fn checksum(&self) -> crate::Result<Option<String>> {
+        self.is_installed()?;
+        Ok(self.read_script()?.lines()
+            .find_map(|line| line.strip_prefix(CHECKSUM_COMMENT))
+            .map(|checksum| checksum.to_string()))
+    }
+
+    fn list_managed() -> crate::Result<Vec<ManagedService>> {
+        let mut services = vec![];
+        for entry in std::fs::read_dir("/etc/init.d")? {
+            let path = entry?.path();
+            let name = match path.file_name().and_then(|n| n.to_str()) {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            let script = std::fs::read_to_string(&path).unwrap_or_default();
+            if script.lines().nth(1) != Some(MANAGED_MARKER) {
+                continue;
+            }
+            let config = SombraOpenrc::config_from_script(&name, &script,
+                                                          SombraOpenrc::is_enabled(&name));
+            let path = config.path.clone();
+            let status = SombraOpenrc::from_config(config)?.status()?;
+            services.push(ManagedService { name, status, path });
+        }
+        Ok(services)
+    }
+
+    fn logs(&self, follow: bool) -> crate::Result<LogReader> {
+        LogReader::files(self.config.log_files(), follow)
+    }
+
+    fn render(&self) -> crate::Result<Vec<Artifact>> {
+        SombraOpenrc::check(&self.config)?;
+        Ok(vec![Artifact::script(SombraOpenrc::script_path(&self.config.name),
+                                 SombraOpenrc::script_content(&self.config)?)])
+    }
+
+    fn export(&self) -> crate::Result<Scripts> {
+        let name = &self.config.name;
+        let mut shell = Shell::new(name);
+        shell.write(&self.render()?);
+        if matches!(self.config.start_type, StartType::AutoStart | StartType::DelayedAutoStart) {
+            shell.install(Shell::line(&[&"rc-update", &"add", name, &"default"]));
+        }
+        if self.config.start_type != StartType::Disabled {
+            shell.install(Shell::line(&[&"rc-service", name, &"start"]));
+        }
+        shell.uninstall(Shell::line(&[&"rc-service", name, &"stop"]));
+        shell.uninstall(Shell::line(&[&"rc-update", &"del", name, &"default"]));
+        shell.remove(SombraOpenrc::script_path(name));
+        Ok(shell.finish())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn script_quotes_command() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp echo");
+        config.args = vec!["-p".into(), "it's".into()];
+        config.account = Account::User { name: "sombra".to_string(), password: None };
+        config.dependencies = vec!["net".to_string()];
+        let content = SombraOpenrc::script_content(&config).unwrap();
+        assert!(content.starts_with("#!/sbin/openrc-run\n# Managed by sombra\n"));
+        assert!(content.contains("\ncommand=''\\''/opt/tcp echo'\\'''\n"));
+        assert!(content.contains("\ncommand_user='sombra'\n"));
+        assert!(content.contains("\ncommand_background=true\n"));
+        assert!(content.ends_with("\ndepend() {\n\tneed net\n}\n"));
+        let read = SombraOpenrc::config_from_script("tcp_echo", &content, false);
+        assert_eq!(read.path, config.path);
+        assert_eq!(read.args, config.args);
+    }
+
+    #[test]
+    fn check_refuses_triggers() {
+        let config = crate::SombraBuilder::new("tcp_echo", "/bin/sh")
+            .trigger(crate::Trigger::Device("/dev/ttyUSB0".to_string()))
+            .config().unwrap();
+        assert_eq!(SombraOpenrc::check(&config),
+                   Err(crate::Error::new(crate::ErrorKind::Other,
+                                         "Start triggers are only supported by systemd and \
+                                          Windows services".to_string())
+                       .content("tcp_echo".to_string())));
+    }
+
+    #[test]
+    fn config_from_script_inverts_script() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp_echo");
+        config.args = vec!["-p".into(), "8080".into(), "$HOME".into()];
+        config.description = Some("Echo server".to_string());
+        config.start_type = StartType::AutoStart;
+        config.env = vec![("MODE".to_string(), "a b".to_string())];
+        config.working_directory = Some(PathBuf::from("/opt"));
+        config.stdout_log = Some(PathBuf::from("/var/log/tcp_echo.log"));
+        config.stop_signal = StopSignal::Interrupt;
+        config.stop_timeout = Some(Duration::from_secs(3));
+        config.priority = Some(Priority::BelowNormal);
+        config.dependencies = vec!["net".to_string(), "localmount".to_string()];
+        config.restart_policy = Some(RestartPolicy {
+            max_restarts: 3,
+            window: Duration::from_secs(60),
+            initial_delay: Duration::from_secs(2),
+            max_delay: Duration::from_secs(2),
+        });
+        let script = SombraOpenrc::script_content(&config).unwrap();
+        assert!(script.contains("\nsupervisor=supervise-daemon\n"));
+        assert!(script.contains("\nretry=SIGINT/3/SIGKILL/5\n"));
+        let read = SombraOpenrc::config_from_script("tcp_echo", &script, true);
+        assert_eq!(config.diff(&read), vec![]);
+
+        config.restart_policy = None;
+        config.failure_actions = Some(FailureActions::restart(Duration::from_secs(5)));
+        let script = SombraOpenrc::script_content(&config).unwrap();
+        let read = SombraOpenrc::config_from_script("tcp_echo", &script, true);
+        assert_eq!(config.diff(&read), vec![]);
+    }
+
+    #[test]
+    fn status_follows_exit_code() {
+        assert_eq!(SombraOpenrc::status_from_code(Some(0)), ServiceStatus::Running);
+        assert_eq!(SombraOpenrc::status_from_code(Some(3)), ServiceStatus::Stopped);
+        assert_eq!(SombraOpenrc::status_from_code(Some(32)), ServiceStatus::Failed);
+    }
+}
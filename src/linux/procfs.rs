@@ -0,0 +1,56 @@
+use crate::Usage;
+use std::time::Duration;
+
+/// Clock ticks per second of the times in /proc, fixed by the kernel ABI
+const USER_HZ: u64 = 100;
+
+pub fn usage(pid: u32) -> crate::Result<Usage> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid))?;
+    // The command name may contain spaces, the fields start after it
+    let fields: Vec<&str> = match stat.rfind(')') {
+        Some(index) => stat[index + 1..].split_whitespace().collect(),
+        None => vec![],
+    };
+    let field = |index: usize| -> crate::Result<u64> {
+        fields.get(index)
+            .and_then(|f| f.parse().ok())
+            .ok_or_else(|| crate::Error::new(crate::ErrorKind::Io,
+                                             "Cannot parse process stat".to_string())
+                .content(format!("/proc/{}/stat", pid)))
+    };
+    // utime and stime are fields 14 and 15, counting the pid as 1
+    let ticks = field(11)? + field(12)?;
+
+    // VmRSS:	    1234 kB
+    let status = std::fs::read_to_string(format!("/proc/{}/status", pid))?;
+    let rss = status.lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))
+        .and_then(|value| value.split_whitespace().next())
+        .and_then(|kb| kb.parse::<u64>().ok())
+        .unwrap_or(0) * 1024;
+
+    let handles = std::fs::read_dir(format!("/proc/{}/fd", pid))?.count() as u32;
+
+    Ok(Usage {
+        pid,
+        rss,
+        cpu_time: Duration::from_millis(ticks * 1000 / USER_HZ),
+        handles,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn usage_of_own_process() {
+        let usage = match usage(std::process::id()) {
+            Ok(usage) => usage,
+            Err(e) => panic!("{}", e),
+        };
+        assert_eq!(usage.pid, std::process::id());
+        assert!(usage.rss > 0);
+        assert!(usage.handles > 0);
+    }
+}
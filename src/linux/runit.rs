@@ -0,0 +1,614 @@
+use crate::{Account, Artifact, Difference, ExitReason, LogReader, ManagedService, Scripts, Sombra,
+            ServiceStats, ServiceStatus, SombraConfig, StartType, StopSignal, Usage};
+use crate::export::Shell;
+use crate::support;
+use crate::linux::procfs;
+use crate::unix::shell::{quote, word};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+/// Directory of the service definitions, linked into the scanned directory
+const SV_DIR: &str = "/etc/sv";
+
+/// Runs the target from a runit service directory in /etc/sv, linked into
+/// the directory runsvdir scans. Without log files, the output goes to a
+/// log service writing /var/log/<name>/current with svlogd. runsv
+/// restarts the target on every exit, so the finish script takes the
+/// service down unless the restart policy or failure actions ask for a
+/// restart. `SombraLinux` uses this backend when runsvdir runs instead of
+/// systemd.
+pub struct SombraRunit {
+    config: SombraConfig,
+    /// The directory runsvdir scans
+    scan_dir: PathBuf,
+}
+
+impl SombraRunit {
+    /// Directory runsvdir scans, when runit supervises the services
+    /// rather than systemd
+    pub(crate) fn detect() -> Option<PathBuf> {
+        use std::os::unix::ffi::OsStrExt;
+        if Path::new("/run/systemd/system").exists() {
+            return None;
+        }
+        for entry in std::fs::read_dir("/proc").ok()?.flatten() {
+            let path = entry.path();
+            let runsvdir = std::fs::read_to_string(path.join("comm"))
+                .map(|comm| comm.trim() == "runsvdir")
+                .unwrap_or(false);
+            if !runsvdir {
+                continue;
+            }
+            // runsvdir [-P] dir [log]
+            let cmdline = std::fs::read(path.join("cmdline")).ok()?;
+            return cmdline.split(|&byte| byte == 0)
+                .skip(1)
+                .find(|arg| !arg.is_empty() && !arg.starts_with(b"-"))
+                .map(|dir| PathBuf::from(std::ffi::OsStr::from_bytes(dir)));
+        }
+        None
+    }
+
+    /// The scanned directory of Void and of Debian, for a runsvdir that
+    /// isn't running yet
+    fn default_scan_dir() -> PathBuf {
+        ["/var/service", "/etc/service"].iter()
+            .map(PathBuf::from)
+            .find(|dir| dir.is_dir())
+            .unwrap_or_else(|| PathBuf::from("/etc/service"))
+    }
+
+    fn service_dir(name: &str) -> PathBuf {
+        Path::new(SV_DIR).join(name)
+    }
+
+    fn dir(&self) -> PathBuf {
+        SombraRunit::service_dir(&self.config.name)
+    }
+
+    fn link(&self) -> PathBuf {
+        self.scan_dir.join(&self.config.name)
+    }
+
+    /// State file kept by runsv, or by the run and finish scripts
+    fn supervise(&self, file: &str) -> PathBuf {
+        self.dir().join("supervise").join(file)
+    }
+
+    fn log_dir(name: &str) -> PathBuf {
+        PathBuf::from(format!("/var/log/{}", name))
+    }
+
+    /// Whether some output goes to the log service
+    fn has_log_service(config: &SombraConfig) -> bool {
+        config.stdout_log.is_none() || config.stderr_log.is_none()
+    }
+
+    /// Fails on the settings runit can't apply
+    fn check(config: &SombraConfig) -> crate::Result<()> {
+        let unsupported = support::unsupported(config, &[]);
+        match unsupported {
+            Some(description) => Err(crate::Error::new(crate::ErrorKind::Other,
+                                                       description.to_string())
+                .content(config.name.clone())),
+            None => Ok(()),
+        }
+    }
+
+    fn is_root() -> crate::Result<()> {
+        if unsafe { libc::geteuid() } != 0 {
+            return Err(crate::Error::new(crate::ErrorKind::AccessDenied,
+                                         "Without root privileges.".to_string()));
+        }
+        Ok(())
+    }
+
+    fn run_content(&self, config: &SombraConfig) -> crate::Result<String> {
+        let mut script = vec!["#!/bin/sh".to_string(), crate::settings::HEADER.to_string()];
+        if config.stderr_log.is_none() {
+            script.push("exec 2>&1".to_string());
+        }
+        // For the window of the restart policy, from the service directory
+        script.push("date +%s > supervise/started".to_string());
+        for (key, value) in &config.env {
+            script.push(format!("export {}", quote(format!("{}={}", key, value).as_ref())));
+        }
+        if let Some(dir) = &config.working_directory {
+            script.push(format!("cd {} || exit 1", word(dir.as_os_str())));
+        }
+        for dependency in &config.dependencies {
+            script.push(format!("sv start {} >/dev/null || exit 1",
+                                word(self.scan_dir.join(dependency).as_os_str())));
+        }
+
+        let mut command = vec!["exec chpst".to_string(), "-u".to_string(),
+                               word(config.account.unix_name(&config.name)?.as_ref())];
+        if let Some(priority) = config.priority {
+            command.push(format!("-n {}", priority.nice()));
+        }
+        if config.kill_process_tree {
+            // A group of its own, for control/t to signal
+            command.push("-P".to_string());
+        }
+        command.push(word(config.path.as_os_str()));
+        command.extend(config.args.iter().map(|arg| word(arg)));
+        command.push("</dev/null".to_string());
+        if let Some(log) = &config.stdout_log {
+            command.push(format!(">>{}", word(log.as_os_str())));
+        }
+        if let Some(log) = &config.stderr_log {
+            command.push(format!("2>>{}", word(log.as_os_str())));
+        }
+        script.push(command.join(" "));
+        script.push(String::new());
+        Ok(script.join("\n"))
+    }
+
+    /// Runs after each exit of the target, with its exit code or -1 and
+    /// the signal that killed it. runsv restarts the target once it
+    /// returns, unless the service is down.
+    fn finish_content(config: &SombraConfig) -> String {
+        let seconds = |delay: Duration| delay.as_millis().div_ceil(1000);
+        let restart_delay = config.failure_actions.as_ref().and_then(|f| f.restart_delay());
+        let (initial_delay, max_delay, limit) = match (&config.restart_policy, restart_delay) {
+            (Some(policy), _) => (Some(seconds(policy.initial_delay)),
+                                  seconds(policy.max_delay),
+                                  Some((policy.max_restarts, seconds(policy.window)))),
+            (None, Some(delay)) => (Some(seconds(delay)), seconds(delay), None),
+            (None, None) => (None, 0, None),
+        };
+
+        let mut script = vec![
+            "#!/bin/sh".to_string(),
+            // As the shell reports it, for stats()
+            "if [ \"$1\" = -1 ]; then code=$((128 + $2)); else code=$1; fi".to_string(),
+            "echo \"$code\" > supervise/exit_code".to_string(),
+            "grep -q 'want down' supervise/stat && exit 0".to_string(),
+            "[ \"$code\" -eq 0 ] && exec sv down .".to_string(),
+        ];
+        match initial_delay {
+            Some(initial_delay) => {
+                script.push("failures=$(cat supervise/failures 2>/dev/null || echo 0)"
+                    .to_string());
+                if let Some((max_restarts, window)) = limit {
+                    // A target that ran for the whole window starts over
+                    script.push(format!("[ $(($(date +%s) - $(cat supervise/started))) -ge {} ] \
+                                         && failures=0", window));
+                    script.push(format!("if [ \"$failures\" -ge {} ]; then \
+                                         touch supervise/failed; exec sv down .; fi",
+                                        max_restarts));
+                }
+                script.push("failures=$((failures + 1))".to_string());
+                script.push("echo \"$failures\" > supervise/failures".to_string());
+                script.push("restarts=$(cat supervise/restarts 2>/dev/null || echo 0)"
+                    .to_string());
+                script.push("echo $((restarts + 1)) > supervise/restarts".to_string());
+                script.push(format!("delay={}", initial_delay));
+                script.push(format!("while [ \"$failures\" -gt 1 ] && [ \"$delay\" -lt {} ]; do \
+                                     delay=$((delay * 2)); failures=$((failures - 1)); done",
+                                    max_delay));
+                script.push(format!("[ \"$delay\" -gt {0} ] && delay={0}", max_delay));
+                script.push("exec sleep \"$delay\"".to_string());
+            },
+            None => {
+                script.push("touch supervise/failed".to_string());
+                script.push("exec sv down .".to_string());
+            },
+        }
+        script.push(String::new());
+        script.join("\n")
+    }
+
+    /// Replaces the SIGTERM runsv sends to the target on `sv down`
+    fn control_content(config: &SombraConfig) -> Option<String> {
+        if config.stop_signal == StopSignal::Terminate && !config.kill_process_tree {
+            return None;
+        }
+        let target = if config.kill_process_tree {
+            "-\"$(cat supervise/pid)\""
+        } else {
+            "\"$(cat supervise/pid)\""
+        };
+        Some(format!("#!/bin/sh\nexec kill -s {} -- {}\n",
+                     config.stop_signal.unix_name().trim_start_matches("SIG"), target))
+    }
+
+    fn log_run_content(name: &str) -> String {
+        let dir = word(SombraRunit::log_dir(name).as_os_str());
+        format!("#!/bin/sh\nmkdir -p {0}\nexec svlogd -tt {0}\n", dir)
+    }
+
+    fn settings_content(config: &SombraConfig) -> crate::Result<String> {
+        let user = config.account.unix_name(&config.name)?;
+        Ok(crate::settings::content(config, &[("user", user)]))
+    }
+
+    fn config_from_settings(name: &str, content: &str) -> (SombraConfig, Option<String>) {
+        let settings = crate::settings::parse(name, content);
+        let mut config = settings.config;
+        if let Some((_, user)) = settings.extra.iter().find(|(key, _)| key == "user") {
+            config.account = Account::from_unix_name(user);
+        }
+        (config, settings.checksum)
+    }
+
+    /// Files of the service directory with their modes, `None` for those
+    /// the configuration leaves out
+    fn files(&self) -> crate::Result<Vec<(PathBuf, Option<String>, u32)>> {
+        let dir = self.dir();
+        let config = &self.config;
+        let log = if SombraRunit::has_log_service(config) {
+            Some(SombraRunit::log_run_content(&config.name))
+        } else {
+            None
+        };
+        // runsv leaves a service with a down file down at boot
+        let down = match config.start_type {
+            StartType::AutoStart | StartType::DelayedAutoStart => None,
+            StartType::OnDemand | StartType::Disabled => Some(String::new()),
+        };
+        Ok(vec![
+            (dir.join("service.conf"), Some(SombraRunit::settings_content(config)?), 0o644),
+            (dir.join("run"), Some(self.run_content(config)?), 0o755),
+            (dir.join("finish"), Some(SombraRunit::finish_content(config)), 0o755),
+            (dir.join("control/t"), SombraRunit::control_content(config), 0o755),
+            (dir.join("log/run"), log, 0o755),
+            (dir.join("down"), down, 0o644),
+        ])
+    }
+
+    /// Writes the scripts and the settings. They are replaced rather than
+    /// rewritten, as a running shell reads its script as it goes.
+    fn write_files(&self) -> crate::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        for (path, content, mode) in self.files()? {
+            let content = match content {
+                Some(content) => content,
+                None => {
+                    if path.exists() {
+                        std::fs::remove_file(&path)?;
+                    }
+                    continue;
+                },
+            };
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)
+                    .map_err(|e| crate::Error::from(e).content(parent.display().to_string()))?;
+            }
+            let staged = path.with_extension("new");
+            std::fs::write(&staged, content)?;
+            std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(mode))?;
+            std::fs::rename(&staged, &path)?;
+        }
+        Ok(())
+    }
+
+    fn sv(&self, args: &[&str]) -> crate::Result<std::process::Output> {
+        Ok(Command::new("sv").args(args).arg(self.dir()).output()?)
+    }
+
+    /// Whether runsv supervises the service directory
+    fn is_supervised(&self) -> bool {
+        self.sv(&["status"]).map(|output| output.status.success()).unwrap_or(false)
+    }
+
+    /// runsvdir picks new links up every five seconds
+    fn wait_supervised(&self, supervised: bool) -> crate::Result<()> {
+        let begin = std::time::Instant::now();
+        while self.is_supervised() != supervised {
+            if begin.elapsed() > Duration::from_secs(10) {
+                let state = if supervised { "supervised" } else { "released" };
+                return Err(crate::Error::new(crate::ErrorKind::Timeout,
+                                             format!("Service wasn't {} by runsvdir", state))
+                    .content(self.config.name.clone()));
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        Ok(())
+    }
+
+    /// Maps supervise/stat: the state, then the flags runsv adds
+    fn status_from_stat(stat: &str) -> ServiceStatus {
+        let want_down = stat.contains("want down");
+        match stat.split(',').next().map(str::trim) {
+            Some("run") | Some("finish") if want_down => ServiceStatus::StopPending,
+            Some("run") => ServiceStatus::Running,
+            // runsv restarts the target once finish returns
+            Some("finish") => ServiceStatus::StartPending,
+            Some("down") if stat.contains("want up") => ServiceStatus::StartPending,
+            _ => ServiceStatus::Stopped,
+        }
+    }
+
+    fn is_installed(&self) -> crate::Result<()> {
+        if self.dir().join("service.conf").exists() {
+            Ok(())
+        } else {
+            Err(crate::Error::new(crate::ErrorKind::ServiceNotFound,
+                                  format!("Service {} does not exist", self.config.name)))
+        }
+    }
+
+    fn installed(&self) -> crate::Result<(SombraConfig, Option<String>)> {
+        self.is_installed()?;
+        let path = self.dir().join("service.conf");
+        let content = std::fs::read_to_string(&path)
+            .map_err(|e| crate::Error::from(e).content(path.display().to_string()))?;
+        Ok(SombraRunit::config_from_settings(&self.config.name, &content))
+    }
+
+    fn read_number(&self, file: &str) -> Option<u32> {
+        std::fs::read_to_string(self.supervise(file)).ok()?.trim().parse().ok()
+    }
+}
+
+impl Sombra for SombraRunit {
+    fn from_config(config: SombraConfig) -> crate::Result<Self> {
+        let scan_dir = SombraRunit::detect().unwrap_or_else(SombraRunit::default_scan_dir);
+        Ok(SombraRunit { config, scan_dir })
+    }
+
+    fn create(&self) -> crate::Result<()> {
+        SombraRunit::check(&self.config)?;
+        SombraRunit::is_root()?;
+        if self.is_installed().is_ok() {
+            return Err(crate::Error::new(crate::ErrorKind::ServiceAlreadyExists,
+                                         format!("Service {} already exist",
+                                                 self.config.name)));
+        }
+        self.write_files()?;
+        std::os::unix::fs::symlink(self.dir(), self.link())?;
+
+        if self.config.start_type == StartType::Disabled || self.config.target_pending() {
+            return Ok(());
+        }
+        self.start()?;
+        match &self.config.readiness {
+            Some(readiness) => readiness.wait(),
+            None => Ok(()),
+        }
+    }
+
+    fn update(&self) -> crate::Result<()> {
+        SombraRunit::check(&self.config)?;
+        SombraRunit::is_root()?;
+        self.is_installed()?;
+        // runsv runs the new scripts at the next start
+        self.write_files()
+    }
+
+    fn delete(&self) -> crate::Result<()> {
+        self.is_installed()?;
+        if self.link().exists() {
+            std::fs::remove_file(self.link())?;
+        }
+        if self.is_supervised() {
+            // runsv stops the target and the log service, then exits
+            self.sv(&["exit"])?;
+            self.wait_supervised(false)?;
+        }
+        std::fs::remove_dir_all(self.dir())?;
+        Ok(())
+    }
+
+    fn start(&self) -> crate::Result<()> {
+        self.config.check_target()?;
+        self.is_installed()?;
+        self.wait_supervised(true)?;
+        if self.status()? == ServiceStatus::Running {
+            return Ok(());
+        }
+        for file in &["failures", "restarts", "exit_code", "failed"] {
+            let _ = std::fs::remove_file(self.supervise(file));
+        }
+        self.sv(&["up"])?;
+        // As on the other init systems, give runsv time to spawn the target
+        std::thread::sleep(Duration::from_millis(100));
+        Ok(())
+    }
+
+    fn stop(&self) -> crate::Result<()> {
+        self.is_installed()?;
+        if !self.is_supervised() {
+            return Ok(());
+        }
+        // Kills the target once the timeout passes
+        let timeout = self.config.stop_timeout.map(|timeout| timeout.as_secs().max(1));
+        match timeout {
+            Some(timeout) => self.sv(&["-w", &timeout.to_string(), "force-stop"])?,
+            None => self.sv(&["force-stop"])?,
+        };
+        Ok(())
+    }
+
+    fn status(&self) -> crate::Result<ServiceStatus> {
+        if self.is_installed().is_err() {
+            return Ok(ServiceStatus::NotInstalled);
+        }
+        let status = match std::fs::read_to_string(self.supervise("stat")) {
+            Ok(stat) => SombraRunit::status_from_stat(&stat),
+            Err(_) => ServiceStatus::Stopped,
+        };
+        if status == ServiceStatus::Stopped && self.supervise("failed").exists() {
+            return Ok(ServiceStatus::Failed);
+        }
+        Ok(status)
+    }
+
+    fn pid(&self) -> crate::Result<Option<u32>> {
+        self.is_installed()?;
+        if self.status()? != ServiceStatus::Running {
+            return Ok(None);
+        }
+        Ok(self.read_number("pid"))
+    }
+
+    fn usage(&self) -> crate::Result<Option<Usage>> {
+        match self.pid()? {
+            Some(pid) => Ok(Some(procfs::usage(pid)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn stats(&self) -> crate::Result<ServiceStats> {
+        let started_at = match self.pid()? {
+            Some(_) => std::fs::metadata(self.supervise("started"))
+                .and_then(|metadata| metadata.modified())
+                .ok(),
+            None => None,
+        };
+        let last_exit = self.read_number("exit_code").map(|code| match code {
+            129..=192 => ExitReason::Signal(code as i32 - 128),
+            code => ExitReason::Code(code as i64),
+        });
+        Ok(ServiceStats {
+            started_at,
+            restarts: self.read_number("restarts").unwrap_or(0),
+            last_exit,
+        })
+    }
+
+    fn config(&self) -> crate::Result<SombraConfig> {
+        Ok(self.installed()?.0)
+    }
+
+    fn diff(&self) -> crate::Result<Vec<Difference>> {
+        // The settings keep only what they render, so compare that part
+        let content = SombraRunit::settings_content(&self.config)?;
+        let desired = SombraRunit::config_from_settings(&self.config.name, &content).0;
+        Ok(desired.diff(&self.config()?))
+    }
+
+    fn checksum(&self) -> crate::Result<Option<String>> {
+        Ok(self.installed()?.1)
+    }
+
+    fn list_managed() -> crate::Result<Vec<ManagedService>> {
+        let mut services = vec![];
+        if !Path::new(SV_DIR).exists() {
+            return Ok(services);
+        }
+        for entry in std::fs::read_dir(SV_DIR)? {
+            let path = entry?.path();
+            let name = match path.file_name().and_then(|n| n.to_str()) {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            let content = std::fs::read_to_string(path.join("service.conf")).unwrap_or_default();
+            if !content.starts_with(crate::settings::HEADER) {
+                continue;
+            }
+            let (config, _) = SombraRunit::config_from_settings(&name, &content);
+            let path = config.path.clone();
+            let status = SombraRunit::from_config(config)?.status()?;
+            services.push(ManagedService { name, status, path });
+        }
+        services.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(services)
+    }
+
+    fn logs(&self, follow: bool) -> crate::Result<LogReader> {
+        let mut files = self.config.log_files();
+        if SombraRunit::has_log_service(&self.config) {
+            files.push(SombraRunit::log_dir(&self.config.name).join("current"));
+        }
+        LogReader::files(files, follow)
+    }
+
+    fn render(&self) -> crate::Result<Vec<Artifact>> {
+        Ok(self.files()?.into_iter()
+            .filter_map(|(path, content, mode)| content.map(|content| match mode {
+                0o755 => Artifact::script(path, content),
+                _ => Artifact::file(path, content),
+            }))
+            .collect())
+    }
+
+    fn export(&self) -> crate::Result<Scripts> {
+        SombraRunit::check(&self.config)?;
+        let dir = self.dir();
+        let mut shell = Shell::new(&self.config.name);
+        shell.write(&self.render()?);
+        shell.install(Shell::line(&[&"ln", &"-s", &dir, &self.link()]));
+        if self.config.start_type != StartType::Disabled {
+            // runsv picks the directory up within five seconds
+            shell.install(format!("until {} >/dev/null 2>&1; do sleep 1; done",
+                                  Shell::line(&[&"sv", &"status", &dir])));
+            shell.install(Shell::line(&[&"sv", &"up", &dir]));
+        }
+        shell.remove(self.link());
+        shell.uninstall(Shell::line(&[&"sv", &"exit", &dir]));
+        shell.remove_dir(dir);
+        Ok(shell.finish())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FailureActions;
+
+    fn runit(config: SombraConfig) -> SombraRunit {
+        SombraRunit { config, scan_dir: PathBuf::from("/var/service") }
+    }
+
+    #[test]
+    fn run_script_execs_target() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp echo");
+        config.args = vec!["-p".into(), "8080".into()];
+        config.account = Account::User { name: "sombra".to_string(), password: None };
+        config.dependencies = vec!["dbus".to_string()];
+        config.stdout_log = Some(PathBuf::from("/var/log/tcp_echo.log"));
+        let service = runit(config);
+        let content = service.run_content(&service.config).unwrap();
+        assert!(content.starts_with("#!/bin/sh\n# Managed by sombra\nexec 2>&1\n\
+                                     date +%s > supervise/started\n"));
+        assert!(content.contains("\nsv start /var/service/dbus >/dev/null || exit 1\n"));
+        assert!(content.ends_with("\nexec chpst -u sombra -P '/opt/tcp echo' -p 8080 \
+                                   </dev/null >>/var/log/tcp_echo.log\n"));
+        assert!(SombraRunit::has_log_service(&service.config));
+        assert_eq!(SombraRunit::control_content(&service.config).unwrap(),
+                   "#!/bin/sh\nexec kill -s TERM -- -\"$(cat supervise/pid)\"\n");
+    }
+
+    #[test]
+    fn finish_script_restarts_on_failure() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp_echo");
+        let content = SombraRunit::finish_content(&config);
+        assert!(content.ends_with("\ntouch supervise/failed\nexec sv down .\n"));
+
+        config.failure_actions = Some(FailureActions::restart(Duration::from_secs(5)));
+        let content = SombraRunit::finish_content(&config);
+        assert!(content.contains("\n[ \"$code\" -eq 0 ] && exec sv down .\n"));
+        assert!(content.contains("\ndelay=5\n"));
+        assert!(content.ends_with("\nexec sleep \"$delay\"\n"));
+        assert!(!content.contains("touch supervise/failed"));
+    }
+
+    #[test]
+    fn stat_mapping() {
+        assert_eq!(SombraRunit::status_from_stat("run\n"), ServiceStatus::Running);
+        assert_eq!(SombraRunit::status_from_stat("run, want down\n"),
+                   ServiceStatus::StopPending);
+        assert_eq!(SombraRunit::status_from_stat("finish\n"), ServiceStatus::StartPending);
+        assert_eq!(SombraRunit::status_from_stat("down, want up\n"),
+                   ServiceStatus::StartPending);
+        assert_eq!(SombraRunit::status_from_stat("down\n"), ServiceStatus::Stopped);
+    }
+
+    #[test]
+    fn render_lists_the_service_directory() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp_echo");
+        config.start_type = StartType::OnDemand;
+        let artifacts = runit(config).render().unwrap();
+        let dir = SombraRunit::service_dir("tcp_echo");
+        let paths: Vec<PathBuf> = artifacts.iter()
+            .map(|artifact| artifact.path.clone().unwrap())
+            .collect();
+        assert_eq!(paths, vec![dir.join("service.conf"), dir.join("run"), dir.join("finish"),
+                               dir.join("control/t"), dir.join("log/run"), dir.join("down")]);
+        assert!(artifacts[1].executable);
+        assert!(!artifacts[5].executable);
+    }
+}
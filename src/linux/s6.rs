@@ -0,0 +1,635 @@
+use crate::{Account, Artifact, Difference, ExitReason, LogReader, ManagedService, Scripts, Sombra,
+            ServiceStats, ServiceStatus, SombraConfig, StartType, StopSignal, Usage};
+use crate::export::Shell;
+use crate::support;
+use crate::linux::procfs;
+use crate::unix::shell::{quote, word};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+/// Source definitions, compiled with the other services of the image
+const SOURCE_DIR: &str = "/etc/s6-rc/source";
+/// Link to the compiled database the next boot starts from
+const COMPILED: &str = "/etc/s6-rc/compiled";
+/// Live state of s6-rc, with a service directory per longrun
+const LIVE_DIR: &str = "/run/s6-rc";
+/// Bundle started at boot
+const DEFAULT_BUNDLE: &str = "default";
+
+/// Runs the target as an s6-rc longrun, for images built around skarnet's
+/// s6. The definition goes to /etc/s6-rc/source, which is compiled into a
+/// new database that `s6-rc-update` switches the live state to; services
+/// started at boot are added to the `default` bundle. Without log files,
+/// the output goes to a `<name>-log` longrun writing
+/// /var/log/<name>/current with s6-log. s6-supervise restarts the target
+/// on every exit, so the finish script exits 125 to leave it down unless
+/// the restart policy or failure actions ask for a restart.
+pub struct SombraS6 {
+    config: SombraConfig,
+}
+
+impl SombraS6 {
+    fn source(name: &str) -> PathBuf {
+        Path::new(SOURCE_DIR).join(name)
+    }
+
+    fn log_name(name: &str) -> String {
+        format!("{}-log", name)
+    }
+
+    fn log_dir(name: &str) -> PathBuf {
+        PathBuf::from(format!("/var/log/{}", name))
+    }
+
+    /// Service directory s6-supervise runs the target from
+    fn live(&self) -> PathBuf {
+        Path::new(LIVE_DIR).join("servicedirs").join(&self.config.name)
+    }
+
+    /// State file kept by the run and finish scripts, in the live service
+    /// directory
+    fn data(&self, file: &str) -> PathBuf {
+        self.live().join("data").join(file)
+    }
+
+    fn settings_path(name: &str) -> PathBuf {
+        SombraS6::source(name).join("data/service.conf")
+    }
+
+    /// Whether some output goes to the log service
+    fn has_log_service(config: &SombraConfig) -> bool {
+        config.stdout_log.is_none() || config.stderr_log.is_none()
+    }
+
+    /// Fails on the settings s6 can't apply
+    fn check(config: &SombraConfig) -> crate::Result<()> {
+        let unsupported = support::unsupported(config, &[]);
+        match unsupported {
+            Some(description) => Err(crate::Error::new(crate::ErrorKind::Other,
+                                                       description.to_string())
+                .content(config.name.clone())),
+            None => Ok(()),
+        }
+    }
+
+    fn is_root() -> crate::Result<()> {
+        if unsafe { libc::geteuid() } != 0 {
+            return Err(crate::Error::new(crate::ErrorKind::AccessDenied,
+                                         "Without root privileges.".to_string()));
+        }
+        Ok(())
+    }
+
+    fn run_content(config: &SombraConfig) -> crate::Result<String> {
+        let mut script = vec!["#!/bin/sh".to_string(), crate::settings::HEADER.to_string()];
+        if config.stderr_log.is_none() {
+            script.push("exec 2>&1".to_string());
+        }
+        // For the window of the restart policy, from the service directory
+        script.push("mkdir -p data && date +%s > data/started".to_string());
+        for (key, value) in &config.env {
+            script.push(format!("export {}", quote(format!("{}={}", key, value).as_ref())));
+        }
+        if let Some(dir) = &config.working_directory {
+            script.push(format!("cd {} || exit 1", word(dir.as_os_str())));
+        }
+
+        let mut command = vec!["exec".to_string()];
+        if let Some(priority) = config.priority {
+            command.push(format!("nice -n {}", priority.nice()));
+        }
+        command.push("s6-setuidgid".to_string());
+        command.push(word(config.account.unix_name(&config.name)?.as_ref()));
+        command.push(word(config.path.as_os_str()));
+        command.extend(config.args.iter().map(|arg| word(arg)));
+        command.push("</dev/null".to_string());
+        if let Some(log) = &config.stdout_log {
+            command.push(format!(">>{}", word(log.as_os_str())));
+        }
+        if let Some(log) = &config.stderr_log {
+            command.push(format!("2>>{}", word(log.as_os_str())));
+        }
+        script.push(command.join(" "));
+        script.push(String::new());
+        Ok(script.join("\n"))
+    }
+
+    /// Runs after each exit of the target, with its exit code or 256 and
+    /// the signal that killed it, then its process group. s6-supervise
+    /// restarts the target unless the script exits 125.
+    fn finish_content(config: &SombraConfig) -> String {
+        let seconds = |delay: Duration| delay.as_millis().div_ceil(1000);
+        let restart_delay = config.failure_actions.as_ref().and_then(|f| f.restart_delay());
+        let (initial_delay, max_delay, limit) = match (&config.restart_policy, restart_delay) {
+            (Some(policy), _) => (Some(seconds(policy.initial_delay)),
+                                  seconds(policy.max_delay),
+                                  Some((policy.max_restarts, seconds(policy.window)))),
+            (None, Some(delay)) => (Some(seconds(delay)), seconds(delay), None),
+            (None, None) => (None, 0, None),
+        };
+
+        let mut script = vec!["#!/bin/sh".to_string()];
+        if config.kill_process_tree {
+            script.push("[ -n \"$4\" ] && kill -s TERM -- -\"$4\" 2>/dev/null".to_string());
+        }
+        // As the shell reports it, for stats()
+        script.push("if [ \"$1\" = 256 ]; then code=$((128 + $2)); else code=$1; fi"
+            .to_string());
+        script.push("echo \"$code\" > data/exit_code".to_string());
+        script.push("[ \"$(s6-svstat -o wantedup .)\" = false ] && exit 0".to_string());
+        script.push("[ \"$code\" -eq 0 ] && exit 125".to_string());
+        match initial_delay {
+            Some(initial_delay) => {
+                script.push("failures=$(cat data/failures 2>/dev/null || echo 0)".to_string());
+                if let Some((max_restarts, window)) = limit {
+                    // A target that ran for the whole window starts over
+                    script.push(format!("[ $(($(date +%s) - $(cat data/started))) -ge {} ] \
+                                         && failures=0", window));
+                    script.push(format!("if [ \"$failures\" -ge {} ]; then \
+                                         touch data/failed; exit 125; fi", max_restarts));
+                }
+                script.push("failures=$((failures + 1))".to_string());
+                script.push("echo \"$failures\" > data/failures".to_string());
+                script.push("restarts=$(cat data/restarts 2>/dev/null || echo 0)".to_string());
+                script.push("echo $((restarts + 1)) > data/restarts".to_string());
+                script.push(format!("delay={}", initial_delay));
+                script.push(format!("while [ \"$failures\" -gt 1 ] && [ \"$delay\" -lt {} ]; do \
+                                     delay=$((delay * 2)); failures=$((failures - 1)); done",
+                                    max_delay));
+                script.push(format!("[ \"$delay\" -gt {0} ] && delay={0}", max_delay));
+                script.push("exec sleep \"$delay\"".to_string());
+            },
+            None => {
+                script.push("touch data/failed".to_string());
+                script.push("exit 125".to_string());
+            },
+        }
+        script.push(String::new());
+        script.join("\n")
+    }
+
+    fn log_run_content(name: &str) -> String {
+        let dir = word(SombraS6::log_dir(name).as_os_str());
+        format!("#!/bin/sh\nmkdir -p {0}\nexec s6-log -b n20 s1000000 T {0}\n", dir)
+    }
+
+    fn settings_content(config: &SombraConfig) -> crate::Result<String> {
+        let user = config.account.unix_name(&config.name)?;
+        Ok(crate::settings::content(config, &[("user", user)]))
+    }
+
+    fn config_from_settings(name: &str, content: &str) -> (SombraConfig, Option<String>) {
+        let settings = crate::settings::parse(name, content);
+        let mut config = settings.config;
+        if let Some((_, user)) = settings.extra.iter().find(|(key, _)| key == "user") {
+            config.account = Account::from_unix_name(user);
+        }
+        (config, settings.checksum)
+    }
+
+    /// Files of the source definitions of the target and of its logger,
+    /// in the layout of s6-rc-compile
+    fn definitions(config: &SombraConfig) -> crate::Result<Vec<(PathBuf, String, u32)>> {
+        let dir = SombraS6::source(&config.name);
+        let mut files = vec![
+            (dir.join("type"), "longrun\n".to_string(), 0o644),
+            (dir.join("run"), SombraS6::run_content(config)?, 0o755),
+            (dir.join("finish"), SombraS6::finish_content(config), 0o755),
+            (SombraS6::settings_path(&config.name), SombraS6::settings_content(config)?, 0o644),
+        ];
+        if config.stop_signal != StopSignal::Terminate {
+            files.push((dir.join("down-signal"),
+                        format!("{}\n", config.stop_signal.unix_name()), 0o644));
+        }
+        if let Some(timeout) = config.stop_timeout {
+            files.push((dir.join("timeout-kill"), format!("{}\n", timeout.as_millis()), 0o644));
+        }
+        if let Some(timeout) = config.start_timeout {
+            files.push((dir.join("timeout-up"), format!("{}\n", timeout.as_millis()), 0o644));
+        }
+        for dependency in &config.dependencies {
+            files.push((dir.join("dependencies.d").join(dependency), String::new(), 0o644));
+        }
+        if SombraS6::has_log_service(config) {
+            let log_name = SombraS6::log_name(&config.name);
+            let log = SombraS6::source(&log_name);
+            files.push((dir.join("producer-for"), format!("{}\n", log_name), 0o644));
+            files.push((log.join("type"), "longrun\n".to_string(), 0o644));
+            files.push((log.join("consumer-for"), format!("{}\n", config.name), 0o644));
+            files.push((log.join("run"), SombraS6::log_run_content(&config.name), 0o755));
+        }
+        Ok(files)
+    }
+
+    /// The source definitions, and the membership of the default bundle.
+    /// The bundle is created if the image has none.
+    fn files(&self) -> crate::Result<Vec<(PathBuf, String, u32)>> {
+        let mut files = SombraS6::definitions(&self.config)?;
+        if matches!(self.config.start_type, StartType::AutoStart | StartType::DelayedAutoStart) {
+            let bundle = SombraS6::source(DEFAULT_BUNDLE);
+            if !bundle.exists() {
+                files.push((bundle.join("type"), "bundle\n".to_string(), 0o644));
+            }
+            let mut members = vec![self.config.name.clone()];
+            if SombraS6::has_log_service(&self.config) {
+                members.push(SombraS6::log_name(&self.config.name));
+            }
+            for member in members {
+                files.push((bundle.join("contents.d").join(member), String::new(), 0o644));
+            }
+        }
+        Ok(files)
+    }
+
+    fn write_definitions(&self) -> crate::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        self.remove_definitions()?;
+        for (path, content, mode) in self.files()? {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)
+                    .map_err(|e| crate::Error::from(e).content(parent.display().to_string()))?;
+            }
+            std::fs::write(&path, content)?;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode))?;
+        }
+        Ok(())
+    }
+
+    fn remove_definitions(&self) -> crate::Result<()> {
+        let name = &self.config.name;
+        let log_name = SombraS6::log_name(name);
+        for service in &[name, &log_name] {
+            let dir = SombraS6::source(service);
+            if dir.exists() {
+                std::fs::remove_dir_all(dir)?;
+            }
+            let member = SombraS6::source(DEFAULT_BUNDLE).join("contents.d").join(service);
+            if member.exists() {
+                std::fs::remove_file(member)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn run(command: &mut Command) -> crate::Result<()> {
+        let output = command.output()?;
+        if !output.status.success() {
+            return Err(crate::Error::new(crate::ErrorKind::Other,
+                                         String::from_utf8_lossy(&output.stderr).trim()
+                                             .to_string())
+                .content(format!("{:?}", command)));
+        }
+        Ok(())
+    }
+
+    /// Compiles the source definitions, switches the live state to the new
+    /// database and points the boot link at it
+    fn compile() -> crate::Result<()> {
+        let link = Path::new(COMPILED);
+        let previous = if link.exists() {
+            match std::fs::read_link(link) {
+                Ok(previous) => Some(previous),
+                Err(_) => return Err(crate::Error::new(crate::ErrorKind::Other,
+                                                       "The compiled database isn't a link"
+                                                           .to_string())
+                    .content(COMPILED.to_string())),
+            }
+        } else {
+            None
+        };
+        let since_epoch = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        let compiled = PathBuf::from(format!("{}-{}", COMPILED, since_epoch.as_nanos()));
+        SombraS6::run(Command::new("s6-rc-compile").arg(&compiled).arg(SOURCE_DIR))?;
+        if Path::new(LIVE_DIR).exists() {
+            SombraS6::run(Command::new("s6-rc-update").arg("-l").arg(LIVE_DIR).arg(&compiled))?;
+        }
+        let staged = link.with_extension("new");
+        let _ = std::fs::remove_file(&staged);
+        std::os::unix::fs::symlink(&compiled, &staged)?;
+        std::fs::rename(&staged, link)?;
+        if let Some(previous) = previous {
+            let previous = link.parent().map(|dir| dir.join(&previous)).unwrap_or(previous);
+            if previous != compiled && previous.is_dir() {
+                std::fs::remove_dir_all(previous)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn change(&self, direction: &str) -> crate::Result<()> {
+        let mut command = Command::new("s6-rc");
+        command.arg("-l").arg(LIVE_DIR).arg(direction).arg("change").arg(&self.config.name);
+        if direction == "-u" && SombraS6::has_log_service(&self.config) {
+            command.arg(SombraS6::log_name(&self.config.name));
+        }
+        SombraS6::run(&mut command)
+    }
+
+    /// `s6-svstat -o up,wantedup,pid` of the live service directory, or
+    /// nothing when s6-supervise doesn't run it
+    fn svstat(&self) -> Option<(bool, bool, Option<u32>)> {
+        let output = Command::new("s6-svstat")
+            .arg("-o")
+            .arg("up,wantedup,pid")
+            .arg(self.live())
+            .output().ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        SombraS6::parse_svstat(std::str::from_utf8(&output.stdout).ok()?)
+    }
+
+    fn parse_svstat(line: &str) -> Option<(bool, bool, Option<u32>)> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields[..] {
+            [up, wanted_up, pid] => Some((up == "true", wanted_up == "true",
+                                          pid.parse().ok().filter(|&pid: &u32| pid > 0))),
+            _ => None,
+        }
+    }
+
+    fn status_from_svstat(up: bool, wanted_up: bool) -> ServiceStatus {
+        match (up, wanted_up) {
+            (true, false) => ServiceStatus::StopPending,
+            (true, true) => ServiceStatus::Running,
+            // s6-supervise restarts the target once finish returns
+            (false, true) => ServiceStatus::StartPending,
+            (false, false) => ServiceStatus::Stopped,
+        }
+    }
+
+    fn is_installed(&self) -> crate::Result<()> {
+        if SombraS6::settings_path(&self.config.name).exists() {
+            Ok(())
+        } else {
+            Err(crate::Error::new(crate::ErrorKind::ServiceNotFound,
+                                  format!("Service {} does not exist", self.config.name)))
+        }
+    }
+
+    fn installed(&self) -> crate::Result<(SombraConfig, Option<String>)> {
+        self.is_installed()?;
+        let path = SombraS6::settings_path(&self.config.name);
+        let content = std::fs::read_to_string(&path)
+            .map_err(|e| crate::Error::from(e).content(path.display().to_string()))?;
+        Ok(SombraS6::config_from_settings(&self.config.name, &content))
+    }
+
+    fn read_number(&self, file: &str) -> Option<u32> {
+        std::fs::read_to_string(self.data(file)).ok()?.trim().parse().ok()
+    }
+}
+
+impl Sombra for SombraS6 {
+    fn from_config(config: SombraConfig) -> crate::Result<Self> {
+        Ok(SombraS6 { config })
+    }
+
+    fn create(&self) -> crate::Result<()> {
+        SombraS6::check(&self.config)?;
+        SombraS6::is_root()?;
+        if self.is_installed().is_ok() {
+            return Err(crate::Error::new(crate::ErrorKind::ServiceAlreadyExists,
+                                         format!("Service {} already exist",
+                                                 self.config.name)));
+        }
+        self.write_definitions()?;
+        SombraS6::compile()?;
+
+        if self.config.start_type == StartType::Disabled || self.config.target_pending() {
+            return Ok(());
+        }
+        self.start()?;
+        match &self.config.readiness {
+            Some(readiness) => readiness.wait(),
+            None => Ok(()),
+        }
+    }
+
+    fn update(&self) -> crate::Result<()> {
+        SombraS6::check(&self.config)?;
+        SombraS6::is_root()?;
+        self.is_installed()?;
+        // s6-rc-update restarts a running target whose definition changed
+        self.write_definitions()?;
+        SombraS6::compile()
+    }
+
+    fn delete(&self) -> crate::Result<()> {
+        self.is_installed()?;
+        // s6-rc-update stops the services missing from the new database
+        self.remove_definitions()?;
+        SombraS6::compile()
+    }
+
+    fn start(&self) -> crate::Result<()> {
+        self.config.check_target()?;
+        self.is_installed()?;
+        for file in &["failures", "restarts", "exit_code", "failed"] {
+            let _ = std::fs::remove_file(self.data(file));
+        }
+        self.change("-u")
+    }
+
+    fn stop(&self) -> crate::Result<()> {
+        self.is_installed()?;
+        // Waits for the target, killing it after timeout-kill
+        self.change("-d")
+    }
+
+    fn status(&self) -> crate::Result<ServiceStatus> {
+        if self.is_installed().is_err() {
+            return Ok(ServiceStatus::NotInstalled);
+        }
+        let status = match self.svstat() {
+            Some((up, wanted_up, _)) => SombraS6::status_from_svstat(up, wanted_up),
+            None => ServiceStatus::Stopped,
+        };
+        if status == ServiceStatus::Stopped && self.data("failed").exists() {
+            return Ok(ServiceStatus::Failed);
+        }
+        Ok(status)
+    }
+
+    fn pid(&self) -> crate::Result<Option<u32>> {
+        self.is_installed()?;
+        Ok(self.svstat().and_then(|(up, _, pid)| if up { pid } else { None }))
+    }
+
+    fn usage(&self) -> crate::Result<Option<Usage>> {
+        match self.pid()? {
+            Some(pid) => Ok(Some(procfs::usage(pid)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn stats(&self) -> crate::Result<ServiceStats> {
+        let started_at = match self.pid()? {
+            Some(_) => std::fs::metadata(self.data("started"))
+                .and_then(|metadata| metadata.modified())
+                .ok(),
+            None => None,
+        };
+        let last_exit = self.read_number("exit_code").map(|code| match code {
+            129..=192 => ExitReason::Signal(code as i32 - 128),
+            code => ExitReason::Code(code as i64),
+        });
+        Ok(ServiceStats {
+            started_at,
+            restarts: self.read_number("restarts").unwrap_or(0),
+            last_exit,
+        })
+    }
+
+    fn config(&self) -> crate::Result<SombraConfig> {
+        Ok(self.installed()?.0)
+    }
+
+    fn diff(&self) -> crate::Result<Vec<Difference>> {
+        // The settings keep only what they render, so compare that part
+        let content = SombraS6::settings_content(&self.config)?;
+        let desired = SombraS6::config_from_settings(&self.config.name, &content).0;
+        Ok(desired.diff(&self.config()?))
+    }
+
+    fn checksum(&self) -> crate::Result<Option<String>> {
+        Ok(self.installed()?.1)
+    }
+
+    fn list_managed() -> crate::Result<Vec<ManagedService>> {
+        let mut services = vec![];
+        if !Path::new(SOURCE_DIR).exists() {
+            return Ok(services);
+        }
+        for entry in std::fs::read_dir(SOURCE_DIR)? {
+            let path = entry?.path();
+            let name = match path.file_name().and_then(|n| n.to_str()) {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            let content = std::fs::read_to_string(SombraS6::settings_path(&name))
+                .unwrap_or_default();
+            if !content.starts_with(crate::settings::HEADER) {
+                continue;
+            }
+            let (config, _) = SombraS6::config_from_settings(&name, &content);
+            let path = config.path.clone();
+            let status = SombraS6::from_config(config)?.status()?;
+            services.push(ManagedService { name, status, path });
+        }
+        services.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(services)
+    }
+
+    fn logs(&self, follow: bool) -> crate::Result<LogReader> {
+        let mut files = self.config.log_files();
+        if SombraS6::has_log_service(&self.config) {
+            files.push(SombraS6::log_dir(&self.config.name).join("current"));
+        }
+        LogReader::files(files, follow)
+    }
+
+    fn render(&self) -> crate::Result<Vec<Artifact>> {
+        Ok(self.files()?.into_iter()
+            .map(|(path, content, mode)| match mode {
+                0o755 => Artifact::script(path, content),
+                _ => Artifact::file(path, content),
+            })
+            .collect())
+    }
+
+    fn export(&self) -> crate::Result<Scripts> {
+        SombraS6::check(&self.config)?;
+        let name = &self.config.name;
+        let log_name = SombraS6::log_name(name);
+        // Compiles a new database and switches the live state and the next
+        // boot to it, as `compile` does
+        let compile = [
+            format!("compiled={}-$(date +%s)", COMPILED),
+            format!("s6-rc-compile \"$compiled\" {}", SOURCE_DIR),
+            format!("if [ -d {0} ]; then s6-rc-update -l {0} \"$compiled\"; fi", LIVE_DIR),
+            format!("ln -sfn \"$compiled\" {}", COMPILED),
+        ];
+        let mut shell = Shell::new(name);
+        shell.write(&self.render()?);
+        for line in &compile {
+            shell.install(line.clone());
+        }
+        if self.config.start_type != StartType::Disabled {
+            let mut change: Vec<&dyn AsRef<std::ffi::OsStr>> =
+                vec![&"s6-rc", &"-l", &LIVE_DIR, &"-u", &"change", name];
+            if SombraS6::has_log_service(&self.config) {
+                change.push(&log_name);
+            }
+            shell.install(Shell::line(&change));
+        }
+        // s6-rc-update stops the services missing from the new database
+        for service in &[name, &log_name] {
+            shell.remove_dir(SombraS6::source(service));
+            shell.remove(SombraS6::source(DEFAULT_BUNDLE).join("contents.d").join(service));
+        }
+        for line in compile {
+            shell.uninstall(line);
+        }
+        Ok(shell.finish())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FailureActions;
+
+    #[test]
+    fn definitions_pipe_output_to_logger() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp echo");
+        config.args = vec!["-p".into(), "8080".into()];
+        config.dependencies = vec!["network".to_string()];
+        config.account = Account::User { name: "sombra".to_string(), password: None };
+        config.stop_signal = StopSignal::Interrupt;
+        let files = SombraS6::definitions(&config).unwrap();
+        let file = |path: &str| files.iter()
+            .find(|(file, _, _)| file == Path::new(path))
+            .map(|(_, content, _)| content.as_str());
+        assert_eq!(file("/etc/s6-rc/source/tcp_echo/type"), Some("longrun\n"));
+        assert_eq!(file("/etc/s6-rc/source/tcp_echo/down-signal"), Some("SIGINT\n"));
+        assert_eq!(file("/etc/s6-rc/source/tcp_echo/dependencies.d/network"), Some(""));
+        assert_eq!(file("/etc/s6-rc/source/tcp_echo/producer-for"), Some("tcp_echo-log\n"));
+        assert_eq!(file("/etc/s6-rc/source/tcp_echo-log/consumer-for"), Some("tcp_echo\n"));
+        assert!(file("/etc/s6-rc/source/tcp_echo/run").unwrap()
+            .ends_with("\nexec s6-setuidgid sombra '/opt/tcp echo' -p 8080 </dev/null\n"));
+
+        config.stdout_log = Some(PathBuf::from("/var/log/tcp_echo.log"));
+        config.stderr_log = Some(PathBuf::from("/var/log/tcp_echo.err"));
+        let files = SombraS6::definitions(&config).unwrap();
+        assert!(!files.iter().any(|(file, _, _)| file.ends_with("producer-for")));
+    }
+
+    #[test]
+    fn finish_script_leaves_target_down() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp_echo");
+        let content = SombraS6::finish_content(&config);
+        assert!(content.starts_with("#!/bin/sh\n[ -n \"$4\" ] && kill -s TERM"));
+        assert!(content.ends_with("\ntouch data/failed\nexit 125\n"));
+
+        config.failure_actions = Some(FailureActions::restart(Duration::from_secs(5)));
+        let content = SombraS6::finish_content(&config);
+        assert!(content.contains("\n[ \"$code\" -eq 0 ] && exit 125\n"));
+        assert!(content.ends_with("\nexec sleep \"$delay\"\n"));
+    }
+
+    #[test]
+    fn svstat_mapping() {
+        let (up, wanted_up, pid) = SombraS6::parse_svstat("true true 123\n").unwrap();
+        assert_eq!(SombraS6::status_from_svstat(up, wanted_up), ServiceStatus::Running);
+        assert_eq!(pid, Some(123));
+        let (up, wanted_up, pid) = SombraS6::parse_svstat("false false -1\n").unwrap();
+        assert_eq!(SombraS6::status_from_svstat(up, wanted_up), ServiceStatus::Stopped);
+        assert_eq!(pid, None);
+        assert_eq!(SombraS6::status_from_svstat(true, false), ServiceStatus::StopPending);
+        assert_eq!(SombraS6::parse_svstat(""), None);
+    }
+}
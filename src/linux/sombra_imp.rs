@@ -1,44 +1,781 @@
-use crate::Sombra;
-use std::path::PathBuf;
-use std::io::Write;
+use crate::{Account, Artifact, Difference, ExitReason, FailureActions, LogReader, ManagedService,
+            Priority, Privileges, ProtectHome, ProtectSystem, RestartPolicy, Scope, Scripts, Sombra,
+            ServiceStats, ServiceStatus, SombraConfig, StartType, StopSignal, Trigger, Usage};
+use crate::linux::procfs;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use crate::export::Shell;
+use crate::linux::host::Host;
+use crate::linux::runit::SombraRunit;
 use crate::linux::systemctl::Systemctl;
+use crate::support::{self, Feature};
 use crate::error::ErrorKind::Other;
 
+/// Unit key marking the services created by sombra. systemd ignores keys
+/// starting with X-.
+const MANAGED_MARKER: &str = "X-Sombra-Managed=yes";
+/// Unit key holding the SHA-256 of the target at install
+const CHECKSUM_KEY: &str = "X-Sombra-Sha256=";
+/// Unit key listing the groups the polkit rule lets control the unit
+const OPERATORS_KEY: &str = "X-Sombra-Operators=";
+/// Keytab `realm join` and `adcli join` write, present on the machines
+/// joined to a domain
+const DOMAIN_KEYTAB: &str = "/etc/krb5.keytab";
+
 pub struct SombraLinux {
-    process_path: PathBuf,
-    process_name: String,
-    process_args: Vec<String>,
+    config: SombraConfig,
     sysctl: Systemctl,
+    /// Takes over on hosts where runsvdir runs instead of systemd
+    runit: Option<SombraRunit>,
 }
 
 impl SombraLinux {
-    fn service_file_content(name: &str, path: &PathBuf, args: &Vec<String>) -> crate::Result<String> {
+    fn service_file_content(config: &SombraConfig) -> crate::Result<String> {
+        let path = config.path_in_root(&config.path);
         let path_str = match path.to_str() {
             Some(path_str) => path_str.to_string(),
             None => return Err(crate::Error::new(crate::ErrorKind::Io,
                                                  "Cannot decode path".to_string()))
         };
 
-        let exec_start = if args.is_empty() {
-            path_str
+        let mut exec_start = SombraLinux::exec_quote(&path_str);
+        for arg in &config.args {
+            // Unit files are UTF-8
+            let arg = arg.to_str().ok_or_else(|| {
+                crate::Error::new(crate::ErrorKind::Utf8,
+                                  "Arguments must be valid UTF-8 for systemd".to_string())
+                    .content(arg.to_string_lossy().to_string())
+            })?;
+            exec_start.push(' ');
+            exec_start.push_str(&SombraLinux::exec_quote(arg));
+        }
+        if let Some(description) = support::unsupported(config, &[
+            Feature::UserScope, Feature::Journal, Feature::Sandboxing, Feature::Capabilities,
+            Feature::RootDirectory, Feature::Operators, Feature::Triggers, Feature::GroupAndUmask,
+            Feature::Notify, Feature::ResourceLimits, Feature::LogRotation,
+            Feature::VirtualAccount,
+        ]) {
+            return Err(crate::Error::new(Other, description.to_string())
+                .content(config.name.clone()));
+        }
+        // polkit rules only cover the units of the system manager
+        if config.scope == Scope::User && !config.operators.is_empty() {
+            return Err(crate::Error::new(Other, "Operators only apply to the system service \
+                                                 manager".to_string())
+                .content(config.name.clone()));
+        }
+        let description = match (&config.description, &config.display_name) {
+            (Some(description), _) => description.clone(),
+            (None, Some(display_name)) => display_name.clone(),
+            (None, None) => format!("{} service", config.name),
+        };
+
+        let dependencies: Vec<String> = config.dependencies.iter()
+            .map(|d| if d.contains('.') { d.clone() } else { format!("{}.service", d) })
+            .collect();
+        let mut after = vec!["network.target".to_string()];
+        after.extend(dependencies.iter().cloned());
+        // Each trigger pulls the unit in once enabled, and orders it after
+        let mut triggers = vec![];
+        for trigger in &config.triggers {
+            match trigger {
+                Trigger::NetworkAvailable => triggers.push("network-online.target".to_string()),
+                Trigger::Device(path) => triggers.push(SombraLinux::device_unit(path)),
+                Trigger::DomainJoin => {},
+            }
+        }
+        after.extend(triggers.iter().cloned());
+
+        let mut unit = vec![
+            format!("Description={}", description),
+            format!("After={}", after.join(" ")),
+            MANAGED_MARKER.to_string(),
+        ];
+        // The target of another host isn't reachable from here
+        let checksum = match config.ssh_host() {
+            Some(_) => None,
+            None => crate::digest::sha256_file(&config.path).ok(),
+        };
+        if let Some(checksum) = checksum {
+            unit.push(format!("{}{}", CHECKSUM_KEY, checksum));
+        }
+        if !config.operators.is_empty() {
+            unit.push(format!("{}{}", OPERATORS_KEY, config.operators.join(" ")));
+        }
+        match &config.restart_policy {
+            // Hitting the start limit leaves the unit failed
+            Some(policy) => {
+                unit.push(format!("StartLimitIntervalSec={}ms", policy.window.as_millis()));
+                unit.push(format!("StartLimitBurst={}", policy.max_restarts));
+            },
+            None => unit.push("StartLimitIntervalSec=0".to_string()),
+        }
+        if !dependencies.is_empty() {
+            unit.push(format!("Requires={}", dependencies.join(" ")));
+        }
+        if config.triggers.contains(&Trigger::NetworkAvailable) {
+            unit.push("Wants=network-online.target".to_string());
+        }
+        let devices: Vec<&String> = triggers.iter()
+            .filter(|unit| unit.ends_with(".device"))
+            .collect();
+        if !devices.is_empty() {
+            // Stopped when the device goes away
+            unit.push(format!("BindsTo={}", devices.iter()
+                .map(|device| device.as_str())
+                .collect::<Vec<_>>()
+                .join(" ")));
+        }
+        if config.triggers.contains(&Trigger::DomainJoin) {
+            unit.push(format!("ConditionPathExists={}", DOMAIN_KEYTAB));
+        }
+
+        let mut service = if config.notify {
+            vec!["Type=notify".to_string(), "NotifyAccess=main".to_string()]
+        } else if config.watchdog.is_some() {
+            vec!["Type=simple".to_string(), "NotifyAccess=main".to_string()]
         } else {
-            format!("{} {}", path_str, args.join(" "))
-        };
-        Ok(format!("[Unit]\n\
-                Description={} service\n\
-                After=network.target\n\
-                StartLimitIntervalSec=0\n\
-                \n\
-                [Service]\n\
-                Type=simple\n\
-                User={}\n\
-                ExecStart={}\n\
-                \n\
-                [Install]\n\
-                WantedBy=multi-user.target",
-                name,
-                whoami::username(),
-                exec_start))
+            vec!["Type=simple".to_string()]
+        };
+        // The manager of a user runs its units as that user
+        if config.scope == Scope::System {
+            service.push(format!("User={}", config.account.unix_name(&config.name)?));
+            if let Some(group) = &config.group {
+                service.push(format!("Group={}", group));
+            }
+        }
+        if let Some(umask) = config.umask {
+            service.push(format!("UMask={:04o}", umask));
+        }
+        if let Some(timeout) = config.start_timeout {
+            service.push(format!("TimeoutStartSec={}ms", timeout.as_millis()));
+        }
+        if config.account == Account::Virtual {
+            service.push("DynamicUser=yes".to_string());
+        }
+        for (key, value) in &config.env {
+            service.push(format!("Environment={}",
+                                 SombraLinux::unit_quote(&format!("{}={}", key, value))));
+        }
+        if let Some(root) = &config.root_directory {
+            service.push(format!("RootDirectory={}", SombraLinux::setting_path(root)?));
+            for path in &config.bind_paths {
+                service.push(format!("BindReadOnlyPaths={}",
+                                     SombraLinux::unit_quote(SombraLinux::path_str(path)?)));
+            }
+        }
+        if let Some(dir) = &config.working_directory {
+            service.push(format!("WorkingDirectory={}",
+                                 SombraLinux::setting_path(&config.path_in_root(dir))?));
+        }
+        if config.journal {
+            // systemd adds the exit code and status to the unit's own entries
+            service.push("StandardOutput=journal".to_string());
+            service.push("StandardError=journal".to_string());
+            service.push(format!("SyslogIdentifier={}", config.name));
+            service.push(format!("LogExtraFields=SOMBRA_SERVICE={}", config.name));
+        }
+        if let Some(log) = &config.stdout_log {
+            service.push(format!("StandardOutput=append:{}", SombraLinux::setting_path(log)?));
+        }
+        if let Some(log) = &config.stderr_log {
+            service.push(format!("StandardError=append:{}", SombraLinux::setting_path(log)?));
+        }
+        if let Some(limits) = &config.resource_limits {
+            if let Some(memory_max) = limits.memory_max {
+                service.push(format!("MemoryMax={}", memory_max));
+            }
+            if let Some(cpu_quota) = limits.cpu_quota {
+                service.push(format!("CPUQuota={}%", cpu_quota));
+            }
+        }
+        if let Some(priority) = config.priority {
+            service.push(format!("Nice={}", priority.nice()));
+        }
+        if let Some(mask) = config.cpu_affinity {
+            let cpus: Vec<String> = (0..64)
+                .filter(|cpu| mask & (1 << cpu) != 0)
+                .map(|cpu| cpu.to_string())
+                .collect();
+            service.push(format!("CPUAffinity={}", cpus.join(" ")));
+        }
+        if !config.capabilities.is_empty() {
+            service.push(format!("AmbientCapabilities={}", config.capabilities.join(" ")));
+        }
+        if let Some(hardening) = &config.hardening {
+            if let Some(protect) = hardening.protect_system {
+                service.push(format!("ProtectSystem={}", match protect {
+                    ProtectSystem::Yes => "yes",
+                    ProtectSystem::Full => "full",
+                    ProtectSystem::Strict => "strict",
+                }));
+            }
+            if let Some(protect) = hardening.protect_home {
+                service.push(format!("ProtectHome={}", match protect {
+                    ProtectHome::Yes => "yes",
+                    ProtectHome::ReadOnly => "read-only",
+                    ProtectHome::Tmpfs => "tmpfs",
+                }));
+            }
+            if hardening.private_tmp {
+                service.push("PrivateTmp=yes".to_string());
+            }
+            if hardening.no_new_privileges {
+                service.push("NoNewPrivileges=yes".to_string());
+            }
+            // One path per line, as the value is split on whitespace
+            for path in &hardening.read_write_paths {
+                service.push(format!("ReadWritePaths={}",
+                                     SombraLinux::unit_quote(SombraLinux::path_str(path)?)));
+            }
+            if !hardening.system_call_filter.is_empty() {
+                service.push(format!("SystemCallFilter={}",
+                                     hardening.system_call_filter.join(" ")));
+            }
+            if !hardening.system_call_architectures.is_empty() {
+                service.push(format!("SystemCallArchitectures={}",
+                                     hardening.system_call_architectures.join(" ")));
+            }
+        }
+        service.push(format!("ExecStart={}", exec_start));
+        // systemd sends SIGKILL once the timeout expires
+        service.push(format!("KillSignal={}", config.stop_signal.unix_name()));
+        service.push(if config.kill_process_tree {
+            "KillMode=control-group".to_string()
+        } else {
+            "KillMode=process".to_string()
+        });
+        if let Some(timeout) = config.stop_timeout {
+            service.push(format!("TimeoutStopSec={}ms", timeout.as_millis()));
+        }
+        if let Some(interval) = config.watchdog {
+            service.push(format!("WatchdogSec={}ms", interval.as_millis()));
+        }
+        let restart_delay = config.failure_actions.as_ref().and_then(|f| f.restart_delay());
+        match (&config.restart_policy, restart_delay) {
+            (Some(policy), _) => {
+                service.push("Restart=on-failure".to_string());
+                service.push(format!("RestartSec={}ms", policy.initial_delay.as_millis()));
+                service.push(format!("RestartSteps={}", policy.backoff_steps()));
+                service.push(format!("RestartMaxDelaySec={}ms", policy.max_delay.as_millis()));
+            },
+            (None, Some(delay)) => {
+                service.push("Restart=on-failure".to_string());
+                service.push(format!("RestartSec={}ms", delay.as_millis()));
+            },
+            // A missed heartbeat must restart the target even without
+            // failure actions
+            (None, None) if config.watchdog.is_some() =>
+                service.push("Restart=on-watchdog".to_string()),
+            (None, None) => {},
+        }
+
+        let mut install = vec![];
+        // An on-demand service with triggers is enabled for them alone
+        if !config.is_triggered() {
+            install.push(match config.scope {
+                Scope::System => "WantedBy=multi-user.target".to_string(),
+                Scope::User => "WantedBy=default.target".to_string(),
+            });
+        }
+        install.extend(triggers.iter().map(|trigger| format!("WantedBy={}", trigger)));
+
+        Ok(format!("[Unit]\n{}\n\n[Service]\n{}\n\n[Install]\n{}",
+                   unit.join("\n"),
+                   service.join("\n"),
+                   install.join("\n")))
+    }
+
+    /// Name of the device unit of `path`, escaped as `systemd-escape --path`
+    /// does: slashes become dashes, and bytes other than ASCII letters,
+    /// digits, `:`, `_` and an inner `.` become `\xXX`
+    fn device_unit(path: &str) -> String {
+        let path = path.split('/').filter(|part| !part.is_empty()).collect::<Vec<_>>().join("/");
+        let mut name = String::new();
+        for (i, byte) in path.bytes().enumerate() {
+            match byte {
+                b'/' => name.push('-'),
+                b'.' if i > 0 => name.push('.'),
+                b':' | b'_' => name.push(byte as char),
+                _ if byte.is_ascii_alphanumeric() => name.push(byte as char),
+                _ => name.push_str(&format!("\\x{:02x}", byte)),
+            }
+        }
+        format!("{}.device", name)
+    }
+
+    /// Inverse of `device_unit`
+    fn device_path(unit: &str) -> Option<String> {
+        let name = unit.strip_suffix(".device")?.as_bytes();
+        let mut path = vec![b'/'];
+        let mut i = 0;
+        while i < name.len() {
+            match name[i] {
+                b'-' => path.push(b'/'),
+                b'\\' => {
+                    let hex = std::str::from_utf8(name.get(i + 2..i + 4)?).ok()?;
+                    path.push(u8::from_str_radix(hex, 16).ok()?);
+                    i += 3;
+                },
+                byte => path.push(byte),
+            }
+            i += 1;
+        }
+        String::from_utf8(path).ok()
+    }
+
+    fn path_str(path: &Path) -> crate::Result<&str> {
+        path.to_str().ok_or_else(|| {
+            crate::Error::new(crate::ErrorKind::Utf8,
+                              "Paths must be valid UTF-8 for systemd".to_string())
+                .content(path.to_string_lossy().to_string())
+        })
+    }
+
+    /// A path of a setting taking a single one, like WorkingDirectory.
+    /// systemd takes the rest of the line as is, without unquoting, so only
+    /// the `%` specifiers are escaped.
+    fn setting_path(path: &Path) -> crate::Result<String> {
+        let path = SombraLinux::path_str(path)?;
+        if path.contains('\n') {
+            return Err(crate::Error::new(crate::ErrorKind::Other,
+                                         "Paths can't span lines in a unit".to_string())
+                .content(path.to_string()));
+        }
+        Ok(path.replace('%', "%%"))
+    }
+
+    /// Quotes a value of a setting systemd splits on whitespace and
+    /// unquotes, like Environment or BindReadOnlyPaths
+    fn unit_quote(value: &str) -> String {
+        format!("\"{}\"", value.replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('%', "%%"))
+    }
+
+    /// Inverse of `unit_quote`
+    fn unit_unquote(value: &str) -> String {
+        let quoted = value.strip_prefix('"').and_then(|v| v.strip_suffix('"'))
+            .unwrap_or(value)
+            .replace("%%", "%");
+        let mut unquoted = String::new();
+        let mut chars = quoted.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => unquoted.extend(chars.next()),
+                c => unquoted.push(c),
+            }
+        }
+        unquoted
+    }
+
+    /// Quotes a word of ExecStart so systemd passes it to the target as is.
+    /// systemd runs the command without a shell, but splits it on
+    /// whitespace, unquotes and unescapes the words, expands `%` specifiers
+    /// and `$` variables, and takes a lone `;` as a command separator.
+    fn exec_quote(word: &str) -> String {
+        let plain = !word.is_empty() && word != ";"
+            && !word.contains(|c: char| c.is_whitespace() || matches!(c, '\\' | '"' | '\''));
+        let quoted = if plain {
+            word.to_string()
+        } else if word == ";" {
+            "\\;".to_string()
+        } else {
+            format!("\"{}\"", word.replace('\\', "\\\\").replace('"', "\\\""))
+        };
+        quoted.replace('%', "%%").replace('$', "$$")
+    }
+
+    /// Splits ExecStart into words the way systemd does, inverse of
+    /// `exec_quote`
+    fn exec_split(value: &str) -> Vec<String> {
+        let mut words = vec![];
+        let mut word = String::new();
+        let mut in_word = false;
+        let mut quote = None;
+        let mut chars = value.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => word.extend(chars.next()),
+                '%' | '$' if chars.peek() == Some(&c) => {
+                    chars.next();
+                    word.push(c);
+                },
+                '"' | '\'' if quote.is_none() => quote = Some(c),
+                c if quote == Some(c) => quote = None,
+                c if c.is_whitespace() && quote.is_none() => {
+                    if in_word {
+                        words.push(std::mem::take(&mut word));
+                    }
+                    in_word = false;
+                    continue;
+                },
+                c => word.push(c),
+            }
+            in_word = true;
+        }
+        if in_word {
+            words.push(word);
+        }
+        words
+    }
+
+    /// Mask of a CPUAffinity list, whose CPUs are separated by whitespace or
+    /// commas and may be ranges like `0-3`. None if a CPU doesn't fit in
+    /// the mask.
+    fn cpu_mask(value: &str) -> Option<u64> {
+        let mut mask = 0u64;
+        for cpus in value.split(|c: char| c.is_whitespace() || c == ',') {
+            if cpus.is_empty() {
+                continue;
+            }
+            let (first, last) = match cpus.split_once('-') {
+                Some((first, last)) => (first.parse::<u32>().ok()?, last.parse::<u32>().ok()?),
+                None => {
+                    let cpu = cpus.parse::<u32>().ok()?;
+                    (cpu, cpu)
+                },
+            };
+            for cpu in first..=last {
+                mask |= 1u64.checked_shl(cpu)?;
+            }
+        }
+        Some(mask)
+    }
+
+    /// Inverse of `service_file_content`. The display name reads back as
+    /// the description, and the arguments are unquoted as ExecStart quotes
+    /// them.
+    fn config_from_unit(name: &str, unit: &str, enabled: bool) -> SombraConfig {
+        let duration = |value: &str| value.strip_suffix("ms")
+            .and_then(|ms| ms.parse().ok())
+            .map(Duration::from_millis);
+
+        let mut config = SombraConfig::new(name, "");
+        let mut user = None;
+        let mut dynamic_user = false;
+        let mut restart = "";
+        let mut restart_sec = None;
+        let mut restart_steps = false;
+        let mut restart_max_delay = None;
+        let mut start_limit_interval = None;
+        let mut start_limit_burst = 0;
+        let mut wanted_at_boot = false;
+        for line in unit.lines() {
+            let (key, value) = match line.split_once('=') {
+                Some(assignment) => assignment,
+                None => continue,
+            };
+            match key {
+                "Description" if value != format!("{} service", name) =>
+                    config.description = Some(value.to_string()),
+                "Wants" if value.split_whitespace().any(|unit| unit == "network-online.target") =>
+                    config.triggers.push(Trigger::NetworkAvailable),
+                "BindsTo" => config.triggers.extend(value.split_whitespace()
+                    .filter_map(SombraLinux::device_path)
+                    .map(Trigger::Device)),
+                "ConditionPathExists" if value == DOMAIN_KEYTAB =>
+                    config.triggers.push(Trigger::DomainJoin),
+                "WantedBy" if value == "multi-user.target" || value == "default.target" =>
+                    wanted_at_boot = true,
+                "X-Sombra-Operators" => config.operators = value.split_whitespace()
+                    .map(str::to_string)
+                    .collect(),
+                "Requires" => config.dependencies = value.split_whitespace()
+                    .map(|d| d.strip_suffix(".service").unwrap_or(d).to_string())
+                    .collect(),
+                "StartLimitIntervalSec" => start_limit_interval = duration(value),
+                "StartLimitBurst" => start_limit_burst = value.parse().unwrap_or(0),
+                "Type" => config.notify = value == "notify",
+                "User" => user = Some(value.to_string()),
+                "Group" => config.group = Some(value.to_string()),
+                "UMask" => config.umask = u32::from_str_radix(value, 8).ok(),
+                "TimeoutStartSec" => config.start_timeout = duration(value),
+                "DynamicUser" => dynamic_user = value == "yes",
+                "Environment" => {
+                    if let Some((key, value)) = SombraLinux::unit_unquote(value).split_once('=') {
+                        config.env.push((key.to_string(), value.to_string()));
+                    }
+                },
+                "RootDirectory" =>
+                    config.root_directory = Some(PathBuf::from(value.replace("%%", "%"))),
+                "BindReadOnlyPaths" =>
+                    config.bind_paths.push(PathBuf::from(SombraLinux::unit_unquote(value))),
+                "WorkingDirectory" =>
+                    config.working_directory = Some(PathBuf::from(value.replace("%%", "%"))),
+                "StandardOutput" | "StandardError" if value == "journal" => config.journal = true,
+                "StandardOutput" => config.stdout_log = value.strip_prefix("append:")
+                    .map(|log| PathBuf::from(log.replace("%%", "%"))),
+                "StandardError" => config.stderr_log = value.strip_prefix("append:")
+                    .map(|log| PathBuf::from(log.replace("%%", "%"))),
+                "MemoryMax" => config.resource_limits.get_or_insert_with(Default::default)
+                    .memory_max = value.parse().ok(),
+                "CPUQuota" => config.resource_limits.get_or_insert_with(Default::default)
+                    .cpu_quota = value.trim_end_matches('%').parse().ok(),
+                "Nice" => config.priority = value.parse().ok().and_then(Priority::from_nice),
+                "CPUAffinity" => config.cpu_affinity = SombraLinux::cpu_mask(value),
+                "AmbientCapabilities" => config.capabilities = value.split_whitespace()
+                    .map(str::to_string)
+                    .collect(),
+                "ProtectSystem" => config.hardening.get_or_insert_with(Default::default)
+                    .protect_system = match value {
+                        "yes" => Some(ProtectSystem::Yes),
+                        "full" => Some(ProtectSystem::Full),
+                        "strict" => Some(ProtectSystem::Strict),
+                        _ => None,
+                    },
+                "ProtectHome" => config.hardening.get_or_insert_with(Default::default)
+                    .protect_home = match value {
+                        "yes" => Some(ProtectHome::Yes),
+                        "read-only" => Some(ProtectHome::ReadOnly),
+                        "tmpfs" => Some(ProtectHome::Tmpfs),
+                        _ => None,
+                    },
+                "PrivateTmp" => config.hardening.get_or_insert_with(Default::default)
+                    .private_tmp = value == "yes",
+                "NoNewPrivileges" => config.hardening.get_or_insert_with(Default::default)
+                    .no_new_privileges = value == "yes",
+                "SystemCallFilter" => config.hardening.get_or_insert_with(Default::default)
+                    .system_call_filter = value.split_whitespace().map(str::to_string).collect(),
+                "SystemCallArchitectures" => config.hardening.get_or_insert_with(Default::default)
+                    .system_call_architectures = value.split_whitespace()
+                    .map(str::to_string)
+                    .collect(),
+                "ReadWritePaths" => config.hardening.get_or_insert_with(Default::default)
+                    .read_write_paths.push(PathBuf::from(SombraLinux::unit_unquote(value))),
+                "ExecStart" => {
+                    let mut words = SombraLinux::exec_split(value).into_iter();
+                    config.path = PathBuf::from(words.next().unwrap_or_default());
+                    config.args = words.map(OsString::from).collect();
+                },
+                "KillSignal" if value == "SIGINT" => config.stop_signal = StopSignal::Interrupt,
+                "KillMode" => config.kill_process_tree = value == "control-group",
+                "TimeoutStopSec" => config.stop_timeout = duration(value),
+                "WatchdogSec" => config.watchdog = duration(value),
+                "Restart" => restart = value,
+                "RestartSec" => restart_sec = duration(value),
+                "RestartSteps" => restart_steps = true,
+                "RestartMaxDelaySec" => restart_max_delay = duration(value),
+                _ => {},
+            }
+        }
+
+        // ExecStart and WorkingDirectory are within the root
+        config.path = config.path_from_root(&config.path);
+        config.working_directory = config.working_directory.as_ref()
+            .map(|dir| config.path_from_root(dir));
+        config.account = match user {
+            _ if dynamic_user => Account::Virtual,
+            Some(user) => Account::from_unix_name(&user),
+            None => Account::LocalSystem,
+        };
+        config.start_type = match enabled && wanted_at_boot {
+            true => StartType::AutoStart,
+            false => StartType::OnDemand,
+        };
+        match (restart, restart_sec) {
+            ("on-failure", Some(delay)) if restart_steps => {
+                config.restart_policy = Some(RestartPolicy {
+                    max_restarts: start_limit_burst,
+                    window: start_limit_interval.unwrap_or_default(),
+                    initial_delay: delay,
+                    max_delay: restart_max_delay.unwrap_or(delay),
+                });
+            },
+            ("on-failure", Some(delay)) =>
+                config.failure_actions = Some(FailureActions::restart(delay)),
+            _ => {},
+        }
+        config
+    }
+
+    fn logrotate_content(config: &SombraConfig) -> Option<String> {
+        let rotation = config.log_rotation?;
+        let logs: Vec<String> = config.log_files().iter()
+            .map(|log| log.display().to_string())
+            .collect();
+        if logs.is_empty() {
+            return None;
+        }
+
+        let mut directives = vec![];
+        if let Some(max_age) = rotation.max_age {
+            let days = max_age.as_secs().div_ceil(24 * 60 * 60);
+            directives.push(match days {
+                0..=1 => "daily",
+                2..=7 => "weekly",
+                _ => "monthly",
+            }.to_string());
+        }
+        if let Some(max_size) = rotation.max_size {
+            // Without a period, size alone triggers the rotation
+            let key = if rotation.max_age.is_some() { "maxsize" } else { "size" };
+            directives.push(format!("{} {}", key, max_size));
+        }
+        directives.push(format!("rotate {}", rotation.keep));
+        directives.push("missingok".to_string());
+        directives.push("notifempty".to_string());
+        // systemd keeps the file open, so truncate it in place
+        directives.push("copytruncate".to_string());
+
+        Some(format!("{} {{\n    {}\n}}\n", logs.join(" "), directives.join("\n    ")))
+    }
+
+    /// JavaScript rule letting the members of the operator groups start,
+    /// stop and restart the unit without being root
+    fn polkit_content(config: &SombraConfig) -> Option<String> {
+        if config.operators.is_empty() {
+            return None;
+        }
+        let string = |value: &str| format!("\"{}\"", value.replace('\\', "\\\\")
+            .replace('"', "\\\""));
+        let groups: Vec<String> = config.operators.iter()
+            .map(|group| format!("subject.isInGroup({})", string(group)))
+            .collect();
+        let rule = [
+            "// Managed by sombra".to_string(),
+            "polkit.addRule(function(action, subject) {".to_string(),
+            "    if (action.id == \"org.freedesktop.systemd1.manage-units\" &&".to_string(),
+            format!("        action.lookup(\"unit\") == {} &&",
+                    string(&format!("{}.service", config.name))),
+            "        [\"start\", \"stop\", \"restart\"].indexOf(action.lookup(\"verb\")) >= 0 &&"
+                .to_string(),
+            format!("        ({})) {{", groups.join(" || ")),
+            "        return polkit.Result.YES;".to_string(),
+            "    }".to_string(),
+            "});".to_string(),
+        ];
+        Some(format!("{}\n", rule.join("\n")))
+    }
+
+    fn host(&self) -> &Host {
+        self.sysctl.host()
+    }
+
+    fn write_files(&self) -> crate::Result<()> {
+        let content = SombraLinux::service_file_content(&self.config)?;
+        self.host().write(&self.unit_path(), &content)?;
+
+        let logrotate = SombraLinux::logrotate_path(&self.config.name);
+        match SombraLinux::logrotate_content(&self.config) {
+            Some(content) => self.host().write(&logrotate, &content)?,
+            None if self.host().exists(&logrotate) => self.host().remove_file(&logrotate)?,
+            None => {},
+        }
+
+        let polkit = SombraLinux::polkit_path(&self.config.name);
+        match SombraLinux::polkit_content(&self.config) {
+            Some(content) => self.host().write(&polkit, &content)?,
+            None if self.host().exists(&polkit) => self.host().remove_file(&polkit)?,
+            None => {},
+        }
+        Ok(())
+    }
+
+    fn logrotate_path(name: &str) -> PathBuf {
+        PathBuf::from(format!("/etc/logrotate.d/{}", name))
+    }
+
+    fn polkit_path(name: &str) -> PathBuf {
+        PathBuf::from(format!("/etc/polkit-1/rules.d/60-sombra-{}.rules", name))
+    }
+
+    /// /etc/systemd/system, or ~/.config/systemd/user for `Scope::User`
+    fn unit_dir(scope: Scope) -> PathBuf {
+        match scope {
+            Scope::System => PathBuf::from("/etc/systemd/system"),
+            Scope::User => {
+                let config_home = std::env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+                    .or_else(|| std::env::var_os("HOME")
+                        .map(|home| PathBuf::from(home).join(".config")))
+                    .unwrap_or_default();
+                config_home.join("systemd/user")
+            },
+        }
+    }
+
+    fn unit_path(&self) -> PathBuf {
+        SombraLinux::unit_dir(self.config.scope).join(format!("{}.service", self.config.name))
+    }
+
+    fn is_installed(&self) -> crate::Result<()> {
+        if self.host().exists(&self.unit_path()) {
+            Ok(())
+        } else {
+            Err(crate::Error::new(crate::ErrorKind::ServiceNotFound,
+                                  format!("Service {} does not exist", self.config.name)))
+        }
+    }
+
+    fn status_from_active_state(state: &str) -> ServiceStatus {
+        match state {
+            "active" | "reloading" => ServiceStatus::Running,
+            "activating" => ServiceStatus::StartPending,
+            "deactivating" => ServiceStatus::StopPending,
+            "failed" => ServiceStatus::Failed,
+            _ => ServiceStatus::Stopped,
+        }
+    }
+
+    /// Reads the stats from `systemctl show`. `boot_elapsed` is the time
+    /// since boot, the clock of the monotonic timestamps.
+    fn stats_from_properties(properties: &[(String, String)], boot_elapsed: Duration)
+        -> ServiceStats {
+        let property = |name: &str| properties.iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+            .unwrap_or_default();
+
+        let running = !matches!(property("MainPID"), "" | "0");
+        let started_at = match property("ExecMainStartTimestampMonotonic").parse::<u64>() {
+            Ok(start) if running && start > 0 => boot_elapsed
+                .checked_sub(Duration::from_micros(start))
+                .and_then(|uptime| std::time::SystemTime::now().checked_sub(uptime)),
+            _ => None,
+        };
+        // CLD_EXITED, CLD_KILLED and CLD_DUMPED
+        let status = property("ExecMainStatus").parse().unwrap_or(0);
+        let last_exit = match property("ExecMainCode") {
+            "1" => Some(ExitReason::Code(status)),
+            "2" | "3" => Some(ExitReason::Signal(status as i32)),
+            _ => None,
+        };
+
+        ServiceStats {
+            started_at,
+            restarts: property("NRestarts").parse().unwrap_or(0),
+            last_exit,
+        }
+    }
+
+    /// Root is needed for the system manager. The manager of a user runs
+    /// units as that user and can't write /etc/logrotate.d.
+    fn check_privileges(&self) -> crate::Result<()> {
+        if self.config.scope == Scope::System && self.host().is_remote() {
+            return match self.host().is_root() {
+                true => Ok(()),
+                false => Err(crate::Error::new(crate::ErrorKind::AccessDenied,
+                                               "Without root privileges.".to_string())
+                    .content(self.config.ssh_host().unwrap_or_default().to_string())),
+            };
+        }
+        if self.config.scope == Scope::System {
+            return SombraLinux::is_root();
+        }
+        let unsupported = if self.config.account != Account::LocalSystem {
+            Some("User services run as the user installing them")
+        } else if self.config.log_rotation.is_some() {
+            Some("Log rotation is only supported by system services")
+        } else {
+            None
+        };
+        match unsupported {
+            Some(description) => Err(crate::Error::new(crate::ErrorKind::Other,
+                                                       description.to_string())
+                .content(self.config.name.clone())),
+            None => Ok(()),
+        }
     }
 
     fn is_root() -> crate::Result<()> {
@@ -46,7 +783,7 @@ impl SombraLinux {
             Err(e) => Err(crate::Error::new(Other, e.to_string())),
             Ok(name) => {
                 if name != "root" {
-                    Err(crate::Error::new(Other,
+                    Err(crate::Error::new(crate::ErrorKind::AccessDenied,
                                           "Without root privileges.".to_string()))
                 } else {
                     Ok(())
@@ -56,55 +793,290 @@ impl SombraLinux {
     }
 }
 
-macro_rules! sombra_error {
-    ($kind:ident, $content:expr) => {
-        |e| crate::Error::new(crate::ErrorKind::$kind, e.to_string()).content($content)
-    };
-}
-
 impl Sombra for SombraLinux {
-    fn build(name: &str, path: &str, args: Vec<String>) -> crate::Result<Self> {
-        let path = dunce::canonicalize(path)
-            .map_err(sombra_error!(Io, path.to_string()))?;
-
+    fn from_config(config: SombraConfig) -> crate::Result<Self> {
+        // Rather than installing the service here
+        if cfg!(not(feature = "ssh")) && config.ssh_host.is_some() {
+            return Err(crate::Error::new(Other, "SSH hosts need the ssh feature".to_string())
+                .content(config.ssh_host.unwrap_or_default()));
+        }
+        let host = Host::new(&config);
+        // Other hosts are expected to run systemd
+        let runit = match SombraRunit::detect() {
+            Some(_) if !host.is_remote() => Some(SombraRunit::from_config(config.clone())?),
+            _ => None,
+        };
+        let sysctl = Systemctl::new(&config.name, config.scope == Scope::User, host);
         Ok(SombraLinux {
-            process_path: path,
-            process_name: name.to_string(),
-            process_args: args,
-            sysctl: Systemctl::new(name)
+            config,
+            sysctl,
+            runit,
         })
     }
 
     fn create(&self) -> crate::Result<()> {
-        SombraLinux::is_root()?;
+        if let Some(runit) = &self.runit {
+            return runit.create();
+        }
+        self.check_privileges()?;
 
-        let path = std::path::PathBuf::from(
-            format!("/etc/systemd/system/{}.service", self.process_name));
-        if path.exists() {
-            return Err(crate::Error::new(crate::ErrorKind::Io, format!("Service {} already exist",
-                                     self.process_name)));
-        } else {
-            let mut file = std::fs::File::create(&path)?;
-            let buffer = SombraLinux::service_file_content(&self.process_name,
-                                                           &self.process_path,
-                                                           &self.process_args)?;
-            file.write_all(&mut buffer.as_bytes())?;
+        if self.host().exists(&self.unit_path()) {
+            return Err(crate::Error::new(crate::ErrorKind::ServiceAlreadyExists,
+                                         format!("Service {} already exist",
+                                                 self.config.name)));
         }
+        self.write_files()?;
 
-        self.sysctl.start()?;
+        self.sysctl.daemon_reload()?;
+        match self.config.start_type {
+            StartType::AutoStart | StartType::DelayedAutoStart => self.sysctl.enable()?,
+            // Left for the triggers to start
+            StartType::OnDemand if self.config.is_triggered() => return self.sysctl.enable(),
+            StartType::OnDemand => {},
+            StartType::Disabled => return Ok(()),
+        }
+        // Started once the target is copied in place
+        if self.host().target_pending(&self.config) {
+            return Ok(());
+        }
+        self.start()?;
+        match &self.config.readiness {
+            Some(readiness) => readiness.wait(),
+            None => Ok(()),
+        }
+    }
 
-        // Need a delay after creation on linux version
-        std::thread::sleep(std::time::Duration::from_millis(100));
+    fn update(&self) -> crate::Result<()> {
+        if let Some(runit) = &self.runit {
+            return runit.update();
+        }
+        self.check_privileges()?;
+        self.is_installed()?;
+        self.write_files()?;
 
-        Ok(())
+        self.sysctl.daemon_reload()?;
+        match self.config.start_type {
+            StartType::AutoStart | StartType::DelayedAutoStart => self.sysctl.enable(),
+            StartType::OnDemand if self.config.is_triggered() => self.sysctl.enable(),
+            StartType::OnDemand | StartType::Disabled => self.sysctl.disable(),
+        }
     }
 
     fn delete(&self) -> crate::Result<()> {
+        if let Some(runit) = &self.runit {
+            return runit.delete();
+        }
         let _ = self.sysctl.stop();
         self.sysctl.disable()?;
-        std::fs::remove_file(format!("/etc/systemd/system/{}.service", self.process_name))?;
-        Systemctl::daemon_reload()?;
-        Systemctl::reset_failed()
+        self.host().remove_file(&self.unit_path())?;
+        let logrotate = SombraLinux::logrotate_path(&self.config.name);
+        if self.host().exists(&logrotate) {
+            self.host().remove_file(&logrotate)?;
+        }
+        let polkit = SombraLinux::polkit_path(&self.config.name);
+        if self.host().exists(&polkit) {
+            self.host().remove_file(&polkit)?;
+        }
+        self.sysctl.daemon_reload()?;
+        self.sysctl.reset_failed()
+    }
+
+    fn start(&self) -> crate::Result<()> {
+        if let Some(runit) = &self.runit {
+            return runit.start();
+        }
+        self.host().check_target(&self.config)?;
+        self.is_installed()?;
+        self.sysctl.start()?;
+
+        // Need a delay after start on linux version
+        std::thread::sleep(std::time::Duration::from_millis(100));
+
+        Ok(())
+    }
+
+    fn stop(&self) -> crate::Result<()> {
+        if let Some(runit) = &self.runit {
+            return runit.stop();
+        }
+        self.is_installed()?;
+        self.sysctl.stop()
+    }
+    fn status(&self) -> crate::Result<ServiceStatus> {
+        if let Some(runit) = &self.runit {
+            return runit.status();
+        }
+        if self.is_installed().is_err() {
+            return Ok(ServiceStatus::NotInstalled);
+        }
+        Ok(SombraLinux::status_from_active_state(&self.sysctl.is_active()?))
+    }
+
+    fn pid(&self) -> crate::Result<Option<u32>> {
+        if let Some(runit) = &self.runit {
+            return runit.pid();
+        }
+        self.is_installed()?;
+        match self.sysctl.main_pid()? {
+            0 => Ok(None),
+            pid => Ok(Some(pid)),
+        }
+    }
+
+    fn usage(&self) -> crate::Result<Option<Usage>> {
+        if let Some(runit) = &self.runit {
+            return runit.usage();
+        }
+        if let Some(destination) = self.config.ssh_host() {
+            return Err(crate::Error::new(Other,
+                                         "Usage of the processes of another machine isn't \
+                                          available".to_string())
+                .content(destination.to_string()));
+        }
+        match self.pid()? {
+            Some(pid) => Ok(Some(procfs::usage(pid)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn stats(&self) -> crate::Result<ServiceStats> {
+        if let Some(runit) = &self.runit {
+            return runit.stats();
+        }
+        self.is_installed()?;
+        let properties = self.sysctl.show(&["MainPID", "ExecMainStartTimestampMonotonic",
+                                             "ExecMainCode", "ExecMainStatus", "NRestarts"])?;
+        // /proc/uptime starts with the seconds since boot
+        let uptime = self.host().read_to_string("/proc/uptime".as_ref())?;
+        let boot_elapsed = uptime.split_whitespace().next()
+            .and_then(|seconds| seconds.parse::<f64>().ok())
+            .map(Duration::from_secs_f64)
+            .unwrap_or_default();
+        Ok(SombraLinux::stats_from_properties(&properties, boot_elapsed))
+    }
+
+    fn config(&self) -> crate::Result<SombraConfig> {
+        if let Some(runit) = &self.runit {
+            return runit.config();
+        }
+        self.is_installed()?;
+        let unit = self.host().read_to_string(&self.unit_path())?;
+        let enabled = self.sysctl.show(&["UnitFileState"])?.iter()
+            .any(|(key, value)| key == "UnitFileState" && value == "enabled");
+        let mut config = SombraLinux::config_from_unit(&self.config.name, &unit, enabled);
+        config.scope = self.config.scope;
+        Ok(config)
+    }
+
+    fn diff(&self) -> crate::Result<Vec<Difference>> {
+        if let Some(runit) = &self.runit {
+            return runit.diff();
+        }
+        // The unit keeps only what it renders, so compare that part
+        let unit = SombraLinux::service_file_content(&self.config)?;
+        let enabled = matches!(self.config.start_type,
+                               StartType::AutoStart | StartType::DelayedAutoStart)
+            || self.config.is_triggered();
+        let desired = SombraLinux::config_from_unit(&self.config.name, &unit, enabled);
+        Ok(desired.diff(&self.config()?))
+    }
+
+    fn checksum(&self) -> crate::Result<Option<String>> {
+        if let Some(runit) = &self.runit {
+            return runit.checksum();
+        }
+        self.is_installed()?;
+        let unit = self.host().read_to_string(&self.unit_path())?;
+        Ok(unit.lines()
+            .find_map(|line| line.strip_prefix(CHECKSUM_KEY))
+            .map(|checksum| checksum.to_string()))
+    }
+
+    fn list_managed() -> crate::Result<Vec<ManagedService>> {
+        if SombraRunit::detect().is_some() {
+            return SombraRunit::list_managed();
+        }
+        let mut services = vec![];
+        for entry in std::fs::read_dir(SombraLinux::unit_dir(Scope::System))? {
+            let path = entry?.path();
+            let name = match path.file_name().and_then(|n| n.to_str())
+                .and_then(|n| n.strip_suffix(".service")) {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            // Masked units are links to /dev/null
+            let unit = std::fs::read_to_string(&path).unwrap_or_default();
+            if !unit.lines().any(|line| line == MANAGED_MARKER) {
+                continue;
+            }
+            let config = SombraLinux::config_from_unit(&name, &unit, false);
+            let path = config.path.clone();
+            let status = SombraLinux::from_config(config)?.status()?;
+            services.push(ManagedService { name, status, path });
+        }
+        services.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(services)
+    }
+
+    fn required_privileges(&self) -> Privileges {
+        if let Some(runit) = &self.runit {
+            return runit.required_privileges();
+        }
+        match self.config.scope {
+            Scope::System => Privileges::Administrator,
+            Scope::User => Privileges::User,
+        }
+    }
+
+    fn logs(&self, follow: bool) -> crate::Result<LogReader> {
+        if let Some(runit) = &self.runit {
+            return runit.logs(follow);
+        }
+        let files = self.config.log_files();
+        if files.is_empty() {
+            // Without log files, systemd sends the output to the journal
+            LogReader::command(self.sysctl.journal(follow), follow)
+        } else {
+            LogReader::files(files, follow)
+        }
+    }
+
+    fn render(&self) -> crate::Result<Vec<Artifact>> {
+        if let Some(runit) = &self.runit {
+            return runit.render();
+        }
+        let mut artifacts = vec![Artifact::file(self.unit_path(),
+                                                SombraLinux::service_file_content(&self.config)?)];
+        if let Some(content) = SombraLinux::logrotate_content(&self.config) {
+            artifacts.push(Artifact::file(SombraLinux::logrotate_path(&self.config.name), content));
+        }
+        if let Some(content) = SombraLinux::polkit_content(&self.config) {
+            artifacts.push(Artifact::file(SombraLinux::polkit_path(&self.config.name), content));
+        }
+        Ok(artifacts)
+    }
+
+    fn export(&self) -> crate::Result<Scripts> {
+        if let Some(runit) = &self.runit {
+            return runit.export();
+        }
+        let mut shell = Shell::new(&self.config.name);
+        shell.write(&self.render()?);
+        shell.install(self.sysctl.line("daemon-reload"));
+        if matches!(self.config.start_type, StartType::AutoStart | StartType::DelayedAutoStart)
+            || self.config.is_triggered() {
+            shell.install(self.sysctl.line("enable"));
+        }
+        if self.config.start_type != StartType::Disabled && !self.config.is_triggered() {
+            shell.install(self.sysctl.line("start"));
+        }
+        shell.uninstall(self.sysctl.line("stop"));
+        shell.uninstall(self.sysctl.line("disable"));
+        shell.remove(self.unit_path());
+        shell.remove(SombraLinux::logrotate_path(&self.config.name));
+        shell.remove(SombraLinux::polkit_path(&self.config.name));
+        shell.uninstall(self.sysctl.line("daemon-reload"));
+        Ok(shell.finish())
     }
 }
 
@@ -112,60 +1084,632 @@ impl Sombra for SombraLinux {
 #[cfg(target_os = "linux")]
 mod tests {
     use super::*;
+    use crate::{FailureActions, Hardening, LogRotation, Priority, Probe, ResourceLimits,
+                RestartPolicy, ScopedService, SombraBuilder, StopSignal};
     use std::net::TcpStream;
-    use std::io::Read;
-    use std::time::Duration;
+    use std::io::{Read, Write};
 
     fn echo_check(ip_port: &str, msg: &[u8]) -> std::io::Result<()> {
         std::thread::sleep(Duration::from_millis(10)); // Need to allow tcp_echo open TCP connection
 
         let mut stream = TcpStream::connect(ip_port)?;
-        stream.write(msg)?;
+        stream.write_all(msg)?;
         let mut buffer = [0u8; 512];
-        stream.read(&mut buffer)?;
-        let mut buffer = buffer.to_vec();
+        let len = stream.read(&mut buffer)?;
+        let mut buffer = buffer[..len].to_vec();
         buffer.retain(|&x| x != 0);
         if buffer != msg.to_vec() {
-            Err(std::io::Error::new(std::io::ErrorKind::Other, "Not match"))
+            Err(std::io::Error::other("Not match"))
         } else {
             Ok(())
         }
     }
 
+    #[test]
+    fn active_state_mapping() {
+        assert_eq!(SombraLinux::status_from_active_state("active"), ServiceStatus::Running);
+        assert_eq!(SombraLinux::status_from_active_state("activating"),
+                   ServiceStatus::StartPending);
+        assert_eq!(SombraLinux::status_from_active_state("deactivating"),
+                   ServiceStatus::StopPending);
+        assert_eq!(SombraLinux::status_from_active_state("failed"), ServiceStatus::Failed);
+        assert_eq!(SombraLinux::status_from_active_state("inactive"), ServiceStatus::Stopped);
+    }
+
+    #[test]
+    fn stats_from_properties() {
+        let properties: Vec<(String, String)> = [("MainPID", "0"),
+                                                 ("ExecMainStartTimestampMonotonic", "5000000"),
+                                                 ("ExecMainCode", "2"),
+                                                 ("ExecMainStatus", "9"),
+                                                 ("NRestarts", "4")].iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        let stats = SombraLinux::stats_from_properties(&properties, Duration::from_secs(60));
+        assert_eq!(stats.started_at, None);
+        assert_eq!(stats.restarts, 4);
+        assert_eq!(stats.last_exit, Some(ExitReason::Signal(9)));
+
+        let mut properties = properties;
+        properties[0].1 = "42".to_string();
+        properties[2].1 = "0".to_string();
+        let stats = SombraLinux::stats_from_properties(&properties, Duration::from_secs(60));
+        let uptime = stats.uptime().unwrap();
+        assert!(uptime >= Duration::from_secs(55) && uptime < Duration::from_secs(56));
+        assert_eq!(stats.last_exit, None);
+    }
+
+    #[test]
+    fn service_file_rejects_non_utf8_args() {
+        use std::os::unix::ffi::OsStringExt;
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp_echo");
+        config.args = vec![OsString::from_vec(vec![b'-', 0xff])];
+        assert_eq!(SombraLinux::service_file_content(&config).map_err(|e| e.kind()),
+                   Err(crate::ErrorKind::Utf8));
+    }
+
+    #[test]
+    fn service_file_records_checksum() {
+        let path = std::env::current_exe().unwrap();
+        let content = SombraLinux::service_file_content(&SombraConfig::new("tcp_echo", &path))
+            .unwrap();
+        let checksum = crate::digest::sha256_file(&path).unwrap();
+        assert!(content.contains(&format!("{}\n{}{}\n", MANAGED_MARKER, CHECKSUM_KEY, checksum)));
+        let content = SombraLinux::service_file_content(
+            &SombraConfig::new("tcp_echo", "/opt/tcp_echo")).unwrap();
+        assert!(!content.contains(CHECKSUM_KEY));
+    }
+
+    #[test]
+    fn unit_refuses_health_checks() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp_echo");
+        config.health_check = Some(crate::HealthCheck::new(
+            crate::Probe::Tcp("127.0.0.1:30222".to_string())));
+        assert_eq!(SombraLinux::service_file_content(&config),
+                   Err(crate::Error::new(Other, "Health checks are only run by the Windows \
+                                                 wrapper; use HealthMonitor elsewhere"
+                       .to_string()).content("tcp_echo".to_string())));
+    }
+
+    #[test]
+    fn user_units_run_as_their_user() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp_echo");
+        config.scope = Scope::User;
+        let content = SombraLinux::service_file_content(&config).unwrap();
+        assert!(!content.contains("\nUser="));
+        assert!(content.ends_with("[Install]\nWantedBy=default.target"));
+
+        config.account = Account::LocalService;
+        let service = SombraLinux::from_config(config).unwrap();
+        assert_eq!(service.check_privileges().map_err(|e| e.kind()), Err(Other));
+        assert!(service.unit_path().ends_with("systemd/user/tcp_echo.service"));
+    }
+
+    #[test]
+    fn service_file_quotes_args() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/My App/tcp_echo");
+        config.args = vec!["C:\\Program Files\\My App\\app.exe".into(), "say \"hi\"".into(),
+                           "100%".into(), "$HOME".into(), ";".into(), "".into(), "-p".into()];
+        let content = match SombraLinux::service_file_content(&config) {
+            Ok(content) => content,
+            Err(e) => panic!("{}", e),
+        };
+        assert!(content.contains("ExecStart=\"/opt/My App/tcp_echo\" \
+                                  \"C:\\\\Program Files\\\\My App\\\\app.exe\" \
+                                  \"say \\\"hi\\\"\" 100%% $$HOME \\; \"\" -p\n"));
+
+        let parsed = SombraLinux::config_from_unit("tcp_echo", &content, false);
+        assert_eq!(parsed.path, config.path);
+        assert_eq!(parsed.args, config.args);
+    }
+
+    #[test]
+    fn exec_split_handles_single_quotes() {
+        assert_eq!(SombraLinux::exec_split("/bin/echo 'a b' c\\ d  \"'\""),
+                   vec!["/bin/echo", "a b", "c d", "'"]);
+    }
+
+    #[test]
+    fn config_from_unit_inverts_service_file() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp_echo");
+        config.args = vec!["-p".into(), "30200".into()];
+        config.description = Some("TCP echo server".to_string());
+        config.start_type = StartType::AutoStart;
+        config.account = Account::User { name: "sombra".to_string(), password: None };
+        config.group = Some("www-data".to_string());
+        config.umask = Some(0o027);
+        config.capabilities = vec!["CAP_NET_BIND_SERVICE".to_string(), "CAP_NET_RAW".to_string()];
+        config.dependencies = vec!["postgresql".to_string()];
+        config.env = vec![("GREETING".to_string(), "say \"100%\"".to_string())];
+        config.working_directory = Some(PathBuf::from("/opt"));
+        config.stdout_log = Some(PathBuf::from("/var/log/tcp_echo.log"));
+        config.stop_signal = StopSignal::Interrupt;
+        config.stop_timeout = Some(Duration::from_secs(5));
+        config.kill_process_tree = false;
+        config.priority = Some(Priority::BelowNormal);
+        config.cpu_affinity = Some(0b101);
+        config.hardening = Some(Hardening {
+            read_write_paths: vec![PathBuf::from("/var/lib/tcp echo")],
+            system_call_filter: vec!["@system-service".to_string(), "~@mount".to_string()],
+            system_call_architectures: vec!["native".to_string()],
+            ..Hardening::strict()
+        });
+        config.restart_policy = Some(RestartPolicy {
+            max_restarts: 5,
+            window: Duration::from_secs(60),
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(8),
+        });
+        let unit = SombraLinux::service_file_content(&config).unwrap();
+        assert_eq!(SombraLinux::config_from_unit("tcp_echo", &unit, true), config);
+        assert!(unit.lines().any(|line| line == MANAGED_MARKER));
+
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp_echo");
+        config.failure_actions = Some(FailureActions::restart(Duration::from_secs(2)));
+        config.journal = true;
+        let unit = SombraLinux::service_file_content(&config).unwrap();
+        assert_eq!(SombraLinux::config_from_unit("tcp_echo", &unit, false), config);
+    }
+
+    #[test]
+    fn service_file_hardening() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp_echo");
+        config.hardening = Some(Hardening {
+            read_write_paths: vec![PathBuf::from("/var/lib/tcp_echo")],
+            ..Hardening::strict()
+        });
+        let content = SombraLinux::service_file_content(&config).unwrap();
+        assert!(content.contains("ProtectSystem=strict\nProtectHome=yes\nPrivateTmp=yes\n\
+                                  NoNewPrivileges=yes\nReadWritePaths=\"/var/lib/tcp_echo\"\n"));
+
+        config.hardening = Some(Hardening {
+            protect_home: Some(ProtectHome::ReadOnly),
+            system_call_filter: vec!["@system-service".to_string()],
+            system_call_architectures: vec!["native".to_string()],
+            ..Default::default()
+        });
+        let content = SombraLinux::service_file_content(&config).unwrap();
+        assert!(content.contains("ProtectHome=read-only\nSystemCallFilter=@system-service\n\
+                                  SystemCallArchitectures=native\n"));
+        assert!(!content.contains("ProtectSystem=") && !content.contains("PrivateTmp="));
+    }
+
+    #[test]
+    fn service_file_group_and_umask() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp_echo");
+        config.group = Some("www-data".to_string());
+        config.umask = Some(0o27);
+        let content = SombraLinux::service_file_content(&config).unwrap();
+        assert!(content.contains("\nGroup=www-data\nUMask=0027\n"));
+
+        // The manager of a user can't switch groups
+        config.scope = Scope::User;
+        let content = SombraLinux::service_file_content(&config).unwrap();
+        assert!(!content.contains("Group=") && content.contains("UMask=0027\n"));
+    }
+
+    #[test]
+    fn service_file_capabilities() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp_echo");
+        config.account = Account::LocalService;
+        config.capabilities = vec!["CAP_NET_BIND_SERVICE".to_string()];
+        let content = SombraLinux::service_file_content(&config).unwrap();
+        assert!(content.contains("\nAmbientCapabilities=CAP_NET_BIND_SERVICE\n"));
+
+        let error = SombraBuilder::new("tcp_echo", "/bin/sh").capability("net_bind_service")
+            .config().unwrap_err();
+        assert_eq!(error.kind(), Other);
+    }
+
+    #[test]
+    fn service_file_root_directory() {
+        let mut config = SombraConfig::new("tcp_echo", "/srv/jail/bin/tcp_echo");
+        config.working_directory = Some(PathBuf::from("/srv/jail/bin"));
+        config.root_directory = Some(PathBuf::from("/srv/jail"));
+        config.bind_paths = vec![PathBuf::from("/etc/resolv.conf")];
+        let content = SombraLinux::service_file_content(&config).unwrap();
+        assert!(content.contains("\nRootDirectory=/srv/jail\n\
+                                  BindReadOnlyPaths=\"/etc/resolv.conf\"\n\
+                                  WorkingDirectory=/bin\n"));
+        assert!(content.contains("\nExecStart=/bin/tcp_echo\n"));
+        assert_eq!(SombraLinux::config_from_unit("tcp_echo", &content, false), config);
+
+        let error = SombraBuilder::new("tcp_echo", "/bin/sh").root_directory("/tmp")
+            .config().unwrap_err();
+        assert_eq!(error.kind(), Other);
+    }
+
+    #[test]
+    fn service_file_description() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp_echo");
+        let content = SombraLinux::service_file_content(&config).unwrap();
+        assert!(content.contains("Description=tcp_echo service\n"));
+
+        config.display_name = Some("TCP Echo".to_string());
+        let content = SombraLinux::service_file_content(&config).unwrap();
+        assert!(content.contains("Description=TCP Echo\n"));
+
+        config.description = Some("TCP echo server".to_string());
+        let content = SombraLinux::service_file_content(&config).unwrap();
+        assert!(content.contains("Description=TCP echo server\n"));
+    }
+
+    #[test]
+    fn service_file_notify() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp_echo");
+        config.notify = true;
+        config.start_timeout = Some(Duration::from_secs(120));
+        let content = match SombraLinux::service_file_content(&config) {
+            Ok(content) => content,
+            Err(e) => panic!("{}", e),
+        };
+        assert!(content.contains("[Service]\nType=notify\nNotifyAccess=main\n"));
+        assert!(content.contains("TimeoutStartSec=120000ms\n"));
+    }
+
+    #[test]
+    fn service_file_restart_policy() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp_echo");
+        config.failure_actions = Some(FailureActions::restart(Duration::from_secs(5)));
+        config.restart_policy = Some(RestartPolicy {
+            max_restarts: 5,
+            window: Duration::from_secs(60),
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+        });
+        let content = match SombraLinux::service_file_content(&config) {
+            Ok(content) => content,
+            Err(e) => panic!("{}", e),
+        };
+        assert!(content.contains("StartLimitIntervalSec=60000ms\nStartLimitBurst=5\n"));
+        assert!(content.contains("Restart=on-failure\nRestartSec=1000ms\nRestartSteps=5\n\
+                                  RestartMaxDelaySec=30000ms"));
+    }
+
+    #[test]
+    fn service_file_watchdog() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp_echo");
+        config.watchdog = Some(Duration::from_secs(30));
+        let content = match SombraLinux::service_file_content(&config) {
+            Ok(content) => content,
+            Err(e) => panic!("{}", e),
+        };
+        assert!(content.contains("[Service]\nType=simple\nNotifyAccess=main\n"));
+        assert!(content.contains("WatchdogSec=30000ms\nRestart=on-watchdog"));
+    }
+
+    #[test]
+    fn service_file_user() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp_echo");
+        config.account = Account::LocalService;
+        let content = SombraLinux::service_file_content(&config).unwrap();
+        assert!(content.contains("User=nobody\n"));
+
+        config.account = Account::User { name: "echo".to_string(), password: None };
+        let content = SombraLinux::service_file_content(&config).unwrap();
+        assert!(content.contains("User=echo\n"));
+
+        config.account = Account::Virtual;
+        let content = SombraLinux::service_file_content(&config).unwrap();
+        assert!(content.contains("User=tcp_echo\nDynamicUser=yes\n"));
+
+        config.account = Account::Managed("DOMAIN\\echo$".to_string());
+        assert!(SombraLinux::service_file_content(&config).is_err());
+    }
+
+    #[test]
+    fn service_file_dependencies() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp_echo");
+        config.dependencies = vec!["postgresql".to_string(), "network-online.target".to_string()];
+        let content = SombraLinux::service_file_content(&config).unwrap();
+        assert!(content.contains(
+            "After=network.target postgresql.service network-online.target\n"));
+        assert!(content.contains("Requires=postgresql.service network-online.target\n"));
+    }
+
+    #[test]
+    fn service_file_restart_on_failure() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp_echo");
+        config.failure_actions = Some(FailureActions::restart(Duration::from_secs(5)));
+        let content = SombraLinux::service_file_content(&config).unwrap();
+        assert!(content.contains("Restart=on-failure\nRestartSec=5000ms\n"));
+    }
+
+    #[test]
+    fn service_file_environment() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp_echo");
+        config.env = vec![("RUST_LOG".to_string(), "debug".to_string()),
+                          ("PROGRESS".to_string(), "100%".to_string()),
+                          ("GREETING".to_string(), "say \"hi\"".to_string())];
+        let content = SombraLinux::service_file_content(&config).unwrap();
+        assert!(content.contains("Environment=\"RUST_LOG=debug\"\n"));
+        assert!(content.contains("Environment=\"PROGRESS=100%%\"\n"));
+        assert!(content.contains("Environment=\"GREETING=say \\\"hi\\\"\"\n"));
+    }
+
+    #[test]
+    fn builder_default_working_directory() {
+        let config = SombraBuilder::new("tcp_echo", "executables/tcp_echo").config().unwrap();
+        let content = SombraLinux::service_file_content(&config).unwrap();
+        let dir = dunce::canonicalize("executables").unwrap();
+        assert!(content.contains(&format!("WorkingDirectory={}\n", dir.display())));
+    }
+
+    #[test]
+    fn service_file_log_files() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp_echo");
+        config.stdout_log = Some(PathBuf::from("/var/log/tcp_echo.out"));
+        config.stderr_log = Some(PathBuf::from("/var/log/tcp_echo.err"));
+        let content = SombraLinux::service_file_content(&config).unwrap();
+        assert!(content.contains("StandardOutput=append:/var/log/tcp_echo.out\n\
+                                  StandardError=append:/var/log/tcp_echo.err\n"));
+    }
+
+    #[test]
+    fn ssh_host_needs_the_feature() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp_echo");
+        config.ssh_host = Some("root@example.com".to_string());
+        assert_eq!(SombraLinux::from_config(config).is_err(), cfg!(not(feature = "ssh")));
+    }
+
+    #[test]
+    fn service_file_escapes_paths() {
+        let mut config = SombraConfig::new("tcp_echo", "/srv/my jail/bin/tcp_echo");
+        config.root_directory = Some(PathBuf::from("/srv/my jail"));
+        config.bind_paths = vec![PathBuf::from("/etc/100% \"ok\"\\")];
+        config.working_directory = Some(PathBuf::from("/srv/my jail/100%"));
+        config.stdout_log = Some(PathBuf::from("/var/log/tcp echo/%n.out"));
+        config.stderr_log = Some(PathBuf::from("/var/log/tcp echo\\err"));
+        config.hardening = Some(Hardening {
+            read_write_paths: vec![PathBuf::from("/var/lib/tcp echo/50%")],
+            ..Hardening::default()
+        });
+        let content = SombraLinux::service_file_content(&config).unwrap();
+        assert!(content.contains("\nRootDirectory=/srv/my jail\n\
+                                  BindReadOnlyPaths=\"/etc/100%% \\\"ok\\\"\\\\\"\n\
+                                  WorkingDirectory=/100%%\n"));
+        assert!(content.contains("\nStandardOutput=append:/var/log/tcp echo/%%n.out\n\
+                                  StandardError=append:/var/log/tcp echo\\err\n"));
+        assert!(content.contains("\nReadWritePaths=\"/var/lib/tcp echo/50%%\"\n"));
+        assert_eq!(SombraLinux::config_from_unit("tcp_echo", &content, false), config);
+
+        config.working_directory = Some(PathBuf::from("/srv/my jail/a\nb"));
+        assert!(SombraLinux::service_file_content(&config).is_err());
+    }
+
+    #[test]
+    fn logrotate_file() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp_echo");
+        config.log_rotation = Some(LogRotation { max_size: Some(1024), max_age: None, keep: 3 });
+        assert_eq!(SombraLinux::logrotate_content(&config), None);
+
+        config.stdout_log = Some(PathBuf::from("/var/log/tcp_echo.out"));
+        config.stderr_log = Some(PathBuf::from("/var/log/tcp_echo.err"));
+        assert_eq!(SombraLinux::logrotate_content(&config).unwrap(),
+                   "/var/log/tcp_echo.out /var/log/tcp_echo.err {\n    size 1024\n    rotate 3\n    \
+                    missingok\n    notifempty\n    copytruncate\n}\n");
+
+        config.log_rotation = Some(LogRotation {
+            max_size: Some(1024),
+            max_age: Some(Duration::from_secs(7 * 24 * 60 * 60)),
+            keep: 3,
+        });
+        let content = SombraLinux::logrotate_content(&config).unwrap();
+        assert!(content.contains("    weekly\n    maxsize 1024\n"));
+    }
+
+    #[test]
+    fn service_file_triggers() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp_echo");
+        config.triggers = vec![Trigger::NetworkAvailable,
+                               Trigger::Device("/dev/disk/by-label/DATA".to_string()),
+                               Trigger::DomainJoin];
+        let content = SombraLinux::service_file_content(&config).unwrap();
+        let device = "dev-disk-by\\x2dlabel-DATA.device";
+        assert!(content.contains(&format!("After=network.target network-online.target {}\n",
+                                          device)));
+        assert!(content.contains(&format!("Wants=network-online.target\nBindsTo={}\n\
+                                           ConditionPathExists=/etc/krb5.keytab\n", device)));
+        assert!(content.ends_with(&format!("[Install]\nWantedBy=network-online.target\n\
+                                            WantedBy={}", device)));
+
+        let installed = SombraLinux::config_from_unit("tcp_echo", &content, true);
+        assert_eq!(installed.triggers, config.triggers);
+        assert_eq!(installed.start_type, StartType::OnDemand);
+
+        config.start_type = StartType::AutoStart;
+        let content = SombraLinux::service_file_content(&config).unwrap();
+        assert!(content.contains("[Install]\nWantedBy=multi-user.target\n"));
+        assert_eq!(SombraLinux::config_from_unit("tcp_echo", &content, true).start_type,
+                   StartType::AutoStart);
+    }
+
+    #[test]
+    fn device_unit_names() {
+        assert_eq!(SombraLinux::device_unit("/dev/ttyUSB0"), "dev-ttyUSB0.device");
+        assert_eq!(SombraLinux::device_unit("/dev//sda1/"), "dev-sda1.device");
+        assert_eq!(SombraLinux::device_unit("/dev/.x y"), "dev-.x\\x20y.device");
+        assert_eq!(SombraLinux::device_path("dev-disk-by\\x2dlabel-DATA.device").unwrap(),
+                   "/dev/disk/by-label/DATA");
+        assert_eq!(SombraLinux::device_path("tcp_echo.service"), None);
+    }
+
+    #[test]
+    fn polkit_rule() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp_echo");
+        assert_eq!(SombraLinux::polkit_content(&config), None);
+
+        config.operators = vec!["ops".to_string(), "devs".to_string()];
+        assert_eq!(SombraLinux::polkit_content(&config).unwrap(),
+                   "// Managed by sombra\n\
+                    polkit.addRule(function(action, subject) {\n    \
+                    if (action.id == \"org.freedesktop.systemd1.manage-units\" &&\n        \
+                    action.lookup(\"unit\") == \"tcp_echo.service\" &&\n        \
+                    [\"start\", \"stop\", \"restart\"]\
+                    .indexOf(action.lookup(\"verb\")) >= 0 &&\n        \
+                    (subject.isInGroup(\"ops\") || subject.isInGroup(\"devs\"))) {\n        \
+                    return polkit.Result.YES;\n    \
+                    }\n\
+                    });\n");
+
+        let content = SombraLinux::service_file_content(&config).unwrap();
+        assert!(content.contains("X-Sombra-Operators=ops devs\n"));
+        assert_eq!(SombraLinux::config_from_unit("tcp_echo", &content, false).operators,
+                   config.operators);
+
+        config.scope = Scope::User;
+        assert_eq!(SombraLinux::service_file_content(&config).map_err(|e| e.kind()), Err(Other));
+    }
+
+    #[test]
+    fn render_returns_unit_and_logrotate() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp_echo");
+        config.stdout_log = Some(PathBuf::from("/var/log/tcp_echo.out"));
+        config.log_rotation = Some(LogRotation { max_size: Some(1024), max_age: None, keep: 3 });
+        let sysctl = Systemctl::new(&config.name, false, Host::new(&config));
+        let linux = SombraLinux { config: config.clone(), sysctl, runit: None };
+        let artifacts = linux.render().unwrap();
+        assert_eq!(artifacts.len(), 2);
+        assert_eq!(artifacts[0].path, Some(PathBuf::from("/etc/systemd/system/tcp_echo.service")));
+        assert_eq!(artifacts[0].content, SombraLinux::service_file_content(&config).unwrap());
+        assert_eq!(artifacts[1].path, Some(PathBuf::from("/etc/logrotate.d/tcp_echo")));
+        assert!(!linux.unit_path().exists());
+    }
+
+    #[test]
+    fn export_writes_unit_and_starts_it() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp_echo");
+        config.start_type = StartType::AutoStart;
+        let sysctl = Systemctl::new(&config.name, false, Host::new(&config));
+        let linux = SombraLinux { config: config.clone(), sysctl, runit: None };
+        let scripts = linux.export().unwrap();
+        assert!(scripts.install.starts_with("#!/bin/sh\n# Installs the tcp_echo service\nset -e\n\
+                                             mkdir -p /etc/systemd/system\nprintf '%s' '[Unit]\n"));
+        assert!(scripts.install.contains("WantedBy=multi-user.target' > \
+                                          /etc/systemd/system/tcp_echo.service\n\
+                                          chmod 644 /etc/systemd/system/tcp_echo.service\n"));
+        assert!(scripts.install.ends_with("systemctl daemon-reload\nsystemctl enable tcp_echo\n\
+                                           systemctl start tcp_echo\n"));
+        assert!(scripts.uninstall.contains("systemctl stop tcp_echo\nsystemctl disable tcp_echo\n\
+                                            rm -f /etc/systemd/system/tcp_echo.service\n"));
+        assert!(!linux.unit_path().exists());
+    }
+
+    #[test]
+    fn service_file_stop_signal() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp_echo");
+        let content = match SombraLinux::service_file_content(&config) {
+            Ok(content) => content,
+            Err(e) => panic!("{}", e),
+        };
+        assert!(content.contains("KillSignal=SIGTERM\nKillMode=control-group\n"));
+        assert!(!content.contains("TimeoutStopSec="));
+
+        config.stop_signal = StopSignal::Interrupt;
+        config.stop_timeout = Some(Duration::from_secs(30));
+        let content = match SombraLinux::service_file_content(&config) {
+            Ok(content) => content,
+            Err(e) => panic!("{}", e),
+        };
+        assert!(content.contains("KillSignal=SIGINT\nKillMode=control-group\nTimeoutStopSec=30000ms\n"));
+
+        config.kill_process_tree = false;
+        let content = match SombraLinux::service_file_content(&config) {
+            Ok(content) => content,
+            Err(e) => panic!("{}", e),
+        };
+        assert!(content.contains("KillMode=process\n"));
+    }
+
+    #[test]
+    fn service_file_resource_limits() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp_echo");
+        config.resource_limits = Some(ResourceLimits {
+            memory_max: Some(512 * 1024 * 1024),
+            cpu_quota: Some(150),
+        });
+        let content = match SombraLinux::service_file_content(&config) {
+            Ok(content) => content,
+            Err(e) => panic!("{}", e),
+        };
+        assert!(content.contains("MemoryMax=536870912\nCPUQuota=150%\nExecStart="));
+    }
+
+    #[test]
+    fn service_file_priority_and_affinity() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp_echo");
+        config.priority = Some(Priority::BelowNormal);
+        config.cpu_affinity = Some(0b1011);
+        let content = match SombraLinux::service_file_content(&config) {
+            Ok(content) => content,
+            Err(e) => panic!("{}", e),
+        };
+        assert!(content.contains("Nice=10\nCPUAffinity=0 1 3\n"));
+    }
+
+    #[test]
+    fn cpu_mask_expands_ranges() {
+        assert_eq!(SombraLinux::cpu_mask("0 1 3"), Some(0b1011));
+        assert_eq!(SombraLinux::cpu_mask("0-3,8"), Some(0b1_0000_1111));
+        assert_eq!(SombraLinux::cpu_mask("63"), Some(1 << 63));
+        assert_eq!(SombraLinux::cpu_mask("64"), None);
+        assert_eq!(SombraLinux::cpu_mask("60-70"), None);
+        assert_eq!(SombraLinux::cpu_mask("one"), None);
+    }
+
+    #[test]
+    fn service_file_journal() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp_echo");
+        config.journal = true;
+        let content = match SombraLinux::service_file_content(&config) {
+            Ok(content) => content,
+            Err(e) => panic!("{}", e),
+        };
+        assert!(content.contains("StandardOutput=journal\nStandardError=journal\n\
+                                  SyslogIdentifier=tcp_echo\nLogExtraFields=SOMBRA_SERVICE=tcp_echo\n"));
+    }
+
+    #[test]
+    fn builder_journal_excludes_log_files() {
+        let builder = SombraBuilder::new("tcp_echo", "executables/tcp_echo")
+            .journal(true)
+            .stdout_log("tcp_echo.out");
+        assert!(builder.config().is_err());
+    }
+
     #[test]
     fn spawn_simple() {
-        let s = match SombraLinux::build("tcp_echo", "executables/tcp_echo", vec![]) {
+        let s = match SombraLinux::build("tcp_echo", "executables/tcp_echo", Vec::<String>::new()) {
             Ok(s) => s,
-            Err(e) => panic!(e.to_string()),
+            Err(e) => panic!("{}", e),
         };
-        assert_eq!(s.create(), Ok(()));
-        let res = echo_check("127.0.0.1:30222", b"sombra30222");
-        assert_eq!(s.delete(), Ok(()));
-        if let Err(e) = res {
-            panic!(format!("{:?}", e));
+        let _s = ScopedService::new(s).unwrap();
+        if let Err(e) = echo_check("127.0.0.1:30222", b"sombra30222") {
+            panic!("{:?}", e);
         }
     }
 
     #[test]
     fn spawn_twice_same_name() {
-        let s = match SombraLinux::build("tcp_echo", "executables/tcp_echo", vec![]) {
+        let s = match SombraLinux::build("tcp_echo", "executables/tcp_echo", Vec::<String>::new()) {
             Ok(s) => s,
-            Err(e) => panic!(e.to_string()),
+            Err(e) => panic!("{}", e),
         };
         assert_eq!(s.create(), Ok(()));
 
         match echo_check("127.0.0.1:30222", b"sombra30222") {
             Ok(_) => {
-                let s2 = match SombraLinux::build("tcp_echo", "executables/tcp_echo", vec![]) {
+                let s2 = match SombraLinux::build("tcp_echo", "executables/tcp_echo",
+                                                  Vec::<String>::new()) {
                     Ok(s2) => s2,
-                    Err(e) => panic!(e.to_string()),
+                    Err(e) => panic!("{}", e),
                 };
                 assert_ne!(s2.create(), Ok(()));
                 assert_eq!(s.delete(), Ok(()));
             },
             Err(e) => {
                 assert_eq!(s.delete(), Ok(()));
-                panic!(format!("{:?}", e));
+                panic!("{:?}", e);
             }
         }
     }
@@ -176,7 +1720,7 @@ mod tests {
                                      "executables/tcp_echo",
                                      vec!["-p".to_string(), "30222".to_string()]) {
             Ok(s) => s,
-            Err(e) => panic!(e.to_string()),
+            Err(e) => panic!("{}", e),
         };
         assert_eq!(s.create(), Ok(()));
 
@@ -186,7 +1730,7 @@ mod tests {
                                               "executables/tcp_echo",
                                               vec!["-p".to_string(), "30223".to_string()]) {
                     Ok(s) => s,
-                    Err(e) => panic!(e.to_string()),
+                    Err(e) => panic!("{}", e),
                 };
                 assert_eq!(s2.create(), Ok(()));
                 match echo_check("127.0.0.1:30223", b"sombra30223") {
@@ -197,13 +1741,13 @@ mod tests {
                     Err(e) => {
                         assert_eq!(s.delete(), Ok(()));
                         assert_eq!(s2.delete(), Ok(()));
-                        panic!(format!("{:?}", e));
+                        panic!("{:?}", e);
                     },
                 }
             },
             Err(e) => {
                 assert_eq!(s.delete(), Ok(()));
-                panic!(format!("{:?}", e));
+                panic!("{:?}", e);
             }
         }
     }
@@ -214,21 +1758,21 @@ mod tests {
                                      "executables/tcp_echo",
                                      vec!["-p".to_string(), "30223".to_string()]) {
             Ok(s) => s,
-            Err(e) => panic!(e.to_string()),
+            Err(e) => panic!("{}", e),
         };
         assert_eq!(s.create(), Ok(()));
         let res = echo_check("127.0.0.1:30223", b"sombra30223");
         assert_eq!(s.delete(), Ok(()));
         if let Err(e) = res {
-            panic!(format!("{:?}", e));
+            panic!("{:?}", e);
         }
     }
 
     #[test]
     fn spawn_once_delete_twice() {
-        let s = match SombraLinux::build("tcp_echo", "executables/tcp_echo", vec![]) {
+        let s = match SombraLinux::build("tcp_echo", "executables/tcp_echo", Vec::<String>::new()) {
             Ok(s) => s,
-            Err(e) => panic!(e.to_string()),
+            Err(e) => panic!("{}", e),
         };
         assert_eq!(s.create(), Ok(()));
         match echo_check("127.0.0.1:30222", b"sombra30222") {
@@ -238,16 +1782,16 @@ mod tests {
             },
             Err(e) => {
                 assert_eq!(s.delete(), Ok(()));
-                panic!(format!("{:?}", e));
+                panic!("{:?}", e);
             }
         }
     }
 
     #[test]
     fn spawn_bug_and_correct() {
-        let s = match SombraLinux::build("tcp_echo", "executables/tcp_echo", vec![]) {
+        let s = match SombraLinux::build("tcp_echo", "executables/tcp_echo", Vec::<String>::new()) {
             Ok(s) => s,
-            Err(e) => panic!(e.to_string()),
+            Err(e) => panic!("{}", e),
         };
         assert_eq!(s.create(), Ok(()));
         match echo_check("127.0.0.1:30222", b"bug") {
@@ -260,16 +1804,122 @@ mod tests {
                     },
                     Err(e) => {
                         assert_eq!(s.delete(), Ok(()));
-                        panic!(format!("{:?}", e));
+                        panic!("{:?}", e);
                     }
                 }
             },
             Err(e) => {
                 assert_eq!(s.delete(), Ok(()));
-                panic!(format!("{:?}", e));
+                panic!("{:?}", e);
             }
         }
     }
+
+    #[test]
+    fn spawn_stop_and_start() {
+        let s = match SombraLinux::build("tcp_echo", "executables/tcp_echo", Vec::<String>::new()) {
+            Ok(s) => s,
+            Err(e) => panic!("{}", e),
+        };
+        assert_eq!(s.create(), Ok(()));
+        assert_eq!(s.exists(), Ok(true));
+        assert_eq!(s.status(), Ok(ServiceStatus::Running));
+        assert_eq!(s.stop(), Ok(()));
+        assert_eq!(s.status(), Ok(ServiceStatus::Stopped));
+        if echo_check("127.0.0.1:30222", b"sombra30222").is_ok() {
+            assert_eq!(s.delete(), Ok(()));
+            panic!("Service still running after stop");
+        }
+        assert_eq!(s.start(), Ok(()));
+        let res = echo_check("127.0.0.1:30222", b"sombra30222");
+        assert_eq!(s.delete(), Ok(()));
+        assert_eq!(s.status(), Ok(ServiceStatus::NotInstalled));
+        assert_eq!(s.exists(), Ok(false));
+        if let Err(e) = res {
+            panic!("{:?}", e);
+        }
+    }
+
+    #[test]
+    fn spawn_and_restart() {
+        let s = match SombraLinux::build("tcp_echo", "executables/tcp_echo", Vec::<String>::new()) {
+            Ok(s) => s,
+            Err(e) => panic!("{}", e),
+        };
+        assert_eq!(s.create(), Ok(()));
+        assert_eq!(s.restart(), Ok(()));
+        let res = echo_check("127.0.0.1:30222", b"sombra30222");
+        assert_eq!(s.delete(), Ok(()));
+        if let Err(e) = res {
+            panic!("{:?}", e);
+        }
+    }
+
+    #[test]
+    fn spawn_disabled() {
+        let s = match SombraBuilder::new("tcp_echo", "executables/tcp_echo")
+            .start_type(StartType::Disabled)
+            .build() {
+            Ok(s) => s,
+            Err(e) => panic!("{}", e),
+        };
+        assert_eq!(s.create(), Ok(()));
+        let res = s.status();
+        assert_eq!(s.delete(), Ok(()));
+        assert_eq!(res, Ok(ServiceStatus::Stopped));
+    }
+
+    #[test]
+    fn spawn_update() {
+        let s = match SombraBuilder::new("tcp_echo", "executables/tcp_echo")
+            .start_type(StartType::Disabled)
+            .build() {
+            Ok(s) => s,
+            Err(e) => panic!("{}", e),
+        };
+        assert_eq!(s.create(), Ok(()));
+        let updated = match SombraBuilder::new("tcp_echo", "executables/tcp_echo")
+            .description("Updated echo")
+            .build() {
+            Ok(s) => s,
+            Err(e) => panic!("{}", e),
+        };
+        assert_eq!(updated.update(), Ok(()));
+        assert_eq!(updated.start(), Ok(()));
+        let res = echo_check("127.0.0.1:30222", b"sombra30222");
+        assert_eq!(updated.delete(), Ok(()));
+        if let Err(e) = res {
+            panic!("{:?}", e);
+        }
+    }
+
+    #[test]
+    fn spawn_wait_ready() {
+        let s = match SombraBuilder::new("tcp_echo", "executables/tcp_echo")
+            .wait_ready(Probe::Tcp("127.0.0.1:30222".to_string()), Duration::from_secs(5))
+            .build() {
+            Ok(s) => s,
+            Err(e) => panic!("{}", e),
+        };
+        let res = s.create();
+        assert_eq!(s.delete(), Ok(()));
+        assert_eq!(res, Ok(()));
+    }
+
+    #[test]
+    fn spawn_create_or_update_twice() {
+        let s = match SombraLinux::build("tcp_echo", "executables/tcp_echo", Vec::<String>::new()) {
+            Ok(s) => s,
+            Err(e) => panic!("{}", e),
+        };
+        assert_eq!(s.create_or_update(), Ok(()));
+        assert_eq!(s.create_or_update(), Ok(()));
+        let res = echo_check("127.0.0.1:30222", b"sombra30222");
+        assert_eq!(s.delete(), Ok(()));
+        if let Err(e) = res {
+            panic!("{:?}", e);
+        }
+    }
 }
 
 // Run test on linux as sudo
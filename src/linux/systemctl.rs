@@ -1,57 +1,138 @@
+use crate::linux::host::Host;
+
 pub struct Systemctl {
-    name: String
+    name: String,
+    /// Drives the manager of the current user, `systemctl --user`
+    user: bool,
+    host: Host,
 }
 
 impl Systemctl {
-    pub fn new(name: &str) -> Self {
+    pub fn new(name: &str, user: bool, host: Host) -> Self {
         Systemctl {
-            name: name.to_string()
+            name: name.to_string(),
+            user,
+            host,
+        }
+    }
+
+    /// Machine running the manager
+    pub fn host(&self) -> &Host {
+        &self.host
+    }
+
+    fn command(&self) -> std::process::Command {
+        let mut command = std::process::Command::new("systemctl");
+        if self.user {
+            command.arg("--user");
         }
+        command
+    }
+
+    /// The command line of `action` for a script run on the host itself,
+    /// naming the unit unless the action is about the manager
+    pub fn line(&self, action: &str) -> String {
+        let mut words = vec!["systemctl"];
+        if self.user {
+            words.push("--user");
+        }
+        words.push(action);
+        if action != "daemon-reload" {
+            words.push(&self.name);
+        }
+        let words: Vec<String> = words.iter()
+            .map(|word| crate::unix::shell::word(word.as_ref()))
+            .collect();
+        words.join(" ")
     }
 
     pub fn start(&self) -> crate::Result<()> {
-        let _ = std::process::Command::new("systemctl")
+        let _ = self.host.output(self.command()
             .arg("start")
-            .arg(&self.name)
-            .output()?;
+            .arg(&self.name))?;
         Ok(())
     }
 
     pub fn stop(&self) -> crate::Result<()> {
-        let _ = std::process::Command::new("systemctl")
+        let _ = self.host.output(self.command()
             .arg("stop")
-            .arg(&self.name)
-            .output()?;
+            .arg(&self.name))?;
         Ok(())
     }
 
-    pub fn _is_active(&self) -> crate::Result<bool> {
-        let output = std::process::Command::new("systemctl")
+    pub fn is_active(&self) -> crate::Result<String> {
+        let output = self.host.output(self.command()
             .arg("is-active")
-            .arg(&self.name)
-            .output()?;
-        Ok(std::str::from_utf8(output.stdout.as_slice())? == "active")
+            .arg(&self.name))?;
+        Ok(std::str::from_utf8(output.stdout.as_slice())?.trim().to_string())
+    }
+
+    /// MainPID of the unit, 0 when it isn't running
+    pub fn main_pid(&self) -> crate::Result<u32> {
+        let output = self.host.output(self.command()
+            .arg("show")
+            .arg("--property=MainPID")
+            .arg("--value")
+            .arg(&self.name))?;
+        let stdout = std::str::from_utf8(output.stdout.as_slice())?.trim();
+        Ok(stdout.parse().unwrap_or(0))
+    }
+
+    /// Properties of the unit, as property=value pairs
+    pub fn show(&self, properties: &[&str]) -> crate::Result<Vec<(String, String)>> {
+        let mut command = self.command();
+        command.arg("show");
+        for property in properties {
+            command.arg(format!("--property={}", property));
+        }
+        let output = self.host.output(command.arg(&self.name))?;
+        let stdout = std::str::from_utf8(output.stdout.as_slice())?;
+        Ok(stdout.lines()
+            .filter_map(|line| {
+                let mut parts = line.splitn(2, '=');
+                Some((parts.next()?.to_string(), parts.next()?.to_string()))
+            })
+            .collect())
+    }
+
+    pub fn enable(&self) -> crate::Result<()> {
+        let _ = self.host.output(self.command()
+            .arg("enable")
+            .arg(&self.name))?;
+        Ok(())
     }
 
     pub fn disable(&self) -> crate::Result<()> {
-        let _ = std::process::Command::new("systemctl")
+        let _ = self.host.output(self.command()
             .arg("disable")
-            .arg(&self.name)
-            .output()?;
+            .arg(&self.name))?;
         Ok(())
     }
 
-    pub fn daemon_reload() -> crate::Result<()> {
-        let _ = std::process::Command::new("systemctl")
-            .arg("daemon-reload")
-            .output()?;
+    pub fn daemon_reload(&self) -> crate::Result<()> {
+        let _ = self.host.output(self.command()
+            .arg("daemon-reload"))?;
         Ok(())
     }
 
-    pub fn reset_failed() -> crate::Result<()> {
-        let _ = std::process::Command::new("systemctl")
-            .arg("reset-failed")
-            .output()?;
+    /// journalctl invocation printing the messages of the unit
+    pub fn journal(&self, follow: bool) -> std::process::Command {
+        let mut command = std::process::Command::new("journalctl");
+        if self.user {
+            command.arg("--user");
+        }
+        command.arg("--unit").arg(&self.name)
+            .arg("--output").arg("cat")
+            .arg("--no-pager");
+        if follow {
+            command.arg("--follow");
+        }
+        self.host.command(command)
+    }
+
+    pub fn reset_failed(&self) -> crate::Result<()> {
+        let _ = self.host.output(self.command()
+            .arg("reset-failed"))?;
         Ok(())
     }
 }
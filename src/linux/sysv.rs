@@ -0,0 +1,412 @@
+use crate::{Artifact, Difference, LogReader, ManagedService, Scripts, Sombra, ServiceStats,
+            ServiceStatus, SombraConfig, StartType, Usage};
+use crate::export::Shell;
+use crate::support::{self, Feature};
+use crate::unix::shell::quote;
+use crate::unix::supervisor::Supervisor;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// State directories of the launchers started by the init scripts, apart
+/// from those of cron
+const STATE_DIR: &str = "/var/lib/sombra/init.d";
+
+/// Runs the target from an LSB init script in /etc/init.d, for the RHEL 6
+/// and older Debian hosts without systemd. The script starts the launcher
+/// of `SombraCron`, as the account of the service, which restarts the
+/// target as the restart policy asks. Services started at boot are
+/// registered with update-rc.d on Debian, and with chkconfig elsewhere.
+pub struct SombraSysv {
+    config: SombraConfig,
+    supervisor: Supervisor,
+}
+
+impl SombraSysv {
+    fn script_path(name: &str) -> PathBuf {
+        PathBuf::from(format!("/etc/init.d/{}", name))
+    }
+
+    /// Fails on the settings the launcher can't apply, and on per-user
+    /// services, as init has no manager per user
+    fn check(config: &SombraConfig) -> crate::Result<()> {
+        match support::unsupported(config, &[Feature::RootDirectory]) {
+            Some(description) => Err(crate::Error::new(crate::ErrorKind::Other,
+                                                       description.to_string())
+                .content(config.name.clone())),
+            None => Ok(()),
+        }
+    }
+
+    fn is_root() -> crate::Result<()> {
+        if unsafe { libc::geteuid() } != 0 {
+            return Err(crate::Error::new(crate::ErrorKind::AccessDenied,
+                                         "Without root privileges.".to_string()));
+        }
+        Ok(())
+    }
+
+    fn script_content(config: &SombraConfig, supervisor: &Supervisor)
+        -> crate::Result<String> {
+        let user = config.account.unix_name(&config.name)?;
+        let launcher = quote(supervisor.launcher().as_os_str());
+        // The launcher leads a session of its own, for stop to signal
+        let spawn = if user == "root" {
+            format!("setsid /bin/sh {}", launcher)
+        } else {
+            format!("su -s /bin/sh -c {} {}",
+                    quote(format!("exec setsid /bin/sh {}", launcher).as_ref()),
+                    quote(user.as_ref()))
+        };
+        let signal = config.stop_signal.unix_name().trim_start_matches("SIG");
+        let target = if config.kill_process_tree {
+            format!("kill -s {} -- -\"$supervisor\"", signal)
+        } else {
+            // Between restarts, the launcher ends once the delay is over
+            format!("if [ -r \"$state/target.pid\" ]; then \
+                     kill -s {} \"$(cat \"$state/target.pid\")\"; \
+                     else kill -s TERM \"$supervisor\"; fi", signal)
+        };
+        let timeout = config.stop_timeout.map(|timeout| timeout.as_secs()).unwrap_or(10);
+        let mut required = vec!["$remote_fs".to_string(), "$syslog".to_string()];
+        required.extend(config.dependencies.iter().cloned());
+        let short_description = config.display_name.as_deref().unwrap_or(&config.name);
+        let description = config.description.as_deref().unwrap_or(short_description);
+
+        Ok(format!("#!/bin/sh\n\
+                    ### BEGIN INIT INFO\n\
+                    # Provides:          {name}\n\
+                    # Required-Start:    {required}\n\
+                    # Required-Stop:     {required}\n\
+                    # Default-Start:     2 3 4 5\n\
+                    # Default-Stop:      0 1 6\n\
+                    # Short-Description: {short_description}\n\
+                    # Description:       {description}\n\
+                    ### END INIT INFO\n\
+                    # chkconfig: 2345 90 10\n\
+                    {header}\n\
+                    \n\
+                    state={state}\n\
+                    \n\
+                    running() {{\n\
+                    \t[ -r \"$state/supervisor.pid\" ] && \
+                    kill -0 \"$(cat \"$state/supervisor.pid\")\" 2>/dev/null\n\
+                    }}\n\
+                    \n\
+                    start() {{\n\
+                    \trunning && return 0\n\
+                    \trm -f \"$state/stopping\"\n\
+                    \t{spawn} </dev/null >/dev/null 2>&1 &\n\
+                    }}\n\
+                    \n\
+                    stop() {{\n\
+                    \trunning || return 0\n\
+                    \ttouch \"$state/stopping\"\n\
+                    \tsupervisor=$(cat \"$state/supervisor.pid\")\n\
+                    \t{target}\n\
+                    \twaited=0\n\
+                    \twhile running; do\n\
+                    \t\tif [ \"$waited\" -ge {timeout} ]; then\n\
+                    \t\t\tkill -s KILL -- -\"$supervisor\"\n\
+                    \t\t\trm -f \"$state/supervisor.pid\" \"$state/target.pid\"\n\
+                    \t\t\tbreak\n\
+                    \t\tfi\n\
+                    \t\tsleep 1\n\
+                    \t\twaited=$((waited + 1))\n\
+                    \tdone\n\
+                    }}\n\
+                    \n\
+                    case \"$1\" in\n\
+                    \tstart) start ;;\n\
+                    \tstop) stop ;;\n\
+                    \trestart|force-reload) stop && start ;;\n\
+                    \tstatus)\n\
+                    \t\trunning && {{ echo {name} is running; exit 0; }}\n\
+                    \t\t[ -r \"$state/supervisor.pid\" ] && {{ echo {name} is dead; exit 1; }}\n\
+                    \t\techo {name} is stopped\n\
+                    \t\texit 3\n\
+                    \t\t;;\n\
+                    \t*)\n\
+                    \t\techo \"Usage: $0 {{start|stop|restart|force-reload|status}}\" >&2\n\
+                    \t\texit 2\n\
+                    \t\t;;\n\
+                    esac\n",
+                   name = config.name,
+                   required = required.join(" "),
+                   short_description = short_description.replace('\n', " "),
+                   description = description.replace('\n', " "),
+                   header = crate::settings::HEADER,
+                   state = quote(supervisor.dir().as_os_str()),
+                   spawn = spawn,
+                   target = target,
+                   timeout = timeout))
+    }
+
+    fn has_command(command: &str) -> bool {
+        let path = std::env::var_os("PATH").unwrap_or_default();
+        std::env::split_paths(&path)
+            .chain(["/usr/sbin", "/sbin"].iter().map(PathBuf::from))
+            .any(|dir| dir.join(command).is_file())
+    }
+
+    fn run(command: &mut Command) -> crate::Result<()> {
+        let output = command.output()?;
+        if !output.status.success() {
+            return Err(crate::Error::new(crate::ErrorKind::Other,
+                                         String::from_utf8_lossy(&output.stderr).trim()
+                                             .to_string())
+                .content(format!("{:?}", command)));
+        }
+        Ok(())
+    }
+
+    /// Links the script into the runlevels of the services started at
+    /// boot, or takes it out of them
+    fn register(&self) -> crate::Result<()> {
+        let name = &self.config.name;
+        let enable = matches!(self.config.start_type,
+                              StartType::AutoStart | StartType::DelayedAutoStart);
+        let debian = SombraSysv::has_command("update-rc.d");
+        match (debian, enable) {
+            (true, true) => SombraSysv::run(Command::new("update-rc.d").arg(name)
+                .arg("defaults")),
+            (false, true) => SombraSysv::run(Command::new("chkconfig").arg("--add").arg(name)),
+            // Fails when the script isn't registered
+            (true, false) => {
+                let _ = Command::new("update-rc.d").arg("-f").arg(name).arg("remove").output();
+                Ok(())
+            },
+            (false, false) => {
+                let _ = Command::new("chkconfig").arg("--del").arg(name).output();
+                Ok(())
+            },
+        }
+    }
+
+    /// Installs the launcher and the script. The state directory belongs
+    /// to the account of the service, which the launcher runs as.
+    fn install(&self) -> crate::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        self.supervisor.install(&self.config)?;
+        let user = self.config.account.unix_name(&self.config.name)?;
+        if user != "root" {
+            let mut chown = Command::new("chown");
+            chown.arg("-R").arg(&user).arg(self.supervisor.dir());
+            for log in self.config.log_files() {
+                if !log.exists() {
+                    std::fs::write(&log, "")?;
+                }
+                chown.arg(log);
+            }
+            SombraSysv::run(&mut chown)?;
+        }
+        let path = SombraSysv::script_path(&self.config.name);
+        std::fs::write(&path, SombraSysv::script_content(&self.config, &self.supervisor)?)?;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))?;
+        Ok(())
+    }
+
+    fn script(&self, action: &str) -> crate::Result<()> {
+        SombraSysv::run(Command::new(SombraSysv::script_path(&self.config.name)).arg(action))
+    }
+
+    fn is_installed(&self) -> crate::Result<()> {
+        if self.supervisor.is_installed() && SombraSysv::script_path(&self.config.name).exists() {
+            Ok(())
+        } else {
+            Err(crate::Error::new(crate::ErrorKind::ServiceNotFound,
+                                  format!("Service {} does not exist", self.config.name)))
+        }
+    }
+}
+
+impl Sombra for SombraSysv {
+    fn from_config(config: SombraConfig) -> crate::Result<Self> {
+        let supervisor = Supervisor::in_dir(Path::new(STATE_DIR).join(&config.name));
+        Ok(SombraSysv { config, supervisor })
+    }
+
+    fn create(&self) -> crate::Result<()> {
+        SombraSysv::check(&self.config)?;
+        SombraSysv::is_root()?;
+        if SombraSysv::script_path(&self.config.name).exists() {
+            return Err(crate::Error::new(crate::ErrorKind::ServiceAlreadyExists,
+                                         format!("Service {} already exist",
+                                                 self.config.name)));
+        }
+        self.install()?;
+        self.register()?;
+
+        if self.config.start_type == StartType::Disabled || self.config.target_pending() {
+            return Ok(());
+        }
+        self.start()?;
+        match &self.config.readiness {
+            Some(readiness) => readiness.wait(),
+            None => Ok(()),
+        }
+    }
+
+    fn update(&self) -> crate::Result<()> {
+        SombraSysv::check(&self.config)?;
+        SombraSysv::is_root()?;
+        self.is_installed()?;
+        // The launcher restarts the target with the new settings
+        self.install()?;
+        self.register()
+    }
+
+    fn delete(&self) -> crate::Result<()> {
+        self.is_installed()?;
+        self.script("stop")?;
+        let mut config = self.config.clone();
+        config.start_type = StartType::Disabled;
+        SombraSysv::from_config(config)?.register()?;
+        std::fs::remove_file(SombraSysv::script_path(&self.config.name))?;
+        self.supervisor.remove()
+    }
+
+    fn start(&self) -> crate::Result<()> {
+        self.config.check_target()?;
+        self.is_installed()?;
+        self.script("start")?;
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        Ok(())
+    }
+
+    fn stop(&self) -> crate::Result<()> {
+        self.is_installed()?;
+        self.script("stop")
+    }
+
+    fn status(&self) -> crate::Result<ServiceStatus> {
+        if self.is_installed().is_err() {
+            return Ok(ServiceStatus::NotInstalled);
+        }
+        Ok(self.supervisor.status())
+    }
+
+    fn pid(&self) -> crate::Result<Option<u32>> {
+        self.is_installed()?;
+        Ok(self.supervisor.pid())
+    }
+
+    fn usage(&self) -> crate::Result<Option<Usage>> {
+        self.is_installed()?;
+        self.supervisor.usage()
+    }
+
+    fn stats(&self) -> crate::Result<ServiceStats> {
+        self.is_installed()?;
+        Ok(self.supervisor.stats())
+    }
+
+    fn config(&self) -> crate::Result<SombraConfig> {
+        self.is_installed()?;
+        Ok(self.supervisor.config(&self.config.name)?.0)
+    }
+
+    fn diff(&self) -> crate::Result<Vec<Difference>> {
+        Ok(Supervisor::comparable(&self.config)?.diff(&self.config()?))
+    }
+
+    fn checksum(&self) -> crate::Result<Option<String>> {
+        self.is_installed()?;
+        Ok(self.supervisor.config(&self.config.name)?.1)
+    }
+
+    fn list_managed() -> crate::Result<Vec<ManagedService>> {
+        let mut services = vec![];
+        for entry in std::fs::read_dir("/etc/init.d")? {
+            let path = entry?.path();
+            let name = match path.file_name().and_then(|n| n.to_str()) {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            let script = std::fs::read_to_string(&path).unwrap_or_default();
+            let supervisor = Supervisor::in_dir(Path::new(STATE_DIR).join(&name));
+            if !script.lines().any(|line| line == crate::settings::HEADER)
+                || !supervisor.is_installed() {
+                continue;
+            }
+            let (config, _) = supervisor.config(&name)?;
+            let path = config.path.clone();
+            let status = SombraSysv::from_config(config)?.status()?;
+            services.push(ManagedService { name, status, path });
+        }
+        services.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(services)
+    }
+
+    fn logs(&self, follow: bool) -> crate::Result<LogReader> {
+        LogReader::files(self.config.log_files(), follow)
+    }
+
+    fn render(&self) -> crate::Result<Vec<Artifact>> {
+        SombraSysv::check(&self.config)?;
+        let mut artifacts = self.supervisor.render(&self.config)?;
+        let script = SombraSysv::script_content(&self.config, &self.supervisor)?;
+        artifacts.push(Artifact::script(SombraSysv::script_path(&self.config.name), script));
+        Ok(artifacts)
+    }
+
+    fn export(&self) -> crate::Result<Scripts> {
+        let name = &self.config.name;
+        let script = SombraSysv::script_path(name);
+        let mut shell = Shell::new(name);
+        shell.write(&self.render()?);
+        for line in Supervisor::provision_lines(&self.config) {
+            shell.install(line);
+        }
+        shell.chown(&self.config, self.supervisor.dir())?;
+        // update-rc.d on Debian and its derivatives, chkconfig elsewhere
+        let enable = matches!(self.config.start_type,
+                              StartType::AutoStart | StartType::DelayedAutoStart);
+        if enable {
+            shell.install(format!("if command -v update-rc.d >/dev/null; then {}; else {}; fi",
+                                  Shell::line(&[&"update-rc.d", name, &"defaults"]),
+                                  Shell::line(&[&"chkconfig", &"--add", name])));
+        }
+        if self.config.start_type != StartType::Disabled {
+            shell.install(Shell::line(&[&script, &"start"]));
+        }
+        shell.uninstall(Shell::line(&[&script, &"stop"]));
+        shell.uninstall(format!("if command -v update-rc.d >/dev/null; then {}; else {}; fi",
+                                Shell::line(&[&"update-rc.d", &"-f", name, &"remove"]),
+                                Shell::line(&[&"chkconfig", &"--del", name])));
+        shell.remove(script);
+        shell.remove_dir(self.supervisor.dir());
+        Ok(shell.finish())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Account;
+
+    #[test]
+    fn script_has_lsb_header() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp_echo");
+        config.dependencies = vec!["$network".to_string()];
+        config.description = Some("Echo server".to_string());
+        let service = SombraSysv::from_config(config).unwrap();
+        let content = SombraSysv::script_content(&service.config, &service.supervisor).unwrap();
+        assert!(content.starts_with("#!/bin/sh\n### BEGIN INIT INFO\n# Provides:          \
+                                     tcp_echo\n# Required-Start:    $remote_fs $syslog \
+                                     $network\n"));
+        assert!(content.contains("\n# Description:       Echo server\n### END INIT INFO\n"));
+        assert!(content.contains("\nstate='/var/lib/sombra/init.d/tcp_echo'\n"));
+        assert!(content.contains("\n\tkill -s TERM -- -\"$supervisor\"\n"));
+    }
+
+    #[test]
+    fn script_switches_account() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp_echo");
+        config.account = Account::User { name: "sombra".to_string(), password: None };
+        let service = SombraSysv::from_config(config).unwrap();
+        let content = SombraSysv::script_content(&service.config, &service.supervisor).unwrap();
+        assert!(content.contains("\n\tsu -s /bin/sh -c 'exec setsid /bin/sh \
+                                  '\\''/var/lib/sombra/init.d/tcp_echo/launcher.sh'\\''' \
+                                  'sombra' </dev/null >/dev/null 2>&1 &\n"));
+    }
+}
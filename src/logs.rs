@@ -0,0 +1,136 @@
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::time::Duration;
+
+enum Source {
+    Files(Vec<FileTail>),
+    Process(std::process::Child, BufReader<std::process::ChildStdout>),
+}
+
+struct FileTail {
+    path: PathBuf,
+    reader: Option<BufReader<std::fs::File>>,
+    pending: String,
+}
+
+/// Iterator over the lines written by the target process, returned by
+/// `Sombra::logs`. With `follow`, it waits for new lines instead of
+/// ending, like `tail -f`.
+pub struct LogReader {
+    source: Source,
+    follow: bool,
+}
+
+impl LogReader {
+    /// Reads the log files of the service. Files that don't exist yet are
+    /// picked up once the target creates them.
+    pub(crate) fn files(paths: Vec<PathBuf>, follow: bool) -> crate::Result<Self> {
+        if paths.is_empty() {
+            return Err(crate::Error::new(crate::ErrorKind::Other,
+                                         "Service has no log files".to_string()));
+        }
+        let tails = paths.into_iter()
+            .map(|path| FileTail { path, reader: None, pending: String::new() })
+            .collect();
+        Ok(LogReader { source: Source::Files(tails), follow })
+    }
+
+    /// Reads the lines printed by a command, such as journalctl
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    pub(crate) fn command(mut command: std::process::Command, follow: bool)
+        -> crate::Result<Self> {
+        let mut child = command.stdout(std::process::Stdio::piped()).spawn()?;
+        let stdout = match child.stdout.take() {
+            Some(stdout) => stdout,
+            None => return Err(crate::Error::new(crate::ErrorKind::Io,
+                                                 "Cannot read command output".to_string())),
+        };
+        Ok(LogReader { source: Source::Process(child, BufReader::new(stdout)), follow })
+    }
+
+    fn next_from_files(tails: &mut [FileTail], follow: bool) -> Option<crate::Result<String>> {
+        loop {
+            for tail in tails.iter_mut() {
+                if tail.reader.is_none() {
+                    if let Ok(file) = std::fs::File::open(&tail.path) {
+                        tail.reader = Some(BufReader::new(file));
+                    }
+                }
+                let reader = match tail.reader.as_mut() {
+                    Some(reader) => reader,
+                    None => continue,
+                };
+                match reader.read_line(&mut tail.pending) {
+                    Err(e) => return Some(Err(e.into())),
+                    Ok(0) => {}
+                    Ok(_) => {
+                        // A line without a newline may still be being written
+                        if tail.pending.ends_with('\n') || !follow {
+                            let line = std::mem::take(&mut tail.pending);
+                            return Some(Ok(line.trim_end_matches(['\r', '\n']).to_string()));
+                        }
+                    }
+                }
+            }
+
+            if !follow {
+                return None;
+            }
+            std::thread::sleep(Duration::from_millis(200));
+        }
+    }
+}
+
+impl Iterator for LogReader {
+    type Item = crate::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match &mut self.source {
+            Source::Files(tails) => LogReader::next_from_files(tails, self.follow),
+            Source::Process(_, reader) => {
+                let mut line = String::new();
+                match reader.read_line(&mut line) {
+                    Err(e) => Some(Err(e.into())),
+                    Ok(0) => None,
+                    Ok(_) => Some(Ok(line.trim_end_matches(['\r', '\n']).to_string())),
+                }
+            }
+        }
+    }
+}
+
+impl Drop for LogReader {
+    fn drop(&mut self) {
+        if let Source::Process(child, _) = &mut self.source {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_reads_every_line() {
+        let dir = std::env::temp_dir().join("sombra_logs_test");
+        let _ = std::fs::create_dir_all(&dir);
+        let out = dir.join("out.log");
+        let err = dir.join("err.log");
+        std::fs::write(&out, "first\nsecond\n").unwrap();
+        std::fs::write(&err, "failed").unwrap();
+
+        let reader = LogReader::files(vec![out, err, dir.join("missing.log")], false).unwrap();
+        let mut lines: Vec<String> = reader.map(|line| line.unwrap()).collect();
+        lines.sort();
+        assert_eq!(lines, vec!["failed", "first", "second"]);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn files_requires_a_path() {
+        assert!(LogReader::files(vec![], false).is_err());
+    }
+}
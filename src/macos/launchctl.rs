@@ -0,0 +1,94 @@
+pub struct Launchctl {
+    name: String,
+    /// `system`, or the GUI session of the user for agents
+    domain: String,
+}
+
+impl Launchctl {
+    pub fn new(name: &str, user: bool) -> Self {
+        let domain = if user {
+            format!("gui/{}", unsafe { libc::getuid() })
+        } else {
+            "system".to_string()
+        };
+        Launchctl {
+            name: name.to_string(),
+            domain,
+        }
+    }
+
+    fn target(&self) -> String {
+        format!("{}/{}", self.domain, self.name)
+    }
+
+    pub fn bootstrap(&self, plist: &str) -> crate::Result<()> {
+        let _ = std::process::Command::new("launchctl")
+            .arg("bootstrap")
+            .arg(&self.domain)
+            .arg(plist)
+            .output()?;
+        Ok(())
+    }
+
+    pub fn bootout(&self) -> crate::Result<()> {
+        let _ = std::process::Command::new("launchctl")
+            .arg("bootout")
+            .arg(self.target())
+            .output()?;
+        Ok(())
+    }
+
+    pub fn kickstart(&self) -> crate::Result<()> {
+        let _ = std::process::Command::new("launchctl")
+            .arg("kickstart")
+            .arg(self.target())
+            .output()?;
+        Ok(())
+    }
+
+    pub fn kill(&self, signal: &str) -> crate::Result<()> {
+        let _ = std::process::Command::new("launchctl")
+            .arg("kill")
+            .arg(signal)
+            .arg(self.target())
+            .output()?;
+        Ok(())
+    }
+
+    /// Signals the whole process group of the job. launchd makes each
+    /// job the leader of its own group.
+    pub fn kill_group(&self, signal: &str) -> crate::Result<()> {
+        if let Some(pid) = self.pid()? {
+            let _ = std::process::Command::new("kill")
+                .arg("-s")
+                .arg(signal.trim_start_matches("SIG"))
+                .arg("--")
+                .arg(format!("-{}", pid))
+                .output()?;
+        }
+        Ok(())
+    }
+
+    pub fn print(&self) -> crate::Result<String> {
+        let output = std::process::Command::new("launchctl")
+            .arg("print")
+            .arg(self.target())
+            .output()?;
+        Ok(std::str::from_utf8(output.stdout.as_slice())?.to_string())
+    }
+
+    pub fn pid(&self) -> crate::Result<Option<u32>> {
+        Ok(self.print()?.lines()
+            .filter_map(|line| line.trim().strip_prefix("pid = "))
+            .find_map(|pid| pid.parse().ok()))
+    }
+
+    pub fn is_running(&self) -> crate::Result<bool> {
+        let output = std::process::Command::new("launchctl")
+            .arg("print")
+            .arg(self.target())
+            .output()?;
+        let stdout = std::str::from_utf8(output.stdout.as_slice())?;
+        Ok(stdout.lines().any(|line| line.trim() == "state = running"))
+    }
+}
@@ -0,0 +1,2 @@
+pub mod sombra_imp;
+mod launchctl;
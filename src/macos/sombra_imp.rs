@@ -0,0 +1,690 @@
+use crate::{Account, Artifact, Difference, ExitReason, FailureActions, LogReader, ManagedService,
+            Priority, Privileges, Scope, Scripts, Sombra, ServiceStats, ServiceStatus, SombraConfig,
+            StartType, Usage};
+use crate::export::Shell;
+use crate::support::{self, Feature};
+use crate::unix::shell::word;
+use crate::unix::ps;
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::io::Write;
+use crate::macos::launchctl::Launchctl;
+use crate::error::ErrorKind::Other;
+
+/// Comment marking the plists created by sombra
+const MANAGED_MARKER: &str = "<!-- Managed by sombra -->";
+/// Comment holding the SHA-256 of the target at install, before ` -->`
+const CHECKSUM_COMMENT: &str = "<!-- Target sha256 ";
+
+/// Default ExitTimeOut of launchd
+const EXIT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(20);
+
+pub struct SombraMacos {
+    config: SombraConfig,
+    launchctl: Launchctl,
+}
+
+impl SombraMacos {
+    /// /Library/LaunchDaemons, or ~/Library/LaunchAgents for `Scope::User`
+    fn plist_dir(scope: Scope) -> PathBuf {
+        match scope {
+            Scope::System => PathBuf::from("/Library/LaunchDaemons"),
+            Scope::User => std::env::var_os("HOME").map(PathBuf::from)
+                .unwrap_or_default()
+                .join("Library/LaunchAgents"),
+        }
+    }
+
+    fn plist_path(&self) -> PathBuf {
+        SombraMacos::plist_dir(self.config.scope).join(format!("{}.plist", self.config.name))
+    }
+
+    fn xml_escape(value: &str) -> String {
+        value.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+            .replace('\'', "&apos;")
+    }
+
+    fn plist_content(config: &SombraConfig) -> crate::Result<String> {
+        let path_str = match config.path.to_str() {
+            Some(path_str) => path_str.to_string(),
+            None => return Err(crate::Error::new(crate::ErrorKind::Io,
+                                                 "Cannot decode path".to_string()))
+        };
+
+        let mut program_args = format!("        <string>{}</string>\n",
+                                       SombraMacos::xml_escape(&path_str));
+        for a in &config.args {
+            // Plists are UTF-8
+            let a = a.to_str().ok_or_else(|| {
+                crate::Error::new(crate::ErrorKind::Utf8,
+                                  "Arguments must be valid UTF-8 for launchd".to_string())
+                    .content(a.to_string_lossy().to_string())
+            })?;
+            program_args.push_str(&format!("        <string>{}</string>\n",
+                                           SombraMacos::xml_escape(a)));
+        }
+
+        if config.restart_policy.is_some() {
+            // KeepAlive restarts forever, only throttled
+            return Err(crate::Error::new(Other,
+                                         "Restart policies are not supported by launchd"
+                                             .to_string()));
+        }
+
+        if config.watchdog.is_some() {
+            return Err(crate::Error::new(Other,
+                                         "Watchdogs are not supported by launchd".to_string()));
+        }
+
+        if let Some(description) = support::unsupported(config, &[Feature::UserScope,
+                                                                   Feature::GroupAndUmask]) {
+            return Err(crate::Error::new(Other, description.to_string())
+                .content(config.name.clone()));
+        }
+
+        let run_at_load = match config.start_type {
+            StartType::AutoStart | StartType::DelayedAutoStart => "true",
+            StartType::OnDemand | StartType::Disabled => "false",
+        };
+
+        // Optional keys, in the order they appear after RunAtLoad
+        let mut keys = String::new();
+        if let Some(delay) = config.failure_actions.as_ref().and_then(|f| f.restart_delay()) {
+            keys.push_str(&format!("    <key>KeepAlive</key>\n\
+                                    \x20   <dict>\n\
+                                    \x20       <key>SuccessfulExit</key>\n\
+                                    \x20       <false/>\n\
+                                    \x20   </dict>\n\
+                                    \x20   <key>ThrottleInterval</key>\n\
+                                    \x20   <integer>{}</integer>\n",
+                                   delay.as_secs()));
+        }
+
+        if !config.env.is_empty() {
+            keys.push_str("    <key>EnvironmentVariables</key>\n    <dict>\n");
+            for (key, value) in &config.env {
+                keys.push_str(&format!("        <key>{}</key>\n\
+                                        \x20       <string>{}</string>\n",
+                                       SombraMacos::xml_escape(key),
+                                       SombraMacos::xml_escape(value)));
+            }
+            keys.push_str("    </dict>\n");
+        }
+
+        if let Some(dir) = &config.working_directory {
+            keys.push_str(&format!("    <key>WorkingDirectory</key>\n\
+                                    \x20   <string>{}</string>\n",
+                                   SombraMacos::xml_escape(&dir.to_string_lossy())));
+        }
+
+        if let Some(log) = &config.stdout_log {
+            keys.push_str(&format!("    <key>StandardOutPath</key>\n    <string>{}</string>\n",
+                                   SombraMacos::xml_escape(&log.to_string_lossy())));
+        }
+        if let Some(log) = &config.stderr_log {
+            keys.push_str(&format!("    <key>StandardErrorPath</key>\n    <string>{}</string>\n",
+                                   SombraMacos::xml_escape(&log.to_string_lossy())));
+        }
+
+        if let Some(priority) = config.priority {
+            keys.push_str(&format!("    <key>Nice</key>\n    <integer>{}</integer>\n",
+                                   priority.nice()));
+        }
+
+        if let Some(umask) = config.umask {
+            keys.push_str(&format!("    <key>Umask</key>\n    <integer>{}</integer>\n", umask));
+        }
+
+        if !config.kill_process_tree {
+            // Otherwise launchd kills the group once the target exits
+            keys.push_str("    <key>AbandonProcessGroup</key>\n    <true/>\n");
+        }
+
+        if let Some(timeout) = config.stop_timeout {
+            keys.push_str(&format!("    <key>ExitTimeOut</key>\n    <integer>{}</integer>\n",
+                                   timeout.as_secs()));
+        }
+
+        // Agents run as the user whose session loads them
+        let mut user_name = match config.scope {
+            Scope::System => format!("    <key>UserName</key>\n    <string>{}</string>\n",
+                                     SombraMacos::xml_escape(
+                                         &config.account.unix_name(&config.name)?)),
+            Scope::User => String::new(),
+        };
+        if let (Scope::System, Some(group)) = (config.scope, &config.group) {
+            user_name.push_str(&format!("    <key>GroupName</key>\n    <string>{}</string>\n",
+                                        SombraMacos::xml_escape(group)));
+        }
+
+        let mut header = MANAGED_MARKER.to_string();
+        if let Ok(checksum) = crate::digest::sha256_file(&config.path) {
+            header.push_str(&format!("\n{}{} -->", CHECKSUM_COMMENT, checksum));
+        }
+
+        Ok(format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \
+                \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+                {}\n\
+                <plist version=\"1.0\">\n\
+                <dict>\n\
+                \x20   <key>Label</key>\n\
+                \x20   <string>{}</string>\n\
+                \x20   <key>ProgramArguments</key>\n\
+                \x20   <array>\n\
+                {}\
+                \x20   </array>\n\
+                {}\
+                \x20   <key>RunAtLoad</key>\n\
+                \x20   <{}/>\n\
+                {}\
+                </dict>\n\
+                </plist>\n",
+                header,
+                SombraMacos::xml_escape(&config.name),
+                program_args,
+                user_name,
+                run_at_load,
+                keys))
+    }
+
+    fn xml_unescape(value: &str) -> String {
+        value.replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&quot;", "\"")
+            .replace("&apos;", "'")
+            .replace("&amp;", "&")
+    }
+
+    /// Inverse of `plist_content`, reading back a plist written by it
+    fn config_from_plist(name: &str, plist: &str) -> SombraConfig {
+        let element = |line: &str, tag: &str| line.strip_prefix(&format!("<{}>", tag))?
+            .strip_suffix(&format!("</{}>", tag))
+            .map(SombraMacos::xml_unescape);
+
+        let mut config = SombraConfig::new(name, "");
+        let mut program_arguments = vec![];
+        let mut depth = 0;
+        // Last key of the top dict, and of the environment dict
+        let mut key = String::new();
+        let mut env_key = None;
+        for line in plist.lines().map(str::trim) {
+            match line {
+                "<dict>" => depth += 1,
+                "</dict>" => depth -= 1,
+                _ => {},
+            }
+            if let Some(name) = element(line, "key") {
+                if depth == 1 {
+                    key = name;
+                } else if key == "EnvironmentVariables" {
+                    env_key = Some(name);
+                }
+                continue;
+            }
+            let string = element(line, "string");
+            let integer = element(line, "integer").and_then(|i| i.parse::<i64>().ok());
+            match (key.as_str(), string, integer) {
+                ("ProgramArguments", Some(argument), _) => program_arguments.push(argument),
+                ("UserName", Some(user), _) => config.account = Account::from_unix_name(&user),
+                ("GroupName", Some(group), _) => config.group = Some(group),
+                ("RunAtLoad", _, _) if line == "<true/>" =>
+                    config.start_type = StartType::AutoStart,
+                ("ThrottleInterval", _, Some(delay)) => config.failure_actions =
+                    Some(FailureActions::restart(Duration::from_secs(delay as u64))),
+                ("EnvironmentVariables", Some(value), _) => if let Some(key) = env_key.take() {
+                    config.env.push((key, value));
+                },
+                ("WorkingDirectory", Some(dir), _) =>
+                    config.working_directory = Some(PathBuf::from(dir)),
+                ("StandardOutPath", Some(log), _) => config.stdout_log = Some(PathBuf::from(log)),
+                ("StandardErrorPath", Some(log), _) => config.stderr_log = Some(PathBuf::from(log)),
+                ("Nice", _, Some(nice)) => config.priority = Priority::from_nice(nice as i32),
+                ("Umask", _, Some(umask)) => config.umask = Some(umask as u32),
+                ("AbandonProcessGroup", _, _) if line == "<true/>" =>
+                    config.kill_process_tree = false,
+                ("ExitTimeOut", _, Some(timeout)) =>
+                    config.stop_timeout = Some(Duration::from_secs(timeout as u64)),
+                _ => {},
+            }
+        }
+        if !program_arguments.is_empty() {
+            config.path = PathBuf::from(program_arguments.remove(0));
+            config.args = program_arguments.into_iter().map(OsString::from).collect();
+        }
+        config
+    }
+
+    /// Reads the run count and last exit from `launchctl print`
+    fn stats_from_print(print: &str) -> ServiceStats {
+        let value = |key: &str| print.lines()
+            .find_map(|line| line.trim().strip_prefix(key)?.strip_prefix(" = "));
+        let runs: u32 = value("runs").and_then(|runs| runs.parse().ok()).unwrap_or(0);
+        // On a signal, launchd reports the exit code as "(never exited)" and
+        // the signal number in "last terminating signal = Terminated: 15"
+        let last_exit = match value("last exit code").and_then(|code| code.parse().ok()) {
+            Some(code) => Some(ExitReason::Code(code)),
+            None => value("last terminating signal")
+                .and_then(|signal| signal.rsplit(": ").next()?.parse().ok())
+                .map(ExitReason::Signal),
+        };
+        ServiceStats {
+            started_at: None,
+            restarts: runs.saturating_sub(1),
+            last_exit,
+        }
+    }
+
+    fn is_installed(&self) -> crate::Result<()> {
+        if self.plist_path().exists() {
+            Ok(())
+        } else {
+            Err(crate::Error::new(crate::ErrorKind::ServiceNotFound,
+                                  format!("Service {} does not exist", self.config.name)))
+        }
+    }
+
+    fn check_privileges(&self) -> crate::Result<()> {
+        if self.config.scope == Scope::System {
+            return SombraMacos::is_root();
+        }
+        if self.config.account != Account::LocalSystem {
+            return Err(crate::Error::new(Other,
+                                         "User agents run as the user installing them"
+                                             .to_string())
+                .content(self.config.name.clone()));
+        }
+        Ok(())
+    }
+
+    fn is_root() -> crate::Result<()> {
+        match std::env::var("USER") {
+            Err(e) => Err(crate::Error::new(Other, e.to_string())),
+            Ok(name) => {
+                if name != "root" {
+                    Err(crate::Error::new(crate::ErrorKind::AccessDenied,
+                                          "Without root privileges.".to_string()))
+                } else {
+                    Ok(())
+                }
+            }
+        }
+    }
+}
+
+impl Sombra for SombraMacos {
+    fn from_config(config: SombraConfig) -> crate::Result<Self> {
+        let launchctl = Launchctl::new(&config.name, config.scope == Scope::User);
+        Ok(SombraMacos {
+            config,
+            launchctl,
+        })
+    }
+
+    fn create(&self) -> crate::Result<()> {
+        self.check_privileges()?;
+
+        let path = self.plist_path();
+        if path.exists() {
+            return Err(crate::Error::new(crate::ErrorKind::ServiceAlreadyExists,
+                                         format!("Service {} already exist",
+                                                 self.config.name)));
+        } else {
+            // ~/Library/LaunchAgents may not exist yet
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            let mut file = std::fs::File::create(&path)?;
+            let buffer = SombraMacos::plist_content(&self.config)?;
+            file.write_all(buffer.as_bytes())?;
+        }
+
+        self.launchctl.bootstrap(&path.to_string_lossy())?;
+        // A deferred target is started once it is copied in place
+        if self.config.start_type == StartType::Disabled || self.config.target_pending() {
+            return Ok(());
+        }
+        self.start()?;
+        match &self.config.readiness {
+            Some(readiness) => readiness.wait(),
+            None => Ok(()),
+        }
+    }
+
+    fn update(&self) -> crate::Result<()> {
+        self.check_privileges()?;
+        self.is_installed()?;
+        let buffer = SombraMacos::plist_content(&self.config)?;
+
+        // launchd only rereads a plist when the job is loaded again
+        let running = self.launchctl.is_running()?;
+        self.launchctl.bootout()?;
+        let path = self.plist_path();
+        std::fs::write(&path, buffer)?;
+        self.launchctl.bootstrap(&path.to_string_lossy())?;
+        if running {
+            self.start()?;
+        }
+        Ok(())
+    }
+
+    fn delete(&self) -> crate::Result<()> {
+        let _ = self.launchctl.bootout();
+        std::fs::remove_file(self.plist_path())?;
+        Ok(())
+    }
+
+    fn start(&self) -> crate::Result<()> {
+        self.config.check_target()?;
+        self.is_installed()?;
+        self.launchctl.kickstart()?;
+
+        // Give launchd time to spawn the process, as on linux
+        std::thread::sleep(std::time::Duration::from_millis(100));
+
+        Ok(())
+    }
+
+    fn stop(&self) -> crate::Result<()> {
+        self.is_installed()?;
+        let signal = |signal: &str| if self.config.kill_process_tree {
+            self.launchctl.kill_group(signal)
+        } else {
+            self.launchctl.kill(signal)
+        };
+        signal(self.config.stop_signal.unix_name())?;
+
+        // launchctl kill doesn't escalate, so wait for the target as
+        // launchd does on shutdown, for ExitTimeOut
+        let timeout = self.config.stop_timeout.unwrap_or(EXIT_TIMEOUT);
+        let begin = std::time::Instant::now();
+        while self.launchctl.is_running()? {
+            if begin.elapsed() > timeout {
+                return signal("SIGKILL");
+            }
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+        Ok(())
+    }
+    fn status(&self) -> crate::Result<ServiceStatus> {
+        if self.is_installed().is_err() {
+            Ok(ServiceStatus::NotInstalled)
+        } else if self.launchctl.is_running()? {
+            Ok(ServiceStatus::Running)
+        } else {
+            Ok(ServiceStatus::Stopped)
+        }
+    }
+
+    fn pid(&self) -> crate::Result<Option<u32>> {
+        self.is_installed()?;
+        self.launchctl.pid()
+    }
+
+    fn usage(&self) -> crate::Result<Option<Usage>> {
+        match self.pid()? {
+            Some(pid) => Ok(Some(ps::usage(pid)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn stats(&self) -> crate::Result<ServiceStats> {
+        self.is_installed()?;
+        let mut stats = SombraMacos::stats_from_print(&self.launchctl.print()?);
+        if let Some(pid) = self.launchctl.pid()? {
+            stats.started_at = ps::elapsed(pid)?
+                .and_then(|elapsed| std::time::SystemTime::now().checked_sub(elapsed));
+        }
+        Ok(stats)
+    }
+
+    fn config(&self) -> crate::Result<SombraConfig> {
+        self.is_installed()?;
+        let plist = std::fs::read_to_string(self.plist_path())?;
+        let mut config = SombraMacos::config_from_plist(&self.config.name, &plist);
+        config.scope = self.config.scope;
+        Ok(config)
+    }
+
+    fn diff(&self) -> crate::Result<Vec<Difference>> {
+        // The plist keeps only what it renders, so compare that part
+        let plist = SombraMacos::plist_content(&self.config)?;
+        let desired = SombraMacos::config_from_plist(&self.config.name, &plist);
+        Ok(desired.diff(&self.config()?))
+    }
+
+    fn checksum(&self) -> crate::Result<Option<String>> {
+        self.is_installed()?;
+        let plist = std::fs::read_to_string(self.plist_path())?;
+        Ok(plist.lines()
+            .find_map(|line| line.strip_prefix(CHECKSUM_COMMENT)?.strip_suffix(" -->"))
+            .map(|checksum| checksum.to_string()))
+    }
+
+    fn list_managed() -> crate::Result<Vec<ManagedService>> {
+        let mut services = vec![];
+        for entry in std::fs::read_dir(SombraMacos::plist_dir(Scope::System))? {
+            let path = entry?.path();
+            let name = match path.file_name().and_then(|n| n.to_str())
+                .and_then(|n| n.strip_suffix(".plist")) {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            let plist = std::fs::read_to_string(&path).unwrap_or_default();
+            if !plist.lines().any(|line| line == MANAGED_MARKER) {
+                continue;
+            }
+            let config = SombraMacos::config_from_plist(&name, &plist);
+            let path = config.path.clone();
+            let status = SombraMacos::from_config(config)?.status()?;
+            services.push(ManagedService { name, status, path });
+        }
+        services.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(services)
+    }
+
+    fn required_privileges(&self) -> Privileges {
+        match self.config.scope {
+            Scope::System => Privileges::Administrator,
+            Scope::User => Privileges::User,
+        }
+    }
+
+    fn logs(&self, follow: bool) -> crate::Result<LogReader> {
+        LogReader::files(self.config.log_files(), follow)
+    }
+
+    fn render(&self) -> crate::Result<Vec<Artifact>> {
+        Ok(vec![Artifact::file(self.plist_path(), SombraMacos::plist_content(&self.config)?)])
+    }
+
+    fn export(&self) -> crate::Result<Scripts> {
+        // Agents go to the session of the user running the script
+        let domain = match self.config.scope {
+            Scope::System => "system".to_string(),
+            Scope::User => "\"gui/$(id -u)\"".to_string(),
+        };
+        let target = format!("{}/{}", domain, word(self.config.name.as_ref()));
+        let plist = self.plist_path();
+        let mut shell = Shell::new(&self.config.name);
+        shell.write(&self.render()?);
+        shell.install(format!("launchctl bootstrap {} {}", domain, word(plist.as_os_str())));
+        if self.config.start_type != StartType::Disabled {
+            shell.install(format!("launchctl kickstart {}", target));
+        }
+        shell.uninstall(format!("launchctl bootout {}", target));
+        shell.remove(plist);
+        Ok(shell.finish())
+    }
+}
+
+#[cfg(test)]
+#[cfg(target_os = "macos")]
+mod tests {
+    use super::*;
+    use crate::FailureActions;
+    use std::net::TcpStream;
+    use std::io::Read;
+    use std::time::Duration;
+
+    fn python_echo(name: &str) -> SombraMacos {
+        let script = match dunce::canonicalize("executables/tcp_echo.py") {
+            Ok(script) => script,
+            Err(e) => panic!("{}", e),
+        };
+        match SombraMacos::build(name, "/usr/bin/python3", [script]) {
+            Ok(s) => s,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    fn echo_check(ip_port: &str, msg: &[u8]) -> std::io::Result<()> {
+        std::thread::sleep(Duration::from_millis(500)); // Need to allow python open TCP connection
+
+        let mut stream = TcpStream::connect(ip_port)?;
+        stream.write_all(msg)?;
+        let mut buffer = [0u8; 512];
+        let len = stream.read(&mut buffer)?;
+        let mut buffer = buffer[..len].to_vec();
+        buffer.retain(|&x| x != 0);
+        if buffer != msg.to_vec() {
+            Err(std::io::Error::other("Not match"))
+        } else {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn plist_escapes_arguments() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp echo");
+        config.args = vec!["<&>".into()];
+        let content = match SombraMacos::plist_content(&config) {
+            Ok(content) => content,
+            Err(e) => panic!("{}", e),
+        };
+        assert!(content.contains("<string>/opt/tcp echo</string>"));
+        assert!(content.contains("<string>&lt;&amp;&gt;</string>"));
+    }
+
+    #[test]
+    fn plist_refuses_what_launchd_ignores() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp_echo");
+        config.notify = true;
+        assert_eq!(SombraMacos::plist_content(&config).map_err(|e| e.kind()), Err(Other));
+
+        config.notify = false;
+        config.health_check = Some(crate::HealthCheck::new(
+            crate::Probe::Tcp("127.0.0.1:30222".to_string())));
+        assert_eq!(SombraMacos::plist_content(&config).map_err(|e| e.kind()), Err(Other));
+    }
+
+    #[test]
+    fn plist_keep_alive() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp_echo");
+        config.failure_actions = Some(FailureActions::restart(Duration::from_secs(5)));
+        let content = match SombraMacos::plist_content(&config) {
+            Ok(content) => content,
+            Err(e) => panic!("{}", e),
+        };
+        assert!(content.contains("    <key>KeepAlive</key>\n    <dict>\n        \
+                                  <key>SuccessfulExit</key>\n        <false/>\n    </dict>\n"));
+        assert!(content.contains("    <key>ThrottleInterval</key>\n    <integer>5</integer>\n\
+                                  </dict>\n"));
+    }
+
+    #[test]
+    fn user_agents_run_as_their_user() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp_echo");
+        config.scope = Scope::User;
+        let content = SombraMacos::plist_content(&config).unwrap();
+        assert!(!content.contains("<key>UserName</key>"));
+
+        config.account = Account::LocalService;
+        let service = SombraMacos::from_config(config).unwrap();
+        assert_eq!(service.check_privileges().map_err(|e| e.kind()), Err(Other));
+        assert!(service.plist_path().ends_with("Library/LaunchAgents/tcp_echo.plist"));
+    }
+
+    #[test]
+    fn config_from_plist_inverts_plist() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp echo");
+        config.args = vec!["<&>".into(), "-p".into()];
+        config.start_type = StartType::AutoStart;
+        config.account = Account::User { name: "sombra".to_string(), password: None };
+        config.group = Some("staff".to_string());
+        config.umask = Some(0o027);
+        config.failure_actions = Some(FailureActions::restart(Duration::from_secs(5)));
+        config.env = vec![("RUST_LOG".to_string(), "debug".to_string()),
+                          ("HOME".to_string(), "/var/empty".to_string())];
+        config.working_directory = Some(PathBuf::from("/opt"));
+        config.stdout_log = Some(PathBuf::from("/var/log/tcp_echo.log"));
+        config.stderr_log = Some(PathBuf::from("/var/log/tcp_echo.err"));
+        config.priority = Some(Priority::High);
+        config.kill_process_tree = false;
+        config.stop_timeout = Some(Duration::from_secs(3));
+        let plist = match SombraMacos::plist_content(&config) {
+            Ok(plist) => plist,
+            Err(e) => panic!("{}", e),
+        };
+        assert_eq!(SombraMacos::config_from_plist("tcp_echo", &plist), config);
+    }
+
+    #[test]
+    fn stats_from_print() {
+        let print = "system/tcp_echo = {\n\truns = 3\n\tlast exit code = 1\n}\n";
+        let stats = SombraMacos::stats_from_print(print);
+        assert_eq!(stats.restarts, 2);
+        assert_eq!(stats.last_exit, Some(ExitReason::Code(1)));
+
+        let print = "\tlast exit code = (never exited)\n\tlast terminating signal = Killed: 9\n";
+        assert_eq!(SombraMacos::stats_from_print(print).last_exit, Some(ExitReason::Signal(9)));
+    }
+
+    #[test]
+    fn plist_environment() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp_echo");
+        config.env = vec![("RUST_LOG".to_string(), "debug".to_string())];
+        let content = match SombraMacos::plist_content(&config) {
+            Ok(content) => content,
+            Err(e) => panic!("{}", e),
+        };
+        assert!(content.contains("    <key>EnvironmentVariables</key>\n    <dict>\n        \
+                                  <key>RUST_LOG</key>\n        <string>debug</string>\n    \
+                                  </dict>\n"));
+    }
+
+    #[test]
+    fn spawn_simple() {
+        let s = python_echo("tcp_echo");
+        assert_eq!(s.create(), Ok(()));
+        let res = echo_check("127.0.0.1:30222", b"sombra30222");
+        assert_eq!(s.delete(), Ok(()));
+        if let Err(e) = res {
+            panic!("{:?}", e);
+        }
+    }
+
+    #[test]
+    fn spawn_twice_same_name() {
+        let s = python_echo("tcp_echo");
+        assert_eq!(s.create(), Ok(()));
+        let s2 = python_echo("tcp_echo");
+        assert_ne!(s2.create(), Ok(()));
+        assert_eq!(s.delete(), Ok(()));
+    }
+
+    #[test]
+    fn spawn_once_delete_twice() {
+        let s = python_echo("tcp_echo");
+        assert_eq!(s.create(), Ok(()));
+        assert_eq!(s.delete(), Ok(()));
+        assert_ne!(s.delete(), Ok(()));
+    }
+}
+
+// Run test on macos as sudo
+// sudo -E cargo test -- --test-threads 1
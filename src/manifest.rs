@@ -0,0 +1,751 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::time::Duration;
+use crate::{Account, Error, ErrorKind, Operation, Plan, RestartPolicy, Sombra, SombraBuilder,
+            SombraConfig, StartType};
+
+/// TOML values of the subset manifests use
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+    Table(Vec<(String, Value)>),
+}
+
+impl Value {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::Integer(_) => "integer",
+            Value::Boolean(_) => "boolean",
+            Value::Array(_) => "array",
+            Value::Table(_) => "table",
+        }
+    }
+
+    fn get_mut(&mut self, key: &str) -> Option<&mut Value> {
+        match self {
+            Value::Table(entries) => entries.iter_mut()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    fn insert(&mut self, key: String, value: Value) -> Result<(), String> {
+        if self.get_mut(&key).is_some() {
+            return Err(format!("Duplicate key {}", key));
+        }
+        match self {
+            Value::Table(entries) => {
+                entries.push((key, value));
+                Ok(())
+            },
+            _ => Err(format!("Key {} is not in a table", key)),
+        }
+    }
+}
+
+/// Parser of the TOML subset of manifests: tables, arrays of tables,
+/// strings, integers, booleans, arrays and inline tables
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+}
+
+impl Parser {
+    fn parse(text: &str) -> crate::Result<Value> {
+        let mut parser = Parser { chars: text.chars().collect(), pos: 0, line: 1 };
+        parser.document().map_err(|desc| Error::new(ErrorKind::Other, desc)
+            .content(format!("line {}", parser.line)))
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(format!("Expected {:?}, found {:?}", expected, c)),
+            None => Err(format!("Expected {:?}, found the end", expected)),
+        }
+    }
+
+    /// Skips spaces and a comment, but not the end of the line
+    fn skip_spaces(&mut self) {
+        while let Some(c) = self.peek() {
+            match c {
+                ' ' | '\t' | '\r' => self.pos += 1,
+                '#' => while !matches!(self.peek(), None | Some('\n')) {
+                    self.pos += 1;
+                },
+                _ => return,
+            }
+        }
+    }
+
+    fn skip_blank(&mut self) {
+        loop {
+            self.skip_spaces();
+            if self.peek() != Some('\n') {
+                return;
+            }
+            self.next();
+        }
+    }
+
+    fn end_of_line(&mut self) -> Result<(), String> {
+        self.skip_spaces();
+        match self.next() {
+            None | Some('\n') => Ok(()),
+            Some(c) => Err(format!("Unexpected {:?} after the value", c)),
+        }
+    }
+
+    fn document(&mut self) -> Result<Value, String> {
+        let mut root = Value::Table(vec![]);
+        // Keys of the current table, from the root
+        let mut current: Vec<String> = vec![];
+        loop {
+            self.skip_blank();
+            match self.peek() {
+                None => return Ok(root),
+                Some('[') => {
+                    self.next();
+                    let array = self.peek() == Some('[');
+                    if array {
+                        self.next();
+                    }
+                    let path = self.header()?;
+                    self.expect(']')?;
+                    if array {
+                        self.expect(']')?;
+                    }
+                    self.end_of_line()?;
+                    Parser::open_table(&mut root, &path, array)?;
+                    current = path;
+                },
+                Some(_) => {
+                    let key = self.key()?;
+                    self.skip_spaces();
+                    self.expect('=')?;
+                    self.skip_spaces();
+                    let value = self.value()?;
+                    Parser::table(&mut root, &current)?.insert(key, value)?;
+                    self.end_of_line()?;
+                },
+            }
+        }
+    }
+
+    fn header(&mut self) -> Result<Vec<String>, String> {
+        let mut path = vec![];
+        loop {
+            self.skip_spaces();
+            path.push(self.key()?);
+            self.skip_spaces();
+            if self.peek() != Some('.') {
+                return Ok(path);
+            }
+            self.next();
+        }
+    }
+
+    /// The table at `path`, the last element for arrays of tables
+    fn table<'a>(root: &'a mut Value, path: &[String]) -> Result<&'a mut Value, String> {
+        let mut table = root;
+        for key in path {
+            table = match table.get_mut(key) {
+                Some(Value::Array(tables)) => match tables.last_mut() {
+                    Some(last) => last,
+                    None => return Err(format!("Array {} has no table", key)),
+                },
+                Some(value @ Value::Table(_)) => value,
+                Some(value) => return Err(format!("Key {} is a {}, not a table", key,
+                                                  value.type_name())),
+                None => return Err(format!("Table {} is not defined", key)),
+            };
+        }
+        Ok(table)
+    }
+
+    fn open_table(root: &mut Value, path: &[String], array: bool) -> Result<(), String> {
+        let (key, parent) = match path.split_last() {
+            Some((key, parent)) => (key, parent),
+            None => return Err("Empty table header".to_string()),
+        };
+        let parent = Parser::table(root, parent)?;
+        match (parent.get_mut(key), array) {
+            (Some(Value::Array(tables)), true) => {
+                tables.push(Value::Table(vec![]));
+                Ok(())
+            },
+            (None, true) => parent.insert(key.clone(), Value::Array(vec![Value::Table(vec![])])),
+            (None, false) => parent.insert(key.clone(), Value::Table(vec![])),
+            (Some(_), _) => Err(format!("Table {} is already defined", key)),
+        }
+    }
+
+    fn key(&mut self) -> Result<String, String> {
+        match self.peek() {
+            Some('"') => self.basic_string(),
+            Some('\'') => self.literal_string(),
+            _ => {
+                let mut key = String::new();
+                while let Some(c) = self.peek() {
+                    if !(c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+                        break;
+                    }
+                    key.push(c);
+                    self.pos += 1;
+                }
+                if key.is_empty() {
+                    Err(format!("Expected a key, found {:?}", self.peek().unwrap_or(' ')))
+                } else {
+                    Ok(key)
+                }
+            },
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        match self.peek() {
+            Some('"') => Ok(Value::String(self.basic_string()?)),
+            Some('\'') => Ok(Value::String(self.literal_string()?)),
+            Some('[') => self.array(),
+            Some('{') => self.inline_table(),
+            Some(_) => {
+                let mut word = String::new();
+                while let Some(c) = self.peek() {
+                    if !(c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '+') {
+                        break;
+                    }
+                    word.push(c);
+                    self.pos += 1;
+                }
+                match word.as_str() {
+                    "true" => Ok(Value::Boolean(true)),
+                    "false" => Ok(Value::Boolean(false)),
+                    _ => word.replace('_', "").parse().map(Value::Integer)
+                        .map_err(|_| format!("Invalid value {:?}", word)),
+                }
+            },
+            None => Err("Expected a value, found the end".to_string()),
+        }
+    }
+
+    fn basic_string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut string = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(string),
+                Some('\\') => string.push(match self.next() {
+                    Some('\\') => '\\',
+                    Some('"') => '"',
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('r') => '\r',
+                    c => return Err(format!("Invalid escape {:?}", c.unwrap_or(' '))),
+                }),
+                Some('\n') | None => return Err("Unterminated string".to_string()),
+                Some(c) => string.push(c),
+            }
+        }
+    }
+
+    fn literal_string(&mut self) -> Result<String, String> {
+        self.expect('\'')?;
+        let mut string = String::new();
+        loop {
+            match self.next() {
+                Some('\'') => return Ok(string),
+                Some('\n') | None => return Err("Unterminated string".to_string()),
+                Some(c) => string.push(c),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, String> {
+        self.expect('[')?;
+        let mut values = vec![];
+        loop {
+            self.skip_blank();
+            if self.peek() == Some(']') {
+                self.next();
+                return Ok(Value::Array(values));
+            }
+            values.push(self.value()?);
+            self.skip_blank();
+            match self.next() {
+                Some(',') => {},
+                Some(']') => return Ok(Value::Array(values)),
+                c => return Err(format!("Expected ',' or ']', found {:?}", c.unwrap_or(' '))),
+            }
+        }
+    }
+
+    fn inline_table(&mut self) -> Result<Value, String> {
+        self.expect('{')?;
+        let mut table = Value::Table(vec![]);
+        self.skip_spaces();
+        if self.peek() == Some('}') {
+            self.next();
+            return Ok(table);
+        }
+        loop {
+            self.skip_spaces();
+            let key = self.key()?;
+            self.skip_spaces();
+            self.expect('=')?;
+            self.skip_spaces();
+            let value = self.value()?;
+            table.insert(key, value)?;
+            self.skip_spaces();
+            match self.next() {
+                Some(',') => {},
+                Some('}') => return Ok(table),
+                c => return Err(format!("Expected ',' or '}}', found {:?}", c.unwrap_or(' '))),
+            }
+        }
+    }
+}
+
+/// Services declared in a `sombra.toml` manifest, one `[[service]]` table
+/// per service:
+///
+/// ```toml
+/// [[service]]
+/// name = "api"
+/// path = "/opt/api/api"
+/// args = ["--port", "8080"]
+/// start_type = "auto"  # "auto", "delayed", "manual" or "disabled"
+/// env = { RUST_LOG = "info" }
+///
+/// [service.restart_policy]
+/// max_restarts = 5
+/// window = 60  # seconds, as the delays
+/// initial_delay = 1
+/// max_delay = 30
+/// ```
+///
+/// The other keys are `display_name`, `description`, `dependencies`,
+/// `working_directory`, `stdout_log` and `stderr_log`, and `account` with
+/// `password_secret`, a key of the `SecretStore` of the service holding
+/// the password, which manifests never hold themselves.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Manifest {
+    pub services: Vec<SombraConfig>,
+}
+
+/// Changes made by `Manifest::apply`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Applied {
+    pub created: Vec<String>,
+    pub updated: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+macro_rules! manifest_error {
+    ($service:expr, $desc:expr) => {
+        Error::new(ErrorKind::Other, $desc).content(format!("service {}", $service))
+    };
+}
+
+impl Manifest {
+    pub fn load<P: AsRef<Path>>(path: P) -> crate::Result<Self> {
+        let text = std::fs::read_to_string(path.as_ref()).map_err(|e| {
+            Error::new(ErrorKind::Io, e.to_string()).content(path.as_ref().display().to_string())
+        })?;
+        Manifest::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> crate::Result<Self> {
+        let mut root = match Parser::parse(text)? {
+            Value::Table(entries) => entries,
+            _ => vec![],
+        };
+        let tables = match root.iter().position(|(key, _)| key == "service") {
+            Some(index) => root.remove(index).1,
+            None => Value::Array(vec![]),
+        };
+        if let Some((key, _)) = root.first() {
+            return Err(Error::new(ErrorKind::Other, format!("Unknown key {}", key)));
+        }
+        let tables = match tables {
+            Value::Array(tables) => tables,
+            _ => return Err(Error::new(ErrorKind::Other,
+                                       "service must be an array of tables".to_string())),
+        };
+
+        let mut services: Vec<SombraConfig> = vec![];
+        for (index, table) in tables.into_iter().enumerate() {
+            let service = Manifest::service(table, index)?;
+            if services.iter().any(|s| s.name == service.name) {
+                return Err(manifest_error!(service.name, "Service is declared twice".to_string()));
+            }
+            services.push(service);
+        }
+        Ok(Manifest { services })
+    }
+
+    fn service(table: Value, index: usize) -> crate::Result<SombraConfig> {
+        let mut entries = match table {
+            Value::Table(entries) => entries,
+            _ => vec![],
+        };
+        let mut take = |key: &str| entries.iter().position(|(name, _)| name == key)
+            .map(|index| entries.remove(index).1);
+        let name = match take("name") {
+            Some(Value::String(name)) => name,
+            _ => return Err(manifest_error!(index + 1, "Service needs a name string".to_string())),
+        };
+        let path = match take("path") {
+            Some(Value::String(path)) => path,
+            _ => return Err(manifest_error!(name, "Service needs a path string".to_string())),
+        };
+
+        let mut config = SombraConfig::new(&name, &path);
+        config.args = Manifest::strings(&name, "args", take("args"))?.into_iter()
+            .map(std::ffi::OsString::from)
+            .collect();
+        config.display_name = Manifest::string(&name, "display_name", take("display_name"))?;
+        config.description = Manifest::string(&name, "description", take("description"))?;
+        config.dependencies = Manifest::strings(&name, "dependencies", take("dependencies"))?;
+        config.working_directory = Manifest::string(&name, "working_directory",
+                                                    take("working_directory"))?.map(Into::into);
+        config.stdout_log = Manifest::string(&name, "stdout_log", take("stdout_log"))?
+            .map(Into::into);
+        config.stderr_log = Manifest::string(&name, "stderr_log", take("stderr_log"))?
+            .map(Into::into);
+        if let Some(account) = Manifest::string(&name, "account", take("account"))? {
+            config.account = Account::User { name: account, password: None };
+        }
+        config.password_secret = Manifest::string(&name, "password_secret",
+                                                  take("password_secret"))?;
+        if config.password_secret.is_some() && config.account == Account::LocalSystem {
+            return Err(manifest_error!(name, "password_secret needs an account".to_string()));
+        }
+        if let Some(start_type) = Manifest::string(&name, "start_type", take("start_type"))? {
+            config.start_type = match start_type.as_str() {
+                "auto" => StartType::AutoStart,
+                "delayed" => StartType::DelayedAutoStart,
+                "manual" => StartType::OnDemand,
+                "disabled" => StartType::Disabled,
+                _ => return Err(manifest_error!(name, format!("Unknown start_type {}",
+                                                              start_type))),
+            };
+        }
+        match take("env") {
+            Some(Value::Table(env)) => for (key, value) in env {
+                match value {
+                    Value::String(value) => config.env.push((key, value)),
+                    value => return Err(manifest_error!(name, format!(
+                        "env {} is a {}, not a string", key, value.type_name()))),
+                }
+            },
+            Some(value) => return Err(manifest_error!(name, format!(
+                "env is a {}, not a table", value.type_name()))),
+            None => {},
+        }
+        match take("restart_policy") {
+            Some(Value::Table(mut policy)) => {
+                let mut seconds = |key: &str| {
+                    let value = policy.iter().position(|(name, _)| name == key)
+                        .map(|index| policy.remove(index).1);
+                    match value {
+                        Some(Value::Integer(value)) if value >= 0 => Ok(value as u64),
+                        _ => Err(manifest_error!(name, format!(
+                            "restart_policy needs {} as a positive integer", key))),
+                    }
+                };
+                config.restart_policy = Some(RestartPolicy {
+                    max_restarts: seconds("max_restarts")? as u32,
+                    window: Duration::from_secs(seconds("window")?),
+                    initial_delay: Duration::from_secs(seconds("initial_delay")?),
+                    max_delay: Duration::from_secs(seconds("max_delay")?),
+                });
+                if let Some((key, _)) = policy.first() {
+                    return Err(manifest_error!(name, format!("Unknown key restart_policy.{}",
+                                                             key)));
+                }
+            },
+            Some(value) => return Err(manifest_error!(name, format!(
+                "restart_policy is a {}, not a table", value.type_name()))),
+            None => {},
+        }
+        if let Some((key, _)) = entries.first() {
+            return Err(manifest_error!(name, format!("Unknown key {}", key)));
+        }
+        Ok(config)
+    }
+
+    fn string(service: &str, key: &str, value: Option<Value>) -> crate::Result<Option<String>> {
+        match value {
+            Some(Value::String(value)) => Ok(Some(value)),
+            Some(value) => Err(manifest_error!(service, format!("{} is a {}, not a string",
+                                                                key, value.type_name()))),
+            None => Ok(None),
+        }
+    }
+
+    fn strings(service: &str, key: &str, value: Option<Value>) -> crate::Result<Vec<String>> {
+        match value {
+            Some(Value::Array(values)) => values.into_iter()
+                .map(|value| match value {
+                    Value::String(value) => Ok(value),
+                    value => Err(manifest_error!(service, format!(
+                        "{} holds a {}, not strings", key, value.type_name()))),
+                })
+                .collect(),
+            Some(value) => Err(manifest_error!(service, format!("{} is a {}, not an array",
+                                                                key, value.type_name()))),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Converges the machine to the manifest with a specific backend,
+    /// instead of the default of the platform.
+    ///
+    /// Listed services are created or updated. `state` is the file listing
+    /// the services of previous applies, one name per line, so the ones no
+    /// longer listed are deleted; it is rewritten even when a step fails.
+    pub fn apply_as<S: Sombra>(&self, state: &Path) -> crate::Result<Applied> {
+        let previous = Manifest::previous(state)?;
+        let plan = self.plan_previous::<S>(&previous)?;
+
+        let mut applied = Applied::default();
+        let result = Manifest::execute::<S>(&plan, &mut applied);
+
+        // Services stay listed until their deletion succeeds
+        let pending = |name: &String| !applied.removed.contains(name) &&
+            plan.operations.iter().any(|o| matches!(o, Operation::Delete(n) if n == name));
+        let installed: BTreeSet<&String> = previous.iter()
+            .filter(|name| self.services.iter().any(|s| &s.name == *name) || pending(name))
+            .chain(applied.created.iter())
+            .chain(applied.updated.iter())
+            .collect();
+        let text: String = installed.iter().map(|name| format!("{}\n", name)).collect();
+        std::fs::write(state, text).map_err(|e| {
+            Error::new(ErrorKind::Io, e.to_string()).content(state.display().to_string())
+        })?;
+
+        result.map(|()| applied)
+    }
+
+    /// Operations `apply_as` would perform, without changing anything
+    pub fn plan_as<S: Sombra>(&self, state: &Path) -> crate::Result<Plan> {
+        self.plan_previous::<S>(&Manifest::previous(state)?)
+    }
+
+    fn previous(state: &Path) -> crate::Result<BTreeSet<String>> {
+        match std::fs::read_to_string(state) {
+            Ok(text) => Ok(text.lines().filter(|l| !l.is_empty()).map(String::from).collect()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeSet::new()),
+            Err(e) => Err(Error::new(ErrorKind::Io, e.to_string())
+                .content(state.display().to_string())),
+        }
+    }
+
+    fn plan_previous<S: Sombra>(&self, previous: &BTreeSet<String>) -> crate::Result<Plan> {
+        let mut plan = Plan::default();
+        for config in &self.services {
+            plan.operations.extend(SombraBuilder::from(config.clone()).plan_create_as::<S>()?
+                .operations);
+        }
+        for name in previous {
+            if self.services.iter().any(|s| &s.name == name) {
+                continue;
+            }
+            plan.operations.extend(SombraBuilder::new(name, ".").plan_delete_as::<S>()?
+                .operations);
+        }
+        Ok(plan)
+    }
+
+    fn execute<S: Sombra>(plan: &Plan, applied: &mut Applied) -> crate::Result<()> {
+        for operation in &plan.operations {
+            match operation {
+                Operation::Create(config) => {
+                    S::from_config(config.as_ref().clone())?.create()?;
+                    applied.created.push(config.name.clone());
+                },
+                Operation::Update(config) => {
+                    S::from_config(config.as_ref().clone())?.update()?;
+                    applied.updated.push(config.name.clone());
+                },
+                Operation::Delete(name) => {
+                    S::build(name, ".", Vec::<String>::new())?.delete()?;
+                    applied.removed.push(name.clone());
+                },
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(target_os = "windows")]
+    pub fn apply(&self, state: &Path) -> crate::Result<Applied> {
+        self.apply_as::<crate::SombraWindows>(state)
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn apply(&self, state: &Path) -> crate::Result<Applied> {
+        self.apply_as::<crate::SombraLinux>(state)
+    }
+
+    #[cfg(target_os = "macos")]
+    pub fn apply(&self, state: &Path) -> crate::Result<Applied> {
+        self.apply_as::<crate::SombraMacos>(state)
+    }
+
+    #[cfg(target_os = "freebsd")]
+    pub fn apply(&self, state: &Path) -> crate::Result<Applied> {
+        self.apply_as::<crate::SombraFreebsd>(state)
+    }
+
+    #[cfg(any(target_os = "illumos", target_os = "solaris"))]
+    pub fn apply(&self, state: &Path) -> crate::Result<Applied> {
+        self.apply_as::<crate::SombraSmf>(state)
+    }
+
+    #[cfg(target_os = "windows")]
+    pub fn plan(&self, state: &Path) -> crate::Result<Plan> {
+        self.plan_as::<crate::SombraWindows>(state)
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn plan(&self, state: &Path) -> crate::Result<Plan> {
+        self.plan_as::<crate::SombraLinux>(state)
+    }
+
+    #[cfg(target_os = "macos")]
+    pub fn plan(&self, state: &Path) -> crate::Result<Plan> {
+        self.plan_as::<crate::SombraMacos>(state)
+    }
+
+    #[cfg(target_os = "freebsd")]
+    pub fn plan(&self, state: &Path) -> crate::Result<Plan> {
+        self.plan_as::<crate::SombraFreebsd>(state)
+    }
+
+    #[cfg(any(target_os = "illumos", target_os = "solaris"))]
+    pub fn plan(&self, state: &Path) -> crate::Result<Plan> {
+        self.plan_as::<crate::SombraSmf>(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::SombraMock;
+    use crate::ServiceStatus;
+
+    const MANIFEST: &str = r#"
+# Services of the machine
+[[service]]
+name = "api"
+path = "/opt/api/api"
+args = [
+    "--port", "8080",  # default port
+]
+start_type = "auto"
+env = { RUST_LOG = "info", 'DATA' = "C:\\data" }
+
+[service.restart_policy]
+max_restarts = 5
+window = 60
+initial_delay = 1
+max_delay = 30
+
+[[service]]
+name = "worker"
+path = '/opt/worker/worker'
+dependencies = ["api"]
+account = "CORP\\worker"
+password_secret = "worker-password"
+"#;
+
+    #[test]
+    fn parse_manifest() {
+        let manifest = Manifest::parse(MANIFEST).unwrap();
+        let mut api = SombraConfig::new("api", "/opt/api/api");
+        api.args = vec!["--port".into(), "8080".into()];
+        api.start_type = StartType::AutoStart;
+        api.env = vec![("RUST_LOG".to_string(), "info".to_string()),
+                       ("DATA".to_string(), "C:\\data".to_string())];
+        api.restart_policy = Some(RestartPolicy {
+            max_restarts: 5,
+            window: Duration::from_secs(60),
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+        });
+        let mut worker = SombraConfig::new("worker", "/opt/worker/worker");
+        worker.dependencies = vec!["api".to_string()];
+        worker.account = Account::User { name: "CORP\\worker".to_string(), password: None };
+        worker.password_secret = Some("worker-password".to_string());
+        assert_eq!(manifest, Manifest { services: vec![api, worker] });
+    }
+
+    #[test]
+    fn parse_errors() {
+        let error = |text: &str| Manifest::parse(text).unwrap_err().to_string();
+        assert_eq!(error("[[service]]\nname = \"api\"\npath = \"/opt/api\"\nport = 80\n"),
+                   "<Other> service api: Unknown key port");
+        assert_eq!(error("[[service]]\nname = \"api\"\npath = \"/opt/api\"\nargs = \"-v\"\n"),
+                   "<Other> service api: args is a string, not an array");
+        assert_eq!(error("[[service]]\nname = \"api\"\npath = \"/opt/api\"\n\
+                          password_secret = \"api\"\n"),
+                   "<Other> service api: password_secret needs an account");
+        assert_eq!(error("[[service]]\nname = \"api\nbroken\"\n"),
+                   "<Other> line 3: Unterminated string");
+        assert_eq!(error("[[service]]\nname = \"api\"\nname = \"api\"\n"),
+                   "<Other> line 3: Duplicate key name");
+        assert_eq!(error("[[service]]\nname = \"a\"\npath = \"/a\"\n\
+                          [[service]]\nname = \"a\"\npath = \"/b\"\n"),
+                   "<Other> service a: Service is declared twice");
+    }
+
+    #[test]
+    fn apply_converges() {
+        let state = std::env::temp_dir().join("sombra_manifest_test.applied");
+        let _ = std::fs::remove_file(&state);
+        let path = std::env::current_exe().unwrap().display().to_string();
+        let manifest = |names: &[&str]| Manifest {
+            services: names.iter().map(|name| SombraConfig::new(name, &path)).collect(),
+        };
+        let status = |name: &str| {
+            SombraMock::build(name, ".", Vec::<String>::new()).unwrap().status().unwrap()
+        };
+
+        let applied = manifest(&["api", "worker"]).apply_as::<SombraMock>(&state).unwrap();
+        assert_eq!(applied.created, vec!["api", "worker"]);
+        assert_eq!(status("worker"), ServiceStatus::Running);
+
+        let plan = manifest(&["api"]).plan_as::<SombraMock>(&state).unwrap();
+        assert_eq!(plan.to_string(), format!("update api: {} (OnDemand)\ndelete worker\n", path));
+        assert_eq!(status("worker"), ServiceStatus::Running);
+
+        let applied = manifest(&["api"]).apply_as::<SombraMock>(&state).unwrap();
+        assert_eq!(applied, Applied {
+            created: vec![],
+            updated: vec!["api".to_string()],
+            removed: vec!["worker".to_string()],
+        });
+        assert_eq!(status("worker"), ServiceStatus::NotInstalled);
+        assert_eq!(std::fs::read_to_string(&state).unwrap(), "api\n");
+        let _ = std::fs::remove_file(&state);
+    }
+}
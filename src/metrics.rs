@@ -0,0 +1,189 @@
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use crate::{Probe, ServiceStatus, Sombra};
+
+struct Target {
+    name: String,
+    service: Box<dyn Sombra + Send>,
+    probe: Option<Probe>,
+}
+
+/// Prometheus endpoint serving `/metrics` for a set of services: state,
+/// restarts, CPU, memory and probe results. The Windows wrapper serves the
+/// same metrics on `SombraBuilder::metrics_port`; elsewhere a supervising
+/// process runs this server.
+#[derive(Default)]
+pub struct MetricsServer {
+    targets: Vec<Target>,
+}
+
+/// A running `MetricsServer`, stopped on drop
+pub struct MetricsHandle {
+    address: std::net::SocketAddr,
+    stopped: Arc<AtomicBool>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl MetricsServer {
+    pub fn new() -> Self {
+        MetricsServer::default()
+    }
+
+    pub fn service<S: Sombra + Send + 'static>(mut self, name: &str, service: S,
+                                               probe: Option<Probe>) -> Self {
+        self.targets.push(Target {
+            name: name.to_string(),
+            service: Box::new(service),
+            probe,
+        });
+        self
+    }
+
+    /// Renders the metrics in the Prometheus text format
+    pub fn render(&self) -> String {
+        let mut up = vec![];
+        let mut restarts = vec![];
+        let mut uptime = vec![];
+        let mut cpu = vec![];
+        let mut memory = vec![];
+        let mut handles = vec![];
+        let mut probes = vec![];
+        for target in &self.targets {
+            let label = target.name.replace('\\', "\\\\").replace('"', "\\\"");
+            let running = target.service.status().ok() == Some(ServiceStatus::Running);
+            up.push((label.clone(), if running { 1.0 } else { 0.0 }));
+            if let Ok(stats) = target.service.stats() {
+                restarts.push((label.clone(), stats.restarts as f64));
+                if let Some(elapsed) = stats.uptime() {
+                    uptime.push((label.clone(), elapsed.as_secs_f64()));
+                }
+            }
+            if let Ok(Some(usage)) = target.service.usage() {
+                cpu.push((label.clone(), usage.cpu_time.as_secs_f64()));
+                memory.push((label.clone(), usage.rss as f64));
+                handles.push((label.clone(), usage.handles as f64));
+            }
+            if let Some(probe) = &target.probe {
+                let success = probe.check(Duration::from_secs(5)).is_ok();
+                probes.push((label, if success { 1.0 } else { 0.0 }));
+            }
+        }
+
+        let families = [
+            ("sombra_up", "gauge", "Whether the target is running", up),
+            ("sombra_restarts_total", "counter", "Restarts of the target", restarts),
+            ("sombra_uptime_seconds", "gauge", "Time since the target started", uptime),
+            ("sombra_cpu_seconds_total", "counter", "CPU time used by the target", cpu),
+            ("sombra_memory_bytes", "gauge", "Resident memory of the target", memory),
+            ("sombra_open_handles", "gauge", "Open descriptors or handles of the target",
+             handles),
+            ("sombra_probe_success", "gauge", "Whether the last probe succeeded", probes),
+        ];
+        let mut output = String::new();
+        for (name, kind, help, samples) in families.iter() {
+            if samples.is_empty() {
+                continue;
+            }
+            output.push_str(&format!("# HELP {} {}\n# TYPE {} {}\n", name, help, name, kind));
+            for (service, value) in samples {
+                output.push_str(&format!("{}{{service=\"{}\"}} {}\n", name, service, value));
+            }
+        }
+        output
+    }
+
+    /// Serves the metrics on `address`, e.g. "127.0.0.1:9180"
+    pub fn spawn(self, address: &str) -> crate::Result<MetricsHandle> {
+        let listener = TcpListener::bind(address)?;
+        let address = listener.local_addr()?;
+        let stopped = Arc::new(AtomicBool::new(false));
+        let thread_stopped = stopped.clone();
+        let thread = std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                if thread_stopped.load(Ordering::SeqCst) {
+                    return;
+                }
+                if let Ok(stream) = stream {
+                    let _ = self.respond(stream);
+                }
+            }
+        });
+        Ok(MetricsHandle { address, stopped, thread: Some(thread) })
+    }
+
+    fn respond(&self, mut stream: TcpStream) -> std::io::Result<()> {
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        let mut buffer = [0u8; 1024];
+        let len = stream.read(&mut buffer)?;
+        let request = String::from_utf8_lossy(&buffer[..len]);
+        // GET /metrics HTTP/1.1
+        let path = request.split_whitespace().nth(1).unwrap_or_default();
+        let response = if path == "/metrics" {
+            let body = self.render();
+            format!("HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body)
+        } else {
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                .to_string()
+        };
+        stream.write_all(response.as_bytes())
+    }
+}
+
+impl MetricsHandle {
+    pub fn address(&self) -> std::net::SocketAddr {
+        self.address
+    }
+}
+
+impl Drop for MetricsHandle {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        // Wakes up the blocking accept
+        let _ = TcpStream::connect(self.address);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SombraConfig;
+    use crate::mock::SombraMock;
+
+    #[test]
+    fn render_samples() {
+        let mock = SombraMock::from_config(SombraConfig::new("mock", "/opt/mock")).unwrap();
+        mock.create().unwrap();
+        mock.start().unwrap();
+        let server = MetricsServer::new().service("mock", mock, None);
+        assert_eq!(server.render(),
+                   "# HELP sombra_up Whether the target is running\n\
+                    # TYPE sombra_up gauge\n\
+                    sombra_up{service=\"mock\"} 1\n\
+                    # HELP sombra_restarts_total Restarts of the target\n\
+                    # TYPE sombra_restarts_total counter\n\
+                    sombra_restarts_total{service=\"mock\"} 0\n");
+    }
+
+    #[test]
+    fn serves_metrics() {
+        let mock = SombraMock::from_config(SombraConfig::new("mock", "/opt/mock")).unwrap();
+        let handle = MetricsServer::new().service("mock", mock, None).spawn("127.0.0.1:0")
+            .unwrap();
+        let mut stream = TcpStream::connect(handle.address()).unwrap();
+        stream.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("sombra_up{service=\"mock\"} 0\n\
+                                    # HELP sombra_restarts_total Restarts of the target\n\
+                                    # TYPE sombra_restarts_total counter\n\
+                                    sombra_restarts_total{service=\"mock\"} 0\n"));
+    }
+}
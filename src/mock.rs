@@ -0,0 +1,274 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use crate::{Artifact, Error, ErrorKind, LogReader, Privileges, ServiceStats, ServiceStatus, Sombra,
+            SombraConfig, Usage};
+
+/// A call made to a `SombraMock` that changes the service
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Call {
+    Create,
+    Update,
+    Delete,
+    Start,
+    Stop,
+}
+
+struct Installed {
+    status: Mutex<ServiceStatus>,
+    /// Configuration written by the last create or update
+    config: Mutex<Option<SombraConfig>>,
+    calls: Mutex<Vec<Call>>,
+    /// Checksum of the target at the last create or update
+    checksum: Mutex<Option<String>>,
+}
+
+thread_local! {
+    /// Services of the simulated machine, one per test thread
+    static MACHINE: RefCell<HashMap<String, Arc<Installed>>> = RefCell::new(HashMap::new());
+}
+
+/// In-memory backend, for unit tests of deployment logic without admin
+/// rights or a service manager. Clones, and mocks built on the same thread
+/// with the same name, share the service: its state, its installed
+/// configuration and the calls made to it. Calls fail the way a service
+/// manager would, e.g. creating a service that is already installed.
+#[derive(Clone)]
+pub struct SombraMock {
+    config: SombraConfig,
+    service: Arc<Installed>,
+}
+
+impl SombraMock {
+    /// Forces the state of the service, e.g. `Failed` to simulate a crash
+    pub fn set(&self, status: ServiceStatus) {
+        *self.service.status.lock().unwrap() = status;
+    }
+
+    /// Calls made to the service so far, in order
+    pub fn calls(&self) -> Vec<Call> {
+        self.service.calls.lock().unwrap().clone()
+    }
+
+    fn record(&self, call: Call) -> crate::Result<()> {
+        self.service.calls.lock().unwrap().push(call);
+        let installed = self.service.config.lock().unwrap().is_some()
+            || *self.service.status.lock().unwrap() != ServiceStatus::NotInstalled;
+        match (call, installed) {
+            (Call::Create, true) => Err(Error::new(ErrorKind::ServiceAlreadyExists,
+                                                   "Service already exists".to_string())
+                .content(self.config.name.clone())),
+            (Call::Create, false) => Ok(()),
+            (_, false) => Err(Error::new(ErrorKind::ServiceNotFound,
+                                         "Service does not exist".to_string())
+                .content(self.config.name.clone())),
+            (_, true) => Ok(()),
+        }
+    }
+}
+
+impl Sombra for SombraMock {
+    fn from_config(config: SombraConfig) -> crate::Result<Self> {
+        let service = MACHINE.with(|machine| {
+            machine.borrow_mut().entry(config.name.clone())
+                .or_insert_with(|| Arc::new(Installed {
+                    status: Mutex::new(ServiceStatus::NotInstalled),
+                    config: Mutex::new(None),
+                    calls: Mutex::new(vec![]),
+                    checksum: Mutex::new(None),
+                }))
+                .clone()
+        });
+        Ok(SombraMock { config, service })
+    }
+
+    fn create(&self) -> crate::Result<()> {
+        self.record(Call::Create)?;
+        *self.service.config.lock().unwrap() = Some(self.config.clone());
+        *self.service.checksum.lock().unwrap() =
+            crate::digest::sha256_file(&self.config.path).ok();
+        if self.config.target_pending() {
+            self.set(ServiceStatus::Stopped);
+            return Ok(());
+        }
+        self.start()
+    }
+
+    fn delete(&self) -> crate::Result<()> {
+        self.record(Call::Delete)?;
+        *self.service.config.lock().unwrap() = None;
+        self.set(ServiceStatus::NotInstalled);
+        Ok(())
+    }
+
+    fn update(&self) -> crate::Result<()> {
+        self.record(Call::Update)?;
+        *self.service.config.lock().unwrap() = Some(self.config.clone());
+        *self.service.checksum.lock().unwrap() =
+            crate::digest::sha256_file(&self.config.path).ok();
+        Ok(())
+    }
+
+    fn start(&self) -> crate::Result<()> {
+        self.record(Call::Start)?;
+        self.config.check_target()?;
+        self.set(ServiceStatus::Running);
+        Ok(())
+    }
+
+    fn stop(&self) -> crate::Result<()> {
+        self.record(Call::Stop)?;
+        self.set(ServiceStatus::Stopped);
+        Ok(())
+    }
+
+    fn status(&self) -> crate::Result<ServiceStatus> {
+        Ok(*self.service.status.lock().unwrap())
+    }
+
+    fn pid(&self) -> crate::Result<Option<u32>> {
+        Ok(None)
+    }
+
+    fn usage(&self) -> crate::Result<Option<Usage>> {
+        Ok(None)
+    }
+
+    fn stats(&self) -> crate::Result<ServiceStats> {
+        Ok(ServiceStats::default())
+    }
+
+    fn config(&self) -> crate::Result<SombraConfig> {
+        match &*self.service.config.lock().unwrap() {
+            Some(config) => Ok(config.clone()),
+            None => Err(Error::new(ErrorKind::ServiceNotFound,
+                                   "Service does not exist".to_string())
+                .content(self.config.name.clone())),
+        }
+    }
+
+    fn diff(&self) -> crate::Result<Vec<crate::Difference>> {
+        Ok(self.config.diff(&self.config()?))
+    }
+
+    fn checksum(&self) -> crate::Result<Option<String>> {
+        self.config()?;
+        Ok(self.service.checksum.lock().unwrap().clone())
+    }
+
+    fn list_managed() -> crate::Result<Vec<crate::ManagedService>> {
+        let mut services: Vec<crate::ManagedService> = MACHINE.with(|machine| {
+            machine.borrow().values()
+                .filter_map(|service| {
+                    let config = service.config.lock().unwrap().clone()?;
+                    Some(crate::ManagedService {
+                        name: config.name,
+                        status: *service.status.lock().unwrap(),
+                        path: config.path,
+                    })
+                })
+                .collect()
+        });
+        services.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(services)
+    }
+
+    fn required_privileges(&self) -> Privileges {
+        Privileges::User
+    }
+
+    fn logs(&self, _follow: bool) -> crate::Result<LogReader> {
+        LogReader::files(vec![], false)
+    }
+
+    /// The settings the simulated machine would keep
+    fn render(&self) -> crate::Result<Vec<Artifact>> {
+        Ok(vec![Artifact::registration(crate::settings::content(&self.config, &[]))])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mock_records_calls() {
+        let mock = SombraMock::from_config(SombraConfig::new("api", "/opt/api")).unwrap();
+        assert_eq!(mock.start().map_err(|e| e.kind()), Err(ErrorKind::ServiceNotFound));
+        assert_eq!(mock.create(), Ok(()));
+        assert_eq!(mock.create().map_err(|e| e.kind()), Err(ErrorKind::ServiceAlreadyExists));
+        assert_eq!(mock.restart(), Ok(()));
+        assert_eq!(mock.delete(), Ok(()));
+        assert_eq!(mock.calls(), vec![Call::Start, Call::Create, Call::Start, Call::Create,
+                                      Call::Stop, Call::Start, Call::Delete]);
+        assert_eq!(mock.status(), Ok(ServiceStatus::NotInstalled));
+    }
+
+    #[test]
+    fn install_self_runs_current_exe() {
+        let mock = SombraMock::install_self("daemon", ["--serve"]).unwrap();
+        let config = mock.config().unwrap();
+        assert_eq!(config.path, std::env::current_exe().unwrap());
+        assert_eq!(config.args, vec!["--serve"]);
+        assert_eq!(mock.status(), Ok(ServiceStatus::Running));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn builder_keeps_non_utf8_args_and_paths() {
+        use std::ffi::OsString;
+        use std::os::unix::ffi::OsStringExt;
+        let arg = OsString::from_vec(vec![b'-', 0xff]);
+        let log = std::path::PathBuf::from(OsString::from_vec(b"/var/log/\xffapi.log".to_vec()));
+        let mock = SombraMock::build("raw_args", std::env::current_exe().unwrap(), [arg.clone()])
+            .unwrap();
+        mock.create().unwrap();
+        assert_eq!(mock.config().unwrap().args, vec![arg]);
+        mock.delete().unwrap();
+
+        let config = crate::SombraBuilder::new("raw_args", "/opt/raw_args")
+            .defer_path(true)
+            .args(["--log", "-"])
+            .stdout_log(&log)
+            .working_directory(log.parent().unwrap())
+            .config()
+            .unwrap();
+        assert_eq!(config.args, vec!["--log", "-"]);
+        assert_eq!(config.stdout_log, Some(log));
+    }
+
+    #[test]
+    fn deferred_path_starts_once_in_place() {
+        let path = std::env::temp_dir().join("sombra_deferred_test");
+        let _ = std::fs::remove_file(&path);
+        assert!(crate::SombraBuilder::new("deferred", &path).build_as::<SombraMock>().is_err());
+
+        let mock: SombraMock = crate::SombraBuilder::new("deferred", &path)
+            .defer_path(true)
+            .build_as()
+            .unwrap();
+        assert_eq!(mock.create(), Ok(()));
+        assert_eq!(mock.status(), Ok(ServiceStatus::Stopped));
+        assert_eq!(mock.start().map_err(|e| e.kind()), Err(ErrorKind::Io));
+
+        std::fs::write(&path, "").unwrap();
+        assert_eq!(mock.start(), Ok(()));
+        assert_eq!(mock.status(), Ok(ServiceStatus::Running));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn verify_detects_replaced_target() {
+        let path = std::env::temp_dir().join("sombra_verify_test");
+        std::fs::write(&path, "v1").unwrap();
+        let mock = SombraMock::from_config(SombraConfig::new("verify", &path)).unwrap();
+        mock.create().unwrap();
+        assert_eq!(mock.verify(), Ok(()));
+
+        std::fs::write(&path, "v2").unwrap();
+        assert_eq!(mock.verify().map_err(|e| e.kind()), Err(ErrorKind::ChecksumMismatch));
+        mock.update().unwrap();
+        assert_eq!(mock.verify(), Ok(()));
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,183 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+use crate::{Error, ErrorKind, LogReader, ServiceStatus, Sombra};
+
+struct Shared<T> {
+    result: Option<T>,
+    waker: Option<Waker>,
+}
+
+/// Future of a blocking call, which runs on its own thread so the
+/// executor polling it is never blocked
+pub struct Blocking<T> {
+    shared: Arc<Mutex<Shared<T>>>,
+}
+
+impl<T: Send + 'static> Blocking<T> {
+    pub fn spawn<F: FnOnce() -> T + Send + 'static>(call: F) -> Self {
+        let shared = Arc::new(Mutex::new(Shared { result: None, waker: None }));
+        let thread_shared = shared.clone();
+        std::thread::spawn(move || {
+            let result = call();
+            let mut shared = match thread_shared.lock() {
+                Ok(shared) => shared,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            shared.result = Some(result);
+            if let Some(waker) = shared.waker.take() {
+                waker.wake();
+            }
+        });
+        Blocking { shared }
+    }
+}
+
+impl<T> Future for Blocking<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut shared = match self.shared.lock() {
+            Ok(shared) => shared,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        match shared.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Waits without blocking the executor
+fn sleep(duration: Duration) -> Blocking<()> {
+    Blocking::spawn(move || std::thread::sleep(duration))
+}
+
+/// Async front of a backend, usable from any executor. Every call runs
+/// the blocking backend call on a thread of its own.
+pub struct AsyncSombra<S> {
+    inner: Arc<S>,
+}
+
+impl<S> Clone for AsyncSombra<S> {
+    fn clone(&self) -> Self {
+        AsyncSombra { inner: self.inner.clone() }
+    }
+}
+
+impl<S: Sombra + Send + Sync + 'static> AsyncSombra<S> {
+    pub fn new(service: S) -> Self {
+        AsyncSombra { inner: Arc::new(service) }
+    }
+
+    fn call<T, F>(&self, call: F) -> Blocking<T>
+        where T: Send + 'static, F: FnOnce(&S) -> T + Send + 'static {
+        let inner = self.inner.clone();
+        Blocking::spawn(move || call(&inner))
+    }
+
+    pub fn create(&self) -> Blocking<crate::Result<()>> {
+        self.call(|s| s.create())
+    }
+
+    pub fn update(&self) -> Blocking<crate::Result<()>> {
+        self.call(|s| s.update())
+    }
+
+    pub fn create_or_update(&self) -> Blocking<crate::Result<()>> {
+        self.call(|s| s.create_or_update())
+    }
+
+    pub fn delete(&self) -> Blocking<crate::Result<()>> {
+        self.call(|s| s.delete())
+    }
+
+    pub fn start(&self) -> Blocking<crate::Result<()>> {
+        self.call(|s| s.start())
+    }
+
+    pub fn stop(&self) -> Blocking<crate::Result<()>> {
+        self.call(|s| s.stop())
+    }
+
+    pub fn status(&self) -> Blocking<crate::Result<ServiceStatus>> {
+        self.call(|s| s.status())
+    }
+
+    pub fn exists(&self) -> Blocking<crate::Result<bool>> {
+        self.call(|s| s.exists())
+    }
+
+    pub fn logs(&self, follow: bool) -> Blocking<crate::Result<LogReader>> {
+        self.call(move |s| s.logs(follow))
+    }
+
+    /// Same as `Sombra::restart`, polling the status with async waits
+    pub async fn restart(&self) -> crate::Result<()> {
+        self.stop().await?;
+
+        let timeout = Duration::from_secs(30);
+        let begin = std::time::Instant::now();
+        while !matches!(self.status().await?, ServiceStatus::Stopped | ServiceStatus::Failed) {
+            if begin.elapsed() > timeout {
+                return Err(Error::new(ErrorKind::Timeout,
+                                      "Service did not stop in time".to_string()));
+            }
+            sleep(Duration::from_millis(50)).await;
+        }
+
+        self.start().await
+    }
+
+    /// The backend, for calls without an async version
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SombraConfig;
+    use crate::mock::SombraMock;
+    use std::task::Wake;
+
+    struct Thread(std::thread::Thread);
+
+    impl Wake for Thread {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = Box::pin(future);
+        let waker = Waker::from(Arc::new(Thread(std::thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => std::thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn async_calls_reach_the_backend() {
+        let mock = SombraMock::from_config(SombraConfig::new("mock", "/opt/mock")).unwrap();
+        let service = AsyncSombra::new(mock);
+        block_on(async {
+            assert_eq!(service.create().await, Ok(()));
+            assert_eq!(service.status().await, Ok(ServiceStatus::Running));
+            assert_eq!(service.restart().await, Ok(()));
+            assert_eq!(service.status().await, Ok(ServiceStatus::Running));
+            assert_eq!(service.delete().await, Ok(()));
+            assert_eq!(service.status().await, Ok(ServiceStatus::NotInstalled));
+        });
+    }
+}
@@ -0,0 +1,76 @@
+//! Helpers for the target itself, to report readiness and watchdog
+//! heartbeats to systemd or the Windows wrapper.
+
+use std::time::Duration;
+
+/// Sends a state such as `READY=1` or `WATCHDOG=1` to the service manager.
+/// Returns false when the process isn't run by a service manager that
+/// listens for notifications.
+pub fn notify(state: &str) -> crate::Result<bool> {
+    #[cfg(target_os = "linux")]
+    {
+        let socket = match std::env::var_os("NOTIFY_SOCKET") {
+            Some(socket) => socket,
+            None => return Ok(false),
+        };
+        let datagram = std::os::unix::net::UnixDatagram::unbound()?;
+        let socket = socket.to_string_lossy().to_string();
+        match socket.strip_prefix('@') {
+            Some(name) => {
+                use std::os::linux::net::SocketAddrExt;
+                let address = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+                datagram.send_to_addr(state.as_bytes(), &address)?;
+            },
+            None => {
+                datagram.send_to(state.as_bytes(), socket)?;
+            },
+        }
+        Ok(true)
+    }
+    #[cfg(target_os = "windows")]
+    {
+        use std::io::Write;
+        let pipe = match std::env::var_os("SOMBRA_NOTIFY_PIPE") {
+            Some(pipe) => pipe,
+            None => return Ok(false),
+        };
+        let mut pipe = std::fs::OpenOptions::new().write(true).open(pipe)?;
+        pipe.write_all(format!("{}\n", state).as_bytes())?;
+        Ok(true)
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "windows")))]
+    {
+        let _ = state;
+        Ok(false)
+    }
+}
+
+/// Interval within which the target must send `WATCHDOG=1`, if the
+/// service has a watchdog
+pub fn watchdog_interval() -> Option<Duration> {
+    let usec = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    Some(Duration::from_micros(usec))
+}
+
+#[cfg(test)]
+#[cfg(target_os = "linux")]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notify_sends_state() {
+        let path = std::env::temp_dir().join("sombra_notify_test");
+        let _ = std::fs::remove_file(&path);
+        let listener = std::os::unix::net::UnixDatagram::bind(&path).unwrap();
+
+        std::env::set_var("NOTIFY_SOCKET", &path);
+        let sent = notify("READY=1");
+        std::env::remove_var("NOTIFY_SOCKET");
+        assert_eq!(sent, Ok(true));
+
+        let mut buffer = [0u8; 64];
+        let len = listener.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..len], b"READY=1");
+        let _ = std::fs::remove_file(&path);
+    }
+}
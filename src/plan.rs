@@ -0,0 +1,53 @@
+use crate::SombraConfig;
+
+/// A change a plan would make to a service
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operation {
+    /// Installs and starts a service with the configuration
+    Create(Box<SombraConfig>),
+    /// Applies the configuration to an installed service
+    Update(Box<SombraConfig>),
+    /// Deletes an installed service
+    Delete(String),
+}
+
+impl std::fmt::Display for Operation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (action, config) = match self {
+            Operation::Create(config) => ("create", config),
+            Operation::Update(config) => ("update", config),
+            Operation::Delete(name) => return write!(f, "delete {}", name),
+        };
+        write!(f, "{} {}: {}", action, config.name, config.path.display())?;
+        for arg in &config.args {
+            write!(f, " {}", arg.to_string_lossy())?;
+        }
+        write!(f, " ({:?}", config.start_type)?;
+        if let Some(policy) = &config.restart_policy {
+            write!(f, ", up to {} restarts", policy.max_restarts)?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// Operations computed without changing anything, for a review before
+/// they are applied
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Plan {
+    pub operations: Vec<Operation>,
+}
+
+impl Plan {
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+}
+
+impl std::fmt::Display for Plan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for operation in &self.operations {
+            writeln!(f, "{}", operation)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,87 @@
+use std::ffi::OsString;
+use std::path::Path;
+use std::process::{Command, ExitStatus};
+
+/// Rights the process needs to create and control a service
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Privileges {
+    /// Those of the current user, for per-user services
+    User,
+    /// Root, or an elevated administrator on Windows
+    Administrator,
+}
+
+/// Whether the process runs as root, or on Windows with the rights to
+/// create services, which UAC withholds from administrators until they
+/// elevate
+#[cfg(unix)]
+pub fn is_elevated() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+#[cfg(windows)]
+pub fn is_elevated() -> bool {
+    !crate::windows::sombra_imp::SombraWindows::lacks_rights()
+}
+
+/// Runs the current executable again, with the same arguments, as root or
+/// an elevated administrator, and waits for it to exit: through UAC on
+/// Windows, pkexec in a Linux graphical session and sudo otherwise. On
+/// Windows the elevated process gets a console of its own and starts in
+/// the system directory, so relative paths among the arguments break.
+///
+/// ```no_run
+/// use sombra::{Privileges, Sombra, SombraBuilder};
+///
+/// let service = SombraBuilder::new("tcp_echo", "/opt/tcp_echo").build()?;
+/// if service.required_privileges() == Privileges::Administrator && !sombra::is_elevated() {
+///     let status = sombra::elevate()?;
+///     std::process::exit(status.code().unwrap_or(1));
+/// }
+/// service.create()?;
+/// # Ok::<(), sombra::Error>(())
+/// ```
+pub fn elevate() -> crate::Result<ExitStatus> {
+    if is_elevated() {
+        // Running it again would do the same
+        return Err(crate::Error::new(crate::ErrorKind::Other,
+                                     "The process is already elevated".to_string()));
+    }
+    let executable = std::env::current_exe()?;
+    let args: Vec<OsString> = std::env::args_os().skip(1).collect();
+    Ok(elevated(&executable, &args).status()?)
+}
+
+#[cfg(unix)]
+fn elevated(executable: &Path, args: &[OsString]) -> Command {
+    let graphical = std::env::var_os("DISPLAY").is_some()
+        || std::env::var_os("WAYLAND_DISPLAY").is_some();
+    // pkexec asks through the polkit agent of the session
+    if cfg!(target_os = "linux") && graphical && crate::script::find_in_path("pkexec").is_some() {
+        let mut command = Command::new("pkexec");
+        command.arg(executable).args(args);
+        return command;
+    }
+    let mut command = Command::new("sudo");
+    command.arg("--").arg(executable).args(args);
+    command
+}
+
+#[cfg(windows)]
+fn elevated(executable: &Path, args: &[OsString]) -> Command {
+    use crate::windows::powershell::quote;
+    use crate::windows::sombra_imp::SombraWindows;
+
+    // Start-Process rejects an empty argument list
+    let argument_list = if args.is_empty() {
+        String::new()
+    } else {
+        format!(" -ArgumentList {}", quote(&SombraWindows::command_line(args)))
+    };
+    let mut command = Command::new("powershell.exe");
+    command.args(["-NoProfile", "-NonInteractive", "-Command"])
+        .arg(format!("$p = Start-Process -FilePath {}{} -Verb RunAs -Wait -PassThru; \
+                      exit $p.ExitCode",
+                     quote(&executable.to_string_lossy()), argument_list));
+    command
+}
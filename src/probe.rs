@@ -0,0 +1,255 @@
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::time::Duration;
+use crate::Sombra;
+
+/// A check of whether the target is serving
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Probe {
+    /// A TCP connection to `host:port` succeeds
+    Tcp(String),
+    /// A GET of an `http://` URL answers with a 2xx status
+    Http(String),
+    /// A command exits with success
+    Exec { command: String, args: Vec<String> },
+    /// A file exists, e.g. a pid or socket file created once ready
+    File(PathBuf),
+}
+
+impl Probe {
+    pub fn check(&self, timeout: Duration) -> crate::Result<()> {
+        match self {
+            Probe::Tcp(address) => {
+                Probe::connect(address, timeout)?;
+                Ok(())
+            },
+            Probe::Http(url) => Probe::http_get(url, timeout),
+            Probe::Exec { command, args } => {
+                let status = std::process::Command::new(command)
+                    .args(args)
+                    .stdout(std::process::Stdio::null())
+                    .stderr(std::process::Stdio::null())
+                    .status()?;
+                if status.success() {
+                    Ok(())
+                } else {
+                    Err(crate::Error::new(crate::ErrorKind::Other, status.to_string())
+                        .content(command.clone()))
+                }
+            },
+            Probe::File(path) => {
+                if path.exists() {
+                    Ok(())
+                } else {
+                    Err(crate::Error::new(crate::ErrorKind::Io, "File does not exist".to_string())
+                        .content(path.display().to_string()))
+                }
+            },
+        }
+    }
+
+    fn connect(address: &str, timeout: Duration) -> crate::Result<TcpStream> {
+        let mut last_error = None;
+        for address in address.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&address, timeout) {
+                Ok(stream) => return Ok(stream),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(match last_error {
+            Some(e) => e.into(),
+            None => crate::Error::new(crate::ErrorKind::Io, "No address to connect to".to_string())
+                .content(address.to_string()),
+        })
+    }
+
+    fn http_get(url: &str, timeout: Duration) -> crate::Result<()> {
+        let rest = match url.strip_prefix("http://") {
+            Some(rest) => rest,
+            None => return Err(crate::Error::new(crate::ErrorKind::Other,
+                                                 "Only http:// URLs can be probed".to_string())
+                .content(url.to_string())),
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(index) => (&rest[..index], &rest[index..]),
+            None => (rest, "/"),
+        };
+        let address = if authority.contains(':') {
+            authority.to_string()
+        } else {
+            format!("{}:80", authority)
+        };
+
+        let mut stream = Probe::connect(&address, timeout)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        stream.write_all(format!("GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n",
+                                 path, authority).as_bytes())?;
+        let mut response = vec![];
+        stream.read_to_end(&mut response)?;
+
+        // HTTP/1.x 200 OK
+        let response = String::from_utf8_lossy(&response);
+        let status_line = response.lines().next().unwrap_or_default();
+        match status_line.split_whitespace().nth(1) {
+            Some(code) if code.starts_with('2') => Ok(()),
+            _ => Err(crate::Error::new(crate::ErrorKind::Other, status_line.to_string())
+                .content(url.to_string())),
+        }
+    }
+}
+
+/// Condition `create()` waits for after starting the target, failing with
+/// `ErrorKind::Timeout` if it isn't met in time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Readiness {
+    pub probe: Probe,
+    pub timeout: Duration,
+}
+
+impl Readiness {
+    pub fn wait(&self) -> crate::Result<()> {
+        let begin = std::time::Instant::now();
+        loop {
+            let last_error = match self.probe.check(Duration::from_secs(1)) {
+                Ok(()) => return Ok(()),
+                Err(e) => e,
+            };
+            if begin.elapsed() > self.timeout {
+                return Err(crate::Error::new(crate::ErrorKind::Timeout,
+                                             "Service was not ready in time".to_string())
+                    .content(last_error.to_string()));
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+    }
+}
+
+/// Runs a probe every `interval`, restarting the target after
+/// `failure_threshold` consecutive failures
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthCheck {
+    pub probe: Probe,
+    pub interval: Duration,
+    pub timeout: Duration,
+    pub failure_threshold: u32,
+}
+
+impl HealthCheck {
+    /// Checks every 10 seconds, restarting after 3 failures
+    pub fn new(probe: Probe) -> Self {
+        HealthCheck {
+            probe,
+            interval: Duration::from_secs(10),
+            timeout: Duration::from_secs(5),
+            failure_threshold: 3,
+        }
+    }
+}
+
+/// Runs the health check of a service from this process. The Windows
+/// wrapper runs the check itself; on other platforms the service manager
+/// has no probes, so a supervising process keeps a monitor alive.
+pub struct HealthMonitor {
+    stop: std::sync::mpsc::Sender<()>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl HealthMonitor {
+    pub fn spawn<S: Sombra + Send + 'static>(service: S, check: HealthCheck) -> Self {
+        let (stop, stopped) = std::sync::mpsc::channel();
+        let thread = std::thread::spawn(move || {
+            let mut failures = 0;
+            while stopped.recv_timeout(check.interval).is_err() {
+                if check.probe.check(check.timeout).is_ok() {
+                    failures = 0;
+                    continue;
+                }
+                failures += 1;
+                if failures >= check.failure_threshold {
+                    failures = 0;
+                    let _ = service.restart();
+                }
+            }
+        });
+        HealthMonitor { stop, thread: Some(thread) }
+    }
+}
+
+impl Drop for HealthMonitor {
+    fn drop(&mut self) {
+        let _ = self.stop.send(());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    fn http_server(response: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            if let Ok((mut stream, _)) = listener.accept() {
+                let mut buffer = [0u8; 512];
+                let _ = stream.read(&mut buffer);
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        format!("http://{}/health", address)
+    }
+
+    #[test]
+    fn tcp_probe() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        assert_eq!(Probe::Tcp(address.clone()).check(Duration::from_secs(1)), Ok(()));
+        drop(listener);
+        assert!(Probe::Tcp(address).check(Duration::from_secs(1)).is_err());
+    }
+
+    #[test]
+    fn http_probe() {
+        let url = http_server("HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n");
+        assert_eq!(Probe::Http(url).check(Duration::from_secs(1)), Ok(()));
+        let url = http_server("HTTP/1.1 503 Service Unavailable\r\n\r\n");
+        assert!(Probe::Http(url).check(Duration::from_secs(1)).is_err());
+        assert!(Probe::Http("https://localhost/".to_string()).check(Duration::from_secs(1))
+            .is_err());
+    }
+
+    #[test]
+    fn readiness_waits_for_file() {
+        let path = std::env::temp_dir().join("sombra_readiness_test");
+        let _ = std::fs::remove_file(&path);
+        let readiness = Readiness {
+            probe: Probe::File(path.clone()),
+            timeout: Duration::from_millis(200),
+        };
+        assert_eq!(readiness.wait().map_err(|e| e.kind()), Err(crate::ErrorKind::Timeout));
+
+        let created = path.clone();
+        let writer = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            std::fs::write(created, "ready").unwrap();
+        });
+        let readiness = Readiness { timeout: Duration::from_secs(5), ..readiness };
+        assert_eq!(readiness.wait(), Ok(()));
+        writer.join().unwrap();
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn exec_probe() {
+        let probe = Probe::Exec { command: "true".to_string(), args: vec![] };
+        assert_eq!(probe.check(Duration::from_secs(1)), Ok(()));
+        let probe = Probe::Exec { command: "false".to_string(), args: vec![] };
+        assert!(probe.check(Duration::from_secs(1)).is_err());
+    }
+}
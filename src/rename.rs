@@ -0,0 +1,70 @@
+use crate::{Error, ErrorKind, ServiceStatus, Sombra};
+
+/// Moves `service` to `renamed`, built with the configuration to keep
+/// under the new name. `carry` runs once the new service is installed and
+/// stopped, for what its configuration can't express. On failure the new
+/// service is removed and the old one started again if it was running.
+pub(crate) fn move_service<S: Sombra>(service: &S, renamed: &S,
+                                      carry: Option<&dyn Fn() -> crate::Result<()>>)
+                                      -> crate::Result<()> {
+    if renamed.exists()? {
+        return Err(Error::new(ErrorKind::ServiceAlreadyExists,
+                              "Service already exists".to_string())
+            .content("rename".to_string()));
+    }
+    let running = service.status()? == ServiceStatus::Running;
+    if running {
+        service.stop()?;
+    }
+
+    let moved = renamed.create().and_then(|()| match carry {
+        Some(carry) => {
+            if renamed.status()? == ServiceStatus::Running {
+                renamed.stop()?;
+            }
+            carry()?;
+            if running {
+                renamed.start()?;
+            }
+            Ok(())
+        },
+        None if !running && renamed.status()? == ServiceStatus::Running => renamed.stop(),
+        None => Ok(()),
+    });
+    if let Err(e) = moved {
+        if renamed.exists().unwrap_or(true) {
+            let _ = renamed.delete();
+        }
+        if running {
+            let _ = service.start();
+        }
+        return Err(e.context("rename"));
+    }
+    service.delete()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mock::SombraMock;
+    use crate::{ServiceStatus, Sombra, SombraConfig};
+
+    #[test]
+    fn rename_keeps_config() {
+        let mut config = SombraConfig::new("api", "/opt/api");
+        config.args = vec!["--port".into(), "8080".into()];
+        let api = SombraMock::from_config(config).unwrap();
+        api.create().unwrap();
+        api.stop().unwrap();
+
+        let renamed = api.rename("api_v2").unwrap();
+        assert_eq!(api.status(), Ok(ServiceStatus::NotInstalled));
+        assert_eq!(renamed.status(), Ok(ServiceStatus::Stopped));
+        let config = renamed.config().unwrap();
+        assert_eq!(config.name, "api_v2");
+        assert_eq!(config.args, vec!["--port", "8080"]);
+
+        SombraMock::from_config(SombraConfig::new("api", "/opt/api")).unwrap().create().unwrap();
+        assert!(renamed.rename("api").is_err());
+        assert_eq!(renamed.status(), Ok(ServiceStatus::Stopped));
+    }
+}
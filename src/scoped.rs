@@ -0,0 +1,71 @@
+use crate::Sombra;
+
+/// A service created with the guard and deleted when it drops, panics
+/// included, so a test failing mid-way doesn't leave its service behind
+///
+/// ```no_run
+/// use sombra::{ScopedService, Sombra, SombraBuilder};
+///
+/// let service = ScopedService::new(SombraBuilder::new("tcp_echo", "/opt/tcp_echo").build()?)?;
+/// assert!(service.exists()?);
+/// # Ok::<(), sombra::Error>(())
+/// ```
+pub struct ScopedService<S: Sombra> {
+    service: Option<S>,
+}
+
+impl<S: Sombra> ScopedService<S> {
+    /// Creates the service
+    pub fn new(service: S) -> crate::Result<Self> {
+        service.create()?;
+        Ok(ScopedService { service: Some(service) })
+    }
+
+    /// Keeps the service installed, handing it back
+    pub fn release(mut self) -> S {
+        self.service.take().unwrap()
+    }
+}
+
+impl<S: Sombra> std::ops::Deref for ScopedService<S> {
+    type Target = S;
+
+    fn deref(&self) -> &S {
+        self.service.as_ref().unwrap()
+    }
+}
+
+impl<S: Sombra> Drop for ScopedService<S> {
+    fn drop(&mut self) {
+        if let Some(service) = self.service.take() {
+            if service.exists().unwrap_or(true) {
+                let _ = service.delete();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::SombraMock;
+    use crate::{ServiceStatus, SombraConfig};
+
+    fn mock() -> SombraMock {
+        SombraMock::from_config(SombraConfig::new("scoped", "/opt/scoped")).unwrap()
+    }
+
+    #[test]
+    fn scoped_service_deletes_on_drop() {
+        let result = std::panic::catch_unwind(|| {
+            let service = ScopedService::new(mock()).unwrap();
+            assert_eq!(service.status(), Ok(ServiceStatus::Running));
+            panic!("failed mid-test");
+        });
+        assert!(result.is_err());
+        assert_eq!(mock().status(), Ok(ServiceStatus::NotInstalled));
+
+        let service = ScopedService::new(mock()).unwrap().release();
+        assert_eq!(service.status(), Ok(ServiceStatus::Running));
+    }
+}
@@ -0,0 +1,87 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+/// Interpreter running a script target and its arguments before the
+/// script, resolved from the extension: PowerShell for `.ps1`, cmd.exe for
+/// `.bat` and `.cmd`, Python for `.py` and `/bin/sh` for `.sh`. On Unix an
+/// executable script starting with `#!` runs as is, the kernel resolving
+/// its interpreter. Nothing for other targets.
+pub(crate) fn interpreter(script: &Path) -> crate::Result<Option<(PathBuf, Vec<OsString>)>> {
+    let extension = match script.extension().and_then(|e| e.to_str()) {
+        Some(extension) => extension.to_ascii_lowercase(),
+        None => return Ok(None),
+    };
+    if runs_as_is(script) {
+        return Ok(None);
+    }
+    let (candidates, args): (&[&str], &[&str]) = match extension.as_str() {
+        "ps1" if cfg!(windows) => (&["powershell.exe", "pwsh.exe"],
+                                   &["-NoProfile", "-NonInteractive", "-ExecutionPolicy",
+                                     "Bypass", "-File"]),
+        "ps1" => (&["pwsh"], &["-NoProfile", "-NonInteractive", "-File"]),
+        "bat" | "cmd" if cfg!(windows) => (&["cmd.exe"], &["/d", "/c"]),
+        "py" if cfg!(windows) => (&["python.exe", "py.exe"], &[]),
+        "py" => (&["python3", "python"], &[]),
+        "sh" if cfg!(unix) => return Ok(Some((PathBuf::from("/bin/sh"), vec![]))),
+        _ => return Ok(None),
+    };
+    match candidates.iter().find_map(|name| find_in_path(name)) {
+        Some(interpreter) => Ok(Some((interpreter, args.iter().map(OsString::from).collect()))),
+        None => Err(crate::Error::new(crate::ErrorKind::Io,
+                                      format!("No interpreter for .{} scripts in PATH ({})",
+                                              extension, candidates.join(", ")))
+            .content(script.display().to_string())),
+    }
+}
+
+/// Whether the script is executable and names its interpreter with `#!`
+#[cfg(unix)]
+fn runs_as_is(script: &Path) -> bool {
+    use std::io::Read;
+    use std::os::unix::fs::PermissionsExt;
+    let executable = std::fs::metadata(script)
+        .map(|metadata| metadata.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false);
+    let mut shebang = [0u8; 2];
+    executable && std::fs::File::open(script)
+        .and_then(|mut file| file.read_exact(&mut shebang))
+        .is_ok() && &shebang == b"#!"
+}
+
+#[cfg(windows)]
+fn runs_as_is(_script: &Path) -> bool {
+    false
+}
+
+pub(crate) fn find_in_path(name: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(name))
+        .find(|candidate| candidate.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(unix)]
+    fn shell_scripts_run_with_sh() {
+        let script = std::env::temp_dir().join("sombra_script_test.sh");
+        std::fs::write(&script, "echo hi\n").unwrap();
+        assert_eq!(interpreter(&script), Ok(Some((PathBuf::from("/bin/sh"), vec![]))));
+
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::write(&script, "#!/bin/bash\necho hi\n").unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        assert_eq!(interpreter(&script), Ok(None));
+        let _ = std::fs::remove_file(&script);
+        assert_eq!(interpreter(Path::new("/opt/run.bat")), Ok(None));
+    }
+
+    #[test]
+    fn binaries_run_as_is() {
+        assert_eq!(interpreter(Path::new("/opt/tcp_echo")), Ok(None));
+        assert_eq!(interpreter(Path::new("/opt/tcp_echo.exe")), Ok(None));
+    }
+}
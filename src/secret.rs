@@ -0,0 +1,62 @@
+use std::fmt;
+
+/// A string holding a credential. Its memory is zeroed on drop and it is
+/// never shown by `Debug`, so it can't leak into logs or error messages.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SecretString(String);
+
+impl SecretString {
+    pub fn new(secret: String) -> Self {
+        SecretString(secret)
+    }
+
+    pub fn expose_secret(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for SecretString {
+    fn from(secret: String) -> Self {
+        SecretString::new(secret)
+    }
+}
+
+impl From<&str> for SecretString {
+    fn from(secret: &str) -> Self {
+        SecretString::new(secret.to_string())
+    }
+}
+
+impl fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecretString(***)")
+    }
+}
+
+impl Drop for SecretString {
+    fn drop(&mut self) {
+        // Volatile writes over the whole allocation, so the compiler can't
+        // elide them as dead stores
+        unsafe {
+            let bytes = self.0.as_mut_vec();
+            let ptr = bytes.as_mut_ptr();
+            for i in 0..bytes.capacity() {
+                std::ptr::write_volatile(ptr.add(i), 0);
+            }
+            bytes.set_len(0);
+        }
+        std::sync::atomic::compiler_fence(std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debug_hides_secret() {
+        let secret = SecretString::from("hunter2");
+        assert_eq!(format!("{:?}", secret), "SecretString(***)");
+        assert_eq!(secret.expose_secret(), "hunter2");
+    }
+}
@@ -0,0 +1,260 @@
+use crate::SecretString;
+
+/// Credentials of a service kept out of the code and the manifests:
+/// encrypted with DPAPI for the machine on Windows, in the System keychain
+/// (the login one without root) on macOS, and in the Secret Service
+/// keyring, through secret-tool(1), elsewhere.
+///
+/// `SombraBuilder::password_secret` reads the password of the account from
+/// here when the service is created or updated, and a target can read its
+/// own secrets when it starts. The secrets are kept when the service is
+/// deleted, so a service created again finds them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecretStore {
+    service: String,
+}
+
+impl SecretStore {
+    pub fn new(service: &str) -> Self {
+        SecretStore { service: service.to_string() }
+    }
+
+    /// Stores `secret` under `key`, replacing the one there
+    pub fn set(&self, key: &str, secret: &SecretString) -> crate::Result<()> {
+        imp::set(&self.service, key, secret)
+            .map_err(|e| e.content(format!("secret {} of {}", key, self.service)))
+    }
+
+    /// Fails with `ErrorKind::Other` if nothing is stored under `key`
+    pub fn get(&self, key: &str) -> crate::Result<SecretString> {
+        imp::get(&self.service, key)
+            .map_err(|e| e.content(format!("secret {} of {}", key, self.service)))
+    }
+
+    /// Removes the secret under `key`, if there is one
+    pub fn remove(&self, key: &str) -> crate::Result<()> {
+        imp::remove(&self.service, key)
+            .map_err(|e| e.content(format!("secret {} of {}", key, self.service)))
+    }
+}
+
+/// Runs a command, feeding it `input` so secrets stay off the command
+/// lines other users can see, and fails with its standard error unless it
+/// succeeds
+#[cfg(unix)]
+fn run(command: &mut std::process::Command, input: Option<&str>) -> crate::Result<String> {
+    use std::io::Write;
+    use std::process::Stdio;
+    let mut child = command.stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        stdin.write_all(input.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        let description = match stderr.is_empty() {
+            true => "No secret is stored under this key".to_string(),
+            false => stderr,
+        };
+        return Err(crate::Error::new(crate::ErrorKind::Other, description));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Inverse of `digest::hex`, None unless every byte is two hex digits
+#[cfg_attr(not(windows), allow(dead_code))]
+fn unhex(hex: &str) -> Option<Vec<u8>> {
+    (0..hex.len()).step_by(2)
+        .map(|i| hex.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+        .collect()
+}
+
+/// Command of `security -i` storing `secret`, whose strings are quoted as
+/// the interactive mode of security(1) reads them
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+fn security_line(service: &str, key: &str, secret: &str, keychain: Option<&str>) -> String {
+    let quote = |value: &str| format!("\"{}\"", value.replace('\\', "\\\\")
+        .replace('"', "\\\""));
+    let mut line = format!("add-generic-password -U -s {} -a {} -w {}",
+                           quote(&format!("sombra.{}", service)), quote(key), quote(secret));
+    if let Some(keychain) = keychain {
+        line.push_str(&format!(" {}", quote(keychain)));
+    }
+    line.push('\n');
+    line
+}
+
+#[cfg(windows)]
+mod imp {
+    use crate::SecretString;
+    use crate::digest::hex;
+    use crate::windows::powershell;
+    use crate::windows::reg::Reg;
+
+    /// The secret goes through the standard input in hex, as the console
+    /// code page would mangle the characters outside of it
+    pub(super) fn set(service: &str, key: &str, secret: &SecretString) -> crate::Result<()> {
+        let blob = powershell::run_with_input(
+            "Add-Type -AssemblyName System.Security; \
+             $hex = [Console]::In.ReadToEnd().Trim(); \
+             $data = [byte[]] -split ($hex -replace '..', '0x$& '); \
+             [Convert]::ToBase64String([Security.Cryptography.ProtectedData]::Protect(\
+             $data, $null, 'LocalMachine'))",
+            &hex(secret.expose_secret().as_bytes()))?;
+        Reg::secrets(service).set_string(key, &blob)
+    }
+
+    pub(super) fn get(service: &str, key: &str) -> crate::Result<SecretString> {
+        let blob = Reg::secrets(service).query(key).ok_or_else(|| {
+            crate::Error::new(crate::ErrorKind::Other,
+                              "No secret is stored under this key".to_string())
+        })?;
+        let data = powershell::run(&format!(
+            "Add-Type -AssemblyName System.Security; \
+             -join ([Security.Cryptography.ProtectedData]::Unprotect(\
+             [Convert]::FromBase64String({}), $null, 'LocalMachine') | \
+             ForEach-Object {{ $_.ToString('x2') }})", powershell::quote(&blob)))?;
+        super::unhex(&data).and_then(|data| String::from_utf8(data).ok())
+            .map(SecretString::new)
+            .ok_or_else(|| crate::Error::new(crate::ErrorKind::Utf8,
+                                             "Secret isn't valid UTF-8".to_string()))
+    }
+
+    pub(super) fn remove(service: &str, key: &str) -> crate::Result<()> {
+        Reg::secrets(service).delete_value(key)
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod imp {
+    use crate::SecretString;
+    use std::process::Command;
+
+    /// Daemons run as root, which reads the System keychain without a
+    /// prompt
+    fn keychain() -> Option<&'static str> {
+        match unsafe { libc::geteuid() } {
+            0 => Some("/Library/Keychains/System.keychain"),
+            _ => None,
+        }
+    }
+
+    fn security(action: &str, service: &str, key: &str) -> Command {
+        let mut security = Command::new("security");
+        security.arg(action)
+            .args(["-s", &format!("sombra.{}", service), "-a", key]);
+        security
+    }
+
+    /// Through the interactive mode, which reads the command from the
+    /// standard input
+    pub(super) fn set(service: &str, key: &str, secret: &SecretString) -> crate::Result<()> {
+        let line = super::security_line(service, key, secret.expose_secret(), keychain());
+        super::run(Command::new("security").arg("-i"), Some(&line))?;
+        Ok(())
+    }
+
+    pub(super) fn get(service: &str, key: &str) -> crate::Result<SecretString> {
+        let mut security = security("find-generic-password", service, key);
+        security.arg("-w").args(keychain());
+        let secret = super::run(&mut security, None)?;
+        Ok(SecretString::new(secret.strip_suffix('\n').unwrap_or(&secret).to_string()))
+    }
+
+    pub(super) fn remove(service: &str, key: &str) -> crate::Result<()> {
+        let mut security = security("delete-generic-password", service, key);
+        security.args(keychain());
+        match super::run(&mut security, None) {
+            // Nothing to remove
+            Err(_) if get(service, key).is_err() => Ok(()),
+            result => result.map(|_| ()),
+        }
+    }
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+mod imp {
+    use crate::SecretString;
+    use std::process::Command;
+
+    pub(super) fn secret_tool(action: &str, service: &str, key: &str) -> Command {
+        let mut secret_tool = Command::new("secret-tool");
+        secret_tool.arg(action)
+            .args(["sombra-service", service, "sombra-key", key]);
+        secret_tool
+    }
+
+    pub(super) fn set(service: &str, key: &str, secret: &SecretString) -> crate::Result<()> {
+        let mut secret_tool = Command::new("secret-tool");
+        secret_tool.arg("store")
+            .arg(format!("--label=sombra {} {}", service, key))
+            .args(["sombra-service", service, "sombra-key", key]);
+        super::run(&mut secret_tool, Some(secret.expose_secret()))?;
+        Ok(())
+    }
+
+    pub(super) fn get(service: &str, key: &str) -> crate::Result<SecretString> {
+        let secret = super::run(&mut secret_tool("lookup", service, key), None)?;
+        Ok(SecretString::new(secret))
+    }
+
+    pub(super) fn remove(service: &str, key: &str) -> crate::Result<()> {
+        super::run(&mut secret_tool("clear", service, key), None)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unhex_inverts_hex() {
+        let bytes = "pässwörd \"1\"".as_bytes();
+        assert_eq!(unhex(&crate::digest::hex(bytes)).as_deref(), Some(bytes));
+        assert_eq!(unhex(""), Some(vec![]));
+        assert_eq!(unhex("abc"), None);
+        assert_eq!(unhex("zz"), None);
+    }
+
+    #[test]
+    fn security_line_quotes_strings() {
+        assert_eq!(security_line("tcp_echo", "db", "say \"hi\" \\o/", None),
+                   "add-generic-password -U -s \"sombra.tcp_echo\" -a \"db\" \
+                    -w \"say \\\"hi\\\" \\\\o/\"\n");
+        assert_eq!(security_line("tcp_echo", "db", "secret",
+                                 Some("/Library/Keychains/System.keychain")),
+                   "add-generic-password -U -s \"sombra.tcp_echo\" -a \"db\" -w \"secret\" \
+                    \"/Library/Keychains/System.keychain\"\n");
+    }
+
+    #[cfg(all(unix, not(target_os = "macos")))]
+    #[test]
+    fn secret_tool_arguments() {
+        let secret_tool = imp::secret_tool("lookup", "tcp_echo", "db");
+        assert_eq!(secret_tool.get_program(), "secret-tool");
+        assert_eq!(secret_tool.get_args().collect::<Vec<_>>(),
+                   ["lookup", "sombra-service", "tcp_echo", "sombra-key", "db"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn failures_are_other_errors() {
+        // secret-tool and security fail without a word when nothing is
+        // stored under the key
+        let error = run(&mut std::process::Command::new("false"), None).unwrap_err();
+        assert_eq!(error.kind(), crate::ErrorKind::Other);
+        assert!(error.to_string().contains("No secret is stored under this key"));
+
+        let mut failing = std::process::Command::new("sh");
+        failing.args(["-c", "cat >&2; exit 1"]);
+        let error = run(&mut failing, Some("locked")).unwrap_err();
+        assert_eq!(error.kind(), crate::ErrorKind::Other);
+        assert!(error.to_string().contains("locked"));
+        assert_eq!(run(std::process::Command::new("cat").arg("-"), Some("secret")).unwrap(),
+                   "secret");
+    }
+}
@@ -0,0 +1,187 @@
+use crate::{FailureActions, Priority, RestartPolicy, SombraConfig, StartType, StopSignal};
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// First line of a settings file, marking the services created by sombra
+pub(crate) const HEADER: &str = "# Managed by sombra";
+
+/// Settings of a service started by a launcher script, for init systems
+/// that keep only a command: one `key=value` per line, with backslashes
+/// and newlines escaped. The account is left to the backend, as
+/// `extra` lines.
+pub(crate) fn content(config: &SombraConfig, extra: &[(&str, String)]) -> String {
+    let mut lines = vec![HEADER.to_string()];
+    let mut push = |key: &str, value: &str| {
+        let value = value.replace('\\', "\\\\").replace('\n', "\\n");
+        lines.push(format!("{}={}", key, value));
+    };
+    push("path", &config.path.to_string_lossy());
+    for arg in &config.args {
+        push("arg", &arg.to_string_lossy());
+    }
+    if let Some(display_name) = &config.display_name {
+        push("display_name", display_name);
+    }
+    if let Some(description) = &config.description {
+        push("description", description);
+    }
+    push("start_type", &format!("{:?}", config.start_type));
+    for (key, value) in extra {
+        push(key, value);
+    }
+    for (key, value) in &config.env {
+        push("env", &format!("{}={}", key, value));
+    }
+    if let Some(dir) = &config.working_directory {
+        push("working_directory", &dir.to_string_lossy());
+    }
+    if let Some(root) = &config.root_directory {
+        push("root_directory", &root.to_string_lossy());
+    }
+    for path in &config.bind_paths {
+        push("bind_path", &path.to_string_lossy());
+    }
+    if let Some(log) = &config.stdout_log {
+        push("stdout_log", &log.to_string_lossy());
+    }
+    if let Some(log) = &config.stderr_log {
+        push("stderr_log", &log.to_string_lossy());
+    }
+    if config.stop_signal == StopSignal::Interrupt {
+        push("stop_signal", "SIGINT");
+    }
+    if let Some(timeout) = config.stop_timeout {
+        push("stop_timeout", &timeout.as_millis().to_string());
+    }
+    push("kill_process_tree", &config.kill_process_tree.to_string());
+    if let Some(priority) = config.priority {
+        push("priority", &format!("{:?}", priority));
+    }
+    if let Some(policy) = &config.restart_policy {
+        push("restart_policy", &format!("{},{},{},{}", policy.max_restarts,
+                                        policy.window.as_millis(),
+                                        policy.initial_delay.as_millis(),
+                                        policy.max_delay.as_millis()));
+    } else if let Some(delay) = config.failure_actions.as_ref()
+        .and_then(|f| f.restart_delay()) {
+        push("restart_delay", &delay.as_millis().to_string());
+    }
+    if let Ok(checksum) = crate::digest::sha256_file(&config.path) {
+        push("sha256", &checksum);
+    }
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+/// Settings read back by `parse`
+pub(crate) struct Settings {
+    pub(crate) config: SombraConfig,
+    pub(crate) checksum: Option<String>,
+    /// The lines left to the backend
+    #[cfg_attr(target_os = "windows", allow(dead_code))]
+    pub(crate) extra: Vec<(String, String)>,
+}
+
+/// Inverse of `content`
+pub(crate) fn parse(name: &str, content: &str) -> Settings {
+    let mut config = SombraConfig::new(name, "");
+    let mut checksum = None;
+    let mut extra = vec![];
+    let millis = |value: &str| value.parse().ok().map(Duration::from_millis);
+    for line in content.lines() {
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key, value),
+            None => continue,
+        };
+        let mut unescaped = String::new();
+        let mut chars = value.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some('n') => unescaped.push('\n'),
+                    next => unescaped.extend(next),
+                },
+                c => unescaped.push(c),
+            }
+        }
+        let value = unescaped;
+        match key {
+            "path" => config.path = PathBuf::from(value),
+            "arg" => config.args.push(OsString::from(value)),
+            "display_name" => config.display_name = Some(value),
+            "description" => config.description = Some(value),
+            "start_type" => config.start_type = match value.as_str() {
+                "AutoStart" => StartType::AutoStart,
+                "DelayedAutoStart" => StartType::DelayedAutoStart,
+                "Disabled" => StartType::Disabled,
+                _ => StartType::OnDemand,
+            },
+            "env" => if let Some((key, value)) = value.split_once('=') {
+                config.env.push((key.to_string(), value.to_string()));
+            },
+            "working_directory" => config.working_directory = Some(PathBuf::from(value)),
+            "root_directory" => config.root_directory = Some(PathBuf::from(value)),
+            "bind_path" => config.bind_paths.push(PathBuf::from(value)),
+            "stdout_log" => config.stdout_log = Some(PathBuf::from(value)),
+            "stderr_log" => config.stderr_log = Some(PathBuf::from(value)),
+            "stop_signal" if value == "SIGINT" => config.stop_signal = StopSignal::Interrupt,
+            "stop_timeout" => config.stop_timeout = millis(&value),
+            "kill_process_tree" => config.kill_process_tree = value == "true",
+            "priority" => config.priority = [Priority::Idle, Priority::BelowNormal,
+                                             Priority::Normal, Priority::AboveNormal,
+                                             Priority::High].iter()
+                .find(|priority| format!("{:?}", priority) == value)
+                .copied(),
+            "restart_policy" => {
+                let fields: Vec<u64> = value.split(',')
+                    .filter_map(|field| field.parse().ok())
+                    .collect();
+                if let [max_restarts, window, initial_delay, max_delay] = fields[..] {
+                    config.restart_policy = Some(RestartPolicy {
+                        max_restarts: max_restarts as u32,
+                        window: Duration::from_millis(window),
+                        initial_delay: Duration::from_millis(initial_delay),
+                        max_delay: Duration::from_millis(max_delay),
+                    });
+                }
+            },
+            "restart_delay" => config.failure_actions = millis(&value)
+                .map(FailureActions::restart),
+            "sha256" => checksum = Some(value),
+            key => extra.push((key.to_string(), value)),
+        }
+    }
+    Settings { config, checksum, extra }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_round_trip() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp echo/tcp_echo");
+        config.args = vec!["-p".into(), "it's\nfine\\".into()];
+        config.description = Some("Echo server".to_string());
+        config.start_type = StartType::AutoStart;
+        config.env = vec![("MODE".to_string(), "a=b".to_string())];
+        config.working_directory = Some(PathBuf::from("/opt"));
+        config.root_directory = Some(PathBuf::from("/"));
+        config.bind_paths = vec![PathBuf::from("/etc/resolv.conf")];
+        config.stdout_log = Some(PathBuf::from("/var/log/tcp_echo.log"));
+        config.stop_signal = StopSignal::Interrupt;
+        config.stop_timeout = Some(Duration::from_secs(3));
+        config.priority = Some(Priority::BelowNormal);
+        config.restart_policy = Some(RestartPolicy {
+            max_restarts: 3,
+            window: Duration::from_secs(60),
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(8),
+        });
+        let settings = parse("tcp_echo", &content(&config, &[("user", "sombra".to_string())]));
+        assert_eq!(config.diff(&settings.config), vec![]);
+        assert_eq!(settings.checksum, None);
+        assert_eq!(settings.extra, vec![("user".to_string(), "sombra".to_string())]);
+    }
+}
@@ -0,0 +1,62 @@
+/// Resources used by the target process at one point in time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Usage {
+    pub pid: u32,
+    /// Resident memory, in bytes
+    pub rss: u64,
+    /// User and system CPU time
+    pub cpu_time: std::time::Duration,
+    /// Open file descriptors, or handles on Windows
+    pub handles: u32,
+}
+
+/// A service created by this crate, found on the machine
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManagedService {
+    pub name: String,
+    pub status: ServiceStatus,
+    /// Executable of the target
+    pub path: std::path::PathBuf,
+}
+
+/// How the target last ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitReason {
+    /// Exited with this code
+    Code(i64),
+    /// Killed by this signal (Unix)
+    Signal(i32),
+}
+
+/// Lifetime counters of a service, to spot flapping services
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ServiceStats {
+    /// When the running target was started
+    pub started_at: Option<std::time::SystemTime>,
+    /// Restarts done by the service manager or the wrapper
+    pub restarts: u32,
+    pub last_exit: Option<ExitReason>,
+}
+
+impl ServiceStats {
+    /// Time since the running target was started
+    pub fn uptime(&self) -> Option<std::time::Duration> {
+        self.started_at?.elapsed().ok()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServiceStatus {
+    NotInstalled,
+    Stopped,
+    StartPending,
+    Running,
+    StopPending,
+    /// Windows services between a pause request and `Paused`
+    PausePending,
+    Paused,
+    /// Windows services between a resume request and `Running`
+    ContinuePending,
+    /// Stopped after a failure, e.g. once a restart policy gives up
+    Failed,
+}
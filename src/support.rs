@@ -0,0 +1,119 @@
+use crate::{Account, Scope, SombraConfig};
+
+/// A setting only some backends can apply. The others refuse it rather
+/// than ignore it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Feature {
+    UserScope,
+    Journal,
+    Sandboxing,
+    Capabilities,
+    RootDirectory,
+    ServicePrivileges,
+    Operators,
+    Triggers,
+    GroupAndUmask,
+    Notify,
+    ResourceLimits,
+    LogRotation,
+    HealthCheck,
+    VirtualAccount,
+}
+
+impl Feature {
+    /// In the order they are checked
+    const ALL: [Feature; 14] = [
+        Feature::UserScope,
+        Feature::Journal,
+        Feature::Sandboxing,
+        Feature::Capabilities,
+        Feature::RootDirectory,
+        Feature::ServicePrivileges,
+        Feature::Operators,
+        Feature::Triggers,
+        Feature::GroupAndUmask,
+        Feature::Notify,
+        Feature::ResourceLimits,
+        Feature::LogRotation,
+        Feature::HealthCheck,
+        Feature::VirtualAccount,
+    ];
+
+    fn is_used(self, config: &SombraConfig) -> bool {
+        match self {
+            Feature::UserScope => config.scope == Scope::User,
+            Feature::Journal => config.journal,
+            Feature::Sandboxing => config.hardening.is_some(),
+            Feature::Capabilities => !config.capabilities.is_empty(),
+            Feature::RootDirectory => config.root_directory.is_some(),
+            Feature::ServicePrivileges =>
+                config.restricted_sid || !config.service_privileges.is_empty(),
+            Feature::Operators => !config.operators.is_empty(),
+            Feature::Triggers => !config.triggers.is_empty(),
+            Feature::GroupAndUmask => config.group.is_some() || config.umask.is_some(),
+            Feature::Notify => config.notify || config.watchdog.is_some(),
+            Feature::ResourceLimits =>
+                config.resource_limits.is_some() || config.cpu_affinity.is_some(),
+            Feature::LogRotation => config.log_rotation.is_some(),
+            Feature::HealthCheck => config.health_check.is_some(),
+            Feature::VirtualAccount => config.account == Account::Virtual,
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            Feature::UserScope =>
+                "Per-user services are only supported by systemd, launchd, cron and Windows",
+            Feature::Journal => "Journal logging is only supported by systemd",
+            Feature::Sandboxing => "Sandboxing is only supported by systemd",
+            Feature::Capabilities => "Capabilities are only supported by systemd",
+            Feature::RootDirectory =>
+                "Root directories are only supported by systemd and the launcher of SysV init, \
+                 rc.d and cron",
+            Feature::ServicePrivileges =>
+                "Service SIDs and privileges are only supported by Windows services",
+            Feature::Operators => "Service operators are only supported by systemd and Windows \
+                                   services",
+            Feature::Triggers => "Start triggers are only supported by systemd and Windows \
+                                  services",
+            Feature::GroupAndUmask => "Groups and umasks are only supported by systemd and launchd",
+            Feature::Notify => "The sd_notify protocol is only supported by systemd and Windows \
+                                services",
+            Feature::ResourceLimits =>
+                "Resource limits and CPU affinity are only supported by systemd and Windows \
+                 services",
+            Feature::LogRotation => "Log rotation is only supported by systemd and Windows services",
+            Feature::HealthCheck =>
+                "Health checks are only run by the Windows wrapper; use HealthMonitor elsewhere",
+            Feature::VirtualAccount => "Virtual accounts are only supported by systemd and \
+                                        Windows services",
+        }
+    }
+}
+
+/// Why a backend applying only the `supported` features can't install
+/// `config`: the description of the first other feature it uses
+pub(crate) fn unsupported(config: &SombraConfig, supported: &[Feature]) -> Option<&'static str> {
+    Feature::ALL.iter()
+        .find(|feature| !supported.contains(feature) && feature.is_used(config))
+        .map(|feature| feature.description())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unsupported_reports_the_first_missing_feature() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp_echo");
+        assert_eq!(unsupported(&config, &[]), None);
+
+        config.journal = true;
+        config.umask = Some(0o027);
+        assert_eq!(unsupported(&config, &[]),
+                   Some("Journal logging is only supported by systemd"));
+        assert_eq!(unsupported(&config, &[Feature::Journal]),
+                   Some("Groups and umasks are only supported by systemd and launchd"));
+        assert_eq!(unsupported(&config, &[Feature::Journal, Feature::GroupAndUmask]), None);
+    }
+}
@@ -0,0 +1,294 @@
+use crate::{Artifact, Difference, LogReader, ManagedService, Privileges, Scripts, Sombra,
+            ServiceStats, ServiceStatus, SombraConfig, StartType, Usage};
+use crate::export::Shell;
+use crate::unix::shell::quote;
+use crate::unix::supervisor::Supervisor;
+use std::io::Write;
+
+/// Comment ending the crontab entries of the services created by sombra
+const ENTRY_MARKER: &str = "# sombra:";
+
+/// Runs the target from an `@reboot` crontab entry, for systems without
+/// systemd such as minimal containers and old distributions. The entry
+/// starts a launcher script that restarts the target as the restart
+/// policy asks and records its state under /var/lib/sombra for root, or
+/// ~/.local/state/sombra. The target runs as the user installing the
+/// service, in that user's crontab.
+pub struct SombraCron {
+    config: SombraConfig,
+    supervisor: Supervisor,
+}
+
+impl SombraCron {
+    fn check(config: &SombraConfig) -> crate::Result<()> {
+        Supervisor::check(config)?;
+        let unsupported = if config.account.unix_name(&config.name)? != whoami::username() {
+            Some("Cron services run as the user creating them")
+        } else if !config.dependencies.is_empty() {
+            Some("Dependencies aren't supported by cron")
+        } else {
+            None
+        };
+        match unsupported {
+            Some(description) => Err(crate::Error::new(crate::ErrorKind::Other,
+                                                       description.to_string())
+                .content(config.name.clone())),
+            None => Ok(()),
+        }
+    }
+
+    /// Crontab line starting the launcher at boot, or nothing for services
+    /// started on demand. cron takes `%` as a newline.
+    fn entry(config: &SombraConfig, supervisor: &Supervisor) -> Option<String> {
+        let launcher = quote(supervisor.launcher().as_os_str()).replace('%', "\\%");
+        let command = match config.start_type {
+            StartType::AutoStart => format!("/bin/sh {}", launcher),
+            StartType::DelayedAutoStart => format!("sleep 120 && /bin/sh {}", launcher),
+            StartType::OnDemand | StartType::Disabled => return None,
+        };
+        Some(format!("@reboot {} {}{}", command, ENTRY_MARKER, config.name))
+    }
+
+    /// `crontab` with the entry of `name` replaced by `entry`, or removed
+    fn with_entry(crontab: &str, name: &str, entry: Option<&str>) -> String {
+        let marker = format!("{}{}", ENTRY_MARKER, name);
+        let mut lines: Vec<&str> = crontab.lines()
+            .filter(|line| !line.ends_with(&marker))
+            .collect();
+        lines.extend(entry);
+        let mut crontab = lines.join("\n");
+        if !crontab.is_empty() {
+            crontab.push('\n');
+        }
+        crontab
+    }
+
+    fn read_crontab() -> crate::Result<String> {
+        let output = std::process::Command::new("crontab").arg("-l").output()?;
+        // Fails without a crontab yet
+        if !output.status.success() {
+            return Ok(String::new());
+        }
+        Ok(std::str::from_utf8(&output.stdout)?.to_string())
+    }
+
+    fn write_crontab(crontab: &str) -> crate::Result<()> {
+        let mut child = std::process::Command::new("crontab")
+            .arg("-")
+            .stdin(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(crontab.as_bytes())?;
+        }
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            return Err(crate::Error::new(crate::ErrorKind::Other,
+                                         String::from_utf8_lossy(&output.stderr).trim()
+                                             .to_string())
+                .content("crontab".to_string()));
+        }
+        Ok(())
+    }
+
+    fn update_entry(&self) -> crate::Result<()> {
+        let crontab = SombraCron::read_crontab()?;
+        let entry = SombraCron::entry(&self.config, &self.supervisor);
+        let updated = SombraCron::with_entry(&crontab, &self.config.name, entry.as_deref());
+        if updated != crontab {
+            SombraCron::write_crontab(&updated)?;
+        }
+        Ok(())
+    }
+
+    fn is_installed(&self) -> crate::Result<()> {
+        if self.supervisor.is_installed() {
+            Ok(())
+        } else {
+            Err(crate::Error::new(crate::ErrorKind::ServiceNotFound,
+                                  format!("Service {} does not exist", self.config.name)))
+        }
+    }
+}
+
+impl Sombra for SombraCron {
+    fn from_config(config: SombraConfig) -> crate::Result<Self> {
+        let supervisor = Supervisor::new(&config.name);
+        Ok(SombraCron { config, supervisor })
+    }
+
+    fn create(&self) -> crate::Result<()> {
+        SombraCron::check(&self.config)?;
+        if self.supervisor.is_installed() {
+            return Err(crate::Error::new(crate::ErrorKind::ServiceAlreadyExists,
+                                         format!("Service {} already exist",
+                                                 self.config.name)));
+        }
+        self.supervisor.install(&self.config)?;
+        self.update_entry()?;
+
+        if self.config.start_type == StartType::Disabled || self.config.target_pending() {
+            return Ok(());
+        }
+        self.start()?;
+        match &self.config.readiness {
+            Some(readiness) => readiness.wait(),
+            None => Ok(()),
+        }
+    }
+
+    fn update(&self) -> crate::Result<()> {
+        SombraCron::check(&self.config)?;
+        self.is_installed()?;
+        self.supervisor.install(&self.config)?;
+        self.update_entry()
+    }
+
+    fn delete(&self) -> crate::Result<()> {
+        self.is_installed()?;
+        self.supervisor.terminate(&self.config)?;
+        let crontab = SombraCron::read_crontab()?;
+        let updated = SombraCron::with_entry(&crontab, &self.config.name, None);
+        if updated != crontab {
+            SombraCron::write_crontab(&updated)?;
+        }
+        self.supervisor.remove()
+    }
+
+    fn start(&self) -> crate::Result<()> {
+        self.config.check_target()?;
+        self.is_installed()?;
+        if matches!(self.supervisor.status(),
+                    ServiceStatus::Running | ServiceStatus::StartPending) {
+            return Ok(());
+        }
+        self.supervisor.spawn()?;
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        Ok(())
+    }
+
+    fn stop(&self) -> crate::Result<()> {
+        self.is_installed()?;
+        self.supervisor.terminate(&self.config)
+    }
+
+    fn status(&self) -> crate::Result<ServiceStatus> {
+        Ok(self.supervisor.status())
+    }
+
+    fn pid(&self) -> crate::Result<Option<u32>> {
+        self.is_installed()?;
+        Ok(self.supervisor.pid())
+    }
+
+    fn usage(&self) -> crate::Result<Option<Usage>> {
+        self.is_installed()?;
+        self.supervisor.usage()
+    }
+
+    fn stats(&self) -> crate::Result<ServiceStats> {
+        self.is_installed()?;
+        Ok(self.supervisor.stats())
+    }
+
+    fn config(&self) -> crate::Result<SombraConfig> {
+        self.is_installed()?;
+        Ok(self.supervisor.config(&self.config.name)?.0)
+    }
+
+    fn diff(&self) -> crate::Result<Vec<Difference>> {
+        Ok(Supervisor::comparable(&self.config)?.diff(&self.config()?))
+    }
+
+    fn checksum(&self) -> crate::Result<Option<String>> {
+        self.is_installed()?;
+        Ok(self.supervisor.config(&self.config.name)?.1)
+    }
+
+    fn list_managed() -> crate::Result<Vec<ManagedService>> {
+        let mut services = vec![];
+        for name in Supervisor::list()? {
+            let (config, _) = Supervisor::new(&name).config(&name)?;
+            let path = config.path.clone();
+            let status = SombraCron::from_config(config)?.status()?;
+            services.push(ManagedService { name, status, path });
+        }
+        Ok(services)
+    }
+
+    /// The crontab is the one of the current user
+    fn required_privileges(&self) -> Privileges {
+        Privileges::User
+    }
+
+    fn logs(&self, follow: bool) -> crate::Result<LogReader> {
+        LogReader::files(self.config.log_files(), follow)
+    }
+
+    fn render(&self) -> crate::Result<Vec<Artifact>> {
+        SombraCron::check(&self.config)?;
+        let mut artifacts = self.supervisor.render(&self.config)?;
+        if let Some(entry) = SombraCron::entry(&self.config, &self.supervisor) {
+            artifacts.push(Artifact::registration(format!("# crontab of the user\n{}\n", entry)));
+        }
+        Ok(artifacts)
+    }
+
+    /// The crontab is edited with awk, which drops the previous entry of
+    /// the service
+    fn export(&self) -> crate::Result<Scripts> {
+        let mut shell = Shell::new(&self.config.name);
+        shell.write(&self.render()?);
+        for line in Supervisor::provision_lines(&self.config) {
+            shell.install(line);
+        }
+        let marker = quote(format!("{}{}", ENTRY_MARKER, self.config.name).as_ref());
+        let without = format!("crontab -l 2>/dev/null | M={} awk 'substr($0, length($0) - \
+                               length(ENVIRON[\"M\"]) + 1) != ENVIRON[\"M\"]'", marker);
+        if let Some(entry) = SombraCron::entry(&self.config, &self.supervisor) {
+            shell.install(format!("{{ {}; echo {}; }} | crontab -", without,
+                                  quote(entry.as_ref())));
+        }
+        if self.config.start_type != StartType::Disabled {
+            shell.install(self.supervisor.spawn_line());
+        }
+        for line in self.supervisor.terminate_lines(&self.config) {
+            shell.uninstall(line);
+        }
+        shell.uninstall(format!("{} | crontab -", without));
+        shell.remove_dir(self.supervisor.dir());
+        Ok(shell.finish())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crontab_entries_replaced() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp_echo");
+        let supervisor = Supervisor::new("tcp_echo");
+        assert_eq!(SombraCron::entry(&config, &supervisor), None);
+        config.start_type = StartType::AutoStart;
+        let entry = SombraCron::entry(&config, &supervisor).unwrap();
+        assert!(entry.starts_with("@reboot /bin/sh '"));
+        assert!(entry.ends_with("/tcp_echo/launcher.sh' # sombra:tcp_echo"));
+
+        let crontab = "MAILTO=ops\n0 * * * * /opt/backup\n";
+        let installed = SombraCron::with_entry(crontab, "tcp_echo", Some(&entry));
+        assert_eq!(installed, format!("{}{}\n", crontab, entry));
+        let updated = SombraCron::with_entry(&installed, "tcp_echo", Some("@reboot /bin/true"));
+        assert_eq!(updated, format!("{}@reboot /bin/true\n", crontab));
+        assert_eq!(SombraCron::with_entry(&installed, "tcp_echo", None), crontab);
+        assert_eq!(SombraCron::with_entry(&installed, "tcp", None), installed);
+    }
+
+    #[test]
+    fn other_accounts_rejected() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp_echo");
+        assert_eq!(SombraCron::check(&config), Ok(()));
+        config.account = crate::Account::LocalService;
+        assert!(SombraCron::check(&config).is_err());
+    }
+}
@@ -0,0 +1,6 @@
+pub mod cron;
+#[cfg(any(target_os = "macos", target_os = "freebsd", target_os = "illumos",
+          target_os = "solaris"))]
+pub(crate) mod ps;
+pub(crate) mod shell;
+pub(crate) mod supervisor;
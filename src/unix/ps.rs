@@ -0,0 +1,109 @@
+use crate::Usage;
+use std::time::Duration;
+
+pub fn usage(pid: u32) -> crate::Result<Usage> {
+    let output = std::process::Command::new("ps")
+        .arg("-o")
+        .arg("rss=,time=")
+        .arg("-p")
+        .arg(pid.to_string())
+        .output()?;
+    let stdout = std::str::from_utf8(output.stdout.as_slice())?;
+    let mut columns = stdout.split_whitespace();
+    let (rss, time) = match (columns.next().and_then(|rss| rss.parse::<u64>().ok()),
+                             columns.next().and_then(parse_cpu_time)) {
+        (Some(rss), Some(time)) => (rss, time),
+        _ => return Err(crate::Error::new(crate::ErrorKind::Other,
+                                          "Cannot parse ps output".to_string())
+            .content(stdout.trim().to_string())),
+    };
+
+    Ok(Usage {
+        pid,
+        rss: rss * 1024,
+        cpu_time: time,
+        handles: handles(pid)?,
+    })
+}
+
+/// Open descriptors of the process: lsof prints one "f<fd>" line per
+/// descriptor
+#[cfg(target_os = "macos")]
+fn handles(pid: u32) -> crate::Result<u32> {
+    let output = std::process::Command::new("lsof")
+        .arg("-p")
+        .arg(pid.to_string())
+        .arg("-F")
+        .arg("f")
+        .output()?;
+    Ok(std::str::from_utf8(output.stdout.as_slice())?.lines()
+        .filter(|line| line.starts_with('f'))
+        .count() as u32)
+}
+
+/// Open descriptors of the process: procstat prints one line per
+/// descriptor, the cwd, root and text vnodes aside
+#[cfg(target_os = "freebsd")]
+fn handles(pid: u32) -> crate::Result<u32> {
+    let output = std::process::Command::new("procstat")
+        .arg("-f")
+        .arg(pid.to_string())
+        .output()?;
+    Ok(std::str::from_utf8(output.stdout.as_slice())?.lines()
+        .filter(|line| line.split_whitespace().nth(2)
+            .is_some_and(|fd| fd.parse::<u32>().is_ok()))
+        .count() as u32)
+}
+
+/// Open descriptors of the process: pfiles prints a "<fd>: <type>" line
+/// per descriptor, followed by its details
+#[cfg(any(target_os = "illumos", target_os = "solaris"))]
+fn handles(pid: u32) -> crate::Result<u32> {
+    let output = std::process::Command::new("pfiles")
+        .arg(pid.to_string())
+        .output()?;
+    Ok(std::str::from_utf8(output.stdout.as_slice())?.lines()
+        .filter(|line| line.trim_start().split_once(':')
+            .is_some_and(|(fd, _)| !fd.is_empty() && fd.chars().all(|c| c.is_ascii_digit())))
+        .count() as u32)
+}
+
+/// Time since the process started
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+pub fn elapsed(pid: u32) -> crate::Result<Option<Duration>> {
+    let output = std::process::Command::new("ps")
+        .arg("-o")
+        .arg("etime=")
+        .arg("-p")
+        .arg(pid.to_string())
+        .output()?;
+    let stdout = std::str::from_utf8(output.stdout.as_slice())?.trim();
+    // [dd-]hh:mm:ss
+    Ok(match stdout.split_once('-') {
+        Some((days, time)) => days.parse::<u64>().ok()
+            .zip(parse_cpu_time(time))
+            .map(|(days, time)| Duration::from_secs(days * 24 * 60 * 60) + time),
+        None => parse_cpu_time(stdout),
+    })
+}
+
+/// Parses the ps time format, [hh:]mm:ss.cc
+fn parse_cpu_time(time: &str) -> Option<Duration> {
+    let mut seconds = 0.0;
+    for part in time.split(':') {
+        seconds = seconds * 60.0 + part.parse::<f64>().ok()?;
+    }
+    Some(Duration::from_secs_f64(seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cpu_time_format() {
+        assert_eq!(parse_cpu_time("0:01.50"), Some(Duration::from_millis(1500)));
+        assert_eq!(parse_cpu_time("1:02:03.00"), Some(Duration::from_secs(3723)));
+        assert_eq!(parse_cpu_time("-"), None);
+    }
+}
@@ -0,0 +1,55 @@
+use std::ffi::OsStr;
+
+/// Quotes a word for `/bin/sh`
+pub(crate) fn quote(word: &OsStr) -> String {
+    format!("'{}'", word.to_string_lossy().replace('\'', "'\\''"))
+}
+
+/// Quotes a word for `/bin/sh` only when it needs it, for scripts meant to
+/// be read, and for the variables init scripts pass through `eval`
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+pub(crate) fn word(word: &OsStr) -> String {
+    let text = word.to_string_lossy();
+    let bare = !text.is_empty() && text.chars()
+        .all(|c| c.is_ascii_alphanumeric() || "_-./:@%+=,".contains(c));
+    if bare {
+        text.to_string()
+    } else {
+        quote(word)
+    }
+}
+
+/// Inverse of `quote` and `word`, splitting a line of words
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+pub(crate) fn words(line: &str) -> Vec<String> {
+    let mut words = vec![];
+    let mut current: Option<String> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => current.get_or_insert_with(String::new)
+                .extend(chars.by_ref().take_while(|&c| c != '\'')),
+            '"' => current.get_or_insert_with(String::new)
+                .extend(chars.by_ref().take_while(|&c| c != '"')),
+            '\\' => current.get_or_insert_with(String::new).extend(chars.next()),
+            c if c.is_whitespace() => words.extend(current.take()),
+            c => current.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(current);
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn words_invert_quote() {
+        let args = ["-p", "it's fine", "", "$HOME", "a=b"];
+        let line: Vec<String> = args.iter().map(|arg| word(arg.as_ref())).collect();
+        assert_eq!(line.join(" "), "-p 'it'\\''s fine' '' '$HOME' a=b");
+        assert_eq!(words(&line.join(" ")), args);
+        assert_eq!(words(&quote(line.join(" ").as_ref())), vec![line.join(" ")]);
+    }
+}
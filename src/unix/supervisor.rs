@@ -0,0 +1,507 @@
+use crate::{Account, Artifact, ExitReason, ServiceStats, ServiceStatus, SombraConfig, StopSignal,
+            Usage};
+use crate::support::{self, Feature};
+use crate::unix::shell::quote;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Runs the target of a service under a shell script for init systems that
+/// only start a command, such as cron. The script restarts the target as
+/// the restart policy or failure actions ask and records its pid, exit
+/// code and restarts in the state directory of the service, which also
+/// keeps its settings for `config()`.
+pub(crate) struct Supervisor {
+    dir: PathBuf,
+}
+
+impl Supervisor {
+    pub(crate) fn new(name: &str) -> Self {
+        Supervisor { dir: Supervisor::state_root().join(name) }
+    }
+
+    #[cfg_attr(not(any(target_os = "linux", target_os = "freebsd")), allow(dead_code))]
+    pub(crate) fn in_dir(dir: PathBuf) -> Self {
+        Supervisor { dir }
+    }
+
+    /// /var/lib/sombra for root, ~/.local/state/sombra for other users
+    pub(crate) fn state_root() -> PathBuf {
+        if unsafe { libc::geteuid() } == 0 {
+            return PathBuf::from("/var/lib/sombra");
+        }
+        let home = std::env::var_os("HOME").map(PathBuf::from).unwrap_or_default();
+        home.join(".local/state/sombra")
+    }
+
+    pub(crate) fn launcher(&self) -> PathBuf {
+        self.dir.join("launcher.sh")
+    }
+
+    fn settings(&self) -> PathBuf {
+        self.dir.join("service.conf")
+    }
+
+    fn file(&self, name: &str) -> PathBuf {
+        self.dir.join(name)
+    }
+
+    pub(crate) fn is_installed(&self) -> bool {
+        self.launcher().is_file()
+    }
+
+    /// Fails on the settings the launcher can't apply
+    pub(crate) fn check(config: &SombraConfig) -> crate::Result<()> {
+        let unsupported = support::unsupported(config, &[Feature::UserScope,
+                                                         Feature::RootDirectory]);
+        match unsupported {
+            Some(description) => Err(crate::Error::new(crate::ErrorKind::Other,
+                                                       description.to_string())
+                .content(config.name.clone())),
+            None => Ok(()),
+        }
+    }
+
+    #[cfg_attr(not(any(target_os = "linux", target_os = "freebsd")), allow(dead_code))]
+    pub(crate) fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Shell script running the target until it exits successfully, or
+    /// until the restarts allowed run out. `terminate` stops it by
+    /// creating the `stopping` file and signaling the target.
+    pub(crate) fn launcher_content(&self, config: &SombraConfig) -> String {
+        let state = quote(self.dir.as_os_str());
+        let mut script = vec![
+            "#!/bin/sh".to_string(),
+            format!("# Supervisor of the {} service, generated by sombra", config.name),
+            format!("state={}", state),
+            "echo $$ > \"$state/supervisor.pid\"".to_string(),
+            // The signal is for the target, the loop ends on the stopping file
+            "trap ':' TERM INT".to_string(),
+        ];
+        for (key, value) in &config.env {
+            script.push(format!("export {}", quote(format!("{}={}", key, value).as_ref())));
+        }
+        if let Some(dir) = &config.working_directory {
+            script.push(format!("cd {} || exit 1", quote(dir.as_os_str())));
+        }
+
+        // The inner shell records its pid, then runs the target in its place
+        let mut command = vec!["/bin/sh -c 'echo $$ > \"$0\"; exec \"$@\"' \"$state/target.pid\""
+            .to_string()];
+        if let Some(priority) = config.priority {
+            command.push(format!("nice -n {}", priority.nice()));
+        }
+        // chroot(8) starts the target at the root, whatever the working
+        // directory
+        if let Some(root) = &config.root_directory {
+            command.push(format!("chroot {}", quote(root.as_os_str())));
+        }
+        command.push(quote(config.path_in_root(&config.path).as_os_str()));
+        command.extend(config.args.iter().map(|arg| quote(arg)));
+        let log = |log: &Option<PathBuf>| log.as_ref()
+            .map(|log| quote(log.as_os_str()))
+            .unwrap_or_else(|| "/dev/null".to_string());
+        command.push(format!("</dev/null >>{} 2>>{}", log(&config.stdout_log),
+                             log(&config.stderr_log)));
+
+        let seconds = |delay: Duration| delay.as_millis().div_ceil(1000);
+        let restart_delay = config.failure_actions.as_ref().and_then(|f| f.restart_delay());
+        let (initial_delay, max_delay, limit) = match (&config.restart_policy, restart_delay) {
+            (Some(policy), _) => (Some(seconds(policy.initial_delay)),
+                                  seconds(policy.max_delay),
+                                  Some((policy.max_restarts, seconds(policy.window)))),
+            (None, Some(delay)) => (Some(seconds(delay)), seconds(delay), None),
+            (None, None) => (None, 0, None),
+        };
+
+        script.push("restarts=0".to_string());
+        script.push("echo 0 > \"$state/restarts\"".to_string());
+        script.push("rm -f \"$state/exit_code\"".to_string());
+        if let Some(delay) = initial_delay {
+            script.push(format!("delay={}", delay));
+            script.push("failures=0".to_string());
+        }
+        script.push("while :; do".to_string());
+        script.push("    started=$(date +%s)".to_string());
+        script.push(format!("    {}", command.join(" ")));
+        script.push("    code=$?".to_string());
+        script.push("    rm -f \"$state/target.pid\"".to_string());
+        script.push("    echo \"$code\" > \"$state/exit_code\"".to_string());
+        script.push("    [ -e \"$state/stopping\" ] || [ \"$code\" -eq 0 ] && break".to_string());
+        match initial_delay {
+            Some(initial_delay) => {
+                if let Some((max_restarts, window)) = limit {
+                    // A target that ran for the whole window starts over
+                    script.push(format!("    if [ $(($(date +%s) - started)) -ge {} ]; then \
+                                         failures=0; delay={}; fi", window, initial_delay));
+                    script.push(format!("    [ \"$failures\" -ge {} ] && break", max_restarts));
+                }
+                script.push("    failures=$((failures + 1))".to_string());
+                script.push("    restarts=$((restarts + 1))".to_string());
+                script.push("    echo \"$restarts\" > \"$state/restarts\"".to_string());
+                script.push("    sleep \"$delay\"".to_string());
+                script.push("    [ -e \"$state/stopping\" ] && break".to_string());
+                script.push(format!("    delay=$((delay * 2)); [ \"$delay\" -gt {0} ] && \
+                                     delay={0}", max_delay));
+            },
+            None => script.push("    break".to_string()),
+        }
+        script.push("done".to_string());
+        script.push("rm -f \"$state/supervisor.pid\"".to_string());
+        script.push(String::new());
+        script.join("\n")
+    }
+
+    fn settings_content(config: &SombraConfig) -> crate::Result<String> {
+        let user = config.account.unix_name(&config.name)?;
+        Ok(crate::settings::content(config, &[("user", user)]))
+    }
+
+    fn config_from_settings(name: &str, content: &str) -> (SombraConfig, Option<String>) {
+        let settings = crate::settings::parse(name, content);
+        let mut config = settings.config;
+        if let Some((_, user)) = settings.extra.iter().find(|(key, _)| key == "user") {
+            config.account = Account::from_unix_name(user);
+        }
+        (config, settings.checksum)
+    }
+
+    /// The settings `config()` reads back, the others left at their
+    /// defaults, to compare with the installed service
+    pub(crate) fn comparable(config: &SombraConfig) -> crate::Result<SombraConfig> {
+        let content = Supervisor::settings_content(config)?;
+        Ok(Supervisor::config_from_settings(&config.name, &content).0)
+    }
+
+    /// Writes the launcher and the settings. They are replaced rather than
+    /// rewritten, as a running shell reads its script as it goes.
+    pub(crate) fn install(&self, config: &SombraConfig) -> crate::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::create_dir_all(&self.dir)
+            .map_err(|e| crate::Error::from(e).content(self.dir.display().to_string()))?;
+        let write = |path: PathBuf, content: String, mode: u32| -> crate::Result<()> {
+            let staged = path.with_extension("new");
+            std::fs::write(&staged, content)?;
+            std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(mode))?;
+            std::fs::rename(&staged, &path)?;
+            Ok(())
+        };
+        write(self.settings(), Supervisor::settings_content(config)?, 0o644)?;
+        write(self.launcher(), self.launcher_content(config), 0o755)?;
+        Supervisor::provision(config)
+    }
+
+    /// Copies the bind paths into the root directory, as the launcher has
+    /// no mount namespace to bind them in
+    fn provision(config: &SombraConfig) -> crate::Result<()> {
+        fn copy(from: &Path, to: &Path) -> std::io::Result<()> {
+            let metadata = std::fs::symlink_metadata(from)?;
+            if metadata.file_type().is_symlink() {
+                let _ = std::fs::remove_file(to);
+                std::os::unix::fs::symlink(std::fs::read_link(from)?, to)
+            } else if metadata.is_dir() {
+                std::fs::create_dir_all(to)?;
+                for entry in std::fs::read_dir(from)? {
+                    let entry = entry?;
+                    copy(&entry.path(), &to.join(entry.file_name()))?;
+                }
+                Ok(())
+            } else {
+                std::fs::copy(from, to).map(|_| ())
+            }
+        }
+        for path in &config.bind_paths {
+            let target = config.path_from_root(path);
+            if let Some(dir) = target.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            copy(path, &target).map_err(|e| crate::Error::from(e)
+                .content(path.display().to_string()))?;
+        }
+        Ok(())
+    }
+
+    /// Commands of a script doing what `provision` does
+    pub(crate) fn provision_lines(config: &SombraConfig) -> Vec<String> {
+        config.bind_paths.iter()
+            .flat_map(|path| {
+                let target = config.path_from_root(path);
+                let dir = target.parent().unwrap_or(&target).to_path_buf();
+                vec![format!("mkdir -p {}", quote(dir.as_os_str())),
+                     format!("cp -RP {} {}", quote(path.as_os_str()), quote(dir.as_os_str()))]
+            })
+            .collect()
+    }
+
+    /// The files `install` writes
+    pub(crate) fn render(&self, config: &SombraConfig) -> crate::Result<Vec<Artifact>> {
+        Ok(vec![
+            Artifact::file(self.settings(), Supervisor::settings_content(config)?),
+            Artifact::script(self.launcher(), self.launcher_content(config)),
+        ])
+    }
+
+    pub(crate) fn remove(&self) -> crate::Result<()> {
+        if self.dir.exists() {
+            std::fs::remove_dir_all(&self.dir)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn config(&self, name: &str) -> crate::Result<(SombraConfig, Option<String>)> {
+        let content = std::fs::read_to_string(self.settings())
+            .map_err(|e| crate::Error::from(e).content(self.settings().display().to_string()))?;
+        Ok(Supervisor::config_from_settings(name, &content))
+    }
+
+    /// Names of the services with a state directory, sorted
+    pub(crate) fn list() -> crate::Result<Vec<String>> {
+        let root = Supervisor::state_root();
+        if !root.exists() {
+            return Ok(vec![]);
+        }
+        let mut names = vec![];
+        for entry in std::fs::read_dir(root)? {
+            let path = entry?.path();
+            let managed = std::fs::read_to_string(path.join("service.conf"))
+                .map(|content| content.starts_with(crate::settings::HEADER))
+                .unwrap_or(false);
+            if let (true, Some(name)) = (managed, path.file_name().and_then(|n| n.to_str())) {
+                names.push(name.to_string());
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    fn read_pid(&self, name: &str) -> Option<u32> {
+        std::fs::read_to_string(self.file(name)).ok()?.trim().parse().ok()
+    }
+
+    fn alive(pid: u32) -> bool {
+        let signaled = unsafe { libc::kill(pid as libc::pid_t, 0) } == 0;
+        signaled || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+    }
+
+    /// Starts the launcher in its own process group, so stopping can
+    /// signal the processes spawned by the target
+    pub(crate) fn spawn(&self) -> crate::Result<()> {
+        use std::os::unix::process::CommandExt;
+        let _ = std::fs::remove_file(self.file("stopping"));
+        let mut child = std::process::Command::new("/bin/sh")
+            .arg(self.launcher())
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .process_group(0)
+            .spawn()?;
+        // Reaped once it exits; it goes on without this process otherwise
+        std::thread::spawn(move || child.wait());
+        Ok(())
+    }
+
+    /// Shell lines doing what `spawn` does, for exported scripts
+    pub(crate) fn spawn_line(&self) -> String {
+        format!("/bin/sh {} </dev/null >/dev/null 2>&1 &", quote(self.launcher().as_os_str()))
+    }
+
+    /// Shell lines signaling the target as `terminate` does, without the
+    /// wait and the kill once the timeout passes
+    pub(crate) fn terminate_lines(&self, config: &SombraConfig) -> Vec<String> {
+        let pid = |name: &str| format!("\"$(cat {} 2>/dev/null)\"",
+                                       quote(self.file(name).as_os_str()));
+        vec![
+            format!("touch {}", quote(self.file("stopping").as_os_str())),
+            format!("kill -s {} {}", config.stop_signal.unix_name().trim_start_matches("SIG"),
+                    pid("target.pid")),
+            format!("kill {}", pid("supervisor.pid")),
+        ]
+    }
+
+    /// Sends the stop signal to the target, and kills it and the launcher
+    /// if they are still running after `stop_timeout`
+    pub(crate) fn terminate(&self, config: &SombraConfig) -> crate::Result<()> {
+        let supervisor = match self.read_pid("supervisor.pid") {
+            Some(pid) if Supervisor::alive(pid) => pid as libc::pid_t,
+            _ => return Ok(()),
+        };
+        std::fs::write(self.file("stopping"), "")?;
+        // The launcher leads the process group of the target
+        let target = match (self.read_pid("target.pid"), config.kill_process_tree) {
+            (_, true) => Some(-supervisor),
+            (Some(pid), false) => Some(pid as libc::pid_t),
+            (None, false) => None,
+        };
+        let signal = match config.stop_signal {
+            StopSignal::Terminate => libc::SIGTERM,
+            StopSignal::Interrupt => libc::SIGINT,
+        };
+        match target {
+            Some(target) => unsafe { libc::kill(target, signal) },
+            // Between restarts, the loop ends once the delay is over
+            None => unsafe { libc::kill(supervisor, libc::SIGTERM) },
+        };
+
+        let timeout = config.stop_timeout.unwrap_or(Duration::from_secs(10));
+        let begin = std::time::Instant::now();
+        while Supervisor::alive(supervisor as u32) {
+            if begin.elapsed() > timeout {
+                unsafe { libc::kill(-supervisor, libc::SIGKILL) };
+                let _ = std::fs::remove_file(self.file("supervisor.pid"));
+                let _ = std::fs::remove_file(self.file("target.pid"));
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn status(&self) -> ServiceStatus {
+        if !self.is_installed() {
+            return ServiceStatus::NotInstalled;
+        }
+        let running = |name| self.read_pid(name).map(Supervisor::alive).unwrap_or(false);
+        let stopping = self.file("stopping").exists();
+        match (running("supervisor.pid"), running("target.pid")) {
+            (true, _) if stopping => ServiceStatus::StopPending,
+            (true, true) => ServiceStatus::Running,
+            // Waiting to restart the target
+            (true, false) => ServiceStatus::StartPending,
+            (false, _) if stopping => ServiceStatus::Stopped,
+            (false, _) => match self.read_pid("exit_code") {
+                Some(code) if code != 0 => ServiceStatus::Failed,
+                _ => ServiceStatus::Stopped,
+            },
+        }
+    }
+
+    pub(crate) fn pid(&self) -> Option<u32> {
+        self.read_pid("target.pid").filter(|&pid| Supervisor::alive(pid))
+    }
+
+    pub(crate) fn usage(&self) -> crate::Result<Option<Usage>> {
+        match self.pid() {
+            #[cfg(target_os = "linux")]
+            Some(pid) => Ok(Some(crate::linux::procfs::usage(pid)?)),
+            #[cfg(any(target_os = "macos", target_os = "freebsd", target_os = "illumos",
+                      target_os = "solaris"))]
+            Some(pid) => Ok(Some(crate::unix::ps::usage(pid)?)),
+            _ => Ok(None),
+        }
+    }
+
+    pub(crate) fn stats(&self) -> ServiceStats {
+        let started_at = self.pid()
+            .and_then(|_| std::fs::metadata(self.file("target.pid")).ok())
+            .and_then(|metadata| metadata.modified().ok());
+        // The shell reports a target killed by a signal as 128 + signal
+        let last_exit = self.read_pid("exit_code").map(|code| match code {
+            129..=192 => ExitReason::Signal(code as i32 - 128),
+            code => ExitReason::Code(code as i64),
+        });
+        ServiceStats {
+            started_at,
+            restarts: self.read_pid("restarts").unwrap_or(0),
+            last_exit,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_supervisor(name: &str) -> Supervisor {
+        let dir = std::env::temp_dir().join(format!("sombra_supervisor_{}", name));
+        let _ = std::fs::remove_dir_all(&dir);
+        Supervisor::in_dir(dir)
+    }
+
+    fn wait_for(supervisor: &Supervisor, status: ServiceStatus) {
+        let begin = std::time::Instant::now();
+        while supervisor.status() != status {
+            assert!(begin.elapsed() < Duration::from_secs(5), "{:?}", supervisor.status());
+            std::thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    #[test]
+    fn settings_keep_user() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp_echo");
+        config.account = Account::User { name: "sombra".to_string(), password: None };
+        let content = Supervisor::settings_content(&config).unwrap();
+        assert!(content.contains("\nuser=sombra\n"));
+        assert_eq!(Supervisor::config_from_settings("tcp_echo", &content).0.account,
+                   config.account);
+    }
+
+    #[test]
+    fn launcher_quotes_command() {
+        let mut config = SombraConfig::new("tcp_echo", "/opt/tcp echo/tcp_echo");
+        config.args = vec!["it's".into()];
+        config.env = vec![("MODE".to_string(), "$HOME".to_string())];
+        let content = temp_supervisor("quotes").launcher_content(&config);
+        assert!(content.contains(" '/opt/tcp echo/tcp_echo' 'it'\\''s' </dev/null >>/dev/null"));
+        assert!(content.contains("export 'MODE=$HOME'"));
+        assert!(content.contains("    break\ndone"));
+    }
+
+    #[test]
+    fn launcher_chroots_into_root() {
+        let mut config = SombraConfig::new("tcp_echo", "/srv/jail/bin/tcp_echo");
+        config.root_directory = Some(PathBuf::from("/srv/jail"));
+        config.bind_paths = vec![PathBuf::from("/etc/resolv.conf")];
+        let content = temp_supervisor("root").launcher_content(&config);
+        assert!(content.contains(" chroot '/srv/jail' '/bin/tcp_echo' </dev/null"));
+        assert_eq!(Supervisor::provision_lines(&config),
+                   vec!["mkdir -p '/srv/jail/etc'", "cp -RP '/etc/resolv.conf' '/srv/jail/etc'"]);
+    }
+
+    #[test]
+    fn launcher_restarts_and_stops() {
+        let supervisor = temp_supervisor("restarts");
+        let script = supervisor.dir.with_extension("sh");
+        std::fs::write(&script, "[ -e \"$0.ran\" ] || { touch \"$0.ran\"; exit 3; }\n\
+                                 exec sleep 30\n").unwrap();
+        let _ = std::fs::remove_file(script.with_extension("sh.ran"));
+        let mut config = SombraConfig::new("restarts", "/bin/sh");
+        config.args = vec![script.clone().into()];
+        config.failure_actions = Some(crate::FailureActions::restart(Duration::from_millis(100)));
+        config.stop_timeout = Some(Duration::from_secs(3));
+        supervisor.install(&config).unwrap();
+        assert_eq!(supervisor.status(), ServiceStatus::Stopped);
+
+        supervisor.spawn().unwrap();
+        wait_for(&supervisor, ServiceStatus::Running);
+        let begin = std::time::Instant::now();
+        while supervisor.stats().restarts == 0 && begin.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        wait_for(&supervisor, ServiceStatus::Running);
+        assert_eq!(supervisor.stats().restarts, 1);
+        assert_eq!(supervisor.stats().last_exit, Some(ExitReason::Code(3)));
+        assert!(supervisor.pid().is_some());
+
+        supervisor.terminate(&config).unwrap();
+        wait_for(&supervisor, ServiceStatus::Stopped);
+        assert_eq!(supervisor.pid(), None);
+        assert_eq!(supervisor.stats().last_exit, Some(ExitReason::Signal(15)));
+        supervisor.remove().unwrap();
+        let _ = std::fs::remove_file(&script);
+        let _ = std::fs::remove_file(script.with_extension("sh.ran"));
+    }
+
+    #[test]
+    fn launcher_leaves_failed_target() {
+        let supervisor = temp_supervisor("failed");
+        let mut config = SombraConfig::new("failed", "/bin/sh");
+        config.args = vec!["-c".into(), "exit 7".into()];
+        supervisor.install(&config).unwrap();
+        supervisor.spawn().unwrap();
+        wait_for(&supervisor, ServiceStatus::Failed);
+        assert_eq!(supervisor.stats().last_exit, Some(ExitReason::Code(7)));
+        assert_eq!(supervisor.stats().restarts, 0);
+        supervisor.remove().unwrap();
+    }
+}
@@ -0,0 +1,164 @@
+use std::io::Read;
+use std::path::Path;
+use crate::{Error, ErrorKind};
+
+/// Checks that the host can run the target at `path`: a regular file,
+/// executable by its mode on Unix, and a PE, ELF or Mach-O binary of an
+/// architecture the host runs, or a `#!` script on Unix
+pub(crate) fn validate_target(path: &Path) -> crate::Result<()> {
+    let error = |kind, description: String| {
+        Error::new(kind, description).content(path.display().to_string())
+    };
+    let metadata = std::fs::metadata(path)
+        .map_err(|e| Error::from(e).content(path.display().to_string()))?;
+    if !metadata.is_file() {
+        return Err(error(ErrorKind::Io, "Target is not a file".to_string()));
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if metadata.permissions().mode() & 0o111 == 0 {
+            return Err(error(ErrorKind::AccessDenied, "Target is not executable".to_string()));
+        }
+    }
+
+    let mut header = vec![];
+    std::fs::File::open(path)?.take(4096).read_to_end(&mut header)?;
+    match mismatch(&header, std::env::consts::OS, std::env::consts::ARCH) {
+        Some(reason) => Err(error(ErrorKind::Other, reason)),
+        None => Ok(()),
+    }
+}
+
+/// Why a binary starting with `header` can't run on `os` and `arch`, named
+/// as in `std::env::consts`. Unknown architectures aren't reported.
+fn mismatch(header: &[u8], os: &str, arch: &str) -> Option<String> {
+    let u16_at = |offset: usize, big_endian: bool| header.get(offset..offset + 2)
+        .map(|b| if big_endian {
+            u16::from_be_bytes([b[0], b[1]])
+        } else {
+            u16::from_le_bytes([b[0], b[1]])
+        });
+    let u32_at = |offset: usize| header.get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+
+    let (format, target_os, target) = if header.starts_with(b"#!") {
+        ("a #! script", "unix", None)
+    } else if header.starts_with(b"\x7fELF") {
+        let machine = match header.get(5) {
+            Some(1) => u16_at(18, false),
+            Some(2) => u16_at(18, true),
+            _ => None,
+        };
+        let target = match machine {
+            Some(0x03) => Some("x86"),
+            Some(0x3e) => Some("x86_64"),
+            Some(0x28) => Some("arm"),
+            Some(0xb7) => Some("aarch64"),
+            _ => None,
+        };
+        ("an ELF binary", "linux", target)
+    } else if header.starts_with(b"MZ") {
+        // e_lfanew points to the PE signature, followed by the machine
+        let machine = u32_at(0x3c)
+            .map(|offset| offset as usize)
+            .filter(|&offset| header.get(offset..offset + 4) == Some(b"PE\0\0"))
+            .and_then(|offset| u16_at(offset + 4, false));
+        let target = match machine {
+            Some(0x14c) => Some("x86"),
+            Some(0x8664) => Some("x86_64"),
+            Some(0x1c4) => Some("arm"),
+            Some(0xaa64) => Some("aarch64"),
+            _ => None,
+        };
+        ("a PE binary", "windows", target)
+    } else if matches!(u32_at(0), Some(0xfeedface) | Some(0xfeedfacf)) {
+        let target = match u32_at(4) {
+            Some(0x7) => Some("x86"),
+            Some(0x0100_0007) => Some("x86_64"),
+            Some(0xc) => Some("arm"),
+            Some(0x0100_000c) => Some("aarch64"),
+            _ => None,
+        };
+        ("a Mach-O binary", "macos", target)
+    } else if header.starts_with(&[0xca, 0xfe, 0xba, 0xbe]) {
+        // Universal binaries hold a slice per architecture
+        ("a universal binary", "macos", None)
+    } else {
+        return Some("Target is not an executable binary".to_string());
+    };
+
+    let runs_on_os = match target_os {
+        "unix" => os != "windows",
+        target_os => target_os == os,
+    };
+    if !runs_on_os {
+        return Some(format!("Target is {}, which doesn't run on {}", format, os));
+    }
+    // Architectures the host runs besides its own: 32 bits x86 on x86_64,
+    // and x86 through emulation on Windows and macOS on ARM
+    let compatible = |target: &str| target == arch || matches!((os, arch, target),
+        ("linux", "x86_64", "x86") | ("windows", "x86_64", "x86")
+        | ("windows", "aarch64", "x86") | ("windows", "aarch64", "x86_64")
+        | ("macos", "aarch64", "x86_64"));
+    match target {
+        Some(target) if !compatible(target) =>
+            Some(format!("Target is {} for {}, the host is {}", format, target, arch)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn elf(machine: u16) -> Vec<u8> {
+        let mut header = b"\x7fELF\x02\x01\x01".to_vec();
+        header.resize(18, 0);
+        header.extend(&machine.to_le_bytes());
+        header
+    }
+
+    fn pe(machine: u16) -> Vec<u8> {
+        let mut header = b"MZ".to_vec();
+        header.resize(0x3c, 0);
+        header.extend(&0x80u32.to_le_bytes());
+        header.resize(0x80, 0);
+        header.extend(b"PE\0\0");
+        header.extend(&machine.to_le_bytes());
+        header
+    }
+
+    #[test]
+    fn mismatch_checks_format_and_arch() {
+        assert_eq!(mismatch(&elf(0x3e), "linux", "x86_64"), None);
+        assert_eq!(mismatch(&elf(0x03), "linux", "x86_64"), None);
+        assert_eq!(mismatch(&elf(0xb7), "linux", "x86_64"),
+                   Some("Target is an ELF binary for aarch64, the host is x86_64".to_string()));
+        assert_eq!(mismatch(&pe(0x8664), "windows", "x86_64"), None);
+        assert_eq!(mismatch(&pe(0x8664), "windows", "aarch64"), None);
+        assert_eq!(mismatch(&pe(0xaa64), "windows", "x86_64"),
+                   Some("Target is a PE binary for aarch64, the host is x86_64".to_string()));
+        assert_eq!(mismatch(&pe(0x8664), "linux", "x86_64"),
+                   Some("Target is a PE binary, which doesn't run on linux".to_string()));
+        assert_eq!(mismatch(b"#!/bin/sh\n", "macos", "aarch64"), None);
+        assert_eq!(mismatch(b"#!/bin/sh\n", "windows", "x86_64"),
+                   Some("Target is a #! script, which doesn't run on windows".to_string()));
+        assert_eq!(mismatch(b"hello", "linux", "x86_64"),
+                   Some("Target is not an executable binary".to_string()));
+    }
+
+    #[test]
+    fn validate_target_accepts_current_exe() {
+        assert_eq!(validate_target(&std::env::current_exe().unwrap()), Ok(()));
+        let path = std::env::temp_dir().join("sombra_validate_test");
+        std::fs::write(&path, "#!/bin/sh\n").unwrap();
+        let error = validate_target(&path).unwrap_err();
+        if cfg!(unix) {
+            assert_eq!(error.kind(), ErrorKind::AccessDenied);
+        } else {
+            assert_eq!(error.kind(), ErrorKind::Other);
+        }
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,98 @@
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::time::Duration;
+use crate::{ServiceStatus, Sombra};
+
+/// Reports the status of a service each time it changes, starting with the
+/// current one. The status is polled every `interval` on a thread of the
+/// watcher, which ends after the first error.
+pub struct StatusWatcher {
+    changes: Receiver<crate::Result<ServiceStatus>>,
+    stop: Sender<()>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl StatusWatcher {
+    pub fn spawn<S: Sombra + Send + 'static>(service: S, interval: Duration) -> Self {
+        let (sender, changes) = std::sync::mpsc::channel();
+        StatusWatcher::with_callback(service, interval, move |status| {
+            let _ = sender.send(status);
+        }).changes(changes)
+    }
+
+    /// Calls `callback` on the watcher thread instead of queueing the
+    /// changes. The watcher can't be iterated then.
+    pub fn with_callback<S, F>(service: S, interval: Duration, mut callback: F) -> Self
+        where S: Sombra + Send + 'static,
+              F: FnMut(crate::Result<ServiceStatus>) + Send + 'static {
+        let (stop, stopped) = std::sync::mpsc::channel();
+        let thread = std::thread::spawn(move || {
+            let mut last = None;
+            loop {
+                match service.status() {
+                    Ok(status) if last == Some(status) => {},
+                    Ok(status) => {
+                        last = Some(status);
+                        callback(Ok(status));
+                    },
+                    Err(e) => {
+                        callback(Err(e));
+                        return;
+                    },
+                }
+                if stopped.recv_timeout(interval) != Err(RecvTimeoutError::Timeout) {
+                    return;
+                }
+            }
+        });
+        let (_, changes) = std::sync::mpsc::channel();
+        StatusWatcher { changes, stop, thread: Some(thread) }
+    }
+
+    fn changes(mut self, changes: Receiver<crate::Result<ServiceStatus>>) -> Self {
+        self.changes = changes;
+        self
+    }
+
+    /// Waits for the next change up to `timeout`
+    pub fn next_timeout(&self, timeout: Duration) -> Option<crate::Result<ServiceStatus>> {
+        self.changes.recv_timeout(timeout).ok()
+    }
+}
+
+impl Iterator for StatusWatcher {
+    type Item = crate::Result<ServiceStatus>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.changes.recv().ok()
+    }
+}
+
+impl Drop for StatusWatcher {
+    fn drop(&mut self) {
+        let _ = self.stop.send(());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SombraConfig;
+    use crate::mock::SombraMock;
+
+    #[test]
+    fn watcher_reports_changes() {
+        let mock = SombraMock::from_config(SombraConfig::new("mock", "/opt/mock")).unwrap();
+        let watcher = StatusWatcher::spawn(mock.clone(), Duration::from_millis(10));
+        let timeout = Duration::from_secs(5);
+        assert_eq!(watcher.next_timeout(timeout), Some(Ok(ServiceStatus::NotInstalled)));
+
+        mock.create().unwrap();
+        assert_eq!(watcher.next_timeout(timeout), Some(Ok(ServiceStatus::Running)));
+        mock.stop().unwrap();
+        assert_eq!(watcher.next_timeout(timeout), Some(Ok(ServiceStatus::Stopped)));
+        assert_eq!(watcher.next_timeout(Duration::from_millis(50)), None);
+    }
+}
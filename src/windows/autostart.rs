@@ -0,0 +1,499 @@
+use crate::{Account, Artifact, Difference, ExitReason, LogReader, ManagedService, Privileges,
+            Scripts, Sombra, ServiceStats, ServiceStatus, SombraConfig, StartType, Usage};
+use crate::export::PowerShell;
+use crate::support::{self, Feature};
+use crate::windows::powershell::quote;
+use crate::windows::reg::{Reg, Value};
+use crate::windows::sombra_imp::SombraWindows;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Prefix of the Run key values of the services created by sombra
+const RUN_VALUE_PREFIX: &str = "Sombra ";
+/// Delay of a `DelayedAutoStart` after logon, as the SCM delays services
+const LOGON_DELAY: Duration = Duration::from_secs(120);
+
+/// Starts the target at logon from the Run key of the current user, for
+/// tray apps and user agents that must not run as SYSTEM. A per-user
+/// PowerShell launcher restarts the target as the restart policy asks and
+/// records its state in `%LOCALAPPDATA%\Sombra\<name>`, which also keeps
+/// the settings for `config()`. The target runs in the session of the
+/// user, so log files and other accounts aren't available.
+pub struct SombraAutostart {
+    config: SombraConfig,
+    dir: PathBuf,
+}
+
+impl SombraAutostart {
+    fn state_root() -> PathBuf {
+        let local = std::env::var_os("LOCALAPPDATA").map(PathBuf::from).unwrap_or_default();
+        local.join("Sombra")
+    }
+
+    fn check(config: &SombraConfig) -> crate::Result<()> {
+        let unsupported = if !config.log_files().is_empty() {
+            Some("Autostart entries don't capture the output of the target")
+        } else if config.account != Account::LocalSystem {
+            Some("Autostart entries run as the user logging on")
+        } else if !config.dependencies.is_empty() {
+            Some("Dependencies aren't supported by the launcher")
+        } else {
+            support::unsupported(config, &[Feature::UserScope])
+        };
+        match unsupported {
+            Some(description) => Err(crate::Error::new(crate::ErrorKind::Other,
+                                                       description.to_string())
+                .content(config.name.clone())),
+            None => Ok(()),
+        }
+    }
+
+    fn launcher(&self) -> PathBuf {
+        self.dir.join("launcher.ps1")
+    }
+
+    fn file(&self, name: &str) -> PathBuf {
+        self.dir.join(name)
+    }
+
+    fn run_value(&self) -> String {
+        format!("{}{}", RUN_VALUE_PREFIX, self.config.name)
+    }
+
+    /// Command of the Run key, or nothing for services started on demand
+    fn run_command(&self) -> Option<String> {
+        let command = format!("powershell.exe -NoProfile -NonInteractive -WindowStyle Hidden \
+                               -ExecutionPolicy Bypass -File \"{}\"", self.launcher().display());
+        match self.config.start_type {
+            StartType::AutoStart => Some(command),
+            StartType::DelayedAutoStart => Some(format!("{} -Delayed", command)),
+            StartType::OnDemand | StartType::Disabled => None,
+        }
+    }
+
+    /// PowerShell script running the target until it exits successfully,
+    /// or until the restarts allowed run out. `stop()` ends it by creating
+    /// the `stopping` file and closing the target.
+    fn launcher_content(&self) -> String {
+        let config = &self.config;
+        let mut script = vec![
+            "param([switch]$Delayed)".to_string(),
+            format!("# Supervisor of the {} service, generated by sombra", config.name),
+            format!("$state = {}", quote(&self.dir.to_string_lossy())),
+            format!("if ($Delayed) {{ Start-Sleep -Seconds {} }}", LOGON_DELAY.as_secs()),
+            "Set-Content -LiteralPath \"$state\\supervisor.pid\" -Value $PID".to_string(),
+        ];
+        for (key, value) in &config.env {
+            script.push(format!("[Environment]::SetEnvironmentVariable({}, {})",
+                                quote(key), quote(value)));
+        }
+        let mut start = format!("    $process = Start-Process -PassThru -FilePath {}",
+                                quote(&config.path.to_string_lossy()));
+        if !config.args.is_empty() {
+            start.push_str(&format!(" -ArgumentList {}",
+                                    quote(&SombraWindows::command_line(&config.args))));
+        }
+        if let Some(dir) = &config.working_directory {
+            start.push_str(&format!(" -WorkingDirectory {}", quote(&dir.to_string_lossy())));
+        }
+
+        let seconds = |delay: Duration| delay.as_millis().div_ceil(1000);
+        let restart_delay = config.failure_actions.as_ref().and_then(|f| f.restart_delay());
+        let (initial_delay, max_delay, limit) = match (&config.restart_policy, restart_delay) {
+            (Some(policy), _) => (Some(seconds(policy.initial_delay)),
+                                  seconds(policy.max_delay),
+                                  Some((policy.max_restarts, seconds(policy.window)))),
+            (None, Some(delay)) => (Some(seconds(delay)), seconds(delay), None),
+            (None, None) => (None, 0, None),
+        };
+
+        script.push("$restarts = 0".to_string());
+        script.push("Set-Content -LiteralPath \"$state\\restarts\" -Value 0".to_string());
+        script.push("Remove-Item -LiteralPath \"$state\\exit_code\" \
+                     -ErrorAction SilentlyContinue".to_string());
+        if let Some(delay) = initial_delay {
+            script.push(format!("$delay = {}", delay));
+            script.push("$failures = 0".to_string());
+        }
+        script.push("while ($true) {".to_string());
+        script.push("    $started = Get-Date".to_string());
+        script.push(start);
+        // The exit code is only kept once the handle was opened
+        script.push("    $null = $process.Handle".to_string());
+        script.push("    Set-Content -LiteralPath \"$state\\target.pid\" -Value $process.Id"
+            .to_string());
+        if let Some(priority) = config.priority {
+            // ProcessPriorityClass names match the variants
+            script.push(format!("    $process.PriorityClass = '{:?}'", priority));
+        }
+        script.push("    $process.WaitForExit()".to_string());
+        script.push("    $code = $process.ExitCode".to_string());
+        script.push("    Remove-Item -LiteralPath \"$state\\target.pid\" \
+                     -ErrorAction SilentlyContinue".to_string());
+        script.push("    Set-Content -LiteralPath \"$state\\exit_code\" -Value $code".to_string());
+        script.push("    if ((Test-Path -LiteralPath \"$state\\stopping\") -or $code -eq 0) \
+                     { break }".to_string());
+        match initial_delay {
+            Some(initial_delay) => {
+                if let Some((max_restarts, window)) = limit {
+                    // A target that ran for the whole window starts over
+                    script.push(format!("    if (((Get-Date) - $started).TotalSeconds -ge {}) \
+                                         {{ $failures = 0; $delay = {} }}",
+                                        window, initial_delay));
+                    script.push(format!("    if ($failures -ge {}) {{ break }}", max_restarts));
+                }
+                script.push("    $failures++".to_string());
+                script.push("    $restarts++".to_string());
+                script.push("    Set-Content -LiteralPath \"$state\\restarts\" -Value $restarts"
+                    .to_string());
+                script.push("    Start-Sleep -Seconds $delay".to_string());
+                script.push("    if (Test-Path -LiteralPath \"$state\\stopping\") { break }"
+                    .to_string());
+                script.push(format!("    $delay = [Math]::Min($delay * 2, {})", max_delay));
+            },
+            None => script.push("    break".to_string()),
+        }
+        script.push("}".to_string());
+        script.push("Remove-Item -LiteralPath \"$state\\supervisor.pid\" \
+                     -ErrorAction SilentlyContinue".to_string());
+        script.push(String::new());
+        script.join("\r\n")
+    }
+
+    fn write_files(&self) -> crate::Result<()> {
+        std::fs::create_dir_all(&self.dir)
+            .map_err(|e| crate::Error::from(e).content(self.dir.display().to_string()))?;
+        std::fs::write(self.file("service.conf"), crate::settings::content(&self.config, &[]))?;
+        std::fs::write(self.launcher(), self.launcher_content())?;
+
+        let run = Reg::user_run();
+        match self.run_command() {
+            Some(command) => run.set_string(&self.run_value(), &command),
+            None => run.delete_value(&self.run_value()),
+        }
+    }
+
+    fn is_installed(&self) -> crate::Result<()> {
+        if self.launcher().is_file() {
+            Ok(())
+        } else {
+            Err(crate::Error::new(crate::ErrorKind::ServiceNotFound,
+                                  format!("Service {} does not exist", self.config.name)))
+        }
+    }
+
+    fn read_number(&self, name: &str) -> Option<i64> {
+        std::fs::read_to_string(self.file(name)).ok()?.trim().parse().ok()
+    }
+
+    fn alive(pid: i64) -> bool {
+        std::process::Command::new("tasklist.exe")
+            .args(["/FI", &format!("PID eq {}", pid), "/NH", "/FO", "CSV"])
+            .output()
+            .map(|output| String::from_utf8_lossy(&output.stdout)
+                .contains(&format!("\"{}\"", pid)))
+            .unwrap_or(false)
+    }
+
+    fn running(&self, name: &str) -> Option<u32> {
+        self.read_number(name)
+            .filter(|&pid| SombraAutostart::alive(pid))
+            .map(|pid| pid as u32)
+    }
+
+    fn taskkill(pid: u32, tree: bool, force: bool) {
+        let mut command = std::process::Command::new("taskkill.exe");
+        command.args(["/PID", &pid.to_string()]);
+        if tree {
+            command.arg("/T");
+        }
+        if force {
+            command.arg("/F");
+        }
+        let _ = command.output();
+    }
+
+    fn settings(&self) -> crate::Result<crate::settings::Settings> {
+        self.is_installed()?;
+        let path = self.file("service.conf");
+        let content = std::fs::read_to_string(&path)
+            .map_err(|e| crate::Error::from(e).content(path.display().to_string()))?;
+        Ok(crate::settings::parse(&self.config.name, &content))
+    }
+}
+
+impl Sombra for SombraAutostart {
+    fn from_config(config: SombraConfig) -> crate::Result<Self> {
+        let dir = SombraAutostart::state_root().join(&config.name);
+        Ok(SombraAutostart { config, dir })
+    }
+
+    fn create(&self) -> crate::Result<()> {
+        SombraAutostart::check(&self.config)?;
+        if self.launcher().is_file() {
+            return Err(crate::Error::new(crate::ErrorKind::ServiceAlreadyExists,
+                                         format!("Service {} already exist",
+                                                 self.config.name)));
+        }
+        self.write_files()?;
+
+        if self.config.start_type == StartType::Disabled || self.config.target_pending() {
+            return Ok(());
+        }
+        self.start()?;
+        match &self.config.readiness {
+            Some(readiness) => readiness.wait(),
+            None => Ok(()),
+        }
+    }
+
+    fn delete(&self) -> crate::Result<()> {
+        self.is_installed()?;
+        self.stop()?;
+        Reg::user_run().delete_value(&self.run_value())?;
+        std::fs::remove_dir_all(&self.dir)?;
+        Ok(())
+    }
+
+    fn update(&self) -> crate::Result<()> {
+        SombraAutostart::check(&self.config)?;
+        self.is_installed()?;
+        self.write_files()
+    }
+
+    fn start(&self) -> crate::Result<()> {
+        use std::os::windows::process::CommandExt;
+        // CREATE_NO_WINDOW | CREATE_NEW_PROCESS_GROUP
+        const CREATION_FLAGS: u32 = 0x0800_0000 | 0x0000_0200;
+
+        self.config.check_target()?;
+        self.is_installed()?;
+        if self.running("supervisor.pid").is_some() {
+            return Ok(());
+        }
+        let _ = std::fs::remove_file(self.file("stopping"));
+        let mut child = std::process::Command::new("powershell.exe")
+            .args(["-NoProfile", "-NonInteractive", "-WindowStyle", "Hidden",
+                   "-ExecutionPolicy", "Bypass", "-File"])
+            .arg(self.launcher())
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .creation_flags(CREATION_FLAGS)
+            .spawn()?;
+        // Reaped once it exits; it goes on without this process otherwise
+        std::thread::spawn(move || child.wait());
+        Ok(())
+    }
+
+    /// Asks the target to close, as a tray app would be closed, and kills
+    /// it if it is still running after the stop timeout
+    fn stop(&self) -> crate::Result<()> {
+        self.is_installed()?;
+        let supervisor = match self.running("supervisor.pid") {
+            Some(pid) => pid,
+            None => return Ok(()),
+        };
+        std::fs::write(self.file("stopping"), "")?;
+        let target = self.running("target.pid");
+        match target {
+            Some(pid) => SombraAutostart::taskkill(pid, self.config.kill_process_tree, false),
+            // Between restarts, the launcher would only wait for the delay
+            None => SombraAutostart::taskkill(supervisor, false, true),
+        }
+
+        let timeout = self.config.stop_timeout.unwrap_or(Duration::from_secs(10));
+        let begin = std::time::Instant::now();
+        while SombraAutostart::alive(supervisor as i64) {
+            if begin.elapsed() > timeout {
+                if let Some(pid) = target {
+                    SombraAutostart::taskkill(pid, self.config.kill_process_tree, true);
+                }
+                SombraAutostart::taskkill(supervisor, false, true);
+                let _ = std::fs::remove_file(self.file("supervisor.pid"));
+                let _ = std::fs::remove_file(self.file("target.pid"));
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        Ok(())
+    }
+
+    fn status(&self) -> crate::Result<ServiceStatus> {
+        if self.is_installed().is_err() {
+            return Ok(ServiceStatus::NotInstalled);
+        }
+        let stopping = self.file("stopping").exists();
+        let supervisor = self.running("supervisor.pid").is_some();
+        Ok(match (supervisor, self.running("target.pid").is_some()) {
+            (true, _) if stopping => ServiceStatus::StopPending,
+            (true, true) => ServiceStatus::Running,
+            // Waiting to restart the target
+            (true, false) => ServiceStatus::StartPending,
+            (false, _) if stopping => ServiceStatus::Stopped,
+            (false, _) => match self.read_number("exit_code") {
+                Some(code) if code != 0 => ServiceStatus::Failed,
+                _ => ServiceStatus::Stopped,
+            },
+        })
+    }
+
+    fn pid(&self) -> crate::Result<Option<u32>> {
+        self.is_installed()?;
+        Ok(self.running("target.pid"))
+    }
+
+    fn usage(&self) -> crate::Result<Option<Usage>> {
+        match self.pid()? {
+            Some(pid) => Ok(Some(crate::windows::powershell::usage(pid)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn stats(&self) -> crate::Result<ServiceStats> {
+        let started_at = match self.pid()? {
+            Some(_) => std::fs::metadata(self.file("target.pid"))
+                .and_then(|metadata| metadata.modified())
+                .ok(),
+            None => None,
+        };
+        Ok(ServiceStats {
+            started_at,
+            restarts: self.read_number("restarts").unwrap_or(0) as u32,
+            last_exit: self.read_number("exit_code").map(ExitReason::Code),
+        })
+    }
+
+    fn config(&self) -> crate::Result<SombraConfig> {
+        Ok(self.settings()?.config)
+    }
+
+    fn diff(&self) -> crate::Result<Vec<Difference>> {
+        let desired = crate::settings::parse(&self.config.name,
+                                             &crate::settings::content(&self.config, &[]));
+        Ok(desired.config.diff(&self.config()?))
+    }
+
+    fn checksum(&self) -> crate::Result<Option<String>> {
+        Ok(self.settings()?.checksum)
+    }
+
+    fn list_managed() -> crate::Result<Vec<ManagedService>> {
+        let root = SombraAutostart::state_root();
+        if !root.exists() {
+            return Ok(vec![]);
+        }
+        let mut services = vec![];
+        for entry in std::fs::read_dir(root)? {
+            let path = entry?.path();
+            let content = std::fs::read_to_string(path.join("service.conf")).unwrap_or_default();
+            let name = match path.file_name().and_then(|n| n.to_str()) {
+                Some(name) if content.starts_with(crate::settings::HEADER) => name.to_string(),
+                _ => continue,
+            };
+            let config = crate::settings::parse(&name, &content).config;
+            let path = config.path.clone();
+            let status = SombraAutostart::from_config(config)?.status()?;
+            services.push(ManagedService { name, status, path });
+        }
+        services.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(services)
+    }
+
+    fn required_privileges(&self) -> Privileges {
+        Privileges::User
+    }
+
+    fn logs(&self, _follow: bool) -> crate::Result<LogReader> {
+        Err(crate::Error::new(crate::ErrorKind::Other,
+                              "Autostart entries don't capture the output of the target"
+                                  .to_string())
+            .content(self.config.name.clone()))
+    }
+
+    fn render(&self) -> crate::Result<Vec<Artifact>> {
+        SombraAutostart::check(&self.config)?;
+        let mut artifacts = vec![
+            Artifact::file(self.file("service.conf"), crate::settings::content(&self.config, &[])),
+            Artifact::script(self.launcher(), self.launcher_content()),
+        ];
+        if let Some(command) = self.run_command() {
+            let run_value = self.run_value();
+            let values = [(run_value.as_str(), Value::String(command))];
+            artifacts.push(Artifact::registration(Reg::user_run().describe(&values)));
+        }
+        Ok(artifacts)
+    }
+
+    /// The launcher is started hidden, and stopped with taskkill
+    fn export(&self) -> crate::Result<Scripts> {
+        let mut script = PowerShell::new(&self.config.name);
+        script.write(&self.render()?);
+        let run_value = self.run_value();
+        if let Some(command) = self.run_command() {
+            script.install_native("reg.exe",
+                                  &Reg::user_run().set_args(&run_value, &Value::String(command)));
+        }
+        if self.config.start_type != StartType::Disabled {
+            script.install(format!("Start-Process -FilePath powershell.exe -WindowStyle Hidden \
+                                    -ArgumentList {}",
+                                   quote(&format!("-NoProfile -NonInteractive -ExecutionPolicy \
+                                                   Bypass -File \"{}\"",
+                                                  self.launcher().display()))));
+        }
+
+        let dir = quote(&self.dir.to_string_lossy());
+        script.uninstall(format!("New-Item -ItemType File -Force -Path (Join-Path {} 'stopping') \
+                                  | Out-Null", dir));
+        script.uninstall(format!("foreach ($file in 'target.pid', 'supervisor.pid') {{ \
+                                  $id = Get-Content -LiteralPath (Join-Path {} $file) \
+                                  -ErrorAction SilentlyContinue; \
+                                  if ($id) {{ taskkill.exe /T /F /PID $id }} }}", dir));
+        script.uninstall_native("reg.exe", &Reg::user_run().delete_value_args(&run_value));
+        script.remove_dir(&self.dir);
+        Ok(script.finish())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn autostart(config: SombraConfig) -> SombraAutostart {
+        let dir = PathBuf::from("C:\\Users\\me\\AppData\\Local\\Sombra\\tray");
+        SombraAutostart { config, dir }
+    }
+
+    #[test]
+    fn launcher_restarts_target() {
+        let mut config = SombraConfig::new("tray", "C:\\Program Files\\Tray\\tray.exe");
+        config.args = vec!["--minimized".into(), "it's".into()];
+        config.failure_actions = Some(crate::FailureActions::restart(Duration::from_secs(5)));
+        let content = autostart(config).launcher_content();
+        assert!(content.contains("$state = 'C:\\Users\\me\\AppData\\Local\\Sombra\\tray'"));
+        assert!(content.contains("Start-Process -PassThru -FilePath \
+                                  'C:\\Program Files\\Tray\\tray.exe' \
+                                  -ArgumentList '--minimized it''s'"));
+        assert!(content.contains("$delay = 5\r\n"));
+        assert!(content.contains("$delay = [Math]::Min($delay * 2, 5)"));
+        assert!(!content.contains("$failures -ge"));
+    }
+
+    #[test]
+    fn run_command_follows_start_type() {
+        let mut config = SombraConfig::new("tray", "C:\\Program Files\\Tray\\tray.exe");
+        assert_eq!(autostart(config.clone()).run_command(), None);
+        config.start_type = StartType::DelayedAutoStart;
+        let command = autostart(config).run_command().unwrap();
+        assert!(command.ends_with("-File \"C:\\Users\\me\\AppData\\Local\\Sombra\\tray\\\
+                                   launcher.ps1\" -Delayed"));
+    }
+
+    #[test]
+    fn other_accounts_rejected() {
+        let mut config = SombraConfig::new("tray", "C:\\Program Files\\Tray\\tray.exe");
+        assert_eq!(SombraAutostart::check(&config), Ok(()));
+        config.account = Account::LocalService;
+        assert!(SombraAutostart::check(&config).is_err());
+    }
+}
@@ -0,0 +1,77 @@
+use std::ffi::OsString;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::time::Duration;
+use windows_service::service::{ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState,
+                               ServiceStatus, ServiceType};
+use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+use windows_service::service_dispatcher;
+use crate::{ServiceMain, Shutdown};
+
+/// The dispatcher calls the service main without context
+static SERVICE: OnceLock<(String, ServiceMain)> = OnceLock::new();
+static EXIT_CODE: AtomicI32 = AtomicI32::new(0);
+
+const ERROR_FAILED_SERVICE_CONTROLLER_CONNECT: i32 = 1063;
+
+windows_service::define_windows_service!(ffi_service_main, service_main);
+
+fn service_main(_arguments: Vec<OsString>) {
+    let (name, main) = SERVICE.get().unwrap();
+    let shutdown = Shutdown::default();
+    let requested = shutdown.clone();
+    let handle = match service_control_handler::register(name, move |control| match control {
+        ServiceControl::Stop | ServiceControl::Shutdown => {
+            requested.request();
+            ServiceControlHandlerResult::NoError
+        },
+        ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+        _ => ServiceControlHandlerResult::NotImplemented,
+    }) {
+        Ok(handle) => handle,
+        Err(e) => {
+            eprintln!("{}", crate::Error::from(e));
+            EXIT_CODE.store(1, Ordering::SeqCst);
+            return;
+        },
+    };
+    let report = |current_state, controls_accepted, exit_code| {
+        handle.set_service_status(ServiceStatus {
+            service_type: ServiceType::OWN_PROCESS,
+            current_state,
+            controls_accepted,
+            exit_code,
+            checkpoint: 0,
+            wait_hint: Duration::default(),
+            process_id: None,
+        })
+    };
+
+    let _ = report(ServiceState::Running,
+                   ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+                   ServiceExitCode::NO_ERROR);
+    let code = crate::entry::exit_code(main(shutdown));
+    EXIT_CODE.store(code, Ordering::SeqCst);
+    let exit_code = match code {
+        0 => ServiceExitCode::NO_ERROR,
+        code => ServiceExitCode::ServiceSpecific(code as u32),
+    };
+    let _ = report(ServiceState::Stopped, ServiceControlAccept::empty(), exit_code);
+}
+
+/// Hands the process to the service dispatcher, which returns once the
+/// service stopped. Started from a console, `main` runs as a plain program.
+pub fn run(name: &str, main: ServiceMain) -> i32 {
+    let _ = SERVICE.set((name.to_string(), main));
+    match service_dispatcher::start(name, ffi_service_main) {
+        Ok(()) => EXIT_CODE.load(Ordering::SeqCst),
+        Err(windows_service::Error::Winapi(e))
+            if e.raw_os_error() == Some(ERROR_FAILED_SERVICE_CONTROLLER_CONNECT) => {
+            crate::entry::exit_code(main(Shutdown::default()))
+        },
+        Err(e) => {
+            eprintln!("{}", crate::Error::from(e));
+            1
+        },
+    }
+}
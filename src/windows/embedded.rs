@@ -0,0 +1,76 @@
+use std::path::{Path, PathBuf};
+
+/// The wrapper built into the crate, extracted for each service
+const WRAPPER: &[u8] = include_bytes!("../../executables/sombra-windows-service.exe");
+const WRAPPER_HASH: u64 = fnv1a(WRAPPER);
+
+/// FNV-1a, to tell whether an extracted wrapper is intact
+const fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        i += 1;
+    }
+    hash
+}
+
+/// Directory of the wrapper of a service, `%ProgramData%\sombra\<name>`
+fn directory(name: &str) -> PathBuf {
+    let program_data = std::env::var("ProgramData")
+        .unwrap_or_else(|_| "C:\\ProgramData".to_string());
+    PathBuf::from(program_data).join("sombra").join(name)
+}
+
+/// Extracts the wrapper for a service, unless an intact copy is there
+pub fn extract(name: &str) -> crate::Result<PathBuf> {
+    extract_to(&directory(name))
+}
+
+fn extract_to(directory: &Path) -> crate::Result<PathBuf> {
+    let path = directory.join(super::sombra_imp::WRAPPER_NAME);
+    if std::fs::read(&path).map(|bytes| fnv1a(&bytes)).ok() == Some(WRAPPER_HASH) {
+        return Ok(path);
+    }
+    std::fs::create_dir_all(directory)?;
+    // The copy is renamed into place, so a wrapper running from the old
+    // one never sees a partial file
+    let partial = path.with_extension("exe.partial");
+    std::fs::write(&partial, WRAPPER)?;
+    if fnv1a(&std::fs::read(&partial)?) != WRAPPER_HASH {
+        let _ = std::fs::remove_file(&partial);
+        return Err(crate::Error::new(crate::ErrorKind::Io,
+                                     "Extracted wrapper is corrupted".to_string())
+            .content(path.display().to_string()));
+    }
+    std::fs::rename(&partial, &path)?;
+    Ok(path)
+}
+
+/// Removes the wrapper extracted for a deleted service
+pub fn remove(name: &str) -> crate::Result<()> {
+    let directory = directory(name);
+    if directory.exists() {
+        std::fs::remove_dir_all(directory)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extract_replaces_corrupted_wrapper() {
+        let directory = std::env::temp_dir().join("sombra_embedded_test");
+        let _ = std::fs::remove_dir_all(&directory);
+
+        let path = extract_to(&directory).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), WRAPPER);
+        std::fs::write(&path, b"corrupted").unwrap();
+        assert_eq!(extract_to(&directory).unwrap(), path);
+        assert_eq!(std::fs::read(&path).unwrap(), WRAPPER);
+        let _ = std::fs::remove_dir_all(&directory);
+    }
+}
@@ -1 +1,11 @@
-pub mod sombra_imp;
\ No newline at end of file
+pub mod sombra_imp;
+pub mod task;
+pub mod autostart;
+mod sc;
+pub(crate) mod reg;
+pub(crate) mod powershell;
+mod signature;
+pub(crate) mod dispatcher;
+#[cfg(feature = "embed-wrapper")]
+mod embedded;
+pub(crate) mod wrapper;
@@ -0,0 +1,254 @@
+/// Runs a PowerShell script, for what neither the SCM API nor sc.exe
+/// exposes
+pub fn run(script: &str) -> crate::Result<String> {
+    spawn(script, None)
+}
+
+/// Runs a script reading `input` from [Console]::In, for data that must
+/// stay off the command line
+pub fn run_with_input(script: &str, input: &str) -> crate::Result<String> {
+    spawn(script, Some(input))
+}
+
+fn spawn(script: &str, input: Option<&str>) -> crate::Result<String> {
+    use std::io::Write;
+    use std::process::Stdio;
+    let mut child = std::process::Command::new("powershell.exe")
+        .arg("-NoProfile")
+        .arg("-NonInteractive")
+        .arg("-Command")
+        .arg(script)
+        .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        stdin.write_all(input.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    let stdout = String::from_utf8_lossy(output.stdout.as_slice()).trim().to_string();
+    if output.status.success() {
+        Ok(stdout)
+    } else {
+        let stderr = String::from_utf8_lossy(output.stderr.as_slice()).trim().to_string();
+        Err(crate::Error::new(crate::ErrorKind::WindowsService, stderr)
+            .content("powershell.exe".to_string()))
+    }
+}
+
+/// Quotes a PowerShell string literal
+pub fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// SID of a user or group, `S-1-5-...`
+pub fn sid(account: &str) -> crate::Result<String> {
+    run(&format!("(New-Object System.Security.Principal.NTAccount({})).Translate(\
+                  [System.Security.Principal.SecurityIdentifier]).Value", quote(account)))
+        .map_err(|e| e.content(account.to_string()))
+}
+
+/// C# wrapper of the LSA functions managing the rights of accounts, which
+/// no cmdlet exposes, added to the session once
+const LSA: &str = r#"if (-not ('SombraLsa' -as [type])) { Add-Type -TypeDefinition @'
+using System;
+using System.ComponentModel;
+using System.Runtime.InteropServices;
+using System.Security.Principal;
+
+public static class SombraLsa {
+    [StructLayout(LayoutKind.Sequential, CharSet = CharSet.Unicode)]
+    struct LsaString {
+        public ushort Length;
+        public ushort MaximumLength;
+        public string Buffer;
+    }
+
+    [StructLayout(LayoutKind.Sequential)]
+    struct LsaBuffer {
+        public ushort Length;
+        public ushort MaximumLength;
+        public IntPtr Buffer;
+    }
+
+    [StructLayout(LayoutKind.Sequential)]
+    struct ObjectAttributes {
+        public int Length;
+        public IntPtr RootDirectory;
+        public IntPtr ObjectName;
+        public uint Attributes;
+        public IntPtr SecurityDescriptor;
+        public IntPtr SecurityQualityOfService;
+    }
+
+    [DllImport("advapi32.dll")]
+    static extern uint LsaOpenPolicy(LsaString[] system, ref ObjectAttributes attributes,
+                                     uint access, out IntPtr policy);
+    [DllImport("advapi32.dll")]
+    static extern uint LsaEnumerateAccountRights(IntPtr policy, byte[] sid, out IntPtr rights,
+                                                 out uint count);
+    [DllImport("advapi32.dll")]
+    static extern uint LsaAddAccountRights(IntPtr policy, byte[] sid, LsaString[] rights,
+                                           uint count);
+    [DllImport("advapi32.dll")]
+    static extern uint LsaRemoveAccountRights(IntPtr policy, byte[] sid, bool all,
+                                              LsaString[] rights, uint count);
+    [DllImport("advapi32.dll")]
+    static extern uint LsaFreeMemory(IntPtr buffer);
+    [DllImport("advapi32.dll")]
+    static extern uint LsaClose(IntPtr policy);
+    [DllImport("advapi32.dll")]
+    static extern int LsaNtStatusToWinError(uint status);
+
+    // STATUS_OBJECT_NAME_NOT_FOUND, for an account holding no right
+    const uint NoRights = 0xC0000034;
+    const uint PolicyAllAccess = 0xF0FFF;
+
+    static LsaString[] Unicode(string value) {
+        LsaString unicode = new LsaString();
+        unicode.Buffer = value;
+        unicode.Length = (ushort)(value.Length * 2);
+        unicode.MaximumLength = (ushort)(unicode.Length + 2);
+        return new LsaString[] { unicode };
+    }
+
+    static void Check(uint status) {
+        if (status != 0) {
+            throw new Win32Exception(LsaNtStatusToWinError(status));
+        }
+    }
+
+    static IntPtr Open(string machine) {
+        ObjectAttributes attributes = new ObjectAttributes();
+        IntPtr policy;
+        Check(LsaOpenPolicy(machine == "" ? null : Unicode(machine), ref attributes,
+                            PolicyAllAccess, out policy));
+        return policy;
+    }
+
+    static byte[] Sid(string account) {
+        SecurityIdentifier sid = (SecurityIdentifier)new NTAccount(account)
+            .Translate(typeof(SecurityIdentifier));
+        byte[] binary = new byte[sid.BinaryLength];
+        sid.GetBinaryForm(binary, 0);
+        return binary;
+    }
+
+    static bool Holds(IntPtr policy, byte[] sid, string right) {
+        IntPtr rights;
+        uint count;
+        uint status = LsaEnumerateAccountRights(policy, sid, out rights, out count);
+        if (status == NoRights) {
+            return false;
+        }
+        Check(status);
+        try {
+            int size = Marshal.SizeOf(typeof(LsaBuffer));
+            for (uint i = 0; i < count; i++) {
+                LsaBuffer held = (LsaBuffer)Marshal.PtrToStructure(
+                    new IntPtr(rights.ToInt64() + i * size), typeof(LsaBuffer));
+                if (Marshal.PtrToStringUni(held.Buffer, held.Length / 2) == right) {
+                    return true;
+                }
+            }
+            return false;
+        } finally {
+            LsaFreeMemory(rights);
+        }
+    }
+
+    public static bool Grant(string machine, string account, string right) {
+        IntPtr policy = Open(machine);
+        try {
+            byte[] sid = Sid(account);
+            if (Holds(policy, sid, right)) {
+                return false;
+            }
+            Check(LsaAddAccountRights(policy, sid, Unicode(right), 1));
+            return true;
+        } finally {
+            LsaClose(policy);
+        }
+    }
+
+    public static void Revoke(string machine, string account, string right) {
+        IntPtr policy = Open(machine);
+        try {
+            uint status = LsaRemoveAccountRights(policy, Sid(account), false, Unicode(right), 1);
+            if (status != NoRights) {
+                Check(status);
+            }
+        } finally {
+            LsaClose(policy);
+        }
+    }
+}
+'@ }"#;
+
+/// Script granting `right`, e.g. SeServiceLogonRight, to `account` on
+/// `machine`, `\\HOST`, or the local one. It prints whether the account
+/// lacked the right.
+pub fn grant_right_script(machine: Option<&str>, account: &str, right: &str) -> String {
+    format!("{}\n[SombraLsa]::Grant({}, {}, {})", LSA, quote(machine.unwrap_or("")),
+            quote(account), quote(right))
+}
+
+/// Script revoking `right` from `account` on `machine`, unless a service
+/// other than `service` still runs as the account
+pub fn revoke_right_script(machine: Option<&str>, account: &str, right: &str, service: &str)
+                           -> String {
+    let computer = match machine {
+        Some(machine) => format!(" -ComputerName {}", quote(machine.trim_start_matches('\\'))),
+        None => String::new(),
+    };
+    let sid = |name: &str| format!("(New-Object System.Security.Principal.NTAccount({})).\
+                                    Translate([System.Security.Principal.SecurityIdentifier])\
+                                    .Value", name);
+    format!("{}\n$sid = {}\n\
+             $used = Get-CimInstance -ClassName Win32_Service{} | \
+             Where-Object {{ $_.Name -ne {} -and $_.StartName }} | \
+             Where-Object {{ try {{ {} -eq $sid }} catch {{ $false }} }}\n\
+             if (-not $used) {{ [SombraLsa]::Revoke({}, {}, {}) }}",
+            LSA, sid(&quote(account)), computer, quote(service),
+            sid("($_.StartName -replace '^\\.\\\\', '')"), quote(machine.unwrap_or("")),
+            quote(account), quote(right))
+}
+
+/// Grants `right` to `account`, returning whether it lacked it
+pub fn grant_right(machine: Option<&str>, account: &str, right: &str) -> crate::Result<bool> {
+    run(&format!("$ErrorActionPreference = 'Stop'\n{}",
+                 grant_right_script(machine, account, right)))
+        .map(|granted| granted == "True")
+        .map_err(|e| e.content(account.to_string()))
+}
+
+/// Revokes `right` from `account`, unless another service runs as it
+pub fn revoke_right(machine: Option<&str>, account: &str, right: &str, service: &str)
+                    -> crate::Result<()> {
+    run(&format!("$ErrorActionPreference = 'Stop'\n{}",
+                 revoke_right_script(machine, account, right, service)))
+        .map(|_| ())
+        .map_err(|e| e.content(account.to_string()))
+}
+
+/// Memory, CPU time and handles of a process, from Get-Process
+pub fn usage(pid: u32) -> crate::Result<crate::Usage> {
+    let output = run(&format!(
+        "$p = Get-Process -Id {}; \
+         \"$($p.WorkingSet64) $([long]$p.TotalProcessorTime.TotalMilliseconds) $($p.HandleCount)\"",
+        pid))?;
+    let columns: Vec<u64> = output.split_whitespace()
+        .filter_map(|column| column.parse().ok())
+        .collect();
+    match columns.as_slice() {
+        [rss, cpu_time, handles] => Ok(crate::Usage {
+            pid,
+            rss: *rss,
+            cpu_time: std::time::Duration::from_millis(*cpu_time),
+            handles: *handles as u32,
+        }),
+        _ => Err(crate::Error::new(crate::ErrorKind::Other,
+                                   "Cannot parse process usage".to_string())
+            .content(output)),
+    }
+}
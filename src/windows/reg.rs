@@ -0,0 +1,222 @@
+pub struct Reg {
+    key: String
+}
+
+/// Data of a registry value
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    MultiString(Vec<String>),
+    Dword(u32),
+    Qword(u64),
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "REG_SZ",
+            Value::MultiString(_) => "REG_MULTI_SZ",
+            Value::Dword(_) => "REG_DWORD",
+            Value::Qword(_) => "REG_QWORD",
+        }
+    }
+
+    /// The data as reg.exe takes it after /d, REG_MULTI_SZ items separated
+    /// by a literal \0
+    pub fn data(&self) -> String {
+        match self {
+            Value::String(data) => data.clone(),
+            Value::MultiString(data) => data.join("\\0"),
+            Value::Dword(data) => data.to_string(),
+            Value::Qword(data) => data.to_string(),
+        }
+    }
+}
+
+impl Reg {
+    pub fn service(name: &str) -> Self {
+        Reg {
+            key: format!("HKLM\\SYSTEM\\CurrentControlSet\\Services\\{}", name)
+        }
+    }
+
+    /// Key read by the wrapper service for the options it applies itself
+    pub fn parameters(name: &str) -> Self {
+        Reg {
+            key: format!("HKLM\\SYSTEM\\CurrentControlSet\\Services\\{}\\Parameters", name)
+        }
+    }
+
+    pub fn event_source(name: &str) -> Self {
+        Reg {
+            key: format!("HKLM\\SYSTEM\\CurrentControlSet\\Services\\EventLog\\Application\\{}",
+                         name)
+        }
+    }
+
+    /// Key of the metadata of a scheduled task created by `SombraTask`
+    pub fn task(name: &str) -> Self {
+        Reg {
+            key: format!("HKLM\\SOFTWARE\\Sombra\\Tasks\\{}", name)
+        }
+    }
+
+    /// Key of the secrets of a service, encrypted with DPAPI, kept by
+    /// `SecretStore`
+    pub fn secrets(name: &str) -> Self {
+        Reg {
+            key: format!("HKLM\\SOFTWARE\\Sombra\\Secrets\\{}", name)
+        }
+    }
+
+    /// Run key of the current user, holding the commands started at logon
+    pub fn user_run() -> Self {
+        Reg {
+            key: "HKCU\\Software\\Microsoft\\Windows\\CurrentVersion\\Run".to_string()
+        }
+    }
+
+    /// The same key in the registry of `machine`, `\\HOST`, which reg.exe
+    /// reaches through the Remote Registry service
+    pub fn on(self, machine: Option<&str>) -> Self {
+        match machine {
+            Some(machine) => Reg { key: format!("{}\\{}", machine, self.key) },
+            None => self,
+        }
+    }
+
+    fn run(&self, command: &str, args: &[&str]) -> crate::Result<String> {
+        let output = std::process::Command::new("reg.exe")
+            .arg(command)
+            .arg(&self.key)
+            .args(args)
+            .output()?;
+        let stdout = String::from_utf8_lossy(output.stdout.as_slice()).trim().to_string();
+        if output.status.success() {
+            Ok(stdout)
+        } else {
+            let stderr = String::from_utf8_lossy(output.stderr.as_slice()).trim().to_string();
+            Err(crate::Error::new(crate::ErrorKind::WindowsService, stderr)
+                .content(format!("reg.exe {}", command)))
+        }
+    }
+
+    pub fn set(&self, value: &str, data: &Value) -> crate::Result<()> {
+        self.run("add", &["/v", value, "/t", data.type_name(), "/d", &data.data(), "/f"])?;
+        Ok(())
+    }
+
+    /// Arguments of reg.exe doing what `set` does, for scripts
+    pub fn set_args(&self, value: &str, data: &Value) -> Vec<String> {
+        ["add", &self.key, "/v", value, "/t", data.type_name(), "/d", &data.data(), "/f"].iter()
+            .map(|arg| arg.to_string())
+            .collect()
+    }
+
+    /// Arguments of reg.exe doing what `delete_value` does, for scripts
+    pub fn delete_value_args(&self, value: &str) -> Vec<String> {
+        ["delete", &self.key, "/v", value, "/f"].iter().map(|arg| arg.to_string()).collect()
+    }
+
+    /// Arguments of reg.exe querying a value, failing without it, for
+    /// scripts
+    pub fn query_args(&self, value: &str) -> Vec<String> {
+        ["query", &self.key, "/v", value].iter().map(|arg| arg.to_string()).collect()
+    }
+
+    /// Arguments of reg.exe doing what `delete` does, for scripts
+    pub fn delete_args(&self) -> Vec<String> {
+        vec!["delete".to_string(), self.key.clone(), "/f".to_string()]
+    }
+
+    /// The key and its values laid out as `reg query` prints them
+    pub fn describe(&self, values: &[(&str, Value)]) -> String {
+        let mut description = format!("{}\n", self.key);
+        for (value, data) in values {
+            description.push_str(&format!("    {}    {}    {}\n",
+                                          value, data.type_name(), data.data()));
+        }
+        description
+    }
+
+    pub fn set_string(&self, value: &str, data: &str) -> crate::Result<()> {
+        self.set(value, &Value::String(data.to_string()))
+    }
+
+    /// Reads a value as printed by reg.exe, REG_MULTI_SZ items separated
+    /// by a literal \0
+    pub fn query(&self, value: &str) -> Option<String> {
+        let output = self.run("query", &["/v", value]).ok()?;
+        Reg::parse_values(&output).into_iter()
+            .find(|(name, _)| name == value)
+            .map(|(_, data)| data)
+    }
+
+    /// Reads all the values of the key at once, as `query` prints them.
+    /// Nothing if the key doesn't exist.
+    pub fn values(&self) -> Vec<(String, String)> {
+        match self.run("query", &[]) {
+            Ok(output) => Reg::parse_values(&output),
+            Err(_) => vec![],
+        }
+    }
+
+    /// Values in `reg query` output, whose lines are the key followed by
+    /// its values indented, and the subkeys
+    fn parse_values(output: &str) -> Vec<(String, String)> {
+        output.lines()
+            .filter(|line| line.starts_with(' '))
+            .filter_map(|line| {
+                let mut columns = line.trim().splitn(3, "    ");
+                match (columns.next(), columns.next(), columns.next()) {
+                    (Some(name), Some(_), data) =>
+                        Some((name.to_string(), data.unwrap_or_default().to_string())),
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+
+    pub fn exists(&self) -> bool {
+        self.run("query", &[]).is_ok()
+    }
+
+    /// Deletes a value, if present
+    pub fn delete_value(&self, value: &str) -> crate::Result<()> {
+        if self.run("query", &["/v", value]).is_ok() {
+            self.run("delete", &["/v", value, "/f"])?;
+        }
+        Ok(())
+    }
+
+    /// Copies the values and subkeys to `other`, overwriting the ones it
+    /// has
+    pub fn copy_to(&self, other: &Reg) -> crate::Result<()> {
+        self.run("copy", &[&other.key, "/s", "/f"])?;
+        Ok(())
+    }
+
+    pub fn delete(&self) -> crate::Result<()> {
+        self.run("delete", &["/f"])?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_values() {
+        let output = "HKEY_LOCAL_MACHINE\\SOFTWARE\\tcp_echo\n    \
+                      WorkingDirectory    REG_SZ    C:\\Program Files\\tcp echo\n    \
+                      StopTimeout    REG_DWORD    0x1388\n    \
+                      Empty    REG_SZ\n\n\
+                      HKEY_LOCAL_MACHINE\\SOFTWARE\\tcp_echo\\Sub";
+        assert_eq!(Reg::parse_values(output), vec![
+            ("WorkingDirectory".to_string(), "C:\\Program Files\\tcp echo".to_string()),
+            ("StopTimeout".to_string(), "0x1388".to_string()),
+            ("Empty".to_string(), String::new()),
+        ]);
+    }
+}
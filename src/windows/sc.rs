@@ -0,0 +1,155 @@
+use crate::windows::sombra_imp::SombraWindows;
+use std::ffi::OsString;
+
+pub struct Sc {
+    name: String,
+    machine: Option<String>,
+}
+
+impl Sc {
+    pub fn new(name: &str) -> Self {
+        Sc {
+            name: name.to_string(),
+            machine: None,
+        }
+    }
+
+    /// Talks to the SCM of `machine`, `\\HOST`, instead of the local one
+    pub fn machine(mut self, machine: Option<&str>) -> Self {
+        self.machine = machine.map(str::to_string);
+        self
+    }
+
+    fn run(&self, command: &str, args: &[&str]) -> crate::Result<String> {
+        let output = std::process::Command::new("sc.exe")
+            .args(&self.machine)
+            .arg(command)
+            .arg(&self.name)
+            .args(args)
+            .output()?;
+        let stdout = String::from_utf8_lossy(output.stdout.as_slice()).trim().to_string();
+        if output.status.success() {
+            Ok(stdout)
+        } else {
+            // sc.exe exits with the Win32 error code of the failure
+            let error = crate::Error::new(crate::ErrorKind::WindowsService, stdout)
+                .content(format!("sc.exe {}", command));
+            Err(match output.status.code() {
+                Some(code) => error.with_os_code(code),
+                None => error,
+            })
+        }
+    }
+
+    /// Each option as the `option= value` pair of arguments sc.exe takes
+    pub fn option_args(options: &[(&str, &str)]) -> Vec<String> {
+        let mut args = vec![];
+        for (option, value) in options {
+            args.push(format!("{}=", option));
+            args.push(value.to_string());
+        }
+        args
+    }
+
+    /// Runs `command` passing each option as `option= value`
+    fn run_options(&self, command: &str, options: &[(&str, &str)]) -> crate::Result<()> {
+        let args = Sc::option_args(options);
+        let args: Vec<&str> = args.iter().map(|arg| arg.as_str()).collect();
+        self.run(command, &args)?;
+        Ok(())
+    }
+
+    /// Arguments of sc.exe running `command`, for scripts
+    pub fn args(&self, command: &str, args: &[String]) -> Vec<String> {
+        let mut line: Vec<String> = self.machine.iter().cloned().collect();
+        line.push(command.to_string());
+        line.push(self.name.clone());
+        line.extend(args.iter().cloned());
+        line
+    }
+
+    /// The sc.exe command line running `command`, for descriptions
+    pub fn command_line(&self, command: &str, args: &[String]) -> String {
+        let line: Vec<OsString> = std::iter::once("sc.exe".to_string())
+            .chain(self.args(command, args))
+            .map(OsString::from)
+            .collect();
+        SombraWindows::command_line(&line)
+    }
+
+    /// Creates the service, for the service types the SCM API of the
+    /// crate doesn't take
+    pub fn create(&self, options: &[(&str, &str)]) -> crate::Result<()> {
+        self.run_options("create", options)
+    }
+
+    /// Changes the configuration of the service
+    pub fn config(&self, options: &[(&str, &str)]) -> crate::Result<()> {
+        self.run_options("config", options)
+    }
+
+    pub fn delayed_auto_start(&self) -> crate::Result<()> {
+        self.run("config", &["start=", "delayed-auto"])?;
+        Ok(())
+    }
+
+    pub fn failure(&self, reset_period: u64, actions: &str) -> crate::Result<()> {
+        self.run("failure", &["reset=", &reset_period.to_string(), "actions=", actions])?;
+        Ok(())
+    }
+
+    /// `sc qfailure` output, listing the reset period and failure actions
+    pub fn qfailure(&self) -> crate::Result<String> {
+        self.run("qfailure", &[])
+    }
+
+    pub fn failure_flag(&self, enabled: bool) -> crate::Result<()> {
+        self.run("failureflag", &[if enabled { "1" } else { "0" }])?;
+        Ok(())
+    }
+
+    /// Sets the type of the service SID: `none`, `unrestricted` or
+    /// `restricted`
+    pub fn sid_type(&self, sid_type: &str) -> crate::Result<()> {
+        self.run("sidtype", &[sid_type])?;
+        Ok(())
+    }
+
+    /// `sc qsidtype` output, with the type of the service SID
+    pub fn qsidtype(&self) -> crate::Result<String> {
+        self.run("qsidtype", &[])
+    }
+
+    /// Sets the privileges the token of the service keeps
+    pub fn privileges(&self, privileges: &[String]) -> crate::Result<()> {
+        self.run("privs", &[&privileges.join("/")])?;
+        Ok(())
+    }
+
+    /// `sc qprivs` output, listing the privileges the service keeps
+    pub fn qprivs(&self) -> crate::Result<String> {
+        self.run("qprivs", &[])
+    }
+
+    /// Replaces the start triggers of the service with `triggers`, e.g.
+    /// `start/networkon`, deleting them all when empty
+    pub fn triggers(&self, triggers: &[String]) -> crate::Result<()> {
+        match triggers.is_empty() {
+            true => self.run("triggerinfo", &["delete"])?,
+            false => self.run("triggerinfo", &triggers.iter().map(String::as_str)
+                .collect::<Vec<_>>())?,
+        };
+        Ok(())
+    }
+
+    /// `sc qtriggerinfo` output, listing the triggers of the service
+    pub fn qtriggerinfo(&self) -> crate::Result<String> {
+        self.run("qtriggerinfo", &[])
+    }
+
+    /// Replaces the security descriptor of the service, in SDDL
+    pub fn sdset(&self, sddl: &str) -> crate::Result<()> {
+        self.run("sdset", &[sddl])?;
+        Ok(())
+    }
+}
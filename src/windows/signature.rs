@@ -0,0 +1,38 @@
+use crate::windows::powershell;
+use std::path::Path;
+
+/// Checks the Authenticode signature of `path` is valid and chains to a
+/// trusted root. Get-AuthenticodeSignature verifies it with WinVerifyTrust.
+pub fn verify(path: &Path) -> crate::Result<()> {
+    let literal = path.display().to_string().replace('\'', "''");
+    let status = powershell::run(&format!(
+        "(Get-AuthenticodeSignature -LiteralPath '{}').Status", literal))?;
+    check_status(path, &status)
+}
+
+/// Status names of System.Management.Automation.SignatureStatus
+fn check_status(path: &Path, status: &str) -> crate::Result<()> {
+    let reason = match status {
+        "Valid" => return Ok(()),
+        "NotSigned" => "is not signed",
+        "HashMismatch" => "was changed after it was signed",
+        "NotTrusted" => "is signed by an untrusted publisher",
+        _ => "has an invalid signature",
+    };
+    Err(crate::Error::new(crate::ErrorKind::Other, format!("Executable {} ({})", reason, status))
+        .content(path.display().to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signature_status_checked() {
+        let path = Path::new("C:\\sombra\\app.exe");
+        assert_eq!(check_status(path, "Valid"), Ok(()));
+        assert_eq!(check_status(path, "NotSigned").unwrap_err().to_string(),
+                   "<Other> C:\\sombra\\app.exe: Executable is not signed (NotSigned)");
+        assert!(check_status(path, "UnknownError").is_err());
+    }
+}
@@ -1,155 +1,1928 @@
-use crate::Sombra;
+use crate::{Account, Artifact, ControlClient, Difference, ExitReason, FailureAction, FailureActions,
+            HealthCheck, LogReader, LogRotation, ManagedService, Privileges, Scripts, Sombra,
+            ServiceStatus, SombraConfig, Priority, Probe, ResourceLimits, RestartPolicy, Scope,
+            ServiceStats, StartType, StopSignal, Trigger, Usage};
+use crate::export::PowerShell;
+use crate::support::{self, Feature};
+use crate::windows::autostart::SombraAutostart;
+use crate::windows::sc::Sc;
+use crate::windows::reg::{Reg, Value};
+use crate::windows::powershell;
+use crate::windows::signature;
 use std::ffi::{OsString, OsStr};
+use std::path::PathBuf;
 use windows_service::{
-    service::{ServiceAccess, ServiceState, ServiceErrorControl, ServiceInfo,
-              ServiceStartType, ServiceType},
+    service::{Service, ServiceAccess, ServiceDependency, ServiceExitCode, ServiceState,
+              ServiceErrorControl, ServiceInfo, ServiceStartType, ServiceType},
     service_manager::{ServiceManager, ServiceManagerAccess}
 };
+use std::sync::Mutex;
 use std::time::Duration;
-use std::path::PathBuf;
+
+const ERROR_SERVICE_DOES_NOT_EXIST: i32 = 1060;
+const ERROR_SERVICE_MARKED_FOR_DELETE: i32 = 1072;
+/// Type of the templates of per-user services, which the service types of
+/// the windows-service crate don't have
+const SERVICE_USER_OWN_PROCESS: u32 = 0x50;
 
 pub struct SombraWindows {
-    process_path: PathBuf,
-    process_name: String,
-    process_args: Vec<String>,
+    config: SombraConfig,
+    /// Launcher running the target in the session of the user, with
+    /// `user_mode_fallback` and without administrator rights
+    fallback: Option<SombraAutostart>,
 }
 
-macro_rules! sombra_error {
-    ($kind:ident, $content:expr) => {
-        |e| crate::Error::new(crate::ErrorKind::$kind, e.to_string()).content($content)
-    };
+/// SCM connection shared by the services of the process, as SC_HANDLEs
+/// can be used from any thread
+struct SharedManager(ServiceManager);
+
+unsafe impl Send for SharedManager {}
+unsafe impl Sync for SharedManager {}
+
+/// Connections by machine, `None` for the local one, and CREATE_SERVICE
+/// right, kept until the process exits
+static MANAGERS: Mutex<Vec<(Option<String>, bool, &'static SharedManager)>> =
+    Mutex::new(Vec::new());
+
+/// File name of the wrapper executable
+pub(crate) const WRAPPER_NAME: &str = "sombra-windows-service.exe";
+
+/// Value of the service key marking the services created by sombra
+const MANAGED_MARKER: &str = "SombraManaged";
+/// Value of the service key holding the SHA-256 of the target at install
+const CHECKSUM_VALUE: &str = "SombraTargetSha256";
+/// Value of the service key listing the operators, which the security
+/// descriptor only holds as SIDs
+const OPERATORS_VALUE: &str = "SombraOperators";
+/// Value of the service key naming the account sombra granted the right
+/// to log on as a service, revoked with the service
+const LOGON_RIGHT_VALUE: &str = "SombraLogonRight";
+const LOGON_RIGHT: &str = "SeServiceLogonRight";
+/// Access list the SCM gives a new service: full control to SYSTEM and the
+/// administrators, query rights to the interactive and service users
+const DEFAULT_DACL: &str = "(A;;CCLCSWRPWPDTLOCRRC;;;SY)(A;;CCDCLCSWRPWPDTLOCRSDRCWDWO;;;BA)\
+                            (A;;CCLCSWLOCRRC;;;IU)(A;;CCLCSWLOCRRC;;;SU)";
+const DEFAULT_SACL: &str = "(AU;FA;CCDCLCSWRPWPDTLOCRSDRCWDWO;;;WD)";
+/// Rights of the operators: query, start, stop, pause and interrogate
+const OPERATOR_RIGHTS: &str = "CCLCSWRPWPDTLOCRRC";
+
+/// What `create()` registers, for `render()` and `export()`
+struct Registration {
+    /// sc.exe commands and their arguments after the service name
+    commands: Vec<(&'static str, Vec<String>)>,
+    keys: Vec<(Reg, Vec<(&'static str, Value)>)>,
 }
 
-impl Sombra for SombraWindows {
-    fn build(name: &str, path: &str, args: Vec<String>) -> crate::Result<Self> {
-        let path = dunce::canonicalize(path)
-            .map_err(sombra_error!(Io, path.to_string()))?;
+impl SombraWindows {
+    fn sc_failure_actions(failure_actions: &FailureActions) -> String {
+        let actions: Vec<String> = [failure_actions.first,
+                                    failure_actions.second,
+                                    failure_actions.subsequent].iter()
+            .map(|action| match action {
+                FailureAction::None => "/0".to_string(),
+                FailureAction::Restart(delay) => format!("restart/{}", delay.as_millis()),
+            })
+            .collect();
+        actions.join("/")
+    }
+
+    /// Inverse of `sc_failure_actions`, reading `sc qfailure`. Windows
+    /// repeats the last action, so a missing second action is the last one.
+    fn failure_actions_from_qfailure(output: &str) -> Option<FailureActions> {
+        let mut reset_period = Duration::from_secs(0);
+        let mut actions = vec![];
+        let mut in_actions = false;
+        for line in output.lines() {
+            let line = line.trim();
+            let (key, value) = match line.split_once(':') {
+                Some((key, value)) if !key.contains("--") => (key.trim(), value.trim()),
+                _ => ("", line),
+            };
+            match key {
+                "RESET_PERIOD (in seconds)" => {
+                    reset_period = Duration::from_secs(value.parse().unwrap_or(0));
+                    continue;
+                },
+                "FAILURE_ACTIONS" => in_actions = true,
+                "" => {},
+                _ => in_actions = false,
+            }
+            if !in_actions || value.is_empty() {
+                continue;
+            }
+            // RESTART -- Delay = 60000 milliseconds.
+            let delay = value.split("Delay = ").nth(1)
+                .and_then(|delay| delay.split_whitespace().next()?.parse().ok())
+                .map(Duration::from_millis)
+                .unwrap_or_default();
+            actions.push(if value.starts_with("RESTART") {
+                FailureAction::Restart(delay)
+            } else {
+                FailureAction::None
+            });
+        }
+        let last = *actions.last()?;
+        Some(FailureActions {
+            first: actions[0],
+            second: actions.get(1).copied().unwrap_or(last),
+            subsequent: last,
+            reset_period,
+        })
+    }
+
+    /// Privileges listed by `sc qprivs`, one per line after the colon
+    fn privileges_from_qprivs(output: &str) -> Vec<String> {
+        output.lines()
+            .filter_map(|line| line.rsplit(':').next())
+            .map(str::trim)
+            .filter(|value| value.starts_with("Se"))
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Arguments of `sc triggerinfo` starting the service on its triggers
+    fn sc_triggers(&self) -> Vec<String> {
+        self.config.triggers.iter()
+            .map(|trigger| match trigger {
+                Trigger::NetworkAvailable => "start/networkon".to_string(),
+                Trigger::Device(class) => format!("start/device/{}", class),
+                Trigger::DomainJoin => "start/domainjoin".to_string(),
+            })
+            .collect()
+    }
+
+    fn status_from_state(state: ServiceState, exit_code: &ServiceExitCode) -> ServiceStatus {
+        match state {
+            ServiceState::Stopped if *exit_code != ServiceExitCode::NO_ERROR => {
+                ServiceStatus::Failed
+            },
+            ServiceState::Stopped => ServiceStatus::Stopped,
+            ServiceState::StartPending => ServiceStatus::StartPending,
+            ServiceState::Running => ServiceStatus::Running,
+            ServiceState::StopPending => ServiceStatus::StopPending,
+            ServiceState::PausePending => ServiceStatus::PausePending,
+            ServiceState::Paused => ServiceStatus::Paused,
+            ServiceState::ContinuePending => ServiceStatus::ContinuePending,
+        }
+    }
+
+    /// Triggers starting the service in `sc qtriggerinfo` output, the ones
+    /// stopping it being left out
+    fn triggers_from_qtriggerinfo(output: &str) -> Vec<Trigger> {
+        let mut triggers = vec![];
+        let mut starting = false;
+        for line in output.lines().map(str::trim) {
+            let (kind, value) = match line {
+                "START SERVICE" => {
+                    starting = true;
+                    continue;
+                },
+                "STOP SERVICE" => {
+                    starting = false;
+                    continue;
+                },
+                _ => match line.split_once(':') {
+                    Some((kind, value)) if starting => (kind.trim(), value),
+                    _ => continue,
+                },
+            };
+            match kind {
+                "NETWORK EVENT" if value.contains("[IP ADDRESS AVAILABLE]") =>
+                    triggers.push(Trigger::NetworkAvailable),
+                "DOMAIN JOINED STATUS" if value.contains("[DOMAIN JOINED]") =>
+                    triggers.push(Trigger::DomainJoin),
+                "DEVICE INTERFACE ARRIVAL" => if let Some(class) = value.split_whitespace().next() {
+                    triggers.push(Trigger::Device(class.to_string()));
+                },
+                _ => {},
+            }
+        }
+        triggers
+    }
+
+    /// Security descriptor of the service, in SDDL: the default one, and
+    /// the rights of each operator
+    fn security_descriptor(&self) -> crate::Result<String> {
+        let mut dacl = DEFAULT_DACL.to_string();
+        for operator in &self.config.operators {
+            let sid = match operator.starts_with("S-1-") {
+                true => operator.clone(),
+                false => powershell::sid(operator)?,
+            };
+            dacl.push_str(&format!("(A;;{};;;{})", OPERATOR_RIGHTS, sid));
+        }
+        Ok(format!("D:{}S:{}", dacl, DEFAULT_SACL))
+    }
+
+    /// Account that needs the right to log on as a service, which the SCM
+    /// doesn't grant: a user or a group managed account. The built-in and
+    /// virtual accounts hold it already.
+    fn logon_account(&self) -> Option<String> {
+        if self.config.scope == Scope::User {
+            return None;
+        }
+        match &self.config.account {
+            Account::User { name, .. } =>
+                Some(name.strip_prefix(".\\").unwrap_or(name).to_string()),
+            Account::Managed(name) => Some(name.clone()),
+            _ => None,
+        }
+    }
+
+    /// Grants the right to log on as a service to the account, unless it
+    /// holds it, and revokes it from the account it was granted to before.
+    /// Without it, the first start fails with an opaque logon failure.
+    fn sync_logon_right(&self) -> crate::Result<()> {
+        let service_key = self.service_key();
+        let granted = service_key.query(LOGON_RIGHT_VALUE);
+        let account = self.logon_account();
+        if granted == account {
+            return Ok(());
+        }
+        if let Some(granted) = granted {
+            powershell::revoke_right(self.machine(), &granted, LOGON_RIGHT, &self.config.name)?;
+            service_key.delete_value(LOGON_RIGHT_VALUE)?;
+        }
+        if let Some(account) = account {
+            if powershell::grant_right(self.machine(), &account, LOGON_RIGHT)? {
+                service_key.set_string(LOGON_RIGHT_VALUE, &account)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Type of the service SID for `sc sidtype`. Virtual accounts are the
+    /// unrestricted service SID.
+    fn sid_type(&self) -> &'static str {
+        if self.config.restricted_sid {
+            "restricted"
+        } else if self.config.account == Account::Virtual {
+            "unrestricted"
+        } else {
+            "none"
+        }
+    }
+
+    fn account_credentials(name: &str, account: &Account) -> (Option<OsString>, Option<OsString>) {
+        match account {
+            Account::LocalSystem => (None, None),
+            Account::LocalService => (Some(OsString::from("NT AUTHORITY\\LocalService")), None),
+            Account::NetworkService => (Some(OsString::from("NT AUTHORITY\\NetworkService")),
+                                        None),
+            Account::User { name, password } => {
+                // Accounts without domain belong to the local machine
+                let name = if name.contains('\\') || name.contains('@') {
+                    name.clone()
+                } else {
+                    format!(".\\{}", name)
+                };
+                (Some(OsString::from(name)),
+                 password.as_ref().map(|p| OsString::from(p.expose_secret())))
+            },
+            Account::Virtual => (Some(OsString::from(format!("NT SERVICE\\{}", name))), None),
+            // The domain manages gMSA passwords, the SCM must get none
+            Account::Managed(name) => (Some(OsString::from(name)), None),
+        }
+    }
+
+    fn check(config: &SombraConfig) -> crate::Result<()> {
+        if let Some(description) = support::unsupported(config, &[
+            Feature::UserScope, Feature::ServicePrivileges, Feature::Operators, Feature::Triggers,
+            Feature::Notify, Feature::ResourceLimits, Feature::LogRotation, Feature::HealthCheck,
+            Feature::VirtualAccount,
+        ]) {
+            return Err(crate::Error::new(crate::ErrorKind::Other, description.to_string()));
+        }
+        if config.scope == Scope::User && config.account != Account::LocalSystem {
+            return Err(crate::Error::new(crate::ErrorKind::Other,
+                                         "Per-user services run as the user logging on"
+                                             .to_string())
+                .content(config.name.clone()));
+        }
+        if config.scope == Scope::User && !config.triggers.is_empty() {
+            return Err(crate::Error::new(crate::ErrorKind::Other,
+                                         "Instances of per-user services start at logon"
+                                             .to_string())
+                .content(config.name.clone()));
+        }
+        Ok(())
+    }
+
+    /// Whether `name` is an instance of the per-user template `template`,
+    /// which Windows names after the LUID of the logon session
+    pub(crate) fn is_instance(template: &str, name: &str) -> bool {
+        name.strip_prefix(template)
+            .and_then(|suffix| suffix.strip_prefix('_'))
+            .is_some_and(|luid| !luid.is_empty() && luid.chars().all(|c| c.is_ascii_hexdigit()))
+    }
+
+    /// Instances of a per-user template, one per user logged on
+    fn instances(&self) -> crate::Result<Vec<SombraWindows>> {
+        let names = powershell::run(&format!(
+            "Get-ChildItem HKLM:\\SYSTEM\\CurrentControlSet\\Services | \
+             Where-Object {{ $_.PSChildName -like {} }} | \
+             ForEach-Object {{ $_.PSChildName }}",
+            powershell::quote(&format!("{}_*", self.config.name))))?;
+        let mut names: Vec<&str> = names.lines()
+            .map(str::trim)
+            .filter(|name| SombraWindows::is_instance(&self.config.name, name))
+            .collect();
+        names.sort_unstable();
+        Ok(names.into_iter()
+            .map(|name| SombraWindows {
+                config: SombraConfig {
+                    name: name.to_string(),
+                    scope: Scope::System,
+                    ..self.config.clone()
+                },
+                fallback: None,
+            })
+            .collect())
+    }
+
+    /// The first running instance of a per-user template, whose target
+    /// `pid()` and `stats()` report
+    fn running_instance(&self) -> crate::Result<Option<SombraWindows>> {
+        for instance in self.instances()? {
+            if instance.status()? == ServiceStatus::Running {
+                return Ok(Some(instance));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Options of sc.exe for the command line, start type, display name
+    /// and dependencies
+    fn sc_options(&self) -> crate::Result<Vec<(&'static str, String)>> {
+        let mut command_line = vec![self.wrapper_path()?.into_os_string()];
+        command_line.extend(self.launch_arguments());
+        let start_type = match self.config.start_type {
+            StartType::AutoStart => "auto",
+            StartType::DelayedAutoStart => "delayed-auto",
+            StartType::OnDemand => "demand",
+            StartType::Disabled => "disabled",
+        };
+        // "/" is how sc.exe clears the dependencies
+        let dependencies = if self.config.dependencies.is_empty() {
+            "/".to_string()
+        } else {
+            self.config.dependencies.join("/")
+        };
+        Ok(vec![
+            ("binPath", SombraWindows::command_line(&command_line)),
+            ("start", start_type.to_string()),
+            ("DisplayName", self.display_name().to_string()),
+            ("depend", dependencies),
+        ])
+    }
+
+    /// Whether the SCM refuses to create services for the current user
+    pub(crate) fn lacks_rights() -> bool {
+        matches!(SombraWindows::service_manager(None, true),
+                 Err(e) if e.kind() == crate::ErrorKind::AccessDenied)
+    }
+
+    /// Connection to the SCM of `machine`, or of the local one, opened
+    /// once per process and reused by every operation, so batches over
+    /// many services don't reconnect each time. Creating services uses a
+    /// second connection with the CREATE_SERVICE right, which only
+    /// administrators have.
+    fn service_manager(machine: Option<&str>, create: bool)
+                       -> crate::Result<&'static ServiceManager> {
+        let mut managers = MANAGERS.lock().unwrap();
+        let shared = managers.iter()
+            .find(|(m, c, _)| m.as_deref() == machine && *c == create)
+            .map(|(_, _, manager)| *manager);
+        if let Some(manager) = shared {
+            return Ok(&manager.0);
+        }
+        let access = if create {
+            ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE
+        } else {
+            ServiceManagerAccess::CONNECT
+        };
+        // A failed connection isn't kept, the next operation retries
+        let connected = match machine {
+            Some(machine) => ServiceManager::remote_computer(machine, None::<&str>, access)?,
+            None => ServiceManager::local_computer(None::<&str>, access)?,
+        };
+        let manager: &'static SharedManager = Box::leak(Box::new(SharedManager(connected)));
+        managers.push((machine.map(str::to_string), create, manager));
+        Ok(&manager.0)
+    }
+
+    fn machine(&self) -> Option<&str> {
+        self.config.machine.as_deref()
+    }
+
+    fn sc(&self) -> Sc {
+        Sc::new(&self.config.name).machine(self.machine())
+    }
+
+    fn service_key(&self) -> Reg {
+        Reg::service(&self.config.name).on(self.machine())
+    }
+
+    fn parameters(&self) -> Reg {
+        Reg::parameters(&self.config.name).on(self.machine())
+    }
+
+    fn event_source(&self) -> Reg {
+        Reg::event_source(&self.config.name).on(self.machine())
+    }
+
+    /// Creates the service, waiting while a deleted service of the same
+    /// name is still marked for deletion: the SCM only removes it once the
+    /// last handle to it closes
+    fn create_service(&self, service_manager: &ServiceManager, service_info: &ServiceInfo)
+                      -> crate::Result<Service> {
+        let timeout = Duration::from_secs(10);
+        let begin = std::time::Instant::now();
+        let mut delay = Duration::from_millis(50);
+        loop {
+            let error = match service_manager.create_service(service_info,
+                                                             ServiceAccess::CHANGE_CONFIG) {
+                Ok(service) => return Ok(service),
+                Err(e) => crate::Error::from(e),
+            };
+            if error.kind() != crate::ErrorKind::MarkedForDeletion {
+                return Err(error);
+            }
+            if begin.elapsed() > timeout {
+                return Err(crate::Error::new(
+                    crate::ErrorKind::MarkedForDeletion,
+                    "Service is still marked for deletion. Close the programs holding it \
+                     open, such as services.msc or Event Viewer, or reboot".to_string())
+                    .content(self.config.name.clone())
+                    .with_os_code(ERROR_SERVICE_MARKED_FOR_DELETE)
+                    .with_source(error));
+            }
+            std::thread::sleep(delay);
+            delay = (delay * 2).min(Duration::from_secs(1));
+        }
+    }
+
+    /// Polls the service until it stopped, for the stop timeout of the
+    /// target plus the time the wrapper needs to kill it and exit
+    fn wait_stopped(&self, service: &Service) -> crate::Result<()> {
+        // The SCM waits 20 seconds for a service to stop by default
+        let timeout = self.config.stop_timeout.unwrap_or_else(|| Duration::from_secs(20)) +
+            Duration::from_secs(10);
+        let begin = std::time::Instant::now();
+        let mut delay = Duration::from_millis(10);
+        while service.query_status()?.current_state != ServiceState::Stopped {
+            if begin.elapsed() > timeout {
+                return Err(crate::Error::new(crate::ErrorKind::Timeout,
+                                             "Service did not stop in time".to_string())
+                    .content(self.config.name.clone()));
+            }
+            std::thread::sleep(delay);
+            delay = (delay * 2).min(Duration::from_millis(500));
+        }
+        Ok(())
+    }
+
+    fn wrapper_path(&self) -> crate::Result<PathBuf> {
+        #[cfg(feature = "embed-wrapper")]
+        {
+            if self.config.wrapper_path.is_none() {
+                return crate::windows::embedded::extract(&self.config.name);
+            }
+        }
+        if let (Some(path), Some(_)) = (&self.config.wrapper_path, self.machine()) {
+            // Registered as the other machine has it
+            let reached = self.config.local_path(path);
+            if !reached.is_file() {
+                return Err(crate::Error::new(crate::ErrorKind::Io,
+                                             "Wrapper executable not found".to_string())
+                    .content(reached.display().to_string()));
+            }
+            return Ok(path.clone());
+        }
+        let candidates = match &self.config.wrapper_path {
+            Some(path) => vec![path.clone()],
+            None => vec![std::env::current_exe()?.with_file_name(WRAPPER_NAME),
+                         PathBuf::from("executables").join(WRAPPER_NAME)],
+        };
+        match candidates.iter().find(|path| path.is_file()) {
+            Some(path) => self.config.canonicalize(path),
+            None => Err(crate::Error::new(crate::ErrorKind::Io,
+                                          "Wrapper executable not found".to_string())
+                .content(candidates.iter()
+                    .map(|path| path.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", "))),
+        }
+    }
+
+    /// With `verify_signature`, checks the target and the wrapper running
+    /// it are signed by a trusted publisher
+    fn verify_signatures(&self) -> crate::Result<()> {
+        if self.config.verify_signature {
+            signature::verify(&self.config.local_path(&self.config.path))?;
+            signature::verify(&self.config.local_path(&self.wrapper_path()?))?;
+        }
+        Ok(())
+    }
+
+    /// The target and its arguments are part of the wrapper command line,
+    /// so a start by the SCM itself (e.g. at boot) launches the right
+    /// child. start() still sends them as start parameters.
+    fn launch_arguments(&self) -> Vec<OsString> {
+        let mut launch_arguments = vec![OsString::from(&self.config.path)];
+        launch_arguments.extend(self.config.args.iter().cloned());
+        launch_arguments
+    }
+
+    fn description(&self) -> String {
+        match &self.config.description {
+            Some(description) => description.clone(),
+            None => format!("Sombra Service Wrapper on {}", self.config.name),
+        }
+    }
+
+    fn display_name(&self) -> &str {
+        self.config.display_name.as_ref().unwrap_or(&self.config.name)
+    }
+
+    /// Quotes arguments the way CommandLineToArgvW splits them
+    pub(crate) fn command_line(args: &[OsString]) -> String {
+        let quoted: Vec<String> = args.iter()
+            .map(|arg| {
+                let arg = arg.to_string_lossy();
+                if !arg.is_empty() && !arg.contains([' ', '\t', '"']) {
+                    return arg.to_string();
+                }
+                let mut quoted = "\"".to_string();
+                let mut backslashes = 0;
+                for c in arg.chars() {
+                    match c {
+                        '\\' => backslashes += 1,
+                        '"' => {
+                            quoted.push_str(&"\\".repeat(backslashes * 2 + 1));
+                            backslashes = 0;
+                        },
+                        _ => {
+                            quoted.push_str(&"\\".repeat(backslashes));
+                            backslashes = 0;
+                        },
+                    }
+                    if c != '\\' {
+                        quoted.push(c);
+                    }
+                }
+                quoted.push_str(&"\\".repeat(backslashes * 2));
+                quoted.push('"');
+                quoted
+            })
+            .collect();
+        quoted.join(" ")
+    }
+
+    /// Splits a command line the way CommandLineToArgvW does
+    pub(crate) fn split_command_line(command_line: &str) -> Vec<String> {
+        let mut args = vec![];
+        let mut arg = String::new();
+        let mut in_arg = false;
+        let mut quoted = false;
+        let mut backslashes = 0;
+        for c in command_line.chars() {
+            match c {
+                '\\' => {
+                    backslashes += 1;
+                    in_arg = true;
+                    continue;
+                },
+                '"' => {
+                    arg.push_str(&"\\".repeat(backslashes / 2));
+                    if backslashes % 2 == 1 {
+                        arg.push('"');
+                    } else {
+                        quoted = !quoted;
+                    }
+                    in_arg = true;
+                },
+                ' ' | '\t' if !quoted => {
+                    arg.push_str(&"\\".repeat(backslashes));
+                    if in_arg {
+                        args.push(std::mem::take(&mut arg));
+                    }
+                    in_arg = false;
+                },
+                _ => {
+                    arg.push_str(&"\\".repeat(backslashes));
+                    arg.push(c);
+                    in_arg = true;
+                },
+            }
+            backslashes = 0;
+        }
+        arg.push_str(&"\\".repeat(backslashes));
+        if in_arg {
+            args.push(arg);
+        }
+        args
+    }
+
+    fn account_from_name(service: &str, name: Option<&str>) -> Account {
+        match name {
+            None => Account::LocalSystem,
+            Some(name) => match name.to_lowercase().as_str() {
+                "localsystem" | ".\\localsystem" => Account::LocalSystem,
+                "nt authority\\localservice" => Account::LocalService,
+                "nt authority\\networkservice" => Account::NetworkService,
+                lower if lower == format!("nt service\\{}", service.to_lowercase()) =>
+                    Account::Virtual,
+                _ if name.ends_with('$') => Account::Managed(name.to_string()),
+                _ => Account::User {
+                    name: name.trim_start_matches(".\\").to_string(),
+                    password: None,
+                },
+            },
+        }
+    }
+
+    /// Opens a service installed elsewhere and reads back its path,
+    /// arguments, start type, display name, description, dependencies,
+    /// account and failure actions, plus the environment and wrapper
+    /// options when it runs under the Sombra wrapper.
+    pub fn from_existing(name: &str) -> crate::Result<Self> {
+        SombraWindows::existing(None, name)
+    }
+
+    fn existing(machine: Option<&str>, name: &str) -> crate::Result<Self> {
+        let service_manager = SombraWindows::service_manager(machine, false)?;
+        let service = service_manager.open_service(name, ServiceAccess::QUERY_CONFIG)?;
+        let service_config = service.query_config()?;
+
+        // The SCM returns the whole command line as the binary path
+        let mut command_line = SombraWindows::split_command_line(
+            &service_config.executable_path.to_string_lossy());
+        if command_line.is_empty() {
+            return Err(crate::Error::new(crate::ErrorKind::WindowsService,
+                                         "Service has no binary path".to_string())
+                .content(name.to_string()));
+        }
+        // Services created by sombra are marked, whatever the file name
+        // of their wrapper
+        let service_key = Reg::service(name).on(machine);
+        let wrapped = command_line.len() > 1 &&
+            (service_key.query(MANAGED_MARKER).is_some() ||
+             PathBuf::from(&command_line[0]).file_name()
+                 .map(|n| n.to_string_lossy().to_lowercase()).as_deref() == Some(WRAPPER_NAME));
+        let wrapper_path = if wrapped {
+            Some(PathBuf::from(command_line.remove(0)))
+        } else {
+            None
+        };
+
+        let mut config = SombraConfig::new(name, command_line.remove(0));
+        config.args = command_line.into_iter().map(OsString::from).collect();
+        config.display_name = Some(service_config.display_name.to_string_lossy().to_string());
+        config.wrapper_path = wrapper_path;
+        if service_key.query("Type")
+            .and_then(|t| u32::from_str_radix(t.trim_start_matches("0x"), 16).ok())
+            == Some(SERVICE_USER_OWN_PROCESS) {
+            config.scope = Scope::User;
+        }
+        config.start_type = match service_config.start_type {
+            ServiceStartType::AutoStart
+                if service_key.query("DelayedAutostart").as_deref() == Some("0x1") =>
+                StartType::DelayedAutoStart,
+            ServiceStartType::AutoStart => StartType::AutoStart,
+            ServiceStartType::OnDemand => StartType::OnDemand,
+            ServiceStartType::Disabled => StartType::Disabled,
+        };
+        config.dependencies = service_config.dependencies.iter()
+            .map(|d| match d {
+                ServiceDependency::Service(name) => name.to_string_lossy().to_string(),
+                ServiceDependency::Group(name) => format!("+{}", name.to_string_lossy()),
+            })
+            .collect();
+        config.account = SombraWindows::account_from_name(
+            name, service_config.account_name.as_ref().and_then(|a| a.to_str()));
+        // configure() writes a default description when there is none
+        config.description = service_key.query("Description")
+            .filter(|d| d != &format!("Sombra Service Wrapper on {}", name));
+        config.failure_actions = Sc::new(name).machine(machine).qfailure().ok()
+            .and_then(|output| SombraWindows::failure_actions_from_qfailure(&output));
+        let sc = Sc::new(name).machine(machine);
+        config.restricted_sid = sc.qsidtype()
+            .is_ok_and(|output| output.lines().any(|line| line.trim_end().ends_with(" RESTRICTED")));
+        config.service_privileges = sc.qprivs()
+            .map(|output| SombraWindows::privileges_from_qprivs(&output))
+            .unwrap_or_default();
+        config.triggers = sc.qtriggerinfo()
+            .map(|output| SombraWindows::triggers_from_qtriggerinfo(&output))
+            .unwrap_or_default();
+
+        config.operators = service_key.query(OPERATORS_VALUE)
+            .map(|operators| operators.split("\\0").map(str::to_string).collect())
+            .unwrap_or_default();
+        if let Some(env) = service_key.query("Environment") {
+            config.env = env.split("\\0")
+                .filter_map(|assignment| {
+                    let mut parts = assignment.splitn(2, '=');
+                    Some((parts.next()?.to_string(), parts.next()?.to_string()))
+                })
+                .collect();
+        }
+        if wrapped {
+            SombraWindows::read_parameters(&mut config,
+                                           &Reg::parameters(name).on(machine).values());
+        }
+        config.machine = machine.map(str::to_string);
+
+        SombraWindows::from_config(config)
+    }
+
+    /// The settings `from_existing` reads back, the others left at their
+    /// defaults
+    fn comparable(config: &SombraConfig) -> SombraConfig {
+        SombraConfig {
+            path: config.path.clone(),
+            args: config.args.clone(),
+            display_name: Some(config.display_name.clone()
+                .unwrap_or_else(|| config.name.clone())),
+            description: config.description.clone(),
+            start_type: config.start_type,
+            scope: config.scope,
+            account: config.account.clone(),
+            dependencies: config.dependencies.clone(),
+            failure_actions: config.failure_actions,
+            env: config.env.clone(),
+            working_directory: config.working_directory.clone(),
+            stdout_log: config.stdout_log.clone(),
+            stderr_log: config.stderr_log.clone(),
+            event_log: config.event_log,
+            stop_signal: config.stop_signal,
+            stop_timeout: config.stop_timeout,
+            kill_process_tree: config.kill_process_tree,
+            restricted_sid: config.restricted_sid,
+            service_privileges: config.service_privileges.clone(),
+            operators: config.operators.clone(),
+            ..SombraConfig::new(&config.name, "")
+        }
+    }
+
+    /// Values of the service key besides the ones the SCM keeps
+    fn service_values(&self) -> Vec<(&'static str, Value)> {
+        let mut values = vec![(MANAGED_MARKER, Value::Dword(1))];
+        let target = self.config.local_path(&self.config.path);
+        if let Ok(checksum) = crate::digest::sha256_file(&target) {
+            values.push((CHECKSUM_VALUE, Value::String(checksum)));
+        }
+        if !self.config.env.is_empty() {
+            // The SCM applies this value to the wrapper process, and the
+            // wrapped process inherits it
+            let env: Vec<String> = self.config.env.iter()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect();
+            values.push(("Environment", Value::MultiString(env)));
+        }
+        if !self.config.operators.is_empty() {
+            values.push((OPERATORS_VALUE, Value::MultiString(self.config.operators.clone())));
+        }
+        values
+    }
+
+    /// A duration in milliseconds, as a QWORD since a DWORD wraps after 49
+    /// days
+    fn millis(duration: Duration) -> Value {
+        Value::Qword(duration.as_millis().min(u64::MAX as u128) as u64)
+    }
+
+    /// Options the wrapper applies itself, read from the Parameters key
+    fn parameter_values(&self) -> Vec<(&'static str, Value)> {
+        let mut values = vec![];
+        if let Some(dir) = &self.config.working_directory {
+            values.push(("WorkingDirectory", Value::String(dir.to_string_lossy().to_string())));
+        }
+        if let Some(log) = &self.config.stdout_log {
+            values.push(("StdoutLog", Value::String(log.to_string_lossy().to_string())));
+        }
+        if let Some(log) = &self.config.stderr_log {
+            values.push(("StderrLog", Value::String(log.to_string_lossy().to_string())));
+        }
+        if let Some(rotation) = &self.config.log_rotation {
+            if let Some(max_size) = rotation.max_size {
+                values.push(("LogMaxSize", Value::Qword(max_size)));
+            }
+            if let Some(max_age) = rotation.max_age {
+                values.push(("LogMaxAge", Value::Qword(max_age.as_secs())));
+            }
+            values.push(("LogKeep", Value::Dword(rotation.keep)));
+        }
+
+        // The wrapper sends the console event to the process group of the
+        // target, and terminates it once the timeout expires
+        values.push(("StopSignal", Value::String(match self.config.stop_signal {
+            StopSignal::Terminate => "CtrlBreak",
+            StopSignal::Interrupt => "CtrlC",
+        }.to_string())));
+        if let Some(timeout) = self.config.stop_timeout {
+            values.push(("StopTimeout", SombraWindows::millis(timeout)));
+        }
+        // The wrapper puts the target in a job object killed on close, so
+        // its children die with it
+        values.push(("KillProcessTree", Value::Dword(self.config.kill_process_tree as u32)));
+        if let Some(priority) = self.config.priority {
+            // Applied by the wrapper right after spawning the target
+            values.push(("PriorityClass", Value::String(match priority {
+                Priority::Idle => "Idle",
+                Priority::BelowNormal => "BelowNormal",
+                Priority::Normal => "Normal",
+                Priority::AboveNormal => "AboveNormal",
+                Priority::High => "High",
+            }.to_string())));
+        }
+        if let Some(mask) = self.config.cpu_affinity {
+            values.push(("CpuAffinity", Value::Qword(mask)));
+        }
+        if let Some(limits) = &self.config.resource_limits {
+            // Applied as job memory and CPU rate limits, the quota being
+            // scaled to the CPU count by the wrapper
+            if let Some(memory_max) = limits.memory_max {
+                values.push(("MemoryMax", Value::Qword(memory_max)));
+            }
+            if let Some(cpu_quota) = limits.cpu_quota {
+                values.push(("CpuQuota", Value::Dword(cpu_quota)));
+            }
+        }
+
+        // The wrapper reports START_PENDING with increasing checkpoints
+        // until the target is running, or ready when it notifies
+        values.push(("Notify", Value::Dword(self.config.notify as u32)));
+        if let Some(timeout) = self.config.start_timeout {
+            values.push(("StartTimeout", SombraWindows::millis(timeout)));
+        }
+        if let Some(interval) = self.config.watchdog {
+            // Heartbeats arrive on the SOMBRA_NOTIFY_PIPE pipe too, and the
+            // wrapper passes the interval in WATCHDOG_USEC
+            values.push(("WatchdogInterval", SombraWindows::millis(interval)));
+        }
+
+        if let Some(policy) = &self.config.restart_policy {
+            // The wrapper restarts the target itself, and stops with an
+            // error once it gives up, which status() reports as Failed
+            values.push(("RestartMax", Value::Dword(policy.max_restarts)));
+            values.push(("RestartWindow", SombraWindows::millis(policy.window)));
+            values.push(("RestartDelay", SombraWindows::millis(policy.initial_delay)));
+            values.push(("RestartMaxDelay", SombraWindows::millis(policy.max_delay)));
+        }
+
+        if let Some(check) = &self.config.health_check {
+            let (kind, target, args) = match &check.probe {
+                Probe::Tcp(address) => ("Tcp", address.clone(), vec![]),
+                Probe::Http(url) => ("Http", url.clone(), vec![]),
+                Probe::Exec { command, args } => ("Exec", command.clone(), args.clone()),
+                Probe::File(path) => ("File", path.to_string_lossy().to_string(), vec![]),
+            };
+            values.push(("HealthProbe", Value::String(kind.to_string())));
+            values.push(("HealthTarget", Value::String(target)));
+            if !args.is_empty() {
+                values.push(("HealthArgs", Value::MultiString(args)));
+            }
+            values.push(("HealthInterval", SombraWindows::millis(check.interval)));
+            values.push(("HealthTimeout", SombraWindows::millis(check.timeout)));
+            values.push(("HealthThreshold", Value::Dword(check.failure_threshold)));
+        }
+
+        if let Some(port) = self.config.metrics_port {
+            values.push(("MetricsPort", Value::Dword(port as u32)));
+        }
+        if self.config.event_log {
+            values.push(("EventLog", Value::Dword(1)));
+        }
+        values
+    }
+
+    /// Inverse of `parameter_values`, from the values as `Reg::values`
+    /// reads them. The wrapper runs the target with the options read here.
+    pub(crate) fn read_parameters(config: &mut SombraConfig, values: &[(String, String)]) {
+        let value = |name: &str| values.iter()
+            .find(|(value, _)| value == name)
+            .map(|(_, data)| data.as_str());
+        let number = |name: &str| value(name)
+            .and_then(|data| u64::from_str_radix(data.trim_start_matches("0x"), 16).ok());
+        config.working_directory = value("WorkingDirectory").map(PathBuf::from);
+        config.stdout_log = value("StdoutLog").map(PathBuf::from);
+        config.stderr_log = value("StderrLog").map(PathBuf::from);
+        if let Some(keep) = number("LogKeep") {
+            config.log_rotation = Some(LogRotation {
+                max_size: number("LogMaxSize"),
+                max_age: number("LogMaxAge").map(Duration::from_secs),
+                keep: keep as u32,
+            });
+        }
+        config.priority = match value("PriorityClass") {
+            Some("Idle") => Some(Priority::Idle),
+            Some("BelowNormal") => Some(Priority::BelowNormal),
+            Some("Normal") => Some(Priority::Normal),
+            Some("AboveNormal") => Some(Priority::AboveNormal),
+            Some("High") => Some(Priority::High),
+            _ => None,
+        };
+        config.cpu_affinity = number("CpuAffinity");
+        let memory_max = number("MemoryMax");
+        let cpu_quota = number("CpuQuota").map(|quota| quota as u32);
+        if memory_max.is_some() || cpu_quota.is_some() {
+            config.resource_limits = Some(ResourceLimits { memory_max, cpu_quota });
+        }
+        if let Some(max_restarts) = number("RestartMax") {
+            let millis = |name: &str| Duration::from_millis(number(name).unwrap_or_default());
+            config.restart_policy = Some(RestartPolicy {
+                max_restarts: max_restarts as u32,
+                window: millis("RestartWindow"),
+                initial_delay: millis("RestartDelay"),
+                max_delay: millis("RestartMaxDelay"),
+            });
+        }
+        config.notify = number("Notify") == Some(1);
+        config.start_timeout = number("StartTimeout").map(Duration::from_millis);
+        config.watchdog = number("WatchdogInterval").map(Duration::from_millis);
+        let health_check = value("HealthProbe").zip(value("HealthTarget"));
+        config.health_check = health_check.and_then(|(kind, target)| {
+            let probe = match kind {
+                "Tcp" => Probe::Tcp(target.to_string()),
+                "Http" => Probe::Http(target.to_string()),
+                "Exec" => Probe::Exec {
+                    command: target.to_string(),
+                    // reg query prints the strings of a REG_MULTI_SZ joined by \0
+                    args: value("HealthArgs").filter(|args| !args.is_empty())
+                        .map(|args| args.split("\\0").map(str::to_string).collect())
+                        .unwrap_or_default(),
+                },
+                "File" => Probe::File(PathBuf::from(target)),
+                _ => return None,
+            };
+            let mut check = HealthCheck::new(probe);
+            if let Some(interval) = number("HealthInterval") {
+                check.interval = Duration::from_millis(interval);
+            }
+            if let Some(timeout) = number("HealthTimeout") {
+                check.timeout = Duration::from_millis(timeout);
+            }
+            if let Some(threshold) = number("HealthThreshold") {
+                check.failure_threshold = threshold as u32;
+            }
+            Some(check)
+        });
+        config.metrics_port = number("MetricsPort").map(|port| port as u16);
+        config.event_log = number("EventLog") == Some(1);
+        if value("StopSignal") == Some("CtrlC") {
+            config.stop_signal = StopSignal::Interrupt;
+        }
+        config.kill_process_tree = number("KillProcessTree") != Some(0);
+        config.stop_timeout = number("StopTimeout").map(Duration::from_millis);
+    }
+
+    /// EventCreate.exe has a message table that prints the first insertion
+    /// string, so wrapper messages render in Event Viewer
+    fn event_source_values() -> Vec<(&'static str, Value)> {
+        vec![
+            ("EventMessageFile",
+             Value::String("%SystemRoot%\\System32\\EventCreate.exe".to_string())),
+            ("TypesSupported", Value::Dword(7)),
+        ]
+    }
+
+    /// The sc.exe commands and registry values creating the service, with
+    /// a placeholder for the password of the account
+    fn registration(&self) -> crate::Result<Registration> {
+        SombraWindows::check(&self.config)?;
+        let mut options = self.sc_options()?;
+        match self.config.scope {
+            Scope::System => {
+                let (account_name, account_password) =
+                    SombraWindows::account_credentials(&self.config.name, &self.config.account);
+                let account_name = account_name.unwrap_or_else(|| OsString::from("LocalSystem"));
+                options.push(("obj", account_name.to_string_lossy().to_string()));
+                if account_password.is_some() || self.config.password_secret.is_some() {
+                    options.push(("password", "<password>".to_string()));
+                }
+            },
+            Scope::User => options.push(("type", "userown".to_string())),
+        }
+        let options: Vec<(&str, &str)> = options.iter()
+            .map(|(option, value)| (*option, value.as_str()))
+            .collect();
+
+        let mut commands = vec![
+            ("create", Sc::option_args(&options)),
+            ("description", vec![self.description()]),
+        ];
+        if let Some(failure_actions) = &self.config.failure_actions {
+            commands.push(("failure", Sc::option_args(&[
+                ("reset", &failure_actions.reset_period.as_secs().to_string()),
+                ("actions", &SombraWindows::sc_failure_actions(failure_actions)),
+            ])));
+            commands.push(("failureflag", vec!["1".to_string()]));
+        }
+        if self.sid_type() != "none" {
+            commands.push(("sidtype", vec![self.sid_type().to_string()]));
+        }
+        if !self.config.service_privileges.is_empty() {
+            commands.push(("privs", vec![self.config.service_privileges.join("/")]));
+        }
+        if !self.config.operators.is_empty() {
+            commands.push(("sdset", vec![self.security_descriptor()?]));
+        }
+        if !self.config.triggers.is_empty() {
+            commands.push(("triggerinfo", self.sc_triggers()));
+        }
+        let mut keys = vec![
+            (self.service_key(), self.service_values()),
+            (self.parameters(), self.parameter_values()),
+        ];
+        if self.config.event_log {
+            keys.push((self.event_source(), SombraWindows::event_source_values()));
+        }
+        Ok(Registration { commands, keys })
+    }
+
+    /// Applies the settings the SCM doesn't take at creation, clearing
+    /// the ones no longer configured
+    fn configure(&self, service: &Service) -> crate::Result<()> {
+        service.set_description(self.description())?;
+
+        let service_key = self.service_key();
+        // The security descriptor is left alone unless sombra granted rights
+        let had_operators = service_key.query(OPERATORS_VALUE).is_some();
+        let values = self.service_values();
+        for optional in [CHECKSUM_VALUE, "Environment", OPERATORS_VALUE] {
+            if !values.iter().any(|(value, _)| *value == optional) {
+                service_key.delete_value(optional)?;
+            }
+        }
+        for (value, data) in &values {
+            service_key.set(value, data)?;
+        }
+
+        let parameters = self.parameters();
+        if parameters.exists() {
+            parameters.delete()?;
+        }
+        for (value, data) in &self.parameter_values() {
+            parameters.set(value, data)?;
+        }
+
+        let source = self.event_source();
+        if self.config.event_log {
+            for (value, data) in &SombraWindows::event_source_values() {
+                source.set(value, data)?;
+            }
+        } else if source.exists() {
+            source.delete()?;
+        }
+
+        let sc = self.sc();
+        match &self.config.failure_actions {
+            Some(failure_actions) => {
+                sc.failure(failure_actions.reset_period.as_secs(),
+                           &SombraWindows::sc_failure_actions(failure_actions))?;
+                // The wrapper reports a crashed child as a stop with an
+                // error code, which only counts as a failure with this flag
+                sc.failure_flag(true)?;
+            },
+            None => {
+                sc.failure(0, "")?;
+                sc.failure_flag(false)?;
+            },
+        }
+        if had_operators || !self.config.operators.is_empty() {
+            sc.sdset(&self.security_descriptor()?)?;
+        }
+        self.sync_logon_right()?;
+        sc.triggers(&self.sc_triggers())?;
+        sc.sid_type(self.sid_type())?;
+        if self.config.service_privileges.is_empty() {
+            // Without the value, the token keeps all the privileges of the
+            // account
+            service_key.delete_value("RequiredPrivileges")?;
+        } else {
+            sc.privileges(&self.config.service_privileges)?;
+        }
+
+        Ok(())
+    }
+
+    /// Suspends the wrapped process. The wrapper service handles
+    /// SERVICE_CONTROL_PAUSE by suspending its child until resumed.
+    pub fn pause(&self) -> crate::Result<()> {
+        let service_manager = SombraWindows::service_manager(self.machine(), false)?;
+        let service = service_manager.open_service(&self.config.name,
+                                                   ServiceAccess::PAUSE_CONTINUE)?;
+        service.pause()?;
 
+        Ok(())
+    }
+
+    /// Resumes a process suspended by `pause()` (SERVICE_CONTROL_CONTINUE).
+    pub fn resume(&self) -> crate::Result<()> {
+        let service_manager = SombraWindows::service_manager(self.machine(), false)?;
+        let service = service_manager.open_service(&self.config.name,
+                                                   ServiceAccess::PAUSE_CONTINUE)?;
+        service.resume()?;
+
+        Ok(())
+    }
+}
+
+impl Sombra for SombraWindows {
+    fn from_config(config: SombraConfig) -> crate::Result<Self> {
+        // A service installed under the launcher stays there once the
+        // rights are granted, until it is deleted
+        let fallback = if config.user_mode_fallback {
+            let autostart = SombraAutostart::from_config(config.clone())?;
+            if autostart.exists()? || SombraWindows::lacks_rights() {
+                Some(autostart)
+            } else {
+                None
+            }
+        } else {
+            None
+        };
         Ok(SombraWindows {
-            process_path: path,
-            process_name: name.to_string(),
-            process_args: args,
+            config,
+            fallback,
         })
     }
 
     fn create(&self) -> crate::Result<()> {
-        let manager_access = ServiceManagerAccess::CONNECT |
-            ServiceManagerAccess::CREATE_SERVICE;
-        let service_manager = ServiceManager::local_computer(None::<&str>,
-                                                             manager_access)?;
-        if std::env::var("SOMBRA_WINDOWS_SERVICE_PATH").is_err() {
-            std::env::set_var("SOMBRA_WINDOWS_SERVICE_PATH",
-                              "executables/sombra-windows-service.exe");
-        }
-        let sombra_win_service = std::env::var("SOMBRA_WINDOWS_SERVICE_PATH")?;
-        let service_binary_path = dunce::canonicalize(&sombra_win_service)
-            .map_err(sombra_error!(Io, sombra_win_service.clone()))?;
+        if let Some(fallback) = &self.fallback {
+            return fallback.create();
+        }
+        SombraWindows::check(&self.config)?;
+        self.verify_signatures()?;
+        let service_manager = SombraWindows::service_manager(self.machine(), true)?;
 
+        if self.config.scope == Scope::User {
+            // Windows adds an instance of the template at each logon, which
+            // runs the wrapper with the token of the user
+            let mut options = self.sc_options()?;
+            options.push(("type", "userown".to_string()));
+            let options: Vec<(&str, &str)> = options.iter()
+                .map(|(option, value)| (*option, value.as_str()))
+                .collect();
+            self.sc().create(&options)?;
+            let service = service_manager.open_service(&self.config.name,
+                                                       ServiceAccess::CHANGE_CONFIG)?;
+            return self.configure(&service);
+        }
+
+        let (account_name, account_password) =
+            SombraWindows::account_credentials(&self.config.name,
+                                               &self.config.account_with_password()?);
         let service_info = ServiceInfo {
-            name: OsString::from(self.process_name.clone()),
-            display_name: OsString::from(self.process_name.clone()),
+            name: OsString::from(self.config.name.clone()),
+            display_name: OsString::from(self.display_name()),
             service_type: ServiceType::OWN_PROCESS,
-            start_type: ServiceStartType::OnDemand,
+            start_type: match self.config.start_type {
+                StartType::AutoStart | StartType::DelayedAutoStart => ServiceStartType::AutoStart,
+                StartType::OnDemand => ServiceStartType::OnDemand,
+                StartType::Disabled => ServiceStartType::Disabled,
+            },
             error_control: ServiceErrorControl::Normal,
-            executable_path: PathBuf::from(service_binary_path),
-            launch_arguments: vec![],
-            dependencies: vec![],
-            account_name: None, // run as System
-            account_password: None,
+            executable_path: self.wrapper_path()?,
+            launch_arguments: self.launch_arguments(),
+            dependencies: self.config.dependencies.iter()
+                .map(|d| match d.strip_prefix('+') {
+                    Some(group) => ServiceDependency::Group(OsString::from(group)),
+                    None => ServiceDependency::Service(OsString::from(d)),
+                })
+                .collect(),
+            account_name,
+            account_password,
         };
-        let service = service_manager.create_service(&service_info,
-                                                     ServiceAccess::CHANGE_CONFIG)?;
-        service.set_description(format!("Sombra Service Wrapper on {}", self.process_name))?;
+        let service = self.create_service(service_manager, &service_info)?;
+        self.configure(&service)?;
+
+        match self.config.start_type {
+            StartType::DelayedAutoStart => self.sc().delayed_auto_start()?,
+            StartType::Disabled => return Ok(()),
+            _ => {},
+        }
+        // Started once the target is copied in place, or by the triggers
+        if self.config.target_pending() || self.config.is_triggered() {
+            return Ok(());
+        }
+        self.start()?;
+        match &self.config.readiness {
+            Some(readiness) => readiness.wait(),
+            None => Ok(()),
+        }
+    }
+
+    fn update(&self) -> crate::Result<()> {
+        if let Some(fallback) = &self.fallback {
+            return fallback.update();
+        }
+        SombraWindows::check(&self.config)?;
+        self.verify_signatures()?;
+        let service_manager = SombraWindows::service_manager(self.machine(), false)?;
+        let service = service_manager.open_service(&self.config.name,
+                                                   ServiceAccess::CHANGE_CONFIG)?;
 
+        let mut options = self.sc_options()?;
+        // Instances of a template run as the user logging on
+        if self.config.scope == Scope::System {
+            let (account_name, account_password) =
+                SombraWindows::account_credentials(&self.config.name,
+                                                   &self.config.account_with_password()?);
+            let account_name = account_name.unwrap_or_else(|| OsString::from("LocalSystem"));
+            options.push(("obj", account_name.to_string_lossy().to_string()));
+            if let Some(password) = account_password {
+                options.push(("password", password.to_string_lossy().to_string()));
+            }
+        }
+        let options: Vec<(&str, &str)> = options.iter()
+            .map(|(option, value)| (*option, value.as_str()))
+            .collect();
+        self.sc().config(&options)?;
+
+        self.configure(&service)
+    }
+
+    fn delete(&self) -> crate::Result<()> {
+        if let Some(fallback) = &self.fallback {
+            return fallback.delete();
+        }
+        if self.config.scope == Scope::User {
+            for instance in self.instances()? {
+                instance.delete()?;
+            }
+        }
+        let service_manager = SombraWindows::service_manager(self.machine(), false)?;
+        let service_access = ServiceAccess::QUERY_STATUS | ServiceAccess::STOP |
+            ServiceAccess::DELETE;
+        let service = service_manager.open_service(&self.config.name,
+                                                   service_access)?;
+        if service.query_status()?.current_state != ServiceState::Stopped {
+            service.stop()?;
+            self.wait_stopped(&service)?;
+        }
+        // The key goes with the service
+        let granted = self.service_key().query(LOGON_RIGHT_VALUE);
+        service.delete()?;
+        if let Some(granted) = granted {
+            powershell::revoke_right(self.machine(), &granted, LOGON_RIGHT, &self.config.name)?;
+        }
+
+        let source = self.event_source();
+        if source.exists() {
+            source.delete()?;
+        }
+        #[cfg(feature = "embed-wrapper")]
+        {
+            if self.config.wrapper_path.is_none() {
+                crate::windows::embedded::remove(&self.config.name)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn start(&self) -> crate::Result<()> {
+        if let Some(fallback) = &self.fallback {
+            return fallback.start();
+        }
+        self.config.check_target()?;
+        if self.config.scope == Scope::User {
+            if !self.exists()? {
+                return Err(crate::Error::new(crate::ErrorKind::ServiceNotFound,
+                                             format!("Service {} does not exist",
+                                                     self.config.name)));
+            }
+            // A template doesn't run itself
+            let instances = self.instances()?;
+            if instances.is_empty() {
+                return Err(crate::Error::new(crate::ErrorKind::Other,
+                                             "Per-user services start at the logon of each \
+                                              user".to_string())
+                    .content(self.config.name.clone()));
+            }
+            for instance in instances {
+                if instance.status()? != ServiceStatus::Running {
+                    instance.start()?;
+                }
+            }
+            return Ok(());
+        }
+        let service_manager = SombraWindows::service_manager(self.machine(), false)?;
         let service_access = ServiceAccess::START;
-        let service = service_manager.open_service(&self.process_name,
+        let service = service_manager.open_service(&self.config.name,
                                                    service_access)?;
-        let mut args = vec![OsStr::new(&self.process_path)];
-        for a in &self.process_args {
-            args.push(a.as_ref());
+        let mut args = vec![OsStr::new(&self.config.path)];
+        for a in &self.config.args {
+            args.push(a.as_os_str());
         }
         service.start(&args)?;
 
         Ok(())
     }
 
-    fn delete(&self) -> crate::Result<()> {
-        let manager_access = ServiceManagerAccess::CONNECT;
-        let service_manager = ServiceManager::local_computer(None::<&str>,
-                                                             manager_access)?;
-        let service_access = ServiceAccess::QUERY_STATUS | ServiceAccess::STOP |
-            ServiceAccess::DELETE;
-        let service = service_manager.open_service(&self.process_name,
+    fn stop(&self) -> crate::Result<()> {
+        if let Some(fallback) = &self.fallback {
+            return fallback.stop();
+        }
+        if self.config.scope == Scope::User {
+            for instance in self.instances()? {
+                instance.stop()?;
+            }
+            return Ok(());
+        }
+        let service_manager = SombraWindows::service_manager(self.machine(), false)?;
+        let service_access = ServiceAccess::QUERY_STATUS | ServiceAccess::STOP;
+        let service = service_manager.open_service(&self.config.name,
                                                    service_access)?;
         let service_status = service.query_status()?;
         if service_status.current_state != ServiceState::Stopped {
             service.stop()?;
-            std::thread::sleep(Duration::from_millis(100))
         }
 
-        service.delete()?;
-
         Ok(())
     }
+
+    fn status(&self) -> crate::Result<ServiceStatus> {
+        if let Some(fallback) = &self.fallback {
+            return fallback.status();
+        }
+        let service_manager = SombraWindows::service_manager(self.machine(), false)?;
+        let service = match service_manager.open_service(&self.config.name,
+                                                         ServiceAccess::QUERY_STATUS) {
+            Ok(service) => service,
+            Err(windows_service::Error::Winapi(e))
+                if e.raw_os_error() == Some(ERROR_SERVICE_DOES_NOT_EXIST) => {
+                return Ok(ServiceStatus::NotInstalled)
+            },
+            Err(e) => return Err(e.into()),
+        };
+        if self.config.scope == Scope::User {
+            // The busiest state of the instances
+            let mut statuses = vec![];
+            for instance in self.instances()? {
+                statuses.push(instance.status()?);
+            }
+            return Ok([ServiceStatus::Running, ServiceStatus::StartPending,
+                       ServiceStatus::ContinuePending, ServiceStatus::StopPending,
+                       ServiceStatus::PausePending, ServiceStatus::Paused,
+                       ServiceStatus::Failed].iter()
+                .find(|status| statuses.contains(status))
+                .copied()
+                .unwrap_or(ServiceStatus::Stopped));
+        }
+
+        let service_status = service.query_status()?;
+        Ok(SombraWindows::status_from_state(service_status.current_state,
+                                            &service_status.exit_code))
+    }
+
+    fn pid(&self) -> crate::Result<Option<u32>> {
+        if let Some(fallback) = &self.fallback {
+            return fallback.pid();
+        }
+        if self.config.scope == Scope::User {
+            return match self.running_instance()? {
+                Some(instance) => instance.pid(),
+                None => Ok(None),
+            };
+        }
+        if self.status()? != ServiceStatus::Running {
+            return Ok(None);
+        }
+        // The SCM only knows the PID of the wrapper. Wrappers without a
+        // control channel publish the PID of the target after each spawn.
+        if self.machine().is_none() {
+            if let Ok(pid) = ControlClient::new(&self.config.name).pid() {
+                return Ok(pid);
+            }
+        }
+        Ok(self.parameters().query("ChildProcessId")
+            .and_then(|pid| u32::from_str_radix(pid.trim_start_matches("0x"), 16).ok()))
+    }
+
+    fn usage(&self) -> crate::Result<Option<Usage>> {
+        if let Some(fallback) = &self.fallback {
+            return fallback.usage();
+        }
+        if let Some(machine) = self.machine() {
+            return Err(crate::Error::new(crate::ErrorKind::Other,
+                                         "Usage of the processes of another machine isn't \
+                                          available".to_string())
+                .content(machine.to_string()));
+        }
+        let pid = match self.pid()? {
+            Some(pid) => pid,
+            None => return Ok(None),
+        };
+        Ok(Some(powershell::usage(pid)?))
+    }
+
+    fn stats(&self) -> crate::Result<ServiceStats> {
+        if let Some(fallback) = &self.fallback {
+            return fallback.stats();
+        }
+        if self.config.scope == Scope::User {
+            if let Some(instance) = self.running_instance()? {
+                return instance.stats();
+            }
+        }
+        // Published by the wrapper, which restarts the target itself
+        let parameters = self.parameters();
+        let dword = |value: &str| parameters.query(value)
+            .and_then(|data| u64::from_str_radix(data.trim_start_matches("0x"), 16).ok());
+        let started_at = match self.pid()? {
+            Some(_) => dword("ChildStartTime")
+                .map(|secs| std::time::UNIX_EPOCH + Duration::from_secs(secs)),
+            None => None,
+        };
+        Ok(ServiceStats {
+            started_at,
+            restarts: dword("RestartCount").unwrap_or(0) as u32,
+            last_exit: dword("ChildExitCode").map(|code| ExitReason::Code(code as i64)),
+        })
+    }
+
+    fn config(&self) -> crate::Result<SombraConfig> {
+        if let Some(fallback) = &self.fallback {
+            return fallback.config();
+        }
+        Ok(SombraWindows::existing(self.machine(), &self.config.name)?.config)
+    }
+
+    fn diff(&self) -> crate::Result<Vec<Difference>> {
+        if let Some(fallback) = &self.fallback {
+            return fallback.diff();
+        }
+        let installed = SombraWindows::comparable(&self.config()?);
+        Ok(SombraWindows::comparable(&self.config).diff(&installed))
+    }
+
+    fn checksum(&self) -> crate::Result<Option<String>> {
+        if let Some(fallback) = &self.fallback {
+            return fallback.checksum();
+        }
+        if !self.exists()? {
+            return Err(crate::Error::new(crate::ErrorKind::ServiceNotFound,
+                                         format!("Service {} does not exist", self.config.name)));
+        }
+        Ok(self.service_key().query(CHECKSUM_VALUE))
+    }
+
+    fn list_managed() -> crate::Result<Vec<ManagedService>> {
+        let names = powershell::run(&format!(
+            "Get-ChildItem HKLM:\\SYSTEM\\CurrentControlSet\\Services | \
+             Where-Object {{ $_.GetValue('{}') -eq 1 }} | \
+             ForEach-Object {{ $_.PSChildName }}",
+            MANAGED_MARKER))?;
+        let mut services = vec![];
+        for name in names.lines().map(str::trim).filter(|name| !name.is_empty()) {
+            let service = SombraWindows::from_existing(name)?;
+            services.push(ManagedService {
+                name: name.to_string(),
+                status: service.status()?,
+                path: service.config.path.clone(),
+            });
+        }
+        services.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(services)
+    }
+
+    fn rename(&self, new_name: &str) -> crate::Result<Self> {
+        let mut config = self.config()?;
+        config.user_mode_fallback = self.config.user_mode_fallback;
+        // Passwords can't be read back
+        if let (Account::User { name, password }, Account::User { name: built, password: known })
+                = (&mut config.account, &self.config.account) {
+            if name == built {
+                *password = known.clone();
+            }
+        }
+        // The display name defaults to the name
+        if config.display_name.as_ref() == Some(&config.name) {
+            config.display_name = None;
+        }
+        config.name = new_name.to_string();
+        let renamed = SombraWindows::from_config(config)?;
+        // from_existing doesn't read every wrapper option back, so the
+        // Parameters key is copied as is
+        let carry = || self.parameters()
+            .copy_to(&Reg::parameters(new_name).on(self.machine()));
+        let carry: Option<&dyn Fn() -> crate::Result<()>> = match self.fallback {
+            Some(_) => None,
+            None => Some(&carry),
+        };
+        crate::rename::move_service(self, &renamed, carry)?;
+        Ok(renamed)
+    }
+
+    /// Per-user templates are created by administrators too
+    fn required_privileges(&self) -> Privileges {
+        match &self.fallback {
+            Some(fallback) => fallback.required_privileges(),
+            None => Privileges::Administrator,
+        }
+    }
+
+    fn logs(&self, follow: bool) -> crate::Result<LogReader> {
+        if let Some(fallback) = &self.fallback {
+            return fallback.logs(follow);
+        }
+        let files = self.config.log_files().iter()
+            .map(|file| self.config.local_path(file))
+            .collect();
+        LogReader::files(files, follow)
+    }
+
+    /// The sc.exe commands and registry values `create()` amounts to, with
+    /// a placeholder for the password of the account
+    fn render(&self) -> crate::Result<Vec<Artifact>> {
+        if let Some(fallback) = &self.fallback {
+            return fallback.render();
+        }
+        let registration = self.registration()?;
+        let sc = self.sc();
+        let mut description: String = registration.commands.iter()
+            .map(|(command, args)| sc.command_line(command, args) + "\n")
+            .collect();
+        for (key, values) in &registration.keys {
+            description.push_str(&key.describe(values));
+        }
+        Ok(vec![Artifact::registration(description)])
+    }
+
+    /// PowerShell running sc.exe and reg.exe. The wrapper stays in place
+    /// when it is uninstalled.
+    fn export(&self) -> crate::Result<Scripts> {
+        if let Some(fallback) = &self.fallback {
+            return fallback.export();
+        }
+        let registration = self.registration()?;
+        let sc = self.sc();
+        let mut script = PowerShell::new(&self.config.name);
+        for (command, args) in &registration.commands {
+            script.install_native("sc.exe", &sc.args(command, args));
+        }
+        for (key, values) in &registration.keys {
+            for (value, data) in values {
+                script.install_native("reg.exe", &key.set_args(value, data));
+            }
+        }
+        let logon_account = self.logon_account();
+        if let Some(account) = &logon_account {
+            script.install(format!("$granted = & {{\n{}\n}}",
+                                   powershell::grant_right_script(self.machine(), account,
+                                                                  LOGON_RIGHT)));
+            let record = self.service_key().set_args(LOGON_RIGHT_VALUE,
+                                                     &Value::String(account.clone()));
+            script.install(format!("if ($granted) {{ {} }}",
+                                   PowerShell::native("reg.exe", &record, true)));
+        }
+        if self.config.start_type != StartType::Disabled && !self.config.is_triggered() {
+            script.install_native("sc.exe", &sc.args("start", &[]));
+        }
+
+        if let Some(account) = &logon_account {
+            script.uninstall_native("reg.exe", &self.service_key().query_args(LOGON_RIGHT_VALUE));
+            script.uninstall(format!("if ($p.ExitCode -eq 0) {{\n{}\n}}",
+                                     powershell::revoke_right_script(self.machine(), account,
+                                                                     LOGON_RIGHT,
+                                                                     &self.config.name)));
+        }
+        script.uninstall_native("sc.exe", &sc.args("stop", &[]));
+        let computer = match self.machine() {
+            Some(machine) => format!(" -ComputerName {}",
+                                     powershell::quote(machine.trim_start_matches('\\'))),
+            None => String::new(),
+        };
+        script.uninstall(format!("while ((Get-Service -Name {}{} -ErrorAction SilentlyContinue)\
+                                  .Status -eq 'StopPending') {{ Start-Sleep -Milliseconds 500 }}",
+                                 powershell::quote(&self.config.name), computer));
+        script.uninstall_native("sc.exe", &sc.args("delete", &[]));
+        if self.config.event_log {
+            script.uninstall_native("reg.exe", &self.event_source().delete_args());
+        }
+        Ok(script.finish())
+    }
 }
 
 #[cfg(test)]
 #[cfg(target_os = "windows")]
 mod tests {
     use super::*;
+    use crate::SombraBuilder;
     use std::io::prelude::*;
     use std::net::TcpStream;
 
     fn echo_check(ip_port: &str, msg: &[u8]) -> std::io::Result<()> {
         let mut stream = TcpStream::connect(ip_port)?;
-        stream.write(msg)?;
+        stream.write_all(msg)?;
         let mut buffer = [0u8; 512];
-        stream.read(&mut buffer);
-        let mut buffer = buffer.to_vec();
+        let len = stream.read(&mut buffer)?;
+        let mut buffer = buffer[..len].to_vec();
         buffer.retain(|&x| x != 0);
         if buffer != msg.to_vec() {
-            Err(std::io::Error::new(std::io::ErrorKind::Other, "Not match"))
+            Err(std::io::Error::other("Not match"))
         } else {
             Ok(())
         }
     }
 
+    #[test]
+    fn command_line_quotes_arguments() {
+        let args = vec![OsString::from("C:\\sombra\\wrapper.exe"),
+                        OsString::from("C:\\Program Files\\app\\"),
+                        OsString::from("say \"hi\""),
+                        OsString::from("")];
+        assert_eq!(SombraWindows::command_line(&args),
+                   "C:\\sombra\\wrapper.exe \"C:\\Program Files\\app\\\\\" \"say \\\"hi\\\"\" \"\"");
+    }
+
+    #[test]
+    fn split_command_line_inverts_quoting() {
+        let args = vec![OsString::from("C:\\sombra\\wrapper.exe"),
+                        OsString::from("C:\\Program Files\\app\\"),
+                        OsString::from("say \"hi\""),
+                        OsString::from("")];
+        let command_line = SombraWindows::command_line(&args);
+        let split: Vec<OsString> = SombraWindows::split_command_line(&command_line).iter()
+            .map(OsString::from)
+            .collect();
+        assert_eq!(split, args);
+    }
+
+    #[test]
+    fn command_line_keeps_spaces_quotes_and_carets() {
+        // CreateProcess doesn't go through cmd.exe, so carets stay as is
+        let args = vec![OsString::from("C:\\Program Files\\My App\\app.exe"),
+                        OsString::from("--greeting=^say \\\"hi\\\"^"),
+                        OsString::from("a^b")];
+        let command_line = SombraWindows::command_line(&args);
+        assert_eq!(command_line,
+                   "\"C:\\Program Files\\My App\\app.exe\" \
+                    \"--greeting=^say \\\\\\\"hi\\\\\\\"^\" a^b");
+        let split: Vec<OsString> = SombraWindows::split_command_line(&command_line).iter()
+            .map(OsString::from)
+            .collect();
+        assert_eq!(split, args);
+    }
+
+    #[test]
+    fn failure_actions_from_qfailure() {
+        let output = "[SC] QueryServiceConfig2 SUCCESS\r\n\r\n\
+                      SERVICE_NAME: tcp_echo\r\n\
+                      \x20       RESET_PERIOD (in seconds)    : 86400\r\n\
+                      \x20       REBOOT_MESSAGE               :\r\n\
+                      \x20       COMMAND_LINE                 :\r\n\
+                      \x20       FAILURE_ACTIONS              : RESTART -- Delay = 1000 milliseconds.\r\n\
+                      \x20                                      RESTART -- Delay = 5000 milliseconds.\r\n";
+        assert_eq!(SombraWindows::failure_actions_from_qfailure(output), Some(FailureActions {
+            first: FailureAction::Restart(Duration::from_secs(1)),
+            second: FailureAction::Restart(Duration::from_secs(5)),
+            subsequent: FailureAction::Restart(Duration::from_secs(5)),
+            reset_period: Duration::from_secs(86400),
+        }));
+        let output = "SERVICE_NAME: tcp_echo\r\n\
+                      \x20       RESET_PERIOD (in seconds)    : 0\r\n\
+                      \x20       FAILURE_ACTIONS              :\r\n";
+        assert_eq!(SombraWindows::failure_actions_from_qfailure(output), None);
+    }
+
+    #[test]
+    fn privileges_from_qprivs() {
+        let output = "[SC] QueryServiceConfig2 SUCCESS\r\n\r\n\
+                      SERVICE_NAME: tcp_echo\r\n\
+                      \x20       PRIVILEGES       : SeChangeNotifyPrivilege\r\n\
+                      \x20                        : SeCreateGlobalPrivilege\r\n";
+        assert_eq!(SombraWindows::privileges_from_qprivs(output),
+                   vec!["SeChangeNotifyPrivilege", "SeCreateGlobalPrivilege"]);
+        assert!(SombraWindows::privileges_from_qprivs("SERVICE_NAME: tcp_echo\r\n").is_empty());
+    }
+
+    /// The config the wrapper reads from the Parameters key of `config`
+    fn read_back(config: &SombraConfig) -> SombraConfig {
+        // As reg query prints them
+        let values: Vec<(String, String)> = SombraWindows { config: config.clone(), fallback: None }
+            .parameter_values().into_iter()
+            .map(|(name, data)| (name.to_string(), match data {
+                Value::String(data) => data,
+                Value::MultiString(data) => data.join("\\0"),
+                Value::Dword(data) => format!("{:#x}", data),
+                Value::Qword(data) => format!("{:#x}", data),
+            }))
+            .collect();
+        let mut read = SombraConfig::new(&config.name, &config.path);
+        SombraWindows::read_parameters(&mut read, &values);
+        read
+    }
+
+    #[test]
+    fn read_parameters_inverts_parameter_values() {
+        let mut config = SombraConfig::new("tcp_echo", "C:\\tcp_echo\\tcp_echo.exe");
+        config.working_directory = Some(PathBuf::from("C:\\tcp echo"));
+        config.stdout_log = Some(PathBuf::from("C:\\logs\\tcp_echo.log"));
+        config.log_rotation = Some(LogRotation {
+            max_size: Some(1 << 20),
+            max_age: None,
+            keep: 3,
+        });
+        config.resource_limits = Some(ResourceLimits { memory_max: None, cpu_quota: Some(150) });
+        config.priority = Some(Priority::BelowNormal);
+        config.cpu_affinity = Some(0b1010);
+        config.restart_policy = Some(RestartPolicy {
+            max_restarts: 5,
+            window: Duration::from_secs(600),
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+        });
+        config.notify = true;
+        config.start_timeout = Some(Duration::from_secs(90));
+        config.watchdog = Some(Duration::from_secs(15));
+        config.health_check = Some(HealthCheck {
+            probe: Probe::Exec {
+                command: "C:\\tcp_echo\\check.exe".to_string(),
+                args: vec!["--port".to_string(), "7".to_string()],
+            },
+            interval: Duration::from_secs(30),
+            timeout: Duration::from_millis(1500),
+            failure_threshold: 2,
+        });
+        config.metrics_port = Some(9180);
+        config.event_log = true;
+        config.stop_signal = StopSignal::Interrupt;
+        config.kill_process_tree = false;
+        config.stop_timeout = Some(Duration::from_secs(5));
+        let read = read_back(&config);
+        assert_eq!(read.working_directory, config.working_directory);
+        assert_eq!(read.stdout_log, config.stdout_log);
+        assert_eq!(read.stderr_log, None);
+        assert_eq!(read.log_rotation, config.log_rotation);
+        assert_eq!(read.resource_limits, config.resource_limits);
+        assert_eq!(read.priority, Some(Priority::BelowNormal));
+        assert_eq!(read.cpu_affinity, Some(0b1010));
+        assert_eq!(read.restart_policy, config.restart_policy);
+        assert!(read.notify);
+        assert_eq!(read.start_timeout, Some(Duration::from_secs(90)));
+        assert_eq!(read.watchdog, Some(Duration::from_secs(15)));
+        assert_eq!(read.health_check, config.health_check);
+        assert_eq!(read.metrics_port, Some(9180));
+        assert!(read.event_log);
+        assert_eq!(read.stop_signal, StopSignal::Interrupt);
+        assert!(!read.kill_process_tree);
+        assert_eq!(read.stop_timeout, Some(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn read_parameters_keeps_durations_over_a_dword() {
+        let mut config = SombraConfig::new("tcp_echo", "C:\\tcp_echo\\tcp_echo.exe");
+        let long = Duration::from_millis(u32::MAX as u64 + 1000);
+        config.stop_timeout = Some(long);
+        config.start_timeout = Some(long);
+        config.watchdog = Some(long);
+        config.restart_policy = Some(RestartPolicy {
+            max_restarts: 5,
+            window: long,
+            initial_delay: long,
+            max_delay: long * 2,
+        });
+        config.health_check = Some(HealthCheck {
+            probe: Probe::Tcp("127.0.0.1:7".to_string()),
+            interval: long,
+            timeout: long,
+            failure_threshold: 3,
+        });
+        let read = read_back(&config);
+        assert_eq!(read.stop_timeout, Some(long));
+        assert_eq!(read.start_timeout, Some(long));
+        assert_eq!(read.watchdog, Some(long));
+        assert_eq!(read.restart_policy, config.restart_policy);
+        assert_eq!(read.health_check, config.health_check);
+    }
+
+    #[test]
+    fn status_from_state() {
+        let no_error = ServiceExitCode::NO_ERROR;
+        assert_eq!(SombraWindows::status_from_state(ServiceState::PausePending, &no_error),
+                   ServiceStatus::PausePending);
+        assert_eq!(SombraWindows::status_from_state(ServiceState::ContinuePending, &no_error),
+                   ServiceStatus::ContinuePending);
+        assert_eq!(SombraWindows::status_from_state(ServiceState::Paused, &no_error),
+                   ServiceStatus::Paused);
+        assert_eq!(SombraWindows::status_from_state(ServiceState::Stopped, &no_error),
+                   ServiceStatus::Stopped);
+        assert_eq!(SombraWindows::status_from_state(ServiceState::Stopped,
+                                                    &ServiceExitCode::Win32(1067)),
+                   ServiceStatus::Failed);
+    }
+
+    #[test]
+    fn triggers_from_qtriggerinfo() {
+        let output = "[SC] QueryServiceConfig2 SUCCESS\r\n\r\n\
+                      SERVICE_NAME: tcp_echo\r\n\r\n\
+                      \x20       START SERVICE\r\n\
+                      \x20         NETWORK EVENT                : \
+                      4f27f2de-14e2-430b-a549-7cd48cbc8245 [IP ADDRESS AVAILABLE]\r\n\
+                      \x20         DEVICE INTERFACE ARRIVAL     : \
+                      53f56307-b6bf-11d0-94f2-00a0c91efb8b [INTERFACE CLASS GUID]\r\n\
+                      \x20         DOMAIN JOINED STATUS         : \
+                      1ce20aba-9851-4421-9430-1ddeb766e809 [DOMAIN JOINED]\r\n\
+                      \x20       STOP SERVICE\r\n\
+                      \x20         DOMAIN JOINED STATUS         : \
+                      ddaf516e-58c2-4866-9574-c3b615d42ea1 [NOT DOMAIN JOINED]\r\n";
+        assert_eq!(SombraWindows::triggers_from_qtriggerinfo(output),
+                   vec![Trigger::NetworkAvailable,
+                        Trigger::Device("53f56307-b6bf-11d0-94f2-00a0c91efb8b".to_string()),
+                        Trigger::DomainJoin]);
+        assert!(SombraWindows::triggers_from_qtriggerinfo("SERVICE_NAME: tcp_echo\r\n")
+            .is_empty());
+    }
+
+    #[test]
+    fn security_descriptor_grants_operators() {
+        let mut config = SombraConfig::new("tcp_echo", "C:\\echo\\tcp_echo.exe");
+        config.operators = vec!["S-1-5-32-545".to_string()];
+        let service = SombraWindows { config, fallback: None };
+        let sddl = service.security_descriptor().unwrap();
+        assert!(sddl.starts_with("D:(A;;CCLCSWRPWPDTLOCRRC;;;SY)"));
+        assert!(sddl.ends_with("(A;;CCLCSWRPWPDTLOCRRC;;;S-1-5-32-545)\
+                                S:(AU;FA;CCDCLCSWRPWPDTLOCRSDRCWDWO;;;WD)"));
+    }
+
+    #[test]
+    fn logon_right_goes_to_custom_accounts() {
+        let logon_account = |account: Account| {
+            let mut config = SombraConfig::new("tcp_echo", "C:\\echo\\tcp_echo.exe");
+            config.account = account;
+            SombraWindows { config, fallback: None }.logon_account()
+        };
+        assert_eq!(logon_account(Account::LocalSystem), None);
+        assert_eq!(logon_account(Account::Virtual), None);
+        assert_eq!(logon_account(Account::User { name: ".\\sombra".to_string(), password: None }),
+                   Some("sombra".to_string()));
+        assert_eq!(logon_account(Account::Managed("CORP\\echo$".to_string())),
+                   Some("CORP\\echo$".to_string()));
+    }
+
+    #[test]
+    fn account_from_name() {
+        assert_eq!(SombraWindows::account_from_name("tcp_echo", None), Account::LocalSystem);
+        assert_eq!(SombraWindows::account_from_name("tcp_echo", Some("NT AUTHORITY\\LocalService")),
+                   Account::LocalService);
+        assert_eq!(SombraWindows::account_from_name("tcp_echo", Some("NT SERVICE\\tcp_echo")),
+                   Account::Virtual);
+        assert_eq!(SombraWindows::account_from_name("tcp_echo", Some(".\\sombra")),
+                   Account::User { name: "sombra".to_string(), password: None });
+    }
+
+    #[test]
+    fn instances_are_named_after_the_luid() {
+        assert!(SombraWindows::is_instance("tcp_echo", "tcp_echo_4a2f1"));
+        assert!(!SombraWindows::is_instance("tcp_echo", "tcp_echo"));
+        assert!(!SombraWindows::is_instance("tcp_echo", "tcp_echo_"));
+        assert!(!SombraWindows::is_instance("tcp_echo", "tcp_echo_backup"));
+        assert!(!SombraWindows::is_instance("tcp_echo", "tcp_echo2_4a2f1"));
+    }
+
     #[test]
     fn spawn_simple() {
         let s = match SombraWindows::build("tcp_echo",
-                                     "executables/tcp_echo.exe", vec![]) {
+                                     "executables/tcp_echo.exe",
+                                     Vec::<String>::new()) {
             Ok(s) => s,
-            Err(e) => panic!(e.to_string()),
+            Err(e) => panic!("{}", e),
         };
         assert_eq!(s.create(), Ok(()));
         let res = echo_check("127.0.0.1:30222", b"sombra30222");
         assert_eq!(s.delete(), Ok(()));
         if let Err(e) = res {
-            panic!(format!("{:?}", e));
+            panic!("{:?}", e);
         }
     }
 
     #[test]
     fn spawn_twice_same_name() {
         let s = match SombraWindows::build("tcp_echo",
-                                           "executables/tcp_echo.exe", vec![]) {
+                                           "executables/tcp_echo.exe",
+                                           Vec::<String>::new()) {
             Ok(s) => s,
-            Err(e) => panic!(e.to_string()),
+            Err(e) => panic!("{}", e),
         };
         assert_eq!(s.create(), Ok(()));
         match echo_check("127.0.0.1:30222", b"sombra30222") {
             Ok(_) => {
                 let s2 = match SombraWindows::build("tcp_echo",
-                                                   "executables/tcp_echo.exe", vec![]) {
+                                                   "executables/tcp_echo.exe",
+                                                   Vec::<String>::new()) {
                     Ok(s2) => s2,
-                    Err(e) => panic!(e.to_string()),
+                    Err(e) => panic!("{}", e),
                 };
                 assert_ne!(s2.create(), Ok(()));
                 assert_eq!(s.delete(), Ok(()));
             },
             Err(e) => {
                 assert_eq!(s.delete(), Ok(()));
-                panic!(format!("{:?}", e));
+                panic!("{:?}", e);
             }
         }
     }
@@ -160,7 +1933,7 @@ mod tests {
                                            "executables/tcp_echo.exe",
                                            vec!["-p".to_string(), "30222".to_string()]) {
             Ok(s) => s,
-            Err(e) => panic!(e.to_string()),
+            Err(e) => panic!("{}", e),
         };
         assert_eq!(s.create(), Ok(()));
 
@@ -170,7 +1943,7 @@ mod tests {
                                                    "executables/tcp_echo.exe",
                                                    vec!["-p".to_string(), "30223".to_string()]) {
                     Ok(s) => s,
-                    Err(e) => panic!(e.to_string()),
+                    Err(e) => panic!("{}", e),
                 };
                 assert_eq!(s2.create(), Ok(()));
                 match echo_check("127.0.0.1:30223", b"sombra30223") {
@@ -181,13 +1954,13 @@ mod tests {
                     Err(e) => {
                         assert_eq!(s.delete(), Ok(()));
                         assert_eq!(s2.delete(), Ok(()));
-                        panic!(format!("{:?}", e));
+                        panic!("{:?}", e);
                     },
                 }
             },
             Err(e) => {
                 assert_eq!(s.delete(), Ok(()));
-                panic!(format!("{:?}", e));
+                panic!("{:?}", e);
             }
         }
     }
@@ -198,22 +1971,23 @@ mod tests {
                                            "executables/tcp_echo.exe",
                                            vec!["-p".to_string(), "30223".to_string()]) {
             Ok(s) => s,
-            Err(e) => panic!(e.to_string()),
+            Err(e) => panic!("{}", e),
         };
         assert_eq!(s.create(), Ok(()));
         let res = echo_check("127.0.0.1:30223", b"sombra30223");
         assert_eq!(s.delete(), Ok(()));
         if let Err(e) = res {
-            panic!(format!("{:?}", e));
+            panic!("{:?}", e);
         }
     }
 
     #[test]
     fn spawn_once_delete_twice() {
         let s = match SombraWindows::build("tcp_echo",
-                                           "executables/tcp_echo.exe", vec![]) {
+                                           "executables/tcp_echo.exe",
+                                           Vec::<String>::new()) {
             Ok(s) => s,
-            Err(e) => panic!(e.to_string()),
+            Err(e) => panic!("{}", e),
         };
         assert_eq!(s.create(), Ok(()));
         match echo_check("127.0.0.1:30222", b"sombra30222") {
@@ -223,7 +1997,7 @@ mod tests {
             },
             Err(e) => {
                 assert_eq!(s.delete(), Ok(()));
-                panic!(format!("{:?}", e));
+                panic!("{:?}", e);
             }
         }
     }
@@ -231,9 +2005,10 @@ mod tests {
     #[test]
     fn spawn_bug_and_correct() {
         let s = match SombraWindows::build("tcp_echo",
-                                           "executables/tcp_echo.exe", vec![]) {
+                                           "executables/tcp_echo.exe",
+                                           Vec::<String>::new()) {
             Ok(s) => s,
-            Err(e) => panic!(e.to_string()),
+            Err(e) => panic!("{}", e),
         };
         assert_eq!(s.create(), Ok(()));
         match echo_check("127.0.0.1:30222", b"bug") {
@@ -246,14 +2021,92 @@ mod tests {
                     },
                     Err(e) => {
                         assert_eq!(s.delete(), Ok(()));
-                        panic!(format!("{:?}", e));
+                        panic!("{:?}", e);
                     }
                 }
             },
             Err(e) => {
                 assert_eq!(s.delete(), Ok(()));
-                panic!(format!("{:?}", e));
+                panic!("{:?}", e);
             }
         }
     }
+
+    #[test]
+    fn spawn_stop_and_start() {
+        let s = match SombraWindows::build("tcp_echo",
+                                           "executables/tcp_echo.exe",
+                                           Vec::<String>::new()) {
+            Ok(s) => s,
+            Err(e) => panic!("{}", e),
+        };
+        assert_eq!(s.create(), Ok(()));
+        assert_eq!(s.exists(), Ok(true));
+        assert_eq!(s.status(), Ok(ServiceStatus::Running));
+        assert_eq!(s.stop(), Ok(()));
+        assert_eq!(s.status(), Ok(ServiceStatus::Stopped));
+        if echo_check("127.0.0.1:30222", b"sombra30222").is_ok() {
+            assert_eq!(s.delete(), Ok(()));
+            panic!("Service still running after stop");
+        }
+        assert_eq!(s.start(), Ok(()));
+        let res = echo_check("127.0.0.1:30222", b"sombra30222");
+        assert_eq!(s.delete(), Ok(()));
+        assert_eq!(s.status(), Ok(ServiceStatus::NotInstalled));
+        assert_eq!(s.exists(), Ok(false));
+        if let Err(e) = res {
+            panic!("{:?}", e);
+        }
+    }
+
+    #[test]
+    fn spawn_and_restart() {
+        let s = match SombraWindows::build("tcp_echo", "executables/tcp_echo.exe",
+                                           Vec::<String>::new()) {
+            Ok(s) => s,
+            Err(e) => panic!("{}", e),
+        };
+        assert_eq!(s.create(), Ok(()));
+        assert_eq!(s.restart(), Ok(()));
+        let res = echo_check("127.0.0.1:30222", b"sombra30222");
+        assert_eq!(s.delete(), Ok(()));
+        if let Err(e) = res {
+            panic!("{:?}", e);
+        }
+    }
+
+    #[test]
+    fn spawn_pause_and_resume() {
+        let s = match SombraWindows::build("tcp_echo",
+                                           "executables/tcp_echo.exe",
+                                           Vec::<String>::new()) {
+            Ok(s) => s,
+            Err(e) => panic!("{}", e),
+        };
+        assert_eq!(s.create(), Ok(()));
+        assert_eq!(s.pause(), Ok(()));
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(s.status(), Ok(ServiceStatus::Paused));
+        assert_eq!(s.resume(), Ok(()));
+        std::thread::sleep(Duration::from_millis(100));
+        let res = echo_check("127.0.0.1:30222", b"sombra30222");
+        assert_eq!(s.delete(), Ok(()));
+        if let Err(e) = res {
+            panic!("{:?}", e);
+        }
+    }
+
+    #[test]
+    fn spawn_disabled() {
+        let s = match SombraBuilder::new("tcp_echo", "executables/tcp_echo.exe")
+            .start_type(StartType::Disabled)
+            .build() {
+            Ok(s) => s,
+            Err(e) => panic!("{}", e),
+        };
+        assert_eq!(s.create(), Ok(()));
+        let res = s.status();
+        assert_eq!(s.delete(), Ok(()));
+        assert_eq!(res, Ok(ServiceStatus::Stopped));
+    }
 }
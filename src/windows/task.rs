@@ -0,0 +1,543 @@
+use crate::{Account, Artifact, Difference, LogReader, ManagedService, Priority, RestartPolicy,
+            Scripts, Sombra, SecretString, ServiceStats, ServiceStatus, SombraConfig, StartType,
+            Usage};
+use crate::export::PowerShell;
+use crate::support;
+use crate::windows::powershell::{self, quote};
+use crate::windows::reg::{Reg, Value};
+use crate::windows::sombra_imp::SombraWindows;
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Folder of the Task Scheduler holding the tasks created by sombra
+const TASK_PATH: &str = "\\Sombra\\";
+/// Task Scheduler waits at least a minute between restarts
+const MIN_RESTART_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Runs the target as a Scheduled Task instead of a service, for machines
+/// where policy forbids creating services. The task runs at startup (or
+/// only on demand), with the highest privileges, and is restarted on
+/// failure. The Task Scheduler runs the target itself, without the
+/// wrapper, so the options the wrapper provides aren't available: log
+/// files, the environment, and virtual accounts are rejected.
+pub struct SombraTask {
+    config: SombraConfig,
+}
+
+impl SombraTask {
+    fn check(config: &SombraConfig) -> crate::Result<()> {
+        let unsupported = if !config.log_files().is_empty() {
+            Some("Scheduled tasks don't capture the output of the target")
+        } else if !config.env.is_empty() {
+            Some("Scheduled tasks don't take environment variables")
+        } else {
+            support::unsupported(config, &[])
+        };
+        match unsupported {
+            Some(description) => Err(crate::Error::new(crate::ErrorKind::Other,
+                                                       description.to_string())
+                .content(config.name.clone())),
+            None => Ok(()),
+        }
+    }
+
+    fn task_arguments(&self) -> String {
+        format!("-TaskPath {} -TaskName {}", quote(TASK_PATH), quote(&self.config.name))
+    }
+
+    /// Task priority of a priority class. Unset, the target runs at normal
+    /// priority, 4, rather than at the Task Scheduler default of below
+    /// normal.
+    fn task_priority(priority: Option<Priority>) -> u32 {
+        match priority {
+            Some(Priority::High) => 1,
+            Some(Priority::AboveNormal) => 3,
+            None => 4,
+            Some(Priority::Normal) => 5,
+            Some(Priority::BelowNormal) => 8,
+            Some(Priority::Idle) => 10,
+        }
+    }
+
+    fn priority_from_task(priority: u32) -> Option<Priority> {
+        [Priority::High, Priority::AboveNormal, Priority::Normal, Priority::BelowNormal,
+         Priority::Idle].iter()
+            .find(|p| SombraTask::task_priority(Some(**p)) == priority)
+            .copied()
+    }
+
+    /// Restarts of the Task Scheduler: a count and a fixed interval
+    fn task_restarts(policy: &RestartPolicy) -> (u32, Duration) {
+        (policy.max_restarts, policy.initial_delay.max(MIN_RESTART_INTERVAL))
+    }
+
+    /// PowerShell script registering the task, replacing an existing one
+    fn register_script(config: &SombraConfig) -> String {
+        let mut script = format!("$action = New-ScheduledTaskAction -Execute {}",
+                                 quote(&config.path.to_string_lossy()));
+        if !config.args.is_empty() {
+            script.push_str(&format!(" -Argument {}",
+                                     quote(&SombraWindows::command_line(&config.args))));
+        }
+        if let Some(dir) = &config.working_directory {
+            script.push_str(&format!(" -WorkingDirectory {}", quote(&dir.to_string_lossy())));
+        }
+
+        script.push_str(&format!("\n$settings = New-ScheduledTaskSettingsSet \
+                                  -ExecutionTimeLimit ([TimeSpan]::Zero) \
+                                  -MultipleInstances IgnoreNew -AllowStartIfOnBatteries \
+                                  -DontStopIfGoingOnBatteries -Priority {}",
+                                 SombraTask::task_priority(config.priority)));
+        if let Some(policy) = &config.restart_policy {
+            let (count, interval) = SombraTask::task_restarts(policy);
+            script.push_str(&format!(" -RestartCount {} -RestartInterval \
+                                      (New-TimeSpan -Seconds {})", count, interval.as_secs()));
+        }
+        if config.start_type == StartType::Disabled {
+            script.push_str(" -Disable");
+        }
+
+        let mut register = format!("Register-ScheduledTask -TaskPath {} -TaskName {} \
+                                    -Action $action -Settings $settings -Force",
+                                   quote(TASK_PATH), quote(&config.name));
+        match config.start_type {
+            StartType::AutoStart | StartType::DelayedAutoStart => {
+                script.push_str("\n$trigger = New-ScheduledTaskTrigger -AtStartup");
+                if config.start_type == StartType::DelayedAutoStart {
+                    // As the SCM delays automatic starts
+                    script.push_str("\n$trigger.Delay = 'PT2M'");
+                }
+                register.push_str(" -Trigger $trigger");
+            },
+            StartType::OnDemand | StartType::Disabled => {},
+        }
+        if let Some(description) = &config.description {
+            register.push_str(&format!(" -Description {}", quote(description)));
+        }
+        match &config.account {
+            Account::User { name, password: Some(password) } => {
+                register.push_str(&format!(" -User {} -Password {} -RunLevel Highest",
+                                           quote(name), quote(password.expose_secret())));
+            },
+            account => {
+                let (user, logon) = match account {
+                    Account::LocalService => ("LOCAL SERVICE".to_string(), "ServiceAccount"),
+                    Account::NetworkService => ("NETWORK SERVICE".to_string(), "ServiceAccount"),
+                    Account::User { name, .. } => (name.clone(), "S4U"),
+                    // The domain provides the password of a gMSA
+                    Account::Managed(name) => (name.clone(), "Password"),
+                    _ => ("SYSTEM".to_string(), "ServiceAccount"),
+                };
+                script.push_str(&format!("\n$principal = New-ScheduledTaskPrincipal -UserId {} \
+                                          -LogonType {} -RunLevel Highest", quote(&user), logon));
+                register.push_str(" -Principal $principal");
+            },
+        }
+        script.push('\n');
+        script.push_str(&register);
+        script.push_str(" | Out-Null");
+        script
+    }
+
+    fn account_from_user(user: &str) -> Account {
+        match user.to_uppercase().as_str() {
+            "" | "SYSTEM" | "NT AUTHORITY\\SYSTEM" => Account::LocalSystem,
+            "LOCAL SERVICE" | "NT AUTHORITY\\LOCAL SERVICE" => Account::LocalService,
+            "NETWORK SERVICE" | "NT AUTHORITY\\NETWORK SERVICE" => Account::NetworkService,
+            _ if user.ends_with('$') => Account::Managed(user.to_string()),
+            _ => Account::User { name: user.to_string(), password: None },
+        }
+    }
+
+    /// Parses the `PT1H2M3S` durations of the Task Scheduler
+    fn duration_from_task(value: &str) -> Option<Duration> {
+        let mut rest = value.strip_prefix("PT")?;
+        let mut seconds = 0;
+        while !rest.is_empty() {
+            let end = rest.find(|c: char| !c.is_ascii_digit())?;
+            let amount: u64 = rest[..end].parse().ok()?;
+            seconds += match &rest[end..=end] {
+                "H" => amount * 3600,
+                "M" => amount * 60,
+                "S" => amount,
+                _ => return None,
+            };
+            rest = &rest[end + 1..];
+        }
+        Some(Duration::from_secs(seconds))
+    }
+
+    /// Inverse of `register_script`, from `key=value` lines printed by
+    /// `config()`
+    fn config_from_task(name: &str, output: &str) -> SombraConfig {
+        let mut config = SombraConfig::new(name, "");
+        let mut trigger = false;
+        let mut delayed = false;
+        let mut enabled = true;
+        let mut restart_count = 0;
+        let mut restart_interval = None;
+        for line in output.lines() {
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key, value.trim()),
+                None => continue,
+            };
+            match key {
+                "Execute" => config.path = PathBuf::from(value),
+                "Arguments" => config.args = SombraWindows::split_command_line(value).into_iter()
+                    .map(OsString::from)
+                    .collect(),
+                "WorkingDirectory" if !value.is_empty() =>
+                    config.working_directory = Some(PathBuf::from(value)),
+                "Description" if !value.is_empty() => config.description = Some(value.to_string()),
+                "UserId" => config.account = SombraTask::account_from_user(value),
+                "Enabled" => enabled = value != "False",
+                "Trigger" => trigger = value.contains("MSFT_TaskBootTrigger"),
+                "Delay" => delayed = !value.is_empty(),
+                "RestartCount" => restart_count = value.parse().unwrap_or(0),
+                "RestartInterval" => restart_interval = SombraTask::duration_from_task(value),
+                "Priority" => config.priority = value.parse().ok()
+                    .and_then(SombraTask::priority_from_task),
+                _ => {},
+            }
+        }
+        config.start_type = match (enabled, trigger, delayed) {
+            (false, _, _) => StartType::Disabled,
+            (true, true, true) => StartType::DelayedAutoStart,
+            (true, true, false) => StartType::AutoStart,
+            (true, false, _) => StartType::OnDemand,
+        };
+        if let (true, Some(interval)) = (restart_count > 0, restart_interval) {
+            config.restart_policy = Some(SombraTask::comparable_restarts(&RestartPolicy {
+                max_restarts: restart_count,
+                window: Duration::default(),
+                initial_delay: interval,
+                max_delay: interval,
+            }));
+        }
+        config
+    }
+
+    fn comparable_restarts(policy: &RestartPolicy) -> RestartPolicy {
+        let (max_restarts, interval) = SombraTask::task_restarts(policy);
+        RestartPolicy {
+            max_restarts,
+            window: Duration::default(),
+            initial_delay: interval,
+            max_delay: interval,
+        }
+    }
+
+    /// The settings `config()` reads back, the others left at their
+    /// defaults, to compare with the installed task
+    fn comparable(config: &SombraConfig) -> SombraConfig {
+        SombraConfig {
+            path: config.path.clone(),
+            args: config.args.clone(),
+            description: config.description.clone(),
+            start_type: config.start_type,
+            account: config.account.clone(),
+            working_directory: config.working_directory.clone(),
+            priority: config.priority,
+            restart_policy: config.restart_policy.as_ref().map(SombraTask::comparable_restarts),
+            ..SombraConfig::new(&config.name, "")
+        }
+    }
+
+    fn is_installed(&self) -> crate::Result<()> {
+        match self.status()? {
+            ServiceStatus::NotInstalled => Err(crate::Error::new(
+                crate::ErrorKind::ServiceNotFound,
+                format!("Task {} does not exist", self.config.name))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Key keeping what the task itself can't, e.g. the target checksum
+    fn metadata(&self) -> Reg {
+        Reg::task(&self.config.name)
+    }
+
+    fn register(&self) -> crate::Result<()> {
+        SombraTask::check(&self.config)?;
+        let mut config = self.config.clone();
+        config.account = self.config.account_with_password()?;
+        powershell::run(&SombraTask::register_script(&config))?;
+        let metadata = self.metadata();
+        match crate::digest::sha256_file(&self.config.path) {
+            Ok(checksum) => metadata.set_string("TargetSha256", &checksum),
+            Err(_) => metadata.delete_value("TargetSha256"),
+        }
+    }
+}
+
+impl Sombra for SombraTask {
+    fn from_config(config: SombraConfig) -> crate::Result<Self> {
+        Ok(SombraTask { config })
+    }
+
+    fn create(&self) -> crate::Result<()> {
+        if self.exists()? {
+            return Err(crate::Error::new(crate::ErrorKind::ServiceAlreadyExists,
+                                         format!("Task {} already exist", self.config.name)));
+        }
+        self.register()?;
+        if self.config.start_type == StartType::Disabled || self.config.target_pending() {
+            return Ok(());
+        }
+        self.start()?;
+        match &self.config.readiness {
+            Some(readiness) => readiness.wait(),
+            None => Ok(()),
+        }
+    }
+
+    fn delete(&self) -> crate::Result<()> {
+        if self.status()? == ServiceStatus::Running {
+            self.stop()?;
+        }
+        powershell::run(&format!("Unregister-ScheduledTask {} -Confirm:$false",
+                                 self.task_arguments()))?;
+        let metadata = self.metadata();
+        if metadata.exists() {
+            metadata.delete()?;
+        }
+        Ok(())
+    }
+
+    fn update(&self) -> crate::Result<()> {
+        self.is_installed()?;
+        self.register()
+    }
+
+    fn start(&self) -> crate::Result<()> {
+        self.config.check_target()?;
+        self.is_installed()?;
+        powershell::run(&format!("Start-ScheduledTask {}", self.task_arguments()))?;
+        Ok(())
+    }
+
+    fn stop(&self) -> crate::Result<()> {
+        self.is_installed()?;
+        powershell::run(&format!("Stop-ScheduledTask {}", self.task_arguments()))?;
+        Ok(())
+    }
+
+    fn status(&self) -> crate::Result<ServiceStatus> {
+        let state = powershell::run(&format!(
+            "$task = Get-ScheduledTask {} -ErrorAction SilentlyContinue; \
+             if ($task) {{ $task.State }} else {{ 'NotInstalled' }}",
+            self.task_arguments()))?;
+        Ok(match state.as_str() {
+            "NotInstalled" => ServiceStatus::NotInstalled,
+            "Running" => ServiceStatus::Running,
+            "Queued" => ServiceStatus::StartPending,
+            _ => ServiceStatus::Stopped,
+        })
+    }
+
+    /// The Task Scheduler doesn't report the processes of a task
+    fn pid(&self) -> crate::Result<Option<u32>> {
+        Ok(None)
+    }
+
+    fn usage(&self) -> crate::Result<Option<Usage>> {
+        Ok(None)
+    }
+
+    fn stats(&self) -> crate::Result<ServiceStats> {
+        self.is_installed()?;
+        let output = powershell::run(&format!(
+            "$info = Get-ScheduledTaskInfo {}; \
+             \"$(([DateTimeOffset]$info.LastRunTime).ToUnixTimeSeconds()) $($info.LastTaskResult)\"",
+            self.task_arguments()))?;
+        let mut columns = output.split_whitespace().map(|column| column.parse::<i64>().ok());
+        let (last_run, last_result) = match (columns.next(), columns.next()) {
+            (Some(last_run), Some(last_result)) => (last_run, last_result),
+            _ => return Err(crate::Error::new(crate::ErrorKind::Other,
+                                              "Cannot parse task info".to_string())
+                .content(output)),
+        };
+        // SCHED_S_TASK_RUNNING and SCHED_S_TASK_HAS_NOT_RUN aren't exits
+        let last_exit = last_result.filter(|code| *code != 0x41301 && *code != 0x41303)
+            .map(crate::ExitReason::Code);
+        let started_at = match self.status()? {
+            ServiceStatus::Running => last_run.filter(|seconds| *seconds > 0)
+                .map(|seconds| std::time::UNIX_EPOCH + Duration::from_secs(seconds as u64)),
+            _ => None,
+        };
+        Ok(ServiceStats { started_at, restarts: 0, last_exit })
+    }
+
+    fn config(&self) -> crate::Result<SombraConfig> {
+        self.is_installed()?;
+        let output = powershell::run(&format!(
+            "$task = Get-ScheduledTask {}; $action = $task.Actions[0]; \
+             \"Execute=$($action.Execute)\"; \"Arguments=$($action.Arguments)\"; \
+             \"WorkingDirectory=$($action.WorkingDirectory)\"; \
+             \"Description=$($task.Description)\"; \"UserId=$($task.Principal.UserId)\"; \
+             \"Enabled=$($task.Settings.Enabled)\"; \
+             \"Trigger=$(@($task.Triggers | ForEach-Object {{ $_.CimClass.CimClassName }}) -join ',')\"; \
+             \"Delay=$(@($task.Triggers | ForEach-Object {{ $_.Delay }}) -join '')\"; \
+             \"RestartCount=$($task.Settings.RestartCount)\"; \
+             \"RestartInterval=$($task.Settings.RestartInterval)\"; \
+             \"Priority=$($task.Settings.Priority)\"",
+            self.task_arguments()))?;
+        Ok(SombraTask::config_from_task(&self.config.name, &output))
+    }
+
+    fn diff(&self) -> crate::Result<Vec<Difference>> {
+        Ok(SombraTask::comparable(&self.config).diff(&self.config()?))
+    }
+
+    fn checksum(&self) -> crate::Result<Option<String>> {
+        self.is_installed()?;
+        Ok(self.metadata().query("TargetSha256"))
+    }
+
+    fn list_managed() -> crate::Result<Vec<ManagedService>> {
+        let output = powershell::run(&format!(
+            "Get-ScheduledTask -TaskPath {} -ErrorAction SilentlyContinue | \
+             ForEach-Object {{ \"$($_.TaskName)|$($_.State)|$($_.Actions[0].Execute)\" }}",
+            quote(TASK_PATH)))?;
+        let mut services: Vec<ManagedService> = output.lines()
+            .filter_map(|line| {
+                let mut columns = line.trim().splitn(3, '|');
+                let (name, state, path) = (columns.next()?, columns.next()?, columns.next()?);
+                Some(ManagedService {
+                    name: name.to_string(),
+                    status: match state {
+                        "Running" => ServiceStatus::Running,
+                        "Queued" => ServiceStatus::StartPending,
+                        _ => ServiceStatus::Stopped,
+                    },
+                    path: PathBuf::from(path),
+                })
+            })
+            .collect();
+        services.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(services)
+    }
+
+    fn logs(&self, follow: bool) -> crate::Result<LogReader> {
+        LogReader::files(self.config.log_files(), follow)
+    }
+
+    /// The PowerShell registering the task, with a placeholder for the
+    /// password of the account
+    fn render(&self) -> crate::Result<Vec<Artifact>> {
+        SombraTask::check(&self.config)?;
+        let mut config = self.config.clone();
+        let stored = config.password_secret.is_some();
+        if let Account::User { password, .. } = &mut config.account {
+            if password.is_some() || stored {
+                *password = Some(SecretString::from("<password>"));
+            }
+        }
+        Ok(vec![Artifact::registration(SombraTask::register_script(&config))])
+    }
+
+    fn export(&self) -> crate::Result<Scripts> {
+        let mut script = PowerShell::new(&self.config.name);
+        for artifact in self.render()? {
+            script.install(artifact.content);
+        }
+        if let Ok(checksum) = crate::digest::sha256_file(&self.config.path) {
+            script.install_native("reg.exe", &self.metadata()
+                .set_args("TargetSha256", &Value::String(checksum)));
+        }
+        if self.config.start_type != StartType::Disabled {
+            script.install(format!("Start-ScheduledTask {}", self.task_arguments()));
+        }
+        script.uninstall(format!("Stop-ScheduledTask {}", self.task_arguments()));
+        script.uninstall(format!("Unregister-ScheduledTask {} -Confirm:$false",
+                                 self.task_arguments()));
+        script.uninstall_native("reg.exe", &self.metadata().delete_args());
+        Ok(script.finish())
+    }
+}
+
+#[cfg(test)]
+#[cfg(target_os = "windows")]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn register_script_declares_task() {
+        let mut config = SombraConfig::new("tcp_echo", "C:\\Program Files\\echo\\tcp_echo.exe");
+        config.args = vec!["-p".into(), "it's".into()];
+        config.working_directory = Some(PathBuf::from("C:\\Program Files\\echo"));
+        config.start_type = StartType::DelayedAutoStart;
+        config.restart_policy = Some(RestartPolicy {
+            max_restarts: 3,
+            window: Duration::from_secs(600),
+            initial_delay: Duration::from_secs(5),
+            max_delay: Duration::from_secs(60),
+        });
+        assert_eq!(SombraTask::register_script(&config),
+                   "$action = New-ScheduledTaskAction \
+                    -Execute 'C:\\Program Files\\echo\\tcp_echo.exe' -Argument '-p it''s' \
+                    -WorkingDirectory 'C:\\Program Files\\echo'\n\
+                    $settings = New-ScheduledTaskSettingsSet -ExecutionTimeLimit ([TimeSpan]::Zero) \
+                    -MultipleInstances IgnoreNew -AllowStartIfOnBatteries \
+                    -DontStopIfGoingOnBatteries -Priority 4 \
+                    -RestartCount 3 -RestartInterval (New-TimeSpan -Seconds 60)\n\
+                    $trigger = New-ScheduledTaskTrigger -AtStartup\n\
+                    $trigger.Delay = 'PT2M'\n\
+                    $principal = New-ScheduledTaskPrincipal -UserId 'SYSTEM' \
+                    -LogonType ServiceAccount -RunLevel Highest\n\
+                    Register-ScheduledTask -TaskPath '\\Sombra\\' -TaskName 'tcp_echo' \
+                    -Action $action -Settings $settings -Force -Trigger $trigger \
+                    -Principal $principal | Out-Null");
+
+        config.account = Account::User {
+            name: "sombra".to_string(),
+            password: Some(SecretString::from("secret")),
+        };
+        assert!(SombraTask::register_script(&config)
+            .ends_with(" -User 'sombra' -Password 'secret' -RunLevel Highest | Out-Null"));
+    }
+
+    #[test]
+    fn config_from_task_inverts_register() {
+        let output = "Execute=C:\\echo\\tcp_echo.exe\n\
+                      Arguments=-p \"30 200\"\n\
+                      WorkingDirectory=C:\\echo\n\
+                      Description=\n\
+                      UserId=SYSTEM\n\
+                      Enabled=True\n\
+                      Trigger=MSFT_TaskBootTrigger\n\
+                      Delay=PT2M\n\
+                      RestartCount=3\n\
+                      RestartInterval=PT1M\n\
+                      Priority=4\n";
+        let mut config = SombraConfig::new("tcp_echo", "C:\\echo\\tcp_echo.exe");
+        config.args = vec!["-p".into(), "30 200".into()];
+        config.working_directory = Some(PathBuf::from("C:\\echo"));
+        config.start_type = StartType::DelayedAutoStart;
+        config.restart_policy = Some(RestartPolicy {
+            max_restarts: 3,
+            window: Duration::from_secs(600),
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+        });
+        assert_eq!(SombraTask::comparable(&config).diff(
+            &SombraTask::config_from_task("tcp_echo", output)), vec![]);
+    }
+
+    #[test]
+    fn task_durations_parse() {
+        assert_eq!(SombraTask::duration_from_task("PT1H2M3S"), Some(Duration::from_secs(3723)));
+        assert_eq!(SombraTask::duration_from_task("PT30S"), Some(Duration::from_secs(30)));
+        assert_eq!(SombraTask::duration_from_task("P1D"), None);
+    }
+
+    #[test]
+    fn check_rejects_wrapper_options() {
+        let mut config = SombraConfig::new("tcp_echo", "C:\\echo\\tcp_echo.exe");
+        assert_eq!(SombraTask::check(&config), Ok(()));
+        config.env = vec![("RUST_LOG".to_string(), "debug".to_string())];
+        assert_eq!(SombraTask::check(&config).map_err(|e| e.kind()),
+                   Err(crate::ErrorKind::Other));
+    }
+}
@@ -0,0 +1,72 @@
+use std::io::{BufRead, BufReader, Write};
+use std::sync::{Arc, Mutex};
+use crate::{ControlClient, Request};
+use super::output::Recent;
+use super::pipe::PipeServer;
+
+/// What the control pipe tells about the target, kept by the supervision
+/// loop
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(super) struct TargetState {
+    /// While it runs
+    pub(super) pid: Option<u32>,
+    /// Of its last run, once it exited
+    pub(super) exit_code: Option<u32>,
+}
+
+pub(super) type State = Arc<Mutex<TargetState>>;
+
+fn handle(request: Request, state: &State, recent: &Recent) -> Result<Vec<String>, String> {
+    let state = *state.lock().unwrap();
+    match request {
+        Request::Pid => Ok(state.pid.iter().map(|pid| pid.to_string()).collect()),
+        Request::ExitCode => Ok(state.exit_code.iter().map(|code| code.to_string()).collect()),
+        Request::Logs(lines) => Ok(recent.tail(lines)),
+        // Windows has no signal asking a process to reload
+        Request::Reload => Err("The target of a Windows service can't reload".to_string()),
+    }
+}
+
+/// Answers the requests of `ControlClient` on the endpoint of the service,
+/// one client at a time
+pub(super) fn listen(name: &str, state: State, recent: Recent) -> crate::Result<()> {
+    let mut server = PipeServer::duplex(&ControlClient::endpoint(name).to_string_lossy(), name)?;
+    std::thread::spawn(move || {
+        while let Ok(client) = server.accept() {
+            let mut line = String::new();
+            if BufReader::new(&client).read_line(&mut line).is_err() {
+                continue;
+            }
+            let answer = crate::control::answer(line.trim_end(), |request| {
+                handle(request, &state, &recent)
+            });
+            let _ = (&client).write_all(answer.as_bytes());
+            // Closing the pipe before the client read the answer drops it
+            let _ = client.sync_all();
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn answers_from_the_state_of_the_target() {
+        let state = State::default();
+        let recent = Recent::default();
+        let answer = |line: &str| crate::control::answer(line, |request| {
+            handle(request, &state, &recent)
+        });
+        assert_eq!(answer("PID"), "OK\n");
+        assert_eq!(answer("EXIT"), "OK\n");
+
+        *state.lock().unwrap() = TargetState { pid: Some(42), exit_code: None };
+        assert_eq!(answer("PID"), "OK\n42\n");
+        *state.lock().unwrap() = TargetState { pid: None, exit_code: Some(3) };
+        assert_eq!(answer("EXIT"), "OK\n3\n");
+        assert_eq!(answer("LOGS 5"), "OK\n");
+        assert!(answer("RELOAD").starts_with("ERR "));
+    }
+}
@@ -0,0 +1,112 @@
+use std::ffi::{c_void, OsStr};
+use std::os::windows::ffi::OsStrExt;
+use std::os::windows::io::RawHandle;
+use std::path::Path;
+
+#[link(name = "advapi32")]
+extern "system" {
+    fn RegisterEventSourceW(server: *const u16, source: *const u16) -> RawHandle;
+    fn ReportEventW(event_log: RawHandle, kind: u16, category: u16, event_id: u32,
+                    user_sid: *mut c_void, num_strings: u16, data_size: u32,
+                    strings: *const *const u16, raw_data: *mut c_void) -> i32;
+    fn DeregisterEventSource(event_log: RawHandle) -> i32;
+}
+
+const EVENTLOG_ERROR_TYPE: u16 = 0x1;
+const EVENTLOG_WARNING_TYPE: u16 = 0x2;
+const EVENTLOG_INFORMATION_TYPE: u16 = 0x4;
+
+/// What happened to the target. The ids are within the 1 to 1000 that
+/// the message table of EventCreate.exe prints as their first string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum Lifecycle {
+    Started { pid: u32 },
+    Stopped,
+    Exited { code: u32 },
+    NotStarted { error: String },
+    Restarted { reason: String },
+    /// The restart policy gave up on the target
+    GaveUp { reason: String },
+}
+
+impl Lifecycle {
+    /// The type, id and message of the event
+    fn event(&self, path: &Path) -> (u16, u32, String) {
+        let path = path.display();
+        match self {
+            Lifecycle::Started { pid } =>
+                (EVENTLOG_INFORMATION_TYPE, 1, format!("Started {} as process {}", path, pid)),
+            Lifecycle::Stopped => (EVENTLOG_INFORMATION_TYPE, 2, format!("Stopped {}", path)),
+            Lifecycle::Exited { code: 0 } =>
+                (EVENTLOG_INFORMATION_TYPE, 3, format!("{} exited", path)),
+            Lifecycle::Exited { code } =>
+                (EVENTLOG_ERROR_TYPE, 4, format!("{} exited with code {:#x}", path, code)),
+            Lifecycle::NotStarted { error } =>
+                (EVENTLOG_ERROR_TYPE, 5, format!("Failed to start {}: {}", path, error)),
+            Lifecycle::Restarted { reason } =>
+                (EVENTLOG_WARNING_TYPE, 6, format!("Restarting {}: {}", path, reason)),
+            Lifecycle::GaveUp { reason } =>
+                (EVENTLOG_ERROR_TYPE, 7, format!("Gave up restarting {}: {}", path, reason)),
+        }
+    }
+}
+
+fn wide(s: &str) -> Vec<u16> {
+    OsStr::new(s).encode_wide().chain(Some(0)).collect()
+}
+
+/// The Application log, under the source registered for the service
+pub(super) struct EventLog {
+    handle: Option<RawHandle>,
+}
+
+impl EventLog {
+    /// Reports nothing unless the service has `event_log`
+    pub(super) fn open(source: &str, enabled: bool) -> Self {
+        let handle = match enabled {
+            true => Some(unsafe { RegisterEventSourceW(std::ptr::null(), wide(source).as_ptr()) })
+                .filter(|handle| !handle.is_null()),
+            false => None,
+        };
+        EventLog { handle }
+    }
+
+    pub(super) fn report(&self, lifecycle: &Lifecycle, path: &Path) {
+        let handle = match self.handle {
+            Some(handle) => handle,
+            None => return,
+        };
+        let (kind, id, message) = lifecycle.event(path);
+        let message = wide(&message);
+        let strings = [message.as_ptr()];
+        unsafe {
+            ReportEventW(handle, kind, 0, id, std::ptr::null_mut(), 1, 0, strings.as_ptr(),
+                         std::ptr::null_mut());
+        }
+    }
+}
+
+impl Drop for EventLog {
+    fn drop(&mut self) {
+        if let Some(handle) = self.handle {
+            unsafe { DeregisterEventSource(handle) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lifecycle_events() {
+        let path = Path::new("C:\\tcp_echo.exe");
+        assert_eq!(Lifecycle::Started { pid: 42 }.event(path),
+                   (EVENTLOG_INFORMATION_TYPE, 1, "Started C:\\tcp_echo.exe as process 42"
+                       .to_string()));
+        assert_eq!(Lifecycle::Exited { code: 0 }.event(path).0, EVENTLOG_INFORMATION_TYPE);
+        assert_eq!(Lifecycle::Exited { code: 0xC0000005 }.event(path),
+                   (EVENTLOG_ERROR_TYPE, 4, "C:\\tcp_echo.exe exited with code 0xc0000005"
+                       .to_string()));
+    }
+}
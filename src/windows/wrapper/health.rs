@@ -0,0 +1,45 @@
+use std::sync::mpsc::{self, Sender};
+use std::thread::JoinHandle;
+use crate::HealthCheck;
+use super::Event;
+
+/// Runs the health check of the target, sending `Event::Unhealthy` once it
+/// fails `failure_threshold` times in a row. Each run of the target gets
+/// its own, and a paused target none.
+pub(super) struct Health {
+    stop: Sender<()>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Health {
+    pub(super) fn spawn(check: HealthCheck, events: Sender<Event>) -> Self {
+        let (stop, stopped) = mpsc::channel();
+        let thread = std::thread::spawn(move || {
+            let mut failures = 0;
+            while stopped.recv_timeout(check.interval).is_err() {
+                let error = match check.probe.check(check.timeout) {
+                    Ok(()) => {
+                        failures = 0;
+                        continue;
+                    },
+                    Err(e) => e,
+                };
+                failures += 1;
+                if failures >= check.failure_threshold {
+                    let _ = events.send(Event::Unhealthy(error.to_string()));
+                    return;
+                }
+            }
+        });
+        Health { stop, thread: Some(thread) }
+    }
+}
+
+impl Drop for Health {
+    fn drop(&mut self) {
+        let _ = self.stop.send(());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
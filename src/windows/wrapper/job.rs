@@ -0,0 +1,219 @@
+use std::ffi::c_void;
+use std::os::windows::io::{AsRawHandle, RawHandle};
+use std::process::Child;
+use crate::SombraConfig;
+
+#[link(name = "kernel32")]
+extern "system" {
+    fn CreateJobObjectW(attributes: *mut c_void, name: *const u16) -> RawHandle;
+    fn SetInformationJobObject(job: RawHandle, class: i32, info: *const c_void, len: u32) -> i32;
+    fn QueryInformationJobObject(job: RawHandle, class: i32, info: *mut c_void, len: u32,
+                                 return_len: *mut u32) -> i32;
+    fn AssignProcessToJobObject(job: RawHandle, process: RawHandle) -> i32;
+    fn TerminateJobObject(job: RawHandle, exit_code: u32) -> i32;
+    fn OpenProcess(access: u32, inherit: i32, process_id: u32) -> RawHandle;
+    fn CloseHandle(handle: RawHandle) -> i32;
+}
+
+#[link(name = "ntdll")]
+extern "system" {
+    fn NtSuspendProcess(process: RawHandle) -> i32;
+    fn NtResumeProcess(process: RawHandle) -> i32;
+}
+
+const JOB_OBJECT_BASIC_PROCESS_ID_LIST: i32 = 3;
+const JOB_OBJECT_EXTENDED_LIMIT_INFORMATION: i32 = 9;
+const JOB_OBJECT_CPU_RATE_CONTROL_INFORMATION: i32 = 15;
+const JOB_OBJECT_LIMIT_JOB_MEMORY: u32 = 0x0200;
+const JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE: u32 = 0x2000;
+const JOB_OBJECT_CPU_RATE_CONTROL_ENABLE: u32 = 0x1;
+const JOB_OBJECT_CPU_RATE_CONTROL_HARD_CAP: u32 = 0x4;
+const PROCESS_SUSPEND_RESUME: u32 = 0x0800;
+
+#[repr(C)]
+#[derive(Default)]
+struct BasicLimitInformation {
+    per_process_user_time_limit: i64,
+    per_job_user_time_limit: i64,
+    limit_flags: u32,
+    minimum_working_set_size: usize,
+    maximum_working_set_size: usize,
+    active_process_limit: u32,
+    affinity: usize,
+    priority_class: u32,
+    scheduling_class: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct ExtendedLimitInformation {
+    basic_limit_information: BasicLimitInformation,
+    io_info: [u64; 6],
+    process_memory_limit: usize,
+    job_memory_limit: usize,
+    peak_process_memory_used: usize,
+    peak_job_memory_used: usize,
+}
+
+#[repr(C)]
+struct CpuRateControlInformation {
+    control_flags: u32,
+    /// In hundredths of a percent of all the CPUs
+    cpu_rate: u32,
+}
+
+/// Job CPU rate for `cpu_quota` percent of one CPU out of `cpus`
+fn cpu_rate(cpu_quota: u32, cpus: u32) -> u32 {
+    (cpu_quota as u64 * 100 / cpus.max(1) as u64).clamp(1, 10000) as u32
+}
+
+/// As many processes as a job lists at once
+const MAX_PROCESSES: usize = 1024;
+
+#[repr(C)]
+struct ProcessIdList {
+    number_of_assigned_processes: u32,
+    number_of_process_ids_in_list: u32,
+    process_id_list: [usize; MAX_PROCESSES],
+}
+
+fn check(result: i32) -> crate::Result<()> {
+    match result {
+        0 => Err(std::io::Error::last_os_error().into()),
+        _ => Ok(()),
+    }
+}
+
+fn nt_result(status: i32) -> crate::Result<()> {
+    match status {
+        0 => Ok(()),
+        status => Err(crate::Error::new(crate::ErrorKind::Io,
+                                        format!("NTSTATUS {:#010x}", status))),
+    }
+}
+
+/// The job object holding the target and the processes it spawns
+pub(super) struct Job {
+    handle: RawHandle,
+}
+
+impl Job {
+    /// A job with the resource limits of `config`. With
+    /// `kill_process_tree`, closing the job, which the end of the wrapper
+    /// does too, kills every process in it.
+    pub(super) fn new(config: &SombraConfig) -> crate::Result<Self> {
+        let handle = unsafe { CreateJobObjectW(std::ptr::null_mut(), std::ptr::null()) };
+        if handle.is_null() {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        let job = Job { handle };
+        let resource_limits = config.resource_limits.unwrap_or_default();
+        let mut limits = ExtendedLimitInformation::default();
+        if config.kill_process_tree {
+            limits.basic_limit_information.limit_flags |= JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+        }
+        if let Some(memory_max) = resource_limits.memory_max {
+            limits.basic_limit_information.limit_flags |= JOB_OBJECT_LIMIT_JOB_MEMORY;
+            limits.job_memory_limit = memory_max as usize;
+        }
+        job.set(JOB_OBJECT_EXTENDED_LIMIT_INFORMATION, &limits)?;
+        if let Some(cpu_quota) = resource_limits.cpu_quota {
+            let cpus = std::thread::available_parallelism().map_or(1, |cpus| cpus.get() as u32);
+            job.set(JOB_OBJECT_CPU_RATE_CONTROL_INFORMATION, &CpuRateControlInformation {
+                control_flags: JOB_OBJECT_CPU_RATE_CONTROL_ENABLE |
+                    JOB_OBJECT_CPU_RATE_CONTROL_HARD_CAP,
+                cpu_rate: cpu_rate(cpu_quota, cpus),
+            })?;
+        }
+        Ok(job)
+    }
+
+    fn set<T>(&self, class: i32, info: &T) -> crate::Result<()> {
+        check(unsafe {
+            SetInformationJobObject(self.handle, class, info as *const T as *const c_void,
+                                    std::mem::size_of::<T>() as u32)
+        })
+    }
+
+    /// The processes it spawns from now on join the job as well
+    pub(super) fn assign(&self, child: &Child) -> crate::Result<()> {
+        check(unsafe { AssignProcessToJobObject(self.handle, child.as_raw_handle()) })
+    }
+
+    /// Kills every process in the job
+    pub(super) fn terminate(&self, exit_code: u32) -> crate::Result<()> {
+        check(unsafe { TerminateJobObject(self.handle, exit_code) })
+    }
+
+    fn processes(&self) -> crate::Result<Vec<u32>> {
+        let mut list = Box::new(ProcessIdList {
+            number_of_assigned_processes: 0,
+            number_of_process_ids_in_list: 0,
+            process_id_list: [0; MAX_PROCESSES],
+        });
+        check(unsafe {
+            QueryInformationJobObject(self.handle, JOB_OBJECT_BASIC_PROCESS_ID_LIST,
+                                      &mut *list as *mut ProcessIdList as *mut c_void,
+                                      std::mem::size_of::<ProcessIdList>() as u32,
+                                      std::ptr::null_mut())
+        })?;
+        Ok(list.process_id_list[..list.number_of_process_ids_in_list as usize].iter()
+            .map(|&pid| pid as u32)
+            .collect())
+    }
+
+    /// Suspends or resumes the threads of every process in the job. A
+    /// process exiting meanwhile is skipped.
+    fn each(&self, action: unsafe extern "system" fn(RawHandle) -> i32) -> crate::Result<()> {
+        for pid in self.processes()? {
+            let process = unsafe { OpenProcess(PROCESS_SUSPEND_RESUME, 0, pid) };
+            if process.is_null() {
+                continue;
+            }
+            let status = unsafe { action(process) };
+            unsafe { CloseHandle(process) };
+            nt_result(status)?;
+        }
+        Ok(())
+    }
+
+    pub(super) fn suspend(&self) -> crate::Result<()> {
+        self.each(NtSuspendProcess)
+    }
+
+    pub(super) fn resume(&self) -> crate::Result<()> {
+        self.each(NtResumeProcess)
+    }
+
+    /// Resumes a process created suspended, once it is in the job
+    pub(super) fn start(child: &Child) -> crate::Result<()> {
+        nt_result(unsafe { NtResumeProcess(child.as_raw_handle()) })
+    }
+}
+
+impl Drop for Job {
+    fn drop(&mut self) {
+        unsafe { CloseHandle(self.handle) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn structures_match_the_windows_sdk() {
+        assert_eq!(std::mem::size_of::<BasicLimitInformation>(), 64);
+        assert_eq!(std::mem::size_of::<ExtendedLimitInformation>(), 144);
+        assert_eq!(std::mem::size_of::<ProcessIdList>(), 8 + 8 * MAX_PROCESSES);
+    }
+
+    #[test]
+    fn cpu_rate_scales_the_quota_to_all_cpus() {
+        assert_eq!(cpu_rate(50, 1), 5000);
+        assert_eq!(cpu_rate(200, 8), 2500);
+        assert_eq!(cpu_rate(400, 2), 10000);
+        assert_eq!(cpu_rate(1, 128), 1);
+    }
+}
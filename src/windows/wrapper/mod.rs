@@ -0,0 +1,467 @@
+//! sombra-windows-service.exe, the wrapper running the target of a
+//! `SombraWindows` service. The SCM starts it with the target and its
+//! arguments, as start parameters or on its command line; it runs the
+//! target as its child and answers the controls of the SCM on its behalf.
+
+mod control;
+mod event_log;
+mod health;
+mod job;
+mod notify;
+mod output;
+mod pipe;
+mod process;
+mod restart;
+
+use std::ffi::OsString;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use windows_service::service::{ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState,
+                               ServiceStatus, ServiceType};
+use windows_service::service_control_handler::{self, ServiceControlHandlerResult,
+                                               ServiceStatusHandle};
+use windows_service::service_dispatcher;
+use crate::SombraConfig;
+use crate::windows::reg::{Reg, Value};
+use crate::windows::sombra_imp::SombraWindows;
+use self::control::{State, TargetState};
+use self::event_log::{EventLog, Lifecycle};
+use self::health::Health;
+use self::output::Output;
+use self::process::Target;
+use self::restart::Restarts;
+
+static EXIT_CODE: AtomicI32 = AtomicI32::new(0);
+
+const ERROR_FAILED_SERVICE_CONTROLLER_CONNECT: i32 = 1063;
+/// How often the wrapper checks whether the target exited
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// What the SCM waits for a service to stop by default
+const DEFAULT_STOP_TIMEOUT: Duration = Duration::from_secs(20);
+/// What systemd waits for a notifying service to be ready by default
+const DEFAULT_START_TIMEOUT: Duration = Duration::from_secs(90);
+const ERROR_SERVICE_REQUEST_TIMEOUT: u32 = 1053;
+
+windows_service::define_windows_service!(ffi_service_main, service_main);
+
+/// Controls of the SCM and findings of the wrapper, handled by the
+/// supervision loop
+pub(super) enum Event {
+    Stop,
+    Pause,
+    Continue,
+    /// The health check failed, for this reason
+    Unhealthy(String),
+    /// The target sent this sd_notify state
+    Notified(String),
+}
+
+/// Reports the state of the service to the SCM
+struct Reporter {
+    handle: ServiceStatusHandle,
+    checkpoint: u32,
+}
+
+impl Reporter {
+    fn report(&mut self, current_state: ServiceState, exit_code: ServiceExitCode) {
+        let pending = matches!(current_state,
+                               ServiceState::StartPending | ServiceState::StopPending |
+                               ServiceState::PausePending | ServiceState::ContinuePending);
+        // The SCM expects the checkpoint to grow while the state is pending
+        self.checkpoint = if pending { self.checkpoint + 1 } else { 0 };
+        let controls_accepted = match current_state {
+            ServiceState::Running | ServiceState::Paused =>
+                ServiceControlAccept::STOP | ServiceControlAccept::PAUSE_CONTINUE |
+                    ServiceControlAccept::SHUTDOWN,
+            _ => ServiceControlAccept::empty(),
+        };
+        let _ = self.handle.set_service_status(ServiceStatus {
+            service_type: ServiceType::OWN_PROCESS,
+            current_state,
+            controls_accepted,
+            exit_code,
+            checkpoint: self.checkpoint,
+            wait_hint: if pending { Duration::from_secs(5) } else { Duration::default() },
+            process_id: None,
+        });
+    }
+}
+
+fn service_main(arguments: Vec<OsString>) {
+    // The name of the service, then the start parameters
+    let mut arguments = arguments.into_iter();
+    let name = arguments.next().map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let (sender, events) = mpsc::channel();
+    let findings = sender.clone();
+    let handle = match service_control_handler::register(&name, move |control| {
+        let event = match control {
+            ServiceControl::Stop | ServiceControl::Shutdown => Event::Stop,
+            ServiceControl::Pause => Event::Pause,
+            ServiceControl::Continue => Event::Continue,
+            ServiceControl::Interrogate => return ServiceControlHandlerResult::NoError,
+            _ => return ServiceControlHandlerResult::NotImplemented,
+        };
+        let _ = sender.send(event);
+        ServiceControlHandlerResult::NoError
+    }) {
+        Ok(handle) => handle,
+        Err(e) => {
+            eprintln!("{}", crate::Error::from(e));
+            EXIT_CODE.store(1, Ordering::SeqCst);
+            return;
+        },
+    };
+    let mut reporter = Reporter { handle, checkpoint: 0 };
+    reporter.report(ServiceState::StartPending, ServiceExitCode::NO_ERROR);
+
+    // The SCM starts the service without parameters at boot, so the
+    // target and its arguments are also on the command line of the wrapper
+    let mut command: Vec<OsString> = arguments.collect();
+    if command.is_empty() {
+        command = std::env::args_os().skip(1).collect();
+    }
+    let exit_code = match supervise(&name, command, findings, &events, &mut reporter) {
+        Ok(exit_code) => exit_code,
+        Err(e) => match e.os_code() {
+            Some(code) => ServiceExitCode::Win32(code as u32),
+            None => ServiceExitCode::ServiceSpecific(1),
+        },
+    };
+    if exit_code != ServiceExitCode::NO_ERROR {
+        EXIT_CODE.store(1, Ordering::SeqCst);
+    }
+    reporter.report(ServiceState::Stopped, exit_code);
+}
+
+/// What the supervision loop finds out about a running target
+enum Check {
+    Running,
+    /// It has to restart, for this reason
+    Restart(String),
+    Exited(u32),
+}
+
+/// The target, and what the wrapper keeps across its restarts
+struct Supervisor {
+    config: SombraConfig,
+    output: Output,
+    event_log: EventLog,
+    events: Sender<Event>,
+    target: Target,
+    /// What the control pipe answers about the target
+    state: State,
+    health: Option<Health>,
+    /// Last WATCHDOG=1 of the target, or when the watchdog was last reset
+    heartbeat: Instant,
+    restarts: Option<Restarts>,
+    /// Restarts since the service started
+    restart_count: u32,
+    /// When the target is started again, once it was stopped for a restart
+    restart_at: Option<Instant>,
+}
+
+impl Supervisor {
+    fn new(config: SombraConfig, key: &str, events: Sender<Event>) -> crate::Result<Self> {
+        // The source is registered under the name of the Parameters key
+        let event_log = EventLog::open(key, config.event_log);
+        let output = Output::new(&config)?;
+        let state = State::default();
+        let target = Supervisor::spawn(&config, &output, &event_log, &state)?;
+        let restarts = config.restart_policy.map(Restarts::new);
+        let mut supervisor = Supervisor {
+            config,
+            output,
+            event_log,
+            events,
+            target,
+            state,
+            health: None,
+            heartbeat: Instant::now(),
+            restarts,
+            restart_count: 0,
+            restart_at: None,
+        };
+        publish(&supervisor.config.name, &[("RestartCount", Value::Dword(0))]);
+        supervisor.watch();
+        Ok(supervisor)
+    }
+
+    fn spawn(config: &SombraConfig, output: &Output, event_log: &EventLog, state: &State)
+             -> crate::Result<Target> {
+        let target = Target::spawn(config, output).inspect_err(|e| {
+            event_log.report(&Lifecycle::NotStarted { error: e.to_string() }, &config.path);
+        })?;
+        event_log.report(&Lifecycle::Started { pid: target.pid() }, &config.path);
+        *state.lock().unwrap() = TargetState { pid: Some(target.pid()), exit_code: None };
+        let started = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        publish(&config.name, &[("ChildProcessId", Value::Dword(target.pid())),
+                                ("ChildStartTime", Value::Qword(started.as_secs()))]);
+        Ok(target)
+    }
+
+    fn report(&self, lifecycle: &Lifecycle) {
+        self.event_log.report(lifecycle, &self.config.path);
+    }
+
+    /// Publishes the exit code of the target, which stats() reports as
+    /// its last exit
+    fn exited(&self, code: u32) {
+        *self.state.lock().unwrap() = TargetState { pid: None, exit_code: Some(code) };
+        publish(&self.config.name, &[("ChildExitCode", Value::Dword(code))]);
+    }
+
+    /// Starts the health check and the watchdog of the current run of the
+    /// target
+    fn watch(&mut self) {
+        self.health = self.config.health_check.clone()
+            .map(|check| Health::spawn(check, self.events.clone()));
+        self.heartbeat = Instant::now();
+    }
+
+    /// Whether the target missed its heartbeat
+    fn hung(&self) -> bool {
+        self.config.watchdog.is_some_and(|interval| self.heartbeat.elapsed() > interval)
+    }
+
+    /// Stops the target as `StopSignal` and `StopTimeout` say, calling
+    /// `waiting` while it waits. Returns the exit code of the target.
+    fn stop<F: FnMut()>(&mut self, waiting: F) -> crate::Result<u32> {
+        self.health = None;
+        let timeout = self.config.stop_timeout.unwrap_or(DEFAULT_STOP_TIMEOUT);
+        self.target.stop(self.config.stop_signal, timeout, waiting)
+    }
+
+    /// Stops the target for a restart, which `RestartPolicy` delays.
+    /// Returns the exit code of the target once the policy gives up.
+    fn restart(&mut self, reason: String) -> crate::Result<Option<u32>> {
+        let code = self.stop(|| {})?;
+        self.exited(code);
+        let delay = match &mut self.restarts {
+            Some(restarts) => match restarts.next(Instant::now()) {
+                Some(delay) => delay,
+                None => {
+                    self.report(&Lifecycle::GaveUp { reason });
+                    return Ok(Some(code));
+                },
+            },
+            None => Duration::default(),
+        };
+        self.report(&Lifecycle::Restarted { reason });
+        self.restart_count += 1;
+        publish(&self.config.name, &[("RestartCount", Value::Dword(self.restart_count))]);
+        self.restart_at = Some(Instant::now() + delay);
+        Ok(None)
+    }
+
+    /// Starts the target again once the delay of its restart passed
+    fn resume_restart(&mut self) -> crate::Result<()> {
+        if self.restart_at.is_some_and(|restart_at| Instant::now() >= restart_at) {
+            self.target = Supervisor::spawn(&self.config, &self.output, &self.event_log,
+                                            &self.state)?;
+            self.restart_at = None;
+            self.watch();
+        }
+        Ok(())
+    }
+
+    fn check(&mut self) -> crate::Result<Check> {
+        if self.hung() {
+            return Ok(Check::Restart("No WATCHDOG=1 within the watchdog interval".to_string()));
+        }
+        Ok(match self.target.exit_code()? {
+            // RestartPolicy restarts a target that failed
+            Some(code) if code != 0 && self.restarts.is_some() =>
+                Check::Restart(format!("Exited with code {:#x}", code)),
+            Some(code) => Check::Exited(code),
+            None => Check::Running,
+        })
+    }
+}
+
+/// Runs the target until it exits or the service is stopped, restarting
+/// it when its health check fails or it misses its heartbeat, and when it
+/// fails with `RestartPolicy`. A target exiting with an error otherwise,
+/// or failing more often than the policy allows, stops the service with
+/// its code, so status() reports it `Failed` and the failure actions of
+/// the SCM apply.
+fn supervise(name: &str, command: Vec<OsString>, sender: Sender<Event>,
+             events: &Receiver<Event>, reporter: &mut Reporter)
+             -> crate::Result<ServiceExitCode> {
+    let mut command = command.into_iter();
+    let path = command.next().ok_or_else(|| {
+        crate::Error::new(crate::ErrorKind::Other, "No target to run".to_string())
+            .content(name.to_string())
+    })?;
+    let mut config = SombraConfig::new(name, path);
+    config.args = command.collect();
+    let (key, values) = parameters(name);
+    SombraWindows::read_parameters(&mut config, &values);
+    if config.notify || config.watchdog.is_some() {
+        let pipe = notify::pipe_name(name);
+        notify::listen(&pipe, name, sender.clone())?;
+        config.env.push(("SOMBRA_NOTIFY_PIPE".to_string(), pipe));
+    }
+    if let Some(interval) = config.watchdog {
+        config.env.push(("WATCHDOG_USEC".to_string(), interval.as_micros().to_string()));
+    }
+
+    // Metrics of the service as the backend reports them, from the SCM and
+    // what the wrapper publishes
+    #[cfg(feature = "metrics")]
+    let _metrics = match config.metrics_port {
+        Some(port) => {
+            let probe = config.health_check.as_ref().map(|check| check.probe.clone());
+            let service = SombraConfig::new(name, &config.path);
+            let service = <SombraWindows as crate::Sombra>::from_config(service)?;
+            Some(crate::MetricsServer::new()
+                .service(name, service, probe)
+                .spawn(&format!("127.0.0.1:{}", port))?)
+        },
+        None => None,
+    };
+
+    let mut supervisor = Supervisor::new(config, &key, sender)?;
+    control::listen(name, supervisor.state.clone(), supervisor.output.recent())?;
+    // A notifying target is running once it sends READY=1, and is given
+    // StartTimeout for it
+    let start = Instant::now();
+    let start_timeout = supervisor.config.start_timeout.unwrap_or(DEFAULT_START_TIMEOUT);
+    let mut starting = supervisor.config.notify;
+    if !starting {
+        reporter.report(ServiceState::Running, ServiceExitCode::NO_ERROR);
+    }
+    let mut paused = false;
+    loop {
+        if starting && start.elapsed() > start_timeout {
+            supervisor.report(&Lifecycle::NotStarted {
+                error: "No READY=1 within the start timeout".to_string(),
+            });
+            reporter.report(ServiceState::StopPending, ServiceExitCode::NO_ERROR);
+            let code = supervisor.stop(|| {
+                reporter.report(ServiceState::StopPending, ServiceExitCode::NO_ERROR)
+            })?;
+            supervisor.exited(code);
+            return Ok(ServiceExitCode::Win32(ERROR_SERVICE_REQUEST_TIMEOUT));
+        }
+        match events.recv_timeout(POLL_INTERVAL) {
+            Ok(Event::Stop) | Err(RecvTimeoutError::Disconnected) => {
+                reporter.report(ServiceState::StopPending, ServiceExitCode::NO_ERROR);
+                // A suspended target can't handle the stop signal
+                if paused {
+                    supervisor.target.resume()?;
+                }
+                let code = supervisor.stop(|| {
+                    reporter.report(ServiceState::StopPending, ServiceExitCode::NO_ERROR)
+                })?;
+                supervisor.exited(code);
+                supervisor.report(&Lifecycle::Stopped);
+                return Ok(ServiceExitCode::NO_ERROR);
+            },
+            Ok(Event::Pause) if !paused => {
+                reporter.report(ServiceState::PausePending, ServiceExitCode::NO_ERROR);
+                // A suspended target fails its health check
+                supervisor.health = None;
+                supervisor.target.suspend()?;
+                paused = true;
+                reporter.report(ServiceState::Paused, ServiceExitCode::NO_ERROR);
+            },
+            Ok(Event::Continue) if paused => {
+                reporter.report(ServiceState::ContinuePending, ServiceExitCode::NO_ERROR);
+                supervisor.target.resume()?;
+                supervisor.watch();
+                paused = false;
+                reporter.report(ServiceState::Running, ServiceExitCode::NO_ERROR);
+            },
+            Ok(Event::Pause) | Ok(Event::Continue) => {},
+            Ok(Event::Unhealthy(reason)) if !paused && supervisor.restart_at.is_none() => {
+                if let Some(code) = supervisor.restart(reason)? {
+                    return Ok(ServiceExitCode::ServiceSpecific(code.max(1)));
+                }
+            },
+            Ok(Event::Unhealthy(_)) => {},
+            Ok(Event::Notified(state)) => match state.as_str() {
+                "READY=1" if starting => {
+                    starting = false;
+                    reporter.report(ServiceState::Running, ServiceExitCode::NO_ERROR);
+                },
+                "WATCHDOG=1" => supervisor.heartbeat = Instant::now(),
+                _ => {},
+            },
+            // The SCM gives up on a start whose checkpoint stalls
+            Err(RecvTimeoutError::Timeout) if starting =>
+                reporter.report(ServiceState::StartPending, ServiceExitCode::NO_ERROR),
+            Err(RecvTimeoutError::Timeout) => {},
+        }
+        // A suspended target sends no heartbeat, and restarts wait for
+        // the service to resume
+        if paused {
+            continue;
+        }
+        if supervisor.restart_at.is_some() {
+            supervisor.resume_restart()?;
+            continue;
+        }
+        match supervisor.check()? {
+            Check::Running => {},
+            Check::Restart(reason) => {
+                if let Some(code) = supervisor.restart(reason)? {
+                    return Ok(ServiceExitCode::ServiceSpecific(code.max(1)));
+                }
+            },
+            Check::Exited(code) => {
+                supervisor.exited(code);
+                supervisor.report(&Lifecycle::Exited { code });
+                return Ok(match code {
+                    0 => ServiceExitCode::NO_ERROR,
+                    code => ServiceExitCode::ServiceSpecific(code),
+                });
+            },
+        }
+    }
+}
+
+/// The name of the Parameters key of the service and its values, read in
+/// one query. The instances of a per-user service share the key of their
+/// template.
+fn parameters(name: &str) -> (String, Vec<(String, String)>) {
+    let template = name.rsplit_once('_')
+        .filter(|(template, _)| SombraWindows::is_instance(template, name));
+    if let Some((template, _)) = template {
+        let values = Reg::parameters(template).values();
+        if !values.is_empty() {
+            return (template.to_string(), values);
+        }
+    }
+    (name.to_string(), Reg::parameters(name).values())
+}
+
+/// Writes what the backend reads about the target to the Parameters key
+/// of the service itself. Only LocalSystem may write there, so it is a
+/// fallback of the control pipe, and failures are ignored.
+fn publish(name: &str, values: &[(&str, Value)]) {
+    let parameters = Reg::parameters(name);
+    for (value, data) in values {
+        let _ = parameters.set(value, data);
+    }
+}
+
+/// Hands the process to the service dispatcher, which returns once the
+/// service stopped
+pub fn run() -> i32 {
+    // The name is ignored for services running in their own process
+    match service_dispatcher::start("", ffi_service_main) {
+        Ok(()) => EXIT_CODE.load(Ordering::SeqCst),
+        Err(windows_service::Error::Winapi(e))
+            if e.raw_os_error() == Some(ERROR_FAILED_SERVICE_CONTROLLER_CONNECT) => {
+            eprintln!("sombra-windows-service runs the services created by SombraWindows, \
+                       and is started by the service control manager");
+            1
+        },
+        Err(e) => {
+            eprintln!("{}", crate::Error::from(e));
+            1
+        },
+    }
+}
@@ -0,0 +1,31 @@
+use std::io::{BufRead, BufReader};
+use std::sync::mpsc::Sender;
+use super::Event;
+use super::pipe::PipeServer;
+
+/// Name of the pipe the target sends its sd_notify states to, in
+/// `SOMBRA_NOTIFY_PIPE`. It ends with the pid of the wrapper, so a wrapper
+/// still exiting doesn't take the pipe of the next one.
+pub(super) fn pipe_name(name: &str) -> String {
+    format!("\\\\.\\pipe\\sombra-notify-{}-{}", name, std::process::id())
+}
+
+/// Passes each state the target of `service` sends, one line per state
+/// as `sombra::notify` writes them, to the supervision loop
+pub(super) fn listen(pipe: &str, service: &str, events: Sender<Event>) -> crate::Result<()> {
+    let mut server = PipeServer::new(pipe, service)?;
+    std::thread::spawn(move || {
+        while let Ok(client) = server.accept() {
+            for state in BufReader::new(client).lines() {
+                let state = match state {
+                    Ok(state) => state,
+                    Err(_) => break,
+                };
+                if events.send(Event::Notified(state)).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    Ok(())
+}
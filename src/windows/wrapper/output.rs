@@ -0,0 +1,253 @@
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use crate::{LogRotation, SombraConfig};
+
+/// A log file of the target, rotated as `rotation` says
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    /// When the file was started, which its creation time can't tell: a
+    /// file created where one was just renamed inherits its creation time
+    started: SystemTime,
+    rotation: Option<LogRotation>,
+}
+
+impl LogFile {
+    fn open(path: &Path, rotation: Option<LogRotation>) -> crate::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)
+            .map_err(|e| crate::Error::from(e).content(path.display().to_string()))?;
+        let metadata = file.metadata()?;
+        Ok(LogFile {
+            path: path.to_path_buf(),
+            file,
+            size: metadata.len(),
+            started: metadata.created().unwrap_or_else(|_| SystemTime::now()),
+            rotation,
+        })
+    }
+
+    /// `<log>.<n>`, the `n`th newest rotated file
+    fn rotated(&self, n: u32) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", n));
+        PathBuf::from(path)
+    }
+
+    /// Whether the file is rotated before `len` more bytes are written
+    fn due(&self, len: usize, now: SystemTime) -> bool {
+        let rotation = match self.rotation {
+            Some(rotation) if self.size > 0 => rotation,
+            _ => return false,
+        };
+        let full = rotation.max_size.is_some_and(|max_size| self.size + len as u64 > max_size);
+        let old = rotation.max_age.is_some_and(|max_age| {
+            now.duration_since(self.started).is_ok_and(|age| age >= max_age)
+        });
+        full || old
+    }
+
+    /// Shifts `<log>.1..<log>.<keep>` up, dropping the oldest, and starts a
+    /// new file
+    fn rotate(&mut self, keep: u32) -> crate::Result<()> {
+        if keep == 0 {
+            std::fs::remove_file(&self.path)?;
+        } else {
+            let _ = std::fs::remove_file(self.rotated(keep));
+            for n in (1..keep).rev() {
+                let _ = std::fs::rename(self.rotated(n), self.rotated(n + 1));
+            }
+            std::fs::rename(&self.path, self.rotated(1))?;
+        }
+        self.file = OpenOptions::new().create(true).write(true).truncate(true).open(&self.path)?;
+        self.size = 0;
+        self.started = SystemTime::now();
+        Ok(())
+    }
+
+    fn write(&mut self, line: &[u8]) -> crate::Result<()> {
+        if let Some(rotation) = self.rotation {
+            if self.due(line.len(), SystemTime::now()) {
+                self.rotate(rotation.keep)?;
+            }
+        }
+        self.file.write_all(line)?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+}
+
+/// A log file of the target, shared by its standard output and error when
+/// both go to the same path
+type Log = Arc<Mutex<LogFile>>;
+
+/// As many lines of output as the wrapper keeps for the control pipe
+const RECENT_LINES: usize = 1000;
+
+/// The last lines the target logged, from both its standard output and
+/// error
+#[derive(Clone, Default)]
+pub(super) struct Recent {
+    lines: Arc<Mutex<VecDeque<String>>>,
+}
+
+impl Recent {
+    fn push(&self, line: &[u8]) {
+        let line = String::from_utf8_lossy(line);
+        let mut lines = self.lines.lock().unwrap();
+        if lines.len() == RECENT_LINES {
+            lines.pop_front();
+        }
+        lines.push_back(line.trim_end_matches(['\r', '\n']).to_string());
+    }
+
+    /// The last `count` lines, oldest first
+    pub(super) fn tail(&self, count: usize) -> Vec<String> {
+        let lines = self.lines.lock().unwrap();
+        lines.iter().skip(lines.len().saturating_sub(count)).cloned().collect()
+    }
+}
+
+/// Where the wrapper copies the output of the target. It outlives the
+/// target, so a restarted target appends to the same files.
+pub(super) struct Output {
+    stdout: Option<Log>,
+    stderr: Option<Log>,
+    recent: Recent,
+}
+
+impl Output {
+    pub(super) fn new(config: &SombraConfig) -> crate::Result<Self> {
+        let open = |path: &Path| -> crate::Result<Log> {
+            Ok(Arc::new(Mutex::new(LogFile::open(path, config.log_rotation)?)))
+        };
+        let stdout = config.stdout_log.as_deref().map(open).transpose()?;
+        let stderr = match (&config.stderr_log, &stdout) {
+            (Some(path), Some(stdout)) if config.stdout_log.as_ref() == Some(path) =>
+                Some(stdout.clone()),
+            (path, _) => path.as_deref().map(open).transpose()?,
+        };
+        Ok(Output { stdout, stderr, recent: Recent::default() })
+    }
+
+    /// The lines copied so far. Output without a log file is discarded, so
+    /// it isn't among them.
+    pub(super) fn recent(&self) -> Recent {
+        self.recent.clone()
+    }
+
+    /// Output without a log file is discarded
+    pub(super) fn stdout(&self) -> Stdio {
+        if self.stdout.is_some() { Stdio::piped() } else { Stdio::null() }
+    }
+
+    pub(super) fn stderr(&self) -> Stdio {
+        if self.stderr.is_some() { Stdio::piped() } else { Stdio::null() }
+    }
+
+    /// Copies the pipes of a target spawned with `stdout()` and `stderr()`
+    /// to their logs, until the target closes them
+    pub(super) fn capture(&self, child: &mut Child) {
+        if let (Some(log), Some(pipe)) = (&self.stdout, child.stdout.take()) {
+            Output::copy(pipe, log.clone(), self.recent.clone());
+        }
+        if let (Some(log), Some(pipe)) = (&self.stderr, child.stderr.take()) {
+            Output::copy(pipe, log.clone(), self.recent.clone());
+        }
+    }
+
+    /// Line by line, so the lines of a shared log don't mix
+    fn copy<R: Read + Send + 'static>(pipe: R, log: Log, recent: Recent) {
+        std::thread::spawn(move || {
+            let mut pipe = BufReader::new(pipe);
+            let mut line = vec![];
+            while let Ok(n) = pipe.read_until(b'\n', &mut line) {
+                if n == 0 {
+                    break;
+                }
+                // A failed rotation keeps the line in the current file
+                let _ = log.lock().unwrap().write(&line);
+                recent.push(&line);
+                line.clear();
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_path_shares_the_log() {
+        let log = std::env::temp_dir().join("sombra_output_test.log");
+        let mut config = SombraConfig::new("tcp_echo", "C:\\tcp_echo.exe");
+        config.stdout_log = Some(log.clone());
+        config.stderr_log = Some(log.clone());
+        let output = Output::new(&config).unwrap();
+        assert!(Arc::ptr_eq(output.stdout.as_ref().unwrap(), output.stderr.as_ref().unwrap()));
+
+        config.stderr_log = None;
+        assert!(Output::new(&config).unwrap().stderr.is_none());
+        let _ = std::fs::remove_file(&log);
+    }
+
+    #[test]
+    fn recent_keeps_the_last_lines() {
+        let recent = Recent::default();
+        for n in 0..RECENT_LINES + 2 {
+            recent.push(format!("{}\r\n", n).as_bytes());
+        }
+        assert_eq!(recent.tail(2), vec![RECENT_LINES.to_string(),
+                                         (RECENT_LINES + 1).to_string()]);
+        assert_eq!(recent.tail(usize::MAX).len(), RECENT_LINES);
+        assert_eq!(recent.tail(usize::MAX)[0], "2");
+        assert!(recent.tail(0).is_empty());
+    }
+
+    #[test]
+    fn rotation_keeps_the_newest_files() {
+        let dir = std::env::temp_dir().join("sombra_rotation_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("tcp_echo.log");
+        let rotation = LogRotation { max_size: Some(8), max_age: None, keep: 2 };
+        let mut log = LogFile::open(&path, Some(rotation)).unwrap();
+        for line in ["one\n", "two\n", "three\n", "four\n"] {
+            log.write(line.as_bytes()).unwrap();
+        }
+        let read = |path: PathBuf| std::fs::read_to_string(path).unwrap();
+        assert_eq!(read(path.clone()), "four\n");
+        assert_eq!(read(log.rotated(1)), "three\n");
+        assert_eq!(read(log.rotated(2)), "one\ntwo\n");
+        assert!(!log.rotated(3).exists());
+        drop(log);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn rotation_is_due_by_size_or_age() {
+        let path = std::env::temp_dir().join("sombra_due_test.log");
+        let _ = std::fs::remove_file(&path);
+        let day = std::time::Duration::from_secs(86400);
+        let rotation = LogRotation { max_size: None, max_age: Some(day), keep: 1 };
+        let mut log = LogFile::open(&path, Some(rotation)).unwrap();
+        let now = SystemTime::now();
+        // An empty file is never rotated
+        assert!(!log.due(1, now + day));
+        log.write(b"line\n").unwrap();
+        assert!(!log.due(1 << 30, now));
+        assert!(log.due(1, log.started + day));
+
+        log.rotation = Some(LogRotation { max_size: Some(6), max_age: None, keep: 1 });
+        assert!(!log.due(1, now + day));
+        assert!(log.due(2, now));
+        drop(log);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,210 @@
+use std::ffi::{c_void, OsStr};
+use std::fs::File;
+use std::os::windows::ffi::OsStrExt;
+use std::os::windows::io::{AsRawHandle, FromRawHandle, RawHandle};
+use std::time::{Duration, Instant};
+
+#[link(name = "kernel32")]
+extern "system" {
+    fn CreateNamedPipeW(name: *const u16, open_mode: u32, pipe_mode: u32, max_instances: u32,
+                        out_buffer_size: u32, in_buffer_size: u32, default_timeout: u32,
+                        security_attributes: *const SecurityAttributes) -> RawHandle;
+    fn ConnectNamedPipe(pipe: RawHandle, overlapped: *mut c_void) -> i32;
+    fn GetCurrentProcess() -> RawHandle;
+    fn CloseHandle(handle: RawHandle) -> i32;
+    fn LocalFree(memory: *mut c_void) -> *mut c_void;
+}
+
+#[link(name = "advapi32")]
+extern "system" {
+    fn ConvertStringSecurityDescriptorToSecurityDescriptorW(
+        sddl: *const u16, revision: u32, descriptor: *mut *mut c_void, size: *mut u32) -> i32;
+    fn OpenProcessToken(process: RawHandle, access: u32, token: *mut RawHandle) -> i32;
+    fn GetTokenInformation(token: RawHandle, class: i32, info: *mut c_void, len: u32,
+                           return_len: *mut u32) -> i32;
+    fn LookupAccountNameW(system: *const u16, account: *const u16, sid: *mut c_void,
+                          sid_size: *mut u32, domain: *mut u16, domain_size: *mut u32,
+                          sid_use: *mut u32) -> i32;
+    fn ConvertSidToStringSidW(sid: *mut c_void, string: *mut *mut u16) -> i32;
+}
+
+const PIPE_ACCESS_INBOUND: u32 = 0x1;
+const PIPE_ACCESS_DUPLEX: u32 = 0x3;
+const FILE_FLAG_FIRST_PIPE_INSTANCE: u32 = 0x0008_0000;
+const PIPE_TYPE_BYTE_WAIT: u32 = 0x0;
+const PIPE_UNLIMITED_INSTANCES: u32 = 255;
+const ERROR_ACCESS_DENIED: i32 = 5;
+const ERROR_PIPE_CONNECTED: i32 = 535;
+const BUFFER_SIZE: u32 = 4096;
+const SDDL_REVISION_1: u32 = 1;
+const TOKEN_QUERY: u32 = 0x8;
+const TOKEN_USER: i32 = 1;
+/// How long the pipe of another process is waited for
+const FIRST_INSTANCE_WAIT: Duration = Duration::from_secs(5);
+
+#[repr(C)]
+struct SecurityAttributes {
+    length: u32,
+    security_descriptor: *mut c_void,
+    inherit_handle: i32,
+}
+
+fn wide(s: &str) -> Vec<u16> {
+    OsStr::new(s).encode_wide().chain(Some(0)).collect()
+}
+
+/// The string of a SID, freeing the buffer Windows allocated for it
+fn sid_string(sid: *mut c_void) -> Option<String> {
+    let mut string = std::ptr::null_mut();
+    if unsafe { ConvertSidToStringSidW(sid, &mut string) } == 0 {
+        return None;
+    }
+    let len = (0..).take_while(|&i| unsafe { *string.add(i) } != 0).count();
+    let sid = String::from_utf16_lossy(unsafe { std::slice::from_raw_parts(string, len) });
+    unsafe { LocalFree(string as *mut c_void) };
+    Some(sid)
+}
+
+/// The SID of the account of the wrapper, which the target runs as too
+fn process_user() -> crate::Result<String> {
+    let mut token = std::ptr::null_mut();
+    if unsafe { OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token) } == 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    let mut len = 0;
+    unsafe { GetTokenInformation(token, TOKEN_USER, std::ptr::null_mut(), 0, &mut len) };
+    // TOKEN_USER starts with the pointer to the SID that follows it
+    let mut info = vec![0usize; (len as usize).div_ceil(std::mem::size_of::<usize>())];
+    let queried = unsafe {
+        GetTokenInformation(token, TOKEN_USER, info.as_mut_ptr() as *mut c_void, len, &mut len)
+    };
+    let error = std::io::Error::last_os_error();
+    unsafe { CloseHandle(token) };
+    if queried == 0 {
+        return Err(error.into());
+    }
+    sid_string(info[0] as *mut c_void).ok_or_else(|| std::io::Error::last_os_error().into())
+}
+
+/// The SID of `NT SERVICE\<service>`, which a restricted service needs
+/// to be granted
+fn service_sid(service: &str) -> Option<String> {
+    let account = wide(&format!("NT SERVICE\\{}", service));
+    let mut sid = vec![0u8; 68];
+    let mut sid_size = sid.len() as u32;
+    let mut domain = vec![0u16; 256];
+    let mut domain_size = domain.len() as u32;
+    let mut sid_use = 0;
+    let found = unsafe {
+        LookupAccountNameW(std::ptr::null(), account.as_ptr(), sid.as_mut_ptr() as *mut c_void,
+                           &mut sid_size, domain.as_mut_ptr(), &mut domain_size, &mut sid_use)
+    };
+    match found {
+        0 => None,
+        _ => sid_string(sid.as_mut_ptr() as *mut c_void),
+    }
+}
+
+/// A DACL granting full access to SYSTEM, administrators, `user` and the
+/// `service` SID, and nothing to anyone else
+fn sddl(user: &str, service: Option<&str>) -> String {
+    let mut sddl = format!("D:P(A;;GA;;;SY)(A;;GA;;;BA)(A;;GA;;;{})", user);
+    if let Some(service) = service {
+        sddl.push_str(&format!("(A;;GA;;;{})", service));
+    }
+    sddl
+}
+
+/// A named pipe server taking one client at a time. The instance for the
+/// next client exists while the current one is served, so clients don't
+/// find the pipe missing in between.
+pub(super) struct PipeServer {
+    name: Vec<u16>,
+    open_mode: u32,
+    sddl: Vec<u16>,
+    next: File,
+}
+
+impl PipeServer {
+    /// A pipe clients only write to. Clients can connect once it returns.
+    pub(super) fn new(name: &str, service: &str) -> crate::Result<Self> {
+        PipeServer::with_open_mode(name, service, PIPE_ACCESS_INBOUND)
+    }
+
+    /// A pipe clients write their requests to and read the answers from
+    pub(super) fn duplex(name: &str, service: &str) -> crate::Result<Self> {
+        PipeServer::with_open_mode(name, service, PIPE_ACCESS_DUPLEX)
+    }
+
+    /// Fails when another process holds the pipe, rather than sharing its
+    /// name with it. The wrapper of a restarting service may still be
+    /// exiting, so it is given `FIRST_INSTANCE_WAIT` to release it.
+    fn with_open_mode(name: &str, service: &str, open_mode: u32) -> crate::Result<Self> {
+        let sddl = wide(&sddl(&process_user()?, service_sid(service).as_deref()));
+        let start = Instant::now();
+        let next = loop {
+            match PipeServer::instance(&wide(name), open_mode | FILE_FLAG_FIRST_PIPE_INSTANCE,
+                                       &sddl) {
+                Err(e) if e.os_code() == Some(ERROR_ACCESS_DENIED) &&
+                    start.elapsed() < FIRST_INSTANCE_WAIT =>
+                    std::thread::sleep(Duration::from_millis(100)),
+                next => break next.map_err(|e| e.content(name.to_string()))?,
+            }
+        };
+        Ok(PipeServer { name: wide(name), open_mode, sddl, next })
+    }
+
+    /// The pipe grants the account of the wrapper, the service SID, SYSTEM
+    /// and administrators full access, and nothing to anyone else
+    fn instance(name: &[u16], open_mode: u32, sddl: &[u16]) -> crate::Result<File> {
+        let mut descriptor = std::ptr::null_mut();
+        if unsafe {
+            ConvertStringSecurityDescriptorToSecurityDescriptorW(
+                sddl.as_ptr(), SDDL_REVISION_1, &mut descriptor, std::ptr::null_mut())
+        } == 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        let attributes = SecurityAttributes {
+            length: std::mem::size_of::<SecurityAttributes>() as u32,
+            security_descriptor: descriptor,
+            inherit_handle: 0,
+        };
+        let handle = unsafe {
+            CreateNamedPipeW(name.as_ptr(), open_mode, PIPE_TYPE_BYTE_WAIT,
+                             PIPE_UNLIMITED_INSTANCES, BUFFER_SIZE, BUFFER_SIZE, 0, &attributes)
+        };
+        let error = std::io::Error::last_os_error();
+        unsafe { LocalFree(descriptor) };
+        // INVALID_HANDLE_VALUE
+        if handle as isize == -1 {
+            return Err(error.into());
+        }
+        Ok(unsafe { File::from_raw_handle(handle) })
+    }
+
+    /// Waits for the next client, the end of the pipe serving it closing
+    /// the connection when dropped
+    pub(super) fn accept(&mut self) -> crate::Result<File> {
+        if unsafe { ConnectNamedPipe(self.next.as_raw_handle(), std::ptr::null_mut()) } == 0 {
+            let error = std::io::Error::last_os_error();
+            // The client connected between the creation and the wait
+            if error.raw_os_error() != Some(ERROR_PIPE_CONNECTED) {
+                return Err(error.into());
+            }
+        }
+        let next = PipeServer::instance(&self.name, self.open_mode, &self.sddl)?;
+        Ok(std::mem::replace(&mut self.next, next))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sddl_grants_the_service_only() {
+        assert_eq!(sddl("S-1-5-19", None), "D:P(A;;GA;;;SY)(A;;GA;;;BA)(A;;GA;;;S-1-5-19)");
+        assert_eq!(sddl("S-1-5-18", Some("S-1-5-80-1")),
+                   "D:P(A;;GA;;;SY)(A;;GA;;;BA)(A;;GA;;;S-1-5-18)(A;;GA;;;S-1-5-80-1)");
+    }
+}
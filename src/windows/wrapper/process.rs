@@ -0,0 +1,182 @@
+use std::os::windows::io::{AsRawHandle, RawHandle};
+use std::os::windows::process::CommandExt;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::Once;
+use std::time::{Duration, Instant};
+use crate::{Priority, SombraConfig, StopSignal};
+use super::job::Job;
+use super::output::Output;
+
+type HandlerRoutine = unsafe extern "system" fn(ctrl_type: u32) -> i32;
+
+#[link(name = "kernel32")]
+extern "system" {
+    fn AttachConsole(process_id: u32) -> i32;
+    fn FreeConsole() -> i32;
+    fn SetConsoleCtrlHandler(handler: Option<HandlerRoutine>, add: i32) -> i32;
+    fn GenerateConsoleCtrlEvent(ctrl_event: u32, process_group_id: u32) -> i32;
+    fn SetPriorityClass(process: RawHandle, priority_class: u32) -> i32;
+    fn SetProcessAffinityMask(process: RawHandle, mask: usize) -> i32;
+}
+
+/// A console of its own, without a window, so console events reach the
+/// target and not the wrapper
+const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+/// The target starts once it is in its job, so its children are too
+const CREATE_SUSPENDED: u32 = 0x0000_0004;
+const CTRL_C_EVENT: u32 = 0;
+const CTRL_BREAK_EVENT: u32 = 1;
+/// How often the wrapper checks whether a signalled target exited
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Keeps the wrapper alive through the events it sends to the console of
+/// the target, which it is attached to while sending them
+unsafe extern "system" fn ignore_stop_events(ctrl_type: u32) -> i32 {
+    (ctrl_type == CTRL_C_EVENT || ctrl_type == CTRL_BREAK_EVENT) as i32
+}
+
+/// The target, run as a child of the wrapper
+pub(super) struct Target {
+    child: Child,
+    job: Job,
+    kill_process_tree: bool,
+}
+
+impl Target {
+    pub(super) fn spawn(config: &SombraConfig, output: &Output) -> crate::Result<Self> {
+        let mut command = Command::new(&config.path);
+        // The environment of the service is the one of the wrapper, which
+        // only adds its own variables to `env`
+        command.args(&config.args)
+            .envs(config.env.iter().map(|(key, value)| (key, value)))
+            .stdin(Stdio::null())
+            .stdout(output.stdout())
+            .stderr(output.stderr())
+            .creation_flags(CREATE_NO_WINDOW | CREATE_SUSPENDED);
+        if let Some(working_directory) = &config.working_directory {
+            command.current_dir(working_directory);
+        }
+        let mut child = command.spawn()
+            .map_err(|e| crate::Error::from(e).content(config.path.display().to_string()))?;
+        let job = Target::schedule(&child, config)
+            .and_then(|()| Job::new(config))
+            .and_then(|job| job.assign(&child).map(|()| job))
+            .and_then(|job| Job::start(&child).map(|()| job));
+        let job = match job {
+            Ok(job) => job,
+            Err(e) => {
+                let _ = child.kill();
+                return Err(e);
+            },
+        };
+        output.capture(&mut child);
+        Ok(Target { child, job, kill_process_tree: config.kill_process_tree })
+    }
+
+    /// Applies the priority class and the CPU affinity of `config`, before
+    /// the target runs
+    fn schedule(child: &Child, config: &SombraConfig) -> crate::Result<()> {
+        let check = |result: i32| match result {
+            0 => Err(crate::Error::from(std::io::Error::last_os_error())),
+            _ => Ok(()),
+        };
+        if let Some(priority) = config.priority {
+            let class = match priority {
+                Priority::Idle => 0x0040,
+                Priority::BelowNormal => 0x4000,
+                Priority::Normal => 0x0020,
+                Priority::AboveNormal => 0x8000,
+                Priority::High => 0x0080,
+            };
+            check(unsafe { SetPriorityClass(child.as_raw_handle(), class) })?;
+        }
+        if let Some(mask) = config.cpu_affinity {
+            check(unsafe { SetProcessAffinityMask(child.as_raw_handle(), mask as usize) })?;
+        }
+        Ok(())
+    }
+
+    pub(super) fn pid(&self) -> u32 {
+        self.child.id()
+    }
+
+    fn code(status: ExitStatus) -> u32 {
+        // NTSTATUS codes such as 0xC0000005 come out negative
+        status.code().map(|code| code as u32).unwrap_or(1)
+    }
+
+    /// Exit code of the target, once it exited
+    pub(super) fn exit_code(&mut self) -> crate::Result<Option<u32>> {
+        Ok(self.child.try_wait()?.map(Target::code))
+    }
+
+    /// Terminates the target, and the processes it spawned with
+    /// `kill_process_tree`, and waits for it, returning its exit code
+    pub(super) fn kill(&mut self) -> crate::Result<u32> {
+        if self.kill_process_tree {
+            self.job.terminate(1)?;
+        } else if self.exit_code()?.is_none() {
+            self.child.kill()?;
+        }
+        Ok(Target::code(self.child.wait()?))
+    }
+
+    /// Sends the console event of `signal` to every process on the console
+    /// of the target
+    fn signal(&self, signal: StopSignal) -> crate::Result<()> {
+        static IGNORE: Once = Once::new();
+        IGNORE.call_once(|| unsafe {
+            SetConsoleCtrlHandler(Some(ignore_stop_events), 1);
+        });
+        let event = match signal {
+            StopSignal::Terminate => CTRL_BREAK_EVENT,
+            StopSignal::Interrupt => CTRL_C_EVENT,
+        };
+        unsafe {
+            // Services start without a console; one left attached is freed
+            FreeConsole();
+            if AttachConsole(self.pid()) == 0 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            let sent = GenerateConsoleCtrlEvent(event, 0);
+            let error = std::io::Error::last_os_error();
+            FreeConsole();
+            if sent == 0 {
+                return Err(error.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends `signal` to the target and kills it unless it exits within
+    /// `timeout`, calling `waiting` while it waits. Returns the exit code.
+    pub(super) fn stop<F: FnMut()>(&mut self, signal: StopSignal, timeout: Duration,
+                                   mut waiting: F) -> crate::Result<u32> {
+        if self.exit_code()?.is_some() {
+            return self.kill();
+        }
+        // A target without a console, e.g. a GUI program, only gets killed
+        if self.signal(signal).is_ok() {
+            let begin = Instant::now();
+            while begin.elapsed() < timeout {
+                if self.exit_code()?.is_some() {
+                    // Takes down the processes it left behind
+                    return self.kill();
+                }
+                waiting();
+                std::thread::sleep(STOP_POLL_INTERVAL);
+            }
+        }
+        self.kill()
+    }
+
+    /// Suspends the threads of the processes of the job, for
+    /// SERVICE_CONTROL_PAUSE
+    pub(super) fn suspend(&self) -> crate::Result<()> {
+        self.job.suspend()
+    }
+
+    pub(super) fn resume(&self) -> crate::Result<()> {
+        self.job.resume()
+    }
+}
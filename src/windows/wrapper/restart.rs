@@ -0,0 +1,64 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use crate::RestartPolicy;
+
+/// The restarts of the target under its `RestartPolicy`
+pub(super) struct Restarts {
+    policy: RestartPolicy,
+    /// Restarts within the window of the policy
+    recent: VecDeque<Instant>,
+    delay: Duration,
+}
+
+impl Restarts {
+    pub(super) fn new(policy: RestartPolicy) -> Self {
+        Restarts { policy, recent: VecDeque::new(), delay: policy.initial_delay }
+    }
+
+    /// The delay before restarting the target at `now`, or None once it was
+    /// restarted `max_restarts` times within the window. The delay doubles
+    /// up to `max_delay`, and starts over after a window without restarts.
+    pub(super) fn next(&mut self, now: Instant) -> Option<Duration> {
+        while let Some(&restart) = self.recent.front() {
+            if now.duration_since(restart) <= self.policy.window {
+                break;
+            }
+            self.recent.pop_front();
+        }
+        if self.recent.len() >= self.policy.max_restarts as usize {
+            return None;
+        }
+        if self.recent.is_empty() {
+            self.delay = self.policy.initial_delay;
+        }
+        let delay = self.delay;
+        self.delay = (delay * 2).min(self.policy.max_delay.max(self.policy.initial_delay));
+        self.recent.push_back(now);
+        Some(delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restarts_back_off_then_give_up() {
+        let mut restarts = Restarts::new(RestartPolicy {
+            max_restarts: 4,
+            window: Duration::from_secs(60),
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(3),
+        });
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        assert_eq!(restarts.next(at(0)), Some(Duration::from_secs(1)));
+        assert_eq!(restarts.next(at(1)), Some(Duration::from_secs(2)));
+        assert_eq!(restarts.next(at(3)), Some(Duration::from_secs(3)));
+        assert_eq!(restarts.next(at(6)), Some(Duration::from_secs(3)));
+        assert_eq!(restarts.next(at(9)), None);
+        // The first restarts left the window
+        assert_eq!(restarts.next(at(62)), Some(Duration::from_secs(3)));
+        assert_eq!(restarts.next(at(200)), Some(Duration::from_secs(1)));
+    }
+}